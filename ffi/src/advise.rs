@@ -0,0 +1,374 @@
+// Access-pattern hints for the page cache.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::os::raw::{c_char, c_void};
+use std::sync::Arc;
+
+use crate::error::{
+    self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_ERR_UNSUPPORTED,
+};
+use crate::registry::{self, Kind};
+
+pub const MMAP_ADVICE_NORMAL: i32 = 0;
+pub const MMAP_ADVICE_SEQUENTIAL: i32 = 1;
+pub const MMAP_ADVICE_RANDOM: i32 = 2;
+pub const MMAP_ADVICE_WILLNEED: i32 = 3;
+pub const MMAP_ADVICE_DONTNEED: i32 = 4;
+
+/// Returned by `mmap_advise` when the advice was valid but has no effect here: the platform
+/// has no equivalent, or dropping the pages would lose data or undo a lock.
+pub const MMAP_HINT_IGNORED: i32 = 1;
+
+/// Hints the page cache about how `[offset, offset + len)` of the file at `path` will be read
+/// (`len == 0` means "to the end of the file"). The file is opened, advised and closed again;
+/// use it before mapping to tune readahead at the file level.
+///
+/// `advice` is one of the `MMAP_ADVICE_*` constants and is translated to `posix_fadvise`
+/// on Linux and to `F_RDAHEAD` / `F_RDADVISE` on macOS (where DONTNEED has no equivalent
+/// and is accepted as a no-op). On Windows there is no equivalent and this returns 0.
+///
+/// Returns 0 on success, -1 on failure with the OS error available from `mmap_last_os_error`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_fadvise(
+    path: *const c_char,
+    offset: u64,
+    len: u64,
+    advice: i32,
+) -> i32 {
+    if path.is_null() || !(MMAP_ADVICE_NORMAL..=MMAP_ADVICE_DONTNEED).contains(&advice) {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let path = unsafe { CStr::from_ptr(path) };
+
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            unsafe {
+                let fd = libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return error::fail(Error::last_os());
+                }
+                let rc = fadvise(fd, offset, len, advice);
+                libc::close(fd);
+                match rc {
+                    Ok(()) => 0,
+                    Err(e) => error::fail(e),
+                }
+            }
+        } else if #[cfg(windows)] {
+            let _ = (path, offset, len);
+            0
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_vendor = "apple")))]
+unsafe fn fadvise(fd: libc::c_int, offset: u64, len: u64, advice: i32) -> Result<(), Error> {
+    let advice = match advice {
+        MMAP_ADVICE_SEQUENTIAL => libc::POSIX_FADV_SEQUENTIAL,
+        MMAP_ADVICE_RANDOM => libc::POSIX_FADV_RANDOM,
+        MMAP_ADVICE_WILLNEED => libc::POSIX_FADV_WILLNEED,
+        MMAP_ADVICE_DONTNEED => libc::POSIX_FADV_DONTNEED,
+        _ => libc::POSIX_FADV_NORMAL,
+    };
+    // posix_fadvise returns the error number instead of setting errno.
+    let rc = unsafe { libc::posix_fadvise(fd, offset as libc::off_t, len as libc::off_t, advice) };
+    if rc != 0 {
+        return Err(Error {
+            code: error::MMAP_ERR_OS,
+            os: rc,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(target_vendor = "apple")]
+unsafe fn fadvise(fd: libc::c_int, offset: u64, len: u64, advice: i32) -> Result<(), Error> {
+    let rc = unsafe {
+        match advice {
+            MMAP_ADVICE_NORMAL | MMAP_ADVICE_SEQUENTIAL => libc::fcntl(fd, libc::F_RDAHEAD, 1),
+            MMAP_ADVICE_RANDOM => libc::fcntl(fd, libc::F_RDAHEAD, 0),
+            MMAP_ADVICE_WILLNEED => {
+                let count = if len == 0 {
+                    i32::MAX as u64
+                } else {
+                    len.min(i32::MAX as u64)
+                };
+                let ra = libc::radvisory {
+                    ra_offset: offset as libc::off_t,
+                    ra_count: count as libc::c_int,
+                };
+                libc::fcntl(fd, libc::F_RDADVISE, &ra)
+            }
+            _ => 0,
+        }
+    };
+    if rc == -1 {
+        return Err(Error::last_os());
+    }
+    Ok(())
+}
+
+/// Hints how `[offset, offset + len)` of the mapping at `base` will be accessed (`len == 0`
+/// means "to the end of the mapping"), e.g. `MMAP_ADVICE_SEQUENTIAL` before scanning a huge
+/// file once, so readahead is aggressive and pages already read are dropped first instead of
+/// crowding out the rest of the page cache. Unlike `mmap_fadvise` this advises the mapping,
+/// not the file. The range is widened to start on a page boundary.
+///
+/// `advice` is one of the `MMAP_ADVICE_*` constants and is translated to `madvise` on Unix.
+/// `MMAP_ADVICE_DONTNEED` drops the pages from the mapping, which reads them back from the
+/// file on the next access; it is ignored for snapshots (see `mmap_open_snapshot`), whose
+/// private pages would come back zeroed, and for mappings opened with `MMAP_LOCKED`. On
+/// Windows `MMAP_ADVICE_WILLNEED` is PrefetchVirtualMemory and `MMAP_ADVICE_DONTNEED` trims
+/// the pages from the working set (VirtualUnlock on pages that aren't locked), both best
+/// effort; there is nothing for the other three.
+///
+/// Returns 0 if the advice was given, `MMAP_HINT_IGNORED` if it has no effect here (see above),
+/// or -1 on failure (`MMAP_ERR_INVALID_ARG` for an unknown `advice` or a `base` that isn't a
+/// mapping base, `MMAP_ERR_OUT_OF_BOUNDS` for a range past the end of the mapping, or the OS
+/// error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_advise(
+    base: *mut c_void,
+    offset: usize,
+    len: usize,
+    advice: i32,
+) -> i32 {
+    match advise(base as usize, offset, len, advice) {
+        Ok(rc) => rc,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// The pages `mmap_advise` and `mmap_evict` act on, and what they need to know of the mapping.
+struct Target {
+    start: usize,
+    len: usize,
+    kind: Kind,
+    locked: bool,
+    file: Option<Arc<File>>,
+}
+
+/// Checks `[offset, offset + len)` (`len == 0`: to the end) against the mapping at `base` and
+/// widens it to start on a page boundary.
+fn target(base: usize, offset: usize, len: usize) -> Result<Target, Error> {
+    let registry = registry::lock();
+    let Some(m) = registry.get(&base) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let end = match len {
+        0 => m.len,
+        len => offset.saturating_add(len),
+    };
+    if offset > m.len || end > m.len {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    let start = (base + offset) & !(crate::page_size() - 1);
+    let pinned = m
+        .pinned
+        .iter()
+        .any(|p| p.start < end && start - base < p.end);
+    Ok(Target {
+        start,
+        len: base + end - start,
+        kind: m.kind,
+        locked: m.locked || pinned,
+        file: m.file.clone(),
+    })
+}
+
+fn advise(base: usize, offset: usize, len: usize, advice: i32) -> Result<i32, Error> {
+    if !(MMAP_ADVICE_NORMAL..=MMAP_ADVICE_DONTNEED).contains(&advice) {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let Target {
+        start,
+        len,
+        kind,
+        locked,
+        ..
+    } = target(base, offset, len)?;
+    if advice == MMAP_ADVICE_DONTNEED && (kind == Kind::Snapshot || locked) {
+        return Ok(MMAP_HINT_IGNORED);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let advice = match advice {
+                MMAP_ADVICE_SEQUENTIAL => libc::MADV_SEQUENTIAL,
+                MMAP_ADVICE_RANDOM => libc::MADV_RANDOM,
+                MMAP_ADVICE_WILLNEED => libc::MADV_WILLNEED,
+                MMAP_ADVICE_DONTNEED => libc::MADV_DONTNEED,
+                _ => libc::MADV_NORMAL,
+            };
+            if unsafe { libc::madvise(start as *mut c_void, len, advice) } != 0 {
+                return Err(Error::last_os());
+            }
+            Ok(0)
+        } else if #[cfg(windows)] {
+            match advice {
+                MMAP_ADVICE_WILLNEED => crate::prefetch::prefault(start, len),
+                MMAP_ADVICE_DONTNEED => {
+                    use windows_sys::Win32::System::Memory::VirtualUnlock;
+                    // Fails with ERROR_NOT_LOCKED, having trimmed the pages all the same.
+                    unsafe { VirtualUnlock(start as *const c_void, len) };
+                }
+                _ => return Ok(MMAP_HINT_IGNORED),
+            }
+            Ok(0)
+        }
+    }
+}
+
+/// Drops `[offset, offset + len)` of the mapping at `base` from memory (`len == 0` means "to
+/// the end of the mapping"), e.g. once a chunk of a huge file has been processed, so that the
+/// page cache keeps the chunks still to come instead. The range is widened to start on a page
+/// boundary. Nothing is lost: evicted pages are read back on the next access. Only clean pages
+/// can be dropped, so the range of a file mapping is flushed first (as by `mmap_flush`), which
+/// writes whatever was modified in it to the file; its pages are then unmapped (MADV_DONTNEED)
+/// and, on Linux, dropped from the page cache (POSIX_FADV_DONTNEED) unless other mappings still
+/// use them (those of files in tmpfs stay: memory is all they have). The pages of a snapshot (see
+/// `mmap_open_snapshot`) exist nowhere else, so Linux swaps them out with MADV_PAGEOUT (5.4 and
+/// later) rather than discarding them as MADV_FREE would. Other Unix systems only deprioritize the
+/// pages (posix_madvise POSIX_MADV_DONTNEED), and Windows trims them from the working set with
+/// VirtualUnlock, as `MMAP_ADVICE_DONTNEED` does, leaving the rest of the process's working set
+/// alone.
+///
+/// Returns 0, `MMAP_HINT_IGNORED` for mappings opened with `MMAP_LOCKED` (and for snapshots on
+/// kernels without MADV_PAGEOUT), or -1 on failure (`MMAP_ERR_INVALID_ARG` for a `base` that
+/// isn't a mapping base, `MMAP_ERR_OUT_OF_BOUNDS` for a range past the end of the mapping, or
+/// the OS error, e.g. of the flush).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_evict(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    match evict(base as usize, offset, len) {
+        Ok(rc) => rc,
+        Err(e) => error::fail(e),
+    }
+}
+
+fn evict(base: usize, offset: usize, len: usize) -> Result<i32, Error> {
+    let Target {
+        start,
+        len,
+        kind,
+        locked,
+        file,
+    } = target(base, offset, len)?;
+    if locked {
+        return Ok(MMAP_HINT_IGNORED);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    if kind == Kind::File {
+        unsafe { crate::sync_view(start, len)? };
+    }
+    let addr = start as *mut c_void;
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            if kind == Kind::Snapshot {
+                if unsafe { libc::madvise(addr, len, libc::MADV_PAGEOUT) } == 0 {
+                    return Ok(0);
+                }
+                let e = Error::last_os();
+                return if e.os == libc::EINVAL { Ok(MMAP_HINT_IGNORED) } else { Err(e) };
+            }
+            if unsafe { libc::madvise(addr, len, libc::MADV_DONTNEED) } != 0 {
+                return Err(Error::last_os());
+            }
+            // Registered mappings with a file map it from its start.
+            if let Some(file) = file {
+                use std::os::fd::AsRawFd;
+                let offset = (start - base) as u64;
+                unsafe { fadvise(file.as_raw_fd(), offset, len as u64, MMAP_ADVICE_DONTNEED)? };
+            }
+            Ok(0)
+        } else if #[cfg(unix)] {
+            let _ = file;
+            // posix_madvise returns the error number instead of setting errno.
+            let rc = unsafe { libc::posix_madvise(addr, len, libc::POSIX_MADV_DONTNEED) };
+            if rc != 0 {
+                return Err(Error {
+                    code: error::MMAP_ERR_OS,
+                    os: rc,
+                });
+            }
+            Ok(0)
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::VirtualUnlock;
+            let _ = file;
+            // Fails with ERROR_NOT_LOCKED, having trimmed the pages all the same.
+            unsafe { VirtualUnlock(addr, len) };
+            Ok(0)
+        }
+    }
+}
+
+/// Tells the OS the mapping at `[addr, addr + len)` will be read front to back, for
+/// `mmap_open_sequential`: MADV_SEQUENTIAL on Unix (aggressive readahead, pages dropped behind
+/// the reader), PrefetchVirtualMemory over the whole range on Windows, which has no sequential
+/// hint for mappings. Best effort: a refused hint leaves an ordinary mapping.
+pub(crate) fn advise_sequential(addr: *mut c_void, len: usize) {
+    if len == 0 {
+        return;
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            unsafe { libc::madvise(addr, len, libc::MADV_SEQUENTIAL) };
+        } else if #[cfg(windows)] {
+            crate::prefetch::prefault(addr as usize, len);
+        }
+    }
+}
+
+/// Opts `[base, base + len)` of the mapping at `base` into transparent huge pages
+/// (madvise MADV_HUGEPAGE), so the kernel backs it with 2 MiB pages where it can and fewer
+/// TLB entries cover large read-mostly mappings. Unlike MAP_HUGETLB this needs no reserved
+/// huge pages: the kernel assembles them as memory allows, in the background for file
+/// mappings (which also needs a kernel built to collapse page cache into huge pages).
+/// Returns 0 on success, -1 on failure: `MMAP_ERR_UNSUPPORTED` outside Linux or on kernels
+/// without THP, `MMAP_ERR_INVALID_ARG` if `base` is not a mapping base, `MMAP_ERR_OUT_OF_BOUNDS`
+/// if `len` exceeds the mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_enable_thp(base: *mut c_void, len: usize) -> i32 {
+    match thp(base, len, true) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Opts `[base, base + len)` out of transparent huge pages (madvise MADV_NOHUGEPAGE), e.g. for
+/// sparse random access, where huge pages would fault in much more than is read, on systems
+/// with THP enabled for everything. Returns as `mmap_enable_thp`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_disable_thp(base: *mut c_void, len: usize) -> i32 {
+    match thp(base, len, false) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+fn thp(base: *mut c_void, len: usize, enable: bool) -> Result<(), Error> {
+    let Some((total, _)) = registry::lookup(base as usize) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if len > total {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            let advice = if enable { libc::MADV_HUGEPAGE } else { libc::MADV_NOHUGEPAGE };
+            if len == 0 || unsafe { libc::madvise(base, len, advice) } == 0 {
+                return Ok(());
+            }
+            let e = Error::last_os();
+            // The kernel was built without THP.
+            Err(if e.os == libc::EINVAL { Error::new(MMAP_ERR_UNSUPPORTED) } else { e })
+        } else {
+            let _ = enable;
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
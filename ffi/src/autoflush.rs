@@ -0,0 +1,158 @@
+// Periodic write-back of dirty pages (see dirty.rs) by one shared background thread.
+//
+// Each auto-flush mapping keeps its interval and next deadline in its registry entry. The
+// thread flushes due mappings while holding the registry lock, so a concurrent `mmap_close`
+// waits for the flush to finish and a closed mapping, whose entry is gone, is never touched.
+// The thread starts with the first `mmap_autoflush_enable` and stops in `mmap_shutdown`.
+
+use std::os::raw::c_void;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG};
+use crate::{dirty, registry};
+
+#[derive(Clone, Copy)]
+pub(crate) struct AutoFlush {
+    interval: Duration,
+    due: Instant,
+}
+
+#[derive(Default)]
+struct Signal {
+    stop: bool,
+    /// Set when a deadline may have moved earlier, so the thread recomputes its sleep.
+    kicked: bool,
+}
+
+static WORKER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static SIGNAL: Mutex<Signal> = Mutex::new(Signal {
+    stop: false,
+    kicked: false,
+});
+static WAKE: Condvar = Condvar::new();
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Flushes every auto-flush mapping that is due (or all of them if `all`) and returns the
+/// earliest remaining deadline. Failed ranges stay dirty and are retried on the next pass.
+fn flush_due(all: bool) -> Option<Instant> {
+    let now = Instant::now();
+    let mut next: Option<Instant> = None;
+    for (&base, m) in registry::lock().iter_mut() {
+        let Some(af) = m.autoflush else { continue };
+        let due = if all || af.due <= now {
+            let _ = dirty::flush(base, m);
+            now + af.interval
+        } else {
+            af.due
+        };
+        m.autoflush = Some(AutoFlush { due, ..af });
+        next = Some(next.map_or(due, |n| n.min(due)));
+    }
+    next
+}
+
+fn run() {
+    loop {
+        let next = flush_due(false);
+        let mut signal = lock(&SIGNAL);
+        loop {
+            if signal.stop {
+                drop(signal);
+                // Last pass so nothing written before shutdown is left behind.
+                flush_due(true);
+                return;
+            }
+            if std::mem::take(&mut signal.kicked) {
+                break;
+            }
+            match next {
+                Some(at) => {
+                    let Some(timeout) = at.checked_duration_since(Instant::now()) else {
+                        break;
+                    };
+                    signal = WAKE
+                        .wait_timeout(signal, timeout)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+                None => signal = WAKE.wait(signal).unwrap_or_else(|e| e.into_inner()),
+            }
+        }
+    }
+}
+
+/// Starts the thread if it isn't running and makes it re-read the deadlines.
+fn kick() -> Result<(), Error> {
+    let mut worker = lock(&WORKER);
+    if worker.is_none() {
+        let thread = std::thread::Builder::new()
+            .name("mmap-autoflush".into())
+            .spawn(run)?;
+        *worker = Some(thread);
+    }
+    lock(&SIGNAL).kicked = true;
+    WAKE.notify_all();
+    Ok(())
+}
+
+/// Flushes the pages written to the mapping at `base` every `interval_ms` milliseconds from a
+/// background thread, as `mmap_flush_dirty` would. Calling it again changes the interval.
+/// Like `mmap_flush_dirty`, only sees writes made through the library or `mmap_mark_dirty`.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base
+/// or `interval_ms` is 0).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_autoflush_enable(base: *mut c_void, interval_ms: u32) -> i32 {
+    if interval_ms == 0 {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    {
+        let mut reg = registry::lock();
+        let Some(m) = reg.get_mut(&(base as usize)) else {
+            return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+        };
+        let interval = Duration::from_millis(interval_ms.into());
+        m.autoflush = Some(AutoFlush {
+            interval,
+            due: Instant::now() + interval,
+        });
+    }
+    match kick() {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Stops auto-flushing the mapping at `base`. Pages written since the last automatic flush
+/// stay dirty until flushed explicitly.
+/// Returns 0 on success, -1 if `base` is not a mapping base.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_autoflush_disable(base: *mut c_void) -> i32 {
+    match registry::lock().get_mut(&(base as usize)) {
+        Some(m) => {
+            m.autoflush = None;
+            0
+        }
+        None => error::fail(Error::new(MMAP_ERR_INVALID_ARG)),
+    }
+}
+
+/// Stops the thread after a final flush of every auto-flush mapping and disables auto-flush
+/// everywhere. Called by `mmap_shutdown`.
+pub(crate) fn shutdown() {
+    let mut worker = lock(&WORKER);
+    let Some(thread) = worker.take() else {
+        return;
+    };
+    lock(&SIGNAL).stop = true;
+    WAKE.notify_all();
+    let _ = thread.join();
+    *lock(&SIGNAL) = Signal::default();
+    for m in registry::lock().values_mut() {
+        m.autoflush = None;
+    }
+}
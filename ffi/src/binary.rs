@@ -0,0 +1,128 @@
+// Binary helpers that decode or scan mapped bytes in bulk, replacing per-element loops across
+// the FFI boundary.
+
+use std::os::raw::c_void;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_ERR_TOO_LARGE};
+use crate::registry;
+
+/// Decodes `count` u32s stored back to back at `base + offset` (no alignment required) into
+/// `dst`, as little-endian if `little_endian`, big-endian otherwise, byte-swapping when that
+/// differs from the host order.
+/// Returns the number of integers written (`count`), or -1 with `MMAP_ERR_INVALID_ARG` if a
+/// pointer is null or `dst_cap` is less than `count`.
+///
+/// Safety: `[base + offset, base + offset + 4 * count)` must lie within the mapping and `dst`
+/// must be writable for `dst_cap` u32s.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_read_u32_array(
+    base: *const c_void,
+    offset: usize,
+    count: usize,
+    little_endian: bool,
+    dst: *mut u32,
+    dst_cap: usize,
+) -> isize {
+    if base.is_null() || dst.is_null() || dst_cap < count || count > isize::MAX as usize / 4 {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    }
+    let src = unsafe { core::slice::from_raw_parts((base as *const u8).add(offset), count * 4) };
+    let out = unsafe { core::slice::from_raw_parts_mut(dst, count) };
+    for (word, bytes) in out.iter_mut().zip(src.chunks_exact(4)) {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        *word = if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        };
+    }
+    count as isize
+}
+
+/// Returns 1 if every byte of `[base + offset, base + offset + len)` is zero (including an
+/// empty range), 0 if not, or -1 with `MMAP_ERR_INVALID_ARG` for a null `base`, e.g. to skip
+/// writing blocks that are already holes. Scans a word at a time, eight words per check.
+///
+/// Safety: the range must lie within the mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_is_zero(base: *const c_void, offset: usize, len: usize) -> i32 {
+    if base.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let bytes = unsafe { core::slice::from_raw_parts((base as *const u8).add(offset), len) };
+    // Reading u8s as aligned words is always valid.
+    let (head, words, tail) = unsafe { bytes.align_to::<usize>() };
+    let zero = head.iter().chain(tail).all(|&b| b == 0)
+        && words
+            .chunks(8)
+            .all(|chunk| chunk.iter().fold(0, |acc, &w| acc | w) == 0);
+    zero as i32
+}
+
+/// Reads a length-prefixed frame at `base + offset`: a `len_field_size`-byte (1, 2, 4 or 8)
+/// unsigned length, little-endian if `little_endian`, big-endian otherwise, followed by that
+/// many payload bytes, which are copied to `payload_out`. The payload length is written to
+/// `payload_len_out` if non-null, even when the payload doesn't fit, so the caller can retry
+/// with a large enough buffer.
+/// Returns the offset just past the frame, i.e. of the next one, or -1:
+/// `MMAP_ERR_INVALID_ARG` if `base` is not a mapping base, `len_field_size` is not a valid
+/// size or `payload_out` is null (and the payload not empty), `MMAP_ERR_OUT_OF_BOUNDS` if the
+/// frame extends past the end of the mapping, `MMAP_ERR_TOO_LARGE` if the payload is longer
+/// than `payload_cap`.
+///
+/// Safety: `payload_out` must be null or writable for `payload_cap` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_read_frame(
+    base: *const c_void,
+    offset: usize,
+    len_field_size: u8,
+    little_endian: bool,
+    payload_out: *mut u8,
+    payload_cap: usize,
+    payload_len_out: *mut usize,
+) -> isize {
+    let Some((total, _)) = registry::lookup(base as usize) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    };
+    let field = len_field_size as usize;
+    if !matches!(field, 1 | 2 | 4 | 8) {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    }
+    let Some(start) = offset.checked_add(field).filter(|&start| start <= total) else {
+        return error::fail(Error::new(MMAP_ERR_OUT_OF_BOUNDS)) as isize;
+    };
+    let src = unsafe { core::slice::from_raw_parts((base as *const u8).add(offset), field) };
+    let mut bytes = [0u8; 8];
+    let len = if little_endian {
+        bytes[..field].copy_from_slice(src);
+        u64::from_le_bytes(bytes)
+    } else {
+        bytes[8 - field..].copy_from_slice(src);
+        u64::from_be_bytes(bytes)
+    };
+    if !payload_len_out.is_null() {
+        unsafe { *payload_len_out = usize::try_from(len).unwrap_or(usize::MAX) };
+    }
+    let Some((len, end)) = usize::try_from(len)
+        .ok()
+        .and_then(|len| Some((len, start.checked_add(len)?)))
+        .filter(|&(_, end)| end <= total && end <= isize::MAX as usize)
+    else {
+        let detail = format!(
+            "frame at offset {offset} with a {len}-byte payload ends past the mapping ({total} bytes)"
+        );
+        return error::fail_with(Error::new(MMAP_ERR_OUT_OF_BOUNDS), detail) as isize;
+    };
+    if len > payload_cap {
+        let detail =
+            format!("frame payload of {len} bytes is larger than the buffer ({payload_cap} bytes)");
+        return error::fail_with(Error::new(MMAP_ERR_TOO_LARGE), detail) as isize;
+    }
+    if len > 0 {
+        if payload_out.is_null() {
+            return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+        }
+        unsafe { core::ptr::copy_nonoverlapping((base as *const u8).add(start), payload_out, len) };
+    }
+    end as isize
+}
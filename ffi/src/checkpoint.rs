@@ -0,0 +1,245 @@
+// Checkpoints: recovery points for a region of a writable mapping, recorded in a small header
+// at the start of the mapping.
+//
+// `mmap_checkpoint_begin` stages the next sequence number with a CRC-32 of the region, and
+// `mmap_checkpoint_commit` makes the region durable before publishing that pair as committed
+// and making the header durable too. After a crash, `mmap_checkpoint_verify` recomputes the
+// CRC: if it matches the committed one the region is exactly the last committed checkpoint;
+// otherwise it holds writes made after it (or a checkpoint that never finished committing).
+// The committed sequence number is written before the committed CRC, so a crash between the
+// two stores reads as uncommitted data rather than as a checkpoint that didn't happen.
+//
+// The header layout is part of the ABI: files written by one version of the library must
+// recover with the next.
+
+use std::os::raw::c_void;
+use std::ptr;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS};
+use crate::registry;
+
+/// `MmapCheckpointHeader::magic`: "MMCP" in file order.
+pub const MMAP_CHECKPOINT_MAGIC: u32 = u32::from_le_bytes(*b"MMCP");
+/// `MmapCheckpointHeader::version` of the layout below.
+pub const MMAP_CHECKPOINT_VERSION: u32 = 1;
+/// Bytes reserved for the header at offset 0; the region must start at or after it.
+pub const MMAP_CHECKPOINT_HEADER_SIZE: usize = 64;
+
+/// The checkpoint header at offset 0 of the mapping, in host byte order. Sequence numbers
+/// start at 1; `committed_seq == 0` means nothing has been committed yet.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MmapCheckpointHeader {
+    pub magic: u32,
+    pub version: u32,
+    /// The region covered by the CRCs, as an offset and length within the mapping.
+    pub region_offset: u64,
+    pub region_len: u64,
+    /// Staged by `mmap_checkpoint_begin`.
+    pub pending_seq: u64,
+    pub pending_crc: u32,
+    /// Published by `mmap_checkpoint_commit`.
+    pub committed_crc: u32,
+    pub committed_seq: u64,
+}
+
+const _: () = assert!(size_of::<MmapCheckpointHeader>() <= MMAP_CHECKPOINT_HEADER_SIZE);
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE, as in zlib).
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| {
+        CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+/// The header of the mapping at `base` and the mapping's length.
+fn header(base: *mut c_void) -> Result<(*mut MmapCheckpointHeader, usize), Error> {
+    let Some((len, _)) = registry::lookup(base as usize) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if len < MMAP_CHECKPOINT_HEADER_SIZE {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    Ok((base.cast(), len))
+}
+
+/// Whether `[offset, offset + len)` is a valid region for a mapping of `total` bytes.
+fn region_ok(offset: u64, len: u64, total: usize) -> bool {
+    offset >= MMAP_CHECKPOINT_HEADER_SIZE as u64
+        && offset
+            .checked_add(len)
+            .is_some_and(|end| end <= total as u64)
+}
+
+/// The header of a formatted mapping, or an error naming what is wrong with it.
+fn formatted(base: *mut c_void) -> Result<*mut MmapCheckpointHeader, (Error, String)> {
+    let (h, total) = header(base).map_err(|e| (e, String::new()))?;
+    let hdr = unsafe { ptr::read_volatile(h) };
+    if hdr.magic != MMAP_CHECKPOINT_MAGIC {
+        return Err((
+            Error::new(MMAP_ERR_INVALID_ARG),
+            "no checkpoint header (see mmap_checkpoint_init)".into(),
+        ));
+    }
+    if hdr.version != MMAP_CHECKPOINT_VERSION {
+        return Err((
+            Error::new(MMAP_ERR_INVALID_ARG),
+            format!("unsupported checkpoint header version {}", hdr.version),
+        ));
+    }
+    // The header may have been written by another process, with a different mapping length.
+    if !region_ok(hdr.region_offset, hdr.region_len, total) {
+        return Err((
+            Error::new(MMAP_ERR_OUT_OF_BOUNDS),
+            "checkpoint region exceeds the mapping".into(),
+        ));
+    }
+    Ok(h)
+}
+
+fn fail(e: (Error, String)) -> i32 {
+    match e {
+        (e, detail) if detail.is_empty() => error::fail(e),
+        (e, detail) => error::fail_with(e, detail),
+    }
+}
+
+/// CRC of the header's region, which `formatted` checked against the mapping.
+fn region_crc(base: *mut c_void, hdr: &MmapCheckpointHeader) -> u32 {
+    let region = unsafe {
+        std::slice::from_raw_parts(
+            (base as *const u8).add(hdr.region_offset as usize),
+            hdr.region_len as usize,
+        )
+    };
+    crc32(region)
+}
+
+/// Writes a fresh checkpoint header (`MmapCheckpointHeader`) at offset 0 of the writable
+/// mapping at `base`, covering `[region_offset, region_offset + region_len)`, and makes it
+/// durable. Any previous checkpoint state is discarded.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base
+/// or the region overlaps the header, `MMAP_ERR_OUT_OF_BOUNDS` if it exceeds the mapping).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_checkpoint_init(
+    base: *mut c_void,
+    region_offset: usize,
+    region_len: usize,
+) -> i32 {
+    let (h, total) = match header(base) {
+        Ok(v) => v,
+        Err(e) => return error::fail(e),
+    };
+    if region_offset < MMAP_CHECKPOINT_HEADER_SIZE {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    if !region_ok(region_offset as u64, region_len as u64, total) {
+        return error::fail(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    let hdr = MmapCheckpointHeader {
+        magic: MMAP_CHECKPOINT_MAGIC,
+        version: MMAP_CHECKPOINT_VERSION,
+        region_offset: region_offset as u64,
+        region_len: region_len as u64,
+        ..Default::default()
+    };
+    unsafe {
+        ptr::write_bytes(base as *mut u8, 0, MMAP_CHECKPOINT_HEADER_SIZE);
+        ptr::write_volatile(h, hdr);
+        crate::mmap_flush_durable(base, 0, MMAP_CHECKPOINT_HEADER_SIZE)
+    }
+}
+
+/// Stages the next checkpoint: records `committed_seq + 1` and a CRC-32 of the region as it is
+/// now in the header's pending fields. Don't write to the region until the matching
+/// `mmap_checkpoint_commit`.
+/// Returns the staged sequence number, or -1 on failure (`MMAP_ERR_INVALID_ARG` if the mapping
+/// has no valid header, `MMAP_ERR_OUT_OF_BOUNDS` if its region exceeds the mapping;
+/// `mmap_last_error_message` says why).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_checkpoint_begin(base: *mut c_void) -> i64 {
+    let h = match formatted(base) {
+        Ok(h) => h,
+        Err(e) => return fail(e) as i64,
+    };
+    let mut hdr = unsafe { ptr::read_volatile(h) };
+    hdr.pending_seq = hdr.committed_seq + 1;
+    hdr.pending_crc = region_crc(base, &hdr);
+    unsafe {
+        ptr::write_volatile(&raw mut (*h).pending_crc, hdr.pending_crc);
+        ptr::write_volatile(&raw mut (*h).pending_seq, hdr.pending_seq);
+    }
+    hdr.pending_seq as i64
+}
+
+/// Commits the checkpoint staged by `mmap_checkpoint_begin`: makes the region durable (see
+/// `mmap_flush_durable`), then publishes the staged sequence number and CRC as committed and
+/// makes the header durable. Once this returns 0 the checkpoint survives a crash or power loss.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if no checkpoint is staged or
+/// the header is invalid, or the flush error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_checkpoint_commit(base: *mut c_void) -> i32 {
+    let h = match formatted(base) {
+        Ok(h) => h,
+        Err(e) => return fail(e),
+    };
+    let hdr = unsafe { ptr::read_volatile(h) };
+    if hdr.pending_seq != hdr.committed_seq + 1 {
+        return error::fail_with(
+            Error::new(MMAP_ERR_INVALID_ARG),
+            "no checkpoint staged (see mmap_checkpoint_begin)".into(),
+        );
+    }
+    unsafe {
+        if hdr.region_len > 0
+            && crate::mmap_flush_durable(base, hdr.region_offset as usize, hdr.region_len as usize)
+                != 0
+        {
+            return -1;
+        }
+        ptr::write_volatile(&raw mut (*h).committed_seq, hdr.pending_seq);
+        ptr::write_volatile(&raw mut (*h).committed_crc, hdr.pending_crc);
+        crate::mmap_flush_durable(base, 0, MMAP_CHECKPOINT_HEADER_SIZE)
+    }
+}
+
+/// Checks the region against the last committed checkpoint, e.g. after reopening a file whose
+/// writer crashed, copying the header to `out` if it is not null.
+/// Returns 1 if a checkpoint was committed and the region still matches its CRC, 0 if the
+/// region holds uncommitted data (or nothing was ever committed), or -1 on failure (as for
+/// `mmap_checkpoint_begin`).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_checkpoint_verify(
+    base: *mut c_void,
+    out: *mut MmapCheckpointHeader,
+) -> i32 {
+    let h = match formatted(base) {
+        Ok(h) => h,
+        Err(e) => return fail(e),
+    };
+    let hdr = unsafe { ptr::read_volatile(h) };
+    if !out.is_null() {
+        unsafe { *out = hdr };
+    }
+    (hdr.committed_seq > 0 && region_crc(base, &hdr) == hdr.committed_crc) as i32
+}
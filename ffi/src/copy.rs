@@ -0,0 +1,531 @@
+// Whole-file copies and clones done by the OS, e.g. to back up a mapped database file
+// without passing its contents through JS. Mappings of the source made by this library are
+// flushed first, so the copy holds everything written through them.
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::os::raw::c_char;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_UNSUPPORTED};
+use crate::registry::{self, Kind};
+use crate::stat;
+
+/// `mmap_copy_file` flag: fail unless the source is currently mapped through this library
+/// (any mapping on Unix, writable ones on Windows), i.e. unless the copy is known to follow a
+/// flush of the mapping.
+pub const MMAP_COPY_REQUIRE_MAPPED: u32 = 1 << 0;
+
+/// Bytes moved per read / write where the copy goes through a buffer.
+const CHUNK: usize = 1 << 20;
+
+type Failure = (Error, String);
+
+fn plain(e: impl Into<Error>) -> Failure {
+    (e.into(), String::new())
+}
+
+fn fail(e: Failure) -> i32 {
+    match e {
+        (e, detail) if detail.is_empty() => error::fail(e),
+        (e, detail) => error::fail_with(e, detail),
+    }
+}
+
+fn same_file() -> Failure {
+    (
+        Error::new(MMAP_ERR_INVALID_ARG),
+        "the source and destination are the same file".into(),
+    )
+}
+
+/// The checked arguments of a copy: the source, open, and both paths (the C strings are only
+/// passed on by the path-based macOS and Windows calls).
+#[cfg_attr(not(any(target_vendor = "apple", windows)), allow(dead_code))]
+struct Job<'a> {
+    src: File,
+    src_id: [u64; 2],
+    src_path: &'a CStr,
+    dst_path: &'a CStr,
+    dst_str: &'a str,
+}
+
+unsafe fn job<'a>(src_path: *const c_char, dst_path: *const c_char) -> Result<Job<'a>, Failure> {
+    if src_path.is_null() || dst_path.is_null() {
+        return Err(plain(Error::new(MMAP_ERR_INVALID_ARG)));
+    }
+    let (src_path, dst_path) = unsafe { (CStr::from_ptr(src_path), CStr::from_ptr(dst_path)) };
+    unsafe {
+        crate::open::precheck(src_path, 0).map_err(plain)?;
+        crate::open::precheck(dst_path, 0).map_err(plain)?;
+    }
+    let (Ok(src_str), Ok(dst_str)) = (src_path.to_str(), dst_path.to_str()) else {
+        return Err(plain(Error::new(MMAP_ERR_INVALID_ARG)));
+    };
+    let src = File::open(src_str).map_err(plain)?;
+    let src_id = stat::stat(&src).map_err(plain)?.file_id;
+    if stat::stat_path(dst_str).is_ok_and(|st| st.file_id == src_id) {
+        return Err(same_file());
+    }
+    Ok(Job {
+        src,
+        src_id,
+        src_path,
+        dst_path,
+        dst_str,
+    })
+}
+
+/// Opens the destination for writing, creating it if need be, without truncating it: it may
+/// have been replaced by the source since `job` checked. Returns whether it was created.
+fn open_dst(job: &Job<'_>) -> Result<(File, bool), Failure> {
+    let open = |create_new| {
+        OpenOptions::new()
+            .write(true)
+            .create_new(create_new)
+            .open(job.dst_str)
+    };
+    let (dst, created) = match open(true) {
+        Ok(dst) => (dst, true),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            (open(false).map_err(plain)?, false)
+        }
+        Err(e) => return Err(plain(e)),
+    };
+    if stat::stat(&dst).map_err(plain)?.file_id == job.src_id {
+        return Err(same_file());
+    }
+    Ok((dst, created))
+}
+
+/// Copies the file at `src_path` to `dst_path`, creating the destination or replacing its
+/// contents. Every mapping of the source opened through this library is flushed (see
+/// `mmap_flush`) before the copy starts; writes made while it runs may or may not be in it.
+///
+/// The copy is made by copy_file_range on Linux (sharing blocks where the file system
+/// supports it), clonefile (when `dst_path` doesn't exist yet) or fcopyfile on macOS, and
+/// CopyFileExW on Windows; other Unix systems, and Linux where copy_file_range can't be used
+/// (copies across file systems, older kernels), read and write through a buffer. Holes in the
+/// source are kept as holes where the file systems support them. On Windows the destination
+/// also gets the source's attributes, and on macOS clonefile copies its permissions.
+///
+/// Returns the size of the copy in bytes, or -1 on failure (`MMAP_ERR_INVALID_ARG` for null
+/// or non-UTF-8 paths, unknown flags, a destination that is the source itself, or a source that
+/// isn't mapped despite `MMAP_COPY_REQUIRE_MAPPED`; the file type errors of `mmap_open` for
+/// either path, the flush error, or the OS error). A failed copy may leave a partial
+/// destination behind.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_copy_file(
+    src_path: *const c_char,
+    dst_path: *const c_char,
+    flags: u32,
+) -> i64 {
+    if flags & !MMAP_COPY_REQUIRE_MAPPED != 0 {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as i64;
+    }
+    match unsafe { job(src_path, dst_path) }.and_then(|job| copy_file(&job, flags)) {
+        Ok(size) => size as i64,
+        Err(e) => fail(e) as i64,
+    }
+}
+
+fn copy_file(job: &Job<'_>, flags: u32) -> Result<u64, Failure> {
+    if flush_mappings_of(job.src_id).map_err(plain)? == 0 && flags & MMAP_COPY_REQUIRE_MAPPED != 0 {
+        return Err((
+            Error::new(MMAP_ERR_INVALID_ARG),
+            "the source is not mapped through this library".into(),
+        ));
+    }
+    let size_of_dst = || {
+        std::fs::metadata(job.dst_str)
+            .map(|m| m.len())
+            .map_err(plain)
+    };
+
+    #[cfg(target_vendor = "apple")]
+    if !std::fs::exists(job.dst_str).unwrap_or(true)
+        && unsafe { libc::clonefile(job.src_path.as_ptr(), job.dst_path.as_ptr(), 0) } == 0
+    {
+        return size_of_dst();
+    }
+    #[cfg(windows)]
+    match copy_file_ex(job.src_path, job.dst_path) {
+        Ok(()) => return size_of_dst(),
+        // The source is mapped writable through this library, and CopyFileExW's open of it
+        // doesn't share write access: copy through our own handle instead.
+        Err(e) if e.os as u32 == windows_sys::Win32::Foundation::ERROR_SHARING_VIOLATION => {}
+        Err(e) => return Err(plain(e)),
+    }
+
+    let (dst, _) = open_dst(job)?;
+    dst.set_len(0).map_err(plain)?;
+    copy_contents(&job.src, &dst).map_err(plain)?;
+    size_of_dst()
+}
+
+/// Clones the file at `src_path` to `dst_path` by sharing its extents instead of copying
+/// them, which takes neither time nor space until one of the files is modified: FICLONE on
+/// Linux (btrfs, XFS, bcachefs), clonefile on macOS (APFS) and FSCTL_DUPLICATE_EXTENTS_TO_FILE
+/// on Windows (ReFS). The destination is created or replaced. Mappings of the source are
+/// flushed first, as for `mmap_copy_file`.
+/// Returns 0 on success, -1 on failure: `MMAP_ERR_UNSUPPORTED` where the file system can't
+/// clone, or the two paths are on different file systems (fall back to `mmap_copy_file`); a
+/// destination the call created is removed again, and an existing one is only left changed
+/// if ReFS refuses partway through. Otherwise as for `mmap_copy_file`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_clone_file(src_path: *const c_char, dst_path: *const c_char) -> i32 {
+    match unsafe { job(src_path, dst_path) }.and_then(|job| clone_file(&job)) {
+        Ok(()) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+fn clone_file(job: &Job<'_>) -> Result<(), Failure> {
+    flush_mappings_of(job.src_id).map_err(plain)?;
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            use std::os::fd::AsRawFd;
+            let (dst, created) = open_dst(job)?;
+            let rc = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, job.src.as_raw_fd()) };
+            if rc == 0 {
+                // A longer destination keeps its tail past the cloned range.
+                let len = job.src.metadata().map_err(plain)?.len();
+                return dst.set_len(len).map_err(plain);
+            }
+            let e = Error::last_os();
+            drop(dst);
+            if created {
+                let _ = std::fs::remove_file(job.dst_str);
+            }
+            match e.os {
+                libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY | libc::ENOSYS => {
+                    Err(plain(Error::new(MMAP_ERR_UNSUPPORTED)))
+                }
+                _ => Err(plain(e)),
+            }
+        } else if #[cfg(target_vendor = "apple")] {
+            // clonefile won't replace a file: clone next to it and rename over it.
+            let replace = std::fs::exists(job.dst_str).map_err(plain)?;
+            let tmp = format!("{}.clone-{}", job.dst_str, std::process::id());
+            let tmp_c = std::ffi::CString::new(tmp.as_str()).map_err(|_| plain(Error::new(MMAP_ERR_INVALID_ARG)))?;
+            let target = if replace { tmp_c.as_c_str() } else { job.dst_path };
+            if unsafe { libc::clonefile(job.src_path.as_ptr(), target.as_ptr(), 0) } != 0 {
+                let e = Error::last_os();
+                return Err(plain(match e.os {
+                    libc::ENOTSUP | libc::EXDEV => Error::new(MMAP_ERR_UNSUPPORTED),
+                    _ => e,
+                }));
+            }
+            if replace && let Err(e) = std::fs::rename(&tmp, job.dst_str) {
+                let _ = std::fs::remove_file(&tmp);
+                return Err(plain(e));
+            }
+            Ok(())
+        } else if #[cfg(windows)] {
+            let (dst, created) = open_dst(job)?;
+            let result = duplicate_extents(job, &dst);
+            if result.is_err() && created {
+                drop(dst);
+                let _ = std::fs::remove_file(job.dst_str);
+            }
+            result
+        } else {
+            Err(plain(Error::new(MMAP_ERR_UNSUPPORTED)))
+        }
+    }
+}
+
+/// Fills the (empty or existing) `dst` with clones of the source's clusters.
+#[cfg(windows)]
+fn duplicate_extents(job: &Job<'_>, dst: &File) -> Result<(), Failure> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::{
+        ERROR_INVALID_FUNCTION, ERROR_NOT_SAME_DEVICE, ERROR_NOT_SUPPORTED,
+    };
+    use windows_sys::Win32::Storage::FileSystem::GetVolumeInformationByHandleW;
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+    use windows_sys::Win32::System::Ioctl::{
+        DUPLICATE_EXTENTS_DATA, FSCTL_DUPLICATE_EXTENTS_TO_FILE, FSCTL_GET_INTEGRITY_INFORMATION,
+        FSCTL_GET_INTEGRITY_INFORMATION_BUFFER,
+    };
+    use windows_sys::Win32::System::SystemServices::FILE_SUPPORTS_BLOCK_REFCOUNTING;
+
+    let unsupported = || plain(Error::new(MMAP_ERR_UNSUPPORTED));
+    let src = job.src.as_raw_handle();
+    // Both checks come before the destination is touched.
+    let mut vol_flags = 0u32;
+    let ok = unsafe {
+        GetVolumeInformationByHandleW(
+            src,
+            core::ptr::null_mut(),
+            0,
+            core::ptr::null_mut(),
+            core::ptr::null_mut(),
+            &mut vol_flags,
+            core::ptr::null_mut(),
+            0,
+        )
+    };
+    if ok == 0 {
+        return Err(plain(Error::last_os()));
+    }
+    // The volume serial number is the first half of a file id.
+    if vol_flags & FILE_SUPPORTS_BLOCK_REFCOUNTING == 0
+        || stat::stat(dst).map_err(plain)?.file_id[0] != job.src_id[0]
+    {
+        return Err(unsupported());
+    }
+    // Cloned ranges are whole clusters; ReFS reports its cluster size with the integrity
+    // settings.
+    let mut integrity = FSCTL_GET_INTEGRITY_INFORMATION_BUFFER::default();
+    let mut returned = 0u32;
+    let ok = unsafe {
+        DeviceIoControl(
+            src,
+            FSCTL_GET_INTEGRITY_INFORMATION,
+            core::ptr::null(),
+            0,
+            (&mut integrity as *mut FSCTL_GET_INTEGRITY_INFORMATION_BUFFER).cast(),
+            size_of::<FSCTL_GET_INTEGRITY_INFORMATION_BUFFER>() as u32,
+            &mut returned,
+            core::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(plain(Error::last_os()));
+    }
+    let cluster = (integrity.ClusterSizeInBytes as u64).max(1);
+    let size = job.src.metadata().map_err(plain)?.len();
+    dst.set_len(0).map_err(plain)?;
+    if stat::stat(&job.src).map_err(plain)?.flags & stat::MMAP_STAT_SPARSE != 0 {
+        set_sparse(dst).map_err(plain)?;
+    }
+    dst.set_len(size).map_err(plain)?;
+    let mut offset = 0;
+    while offset < size {
+        // Each call must clone less than 4 GiB; the last one may run into the final cluster.
+        let count = (size - offset).min(1 << 31).next_multiple_of(cluster);
+        let data = DUPLICATE_EXTENTS_DATA {
+            FileHandle: src,
+            SourceFileOffset: offset as i64,
+            TargetFileOffset: offset as i64,
+            ByteCount: count as i64,
+        };
+        let ok = unsafe {
+            DeviceIoControl(
+                dst.as_raw_handle(),
+                FSCTL_DUPLICATE_EXTENTS_TO_FILE,
+                (&data as *const DUPLICATE_EXTENTS_DATA).cast(),
+                size_of::<DUPLICATE_EXTENTS_DATA>() as u32,
+                core::ptr::null_mut(),
+                0,
+                &mut returned,
+                core::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            let e = Error::last_os();
+            return Err(match e.os as u32 {
+                ERROR_NOT_SUPPORTED | ERROR_INVALID_FUNCTION | ERROR_NOT_SAME_DEVICE => {
+                    unsupported()
+                }
+                _ => plain(e),
+            });
+        }
+        offset += count;
+    }
+    Ok(())
+}
+
+/// Flushes every file mapping in the registry whose file is the one identified by `file_id`
+/// (see `MmapStat::file_id`), returning how many there are.
+fn flush_mappings_of(file_id: [u64; 2]) -> Result<usize, Error> {
+    let registry = registry::lock();
+    let mut found = 0;
+    for (&base, m) in registry.iter() {
+        let Some(file) = m.file.as_ref().filter(|_| m.kind == Kind::File) else {
+            continue;
+        };
+        if stat::stat(file)?.file_id != file_id {
+            continue;
+        }
+        found += 1;
+        if m.len > 0 {
+            // Under the registry lock, so the mapping can't be unmapped meanwhile.
+            unsafe { crate::sync_view(base, m.len)? };
+        }
+    }
+    Ok(found)
+}
+
+/// Copies the contents of `src` into the empty file `dst`, skipping the holes of `src`.
+fn copy_contents(src: &File, dst: &File) -> Result<(), Error> {
+    #[cfg(target_vendor = "apple")]
+    {
+        use std::os::fd::AsRawFd;
+        let data = libc::COPYFILE_DATA | libc::COPYFILE_DATA_SPARSE;
+        let rc = unsafe {
+            libc::fcopyfile(
+                src.as_raw_fd(),
+                dst.as_raw_fd(),
+                core::ptr::null_mut(),
+                data,
+            )
+        };
+        if rc == 0 {
+            return Ok(());
+        }
+        // Not supported between these files: the copy below overwrites whatever was written.
+    }
+    let size = src.metadata()?.len();
+    #[cfg(windows)]
+    if stat::stat(src)?.flags & stat::MMAP_STAT_SPARSE != 0 {
+        set_sparse(dst)?;
+    }
+    // Extending first leaves the holes between the extents copied below unallocated.
+    dst.set_len(size)?;
+    for (offset, len) in crate::space::data_extents(src)? {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let mut done = kernel_copy(src, dst, offset, len)?;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let mut done = 0;
+        let mut buf = vec![0u8; CHUNK.min((len - done) as usize)];
+        while done < len {
+            let want = buf.len().min((len - done) as usize);
+            let n = read_at(src, &mut buf[..want], offset + done)?;
+            if n == 0 {
+                // The source shrank during the copy.
+                break;
+            }
+            write_all_at(dst, &buf[..n], offset + done)?;
+            done += n as u64;
+        }
+    }
+    Ok(())
+}
+
+/// copy_file_range of `[offset, offset + len)` to the same offset of `dst`. Returns the bytes
+/// copied, fewer than `len` if the source ends first or the kernel can't copy between these
+/// files, in which case the caller copies the rest itself.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn kernel_copy(src: &File, dst: &File, offset: u64, len: u64) -> Result<u64, Error> {
+    use std::os::fd::AsRawFd;
+    let (mut off_in, mut off_out) = (offset as i64, offset as i64);
+    let mut copied = 0;
+    while copied < len {
+        let want = (len - copied).min(isize::MAX as u64) as usize;
+        let n = unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                &mut off_in,
+                dst.as_raw_fd(),
+                &mut off_out,
+                want,
+                0,
+            )
+        };
+        if n == 0 {
+            break;
+        }
+        if n > 0 {
+            copied += n as u64;
+            continue;
+        }
+        let err = std::io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EXDEV | libc::ENOSYS | libc::EINVAL | libc::EOPNOTSUPP) => break,
+            _ => return Err(err.into()),
+        }
+    }
+    Ok(copied)
+}
+
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+    loop {
+        #[cfg(unix)]
+        let result = std::os::unix::fs::FileExt::read_at(file, buf, offset);
+        #[cfg(windows)]
+        let result = std::os::windows::fs::FileExt::seek_read(file, buf, offset);
+        match result {
+            Ok(n) => return Ok(n),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> Result<(), Error> {
+    Ok(std::os::unix::fs::FileExt::write_all_at(file, buf, offset)?)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> Result<(), Error> {
+    use std::io::ErrorKind;
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, buf, offset) {
+            Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn copy_file_ex(src: &CStr, dst: &CStr) -> Result<(), Error> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::CopyFileExW;
+    // Both were checked to be UTF-8.
+    let wide = |s: &CStr| -> Vec<u16> {
+        std::ffi::OsStr::new(&*s.to_string_lossy())
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let (src, dst) = (wide(src), wide(dst));
+    let ok = unsafe {
+        CopyFileExW(
+            src.as_ptr(),
+            dst.as_ptr(),
+            None,
+            core::ptr::null(),
+            core::ptr::null_mut(),
+            0,
+        )
+    };
+    if ok == 0 {
+        Err(Error::last_os())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn set_sparse(file: &File) -> Result<(), Error> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+    use windows_sys::Win32::System::Ioctl::FSCTL_SET_SPARSE;
+    let mut returned = 0u32;
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            FSCTL_SET_SPARSE,
+            core::ptr::null(),
+            0,
+            core::ptr::null_mut(),
+            0,
+            &mut returned,
+            core::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        Err(Error::last_os())
+    } else {
+        Ok(())
+    }
+}
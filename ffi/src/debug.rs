@@ -0,0 +1,61 @@
+// Enumeration of the open mappings for leak hunting, compiled in with the `debug-registry`
+// feature.
+//
+// Every mapping is in the registry anyway; the feature only adds the open mode and a hash of
+// the path to each entry (see `registry::Origin`), so builds without it pay nothing for them.
+
+use std::os::raw::c_void;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG};
+
+/// `MmapDebugEntry::mode` of a read-only mapping.
+pub const MMAP_DEBUG_READ: i32 = 0;
+/// `MmapDebugEntry::mode` of a writable mapping of a file (or memfd, or reservation).
+pub const MMAP_DEBUG_WRITE: i32 = 1;
+/// `MmapDebugEntry::mode` of a private copy of a file's content (see `mmap_open_snapshot`).
+pub const MMAP_DEBUG_SNAPSHOT: i32 = 2;
+
+/// One open mapping, as reported by `mmap_debug_list`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MmapDebugEntry {
+    pub base: *mut c_void,
+    pub len: usize,
+    /// One of the `MMAP_DEBUG_*` modes.
+    pub mode: i32,
+    /// 64-bit FNV-1a hash of the path the mapping was opened with, as passed (its bytes
+    /// without the NUL); 0 for mappings without one (descriptors, temporary files, memfds).
+    pub path_hash: u64,
+}
+
+/// 64-bit FNV-1a of `path`.
+pub(crate) fn path_hash(path: &[u8]) -> u64 {
+    path.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Writes an entry for each mapping the library still holds into `out`, in order of base
+/// address, up to `cap` of them, e.g. to find mappings a finalizer never closed. Handle
+/// subviews and reservations count as mappings of their own. Returns the number of mappings
+/// open, which may be more than `cap`: a call with a null `out` and a `cap` of 0 just counts
+/// them. -1 (`MMAP_ERR_INVALID_ARG`) for a null `out` with a non-zero `cap`.
+///
+/// Safety: `out` must be null or writable for `cap` entries.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_debug_list(out: *mut MmapDebugEntry, cap: usize) -> isize {
+    if out.is_null() && cap != 0 {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    }
+    let registry = crate::registry::lock();
+    for (i, (&base, m)) in registry.iter().take(cap).enumerate() {
+        let entry = MmapDebugEntry {
+            base: base as *mut c_void,
+            len: m.len,
+            mode: m.origin.mode,
+            path_hash: m.origin.path_hash,
+        };
+        unsafe { out.add(i).write(entry) };
+    }
+    registry.len() as isize
+}
@@ -0,0 +1,111 @@
+// Page-granular record of the bytes written through this library, so `mmap_flush_dirty`
+// can write back only what changed.
+//
+// Only writes made by the library itself (`mmap_write`, `mmap_copy_between`,
+// `mmap_handle_write`) are recorded. Stores through a raw pointer, e.g. a JS
+// `Deno.UnsafePointerView` / `ArrayBuffer` over the mapping, are invisible here and must be
+// reported with `mmap_mark_dirty`, or they will be missed by `mmap_flush_dirty`.
+
+use std::collections::BTreeMap;
+use std::os::raw::c_void;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS};
+use crate::registry::{self, Kind, Mapping};
+
+/// Disjoint, non-adjacent `[start, end)` intervals of page-aligned offsets, keyed by start.
+#[derive(Default)]
+pub(crate) struct DirtySet(BTreeMap<usize, usize>);
+
+impl DirtySet {
+    /// Adds the pages covering `[offset, offset + len)`, merging with touching intervals.
+    fn mark(&mut self, offset: usize, len: usize) {
+        let page = crate::page_size();
+        let mut start = offset & !(page - 1);
+        let end = offset + len;
+        let mut end = end.checked_next_multiple_of(page).unwrap_or(end);
+        while let Some((&s, &e)) = self.0.range(..=end).next_back() {
+            if e < start {
+                break;
+            }
+            start = start.min(s);
+            end = end.max(e);
+            self.0.remove(&s);
+        }
+        self.0.insert(start, end);
+    }
+
+    /// Forgets the pages at or past `len`, after the mapping shrank to `len` bytes.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.0.retain(|&start, _| start < len);
+    }
+}
+
+/// Records a write of `[offset, offset + len)` into the mapping at `base`.
+/// Writes into unregistered memory or past the mapping's end are ignored.
+pub(crate) fn record(base: *mut c_void, offset: usize, len: usize) {
+    if len == 0 {
+        return;
+    }
+    if let Some(m) = registry::lock().get_mut(&(base as usize))
+        && let Some(end) = offset.checked_add(len).filter(|&end| end <= m.len)
+    {
+        m.dirty.mark(offset, len);
+        m.high_water = m.high_water.max(end);
+    }
+}
+
+/// Marks `[offset, offset + len)` of the mapping at `base` as modified, for writes done
+/// directly through the mapped memory.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base,
+/// `MMAP_ERR_OUT_OF_BOUNDS` if the range exceeds the mapping).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_mark_dirty(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    let mut reg = registry::lock();
+    let Some(m) = reg.get_mut(&(base as usize)) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    match offset.checked_add(len) {
+        Some(end) if end <= m.len => {
+            if len > 0 {
+                m.dirty.mark(offset, len);
+                m.high_water = m.high_water.max(end);
+            }
+            0
+        }
+        _ => error::fail(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+    }
+}
+
+/// Flushes the recorded pages of the mapping `m` at `base` and clears the record. The caller
+/// holds the registry lock, so the mapping can't be unmapped meanwhile. Ranges that could not
+/// be flushed stay recorded.
+pub(crate) fn flush(base: usize, m: &mut Mapping) -> Result<(), Error> {
+    let dirty = std::mem::take(&mut m.dirty);
+    if m.kind == Kind::Snapshot {
+        return Ok(());
+    }
+    let mut ranges = dirty.0.into_iter();
+    while let Some((start, end)) = ranges.next() {
+        if let Err(e) = unsafe { crate::sync_view(base + start, end.min(m.len) - start) } {
+            m.dirty.0.insert(start, end);
+            m.dirty.0.extend(ranges);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Flushes only the pages recorded as modified since the last `mmap_flush_dirty` and clears
+/// the record. Ranges that could not be flushed stay recorded so a retry picks them up.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_dirty(base: *mut c_void) -> i32 {
+    let mut reg = registry::lock();
+    let Some(m) = reg.get_mut(&(base as usize)) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    match flush(base as usize, m) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
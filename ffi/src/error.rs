@@ -0,0 +1,277 @@
+// Error codes and the per-thread "last error" slot.
+//
+// Functions that return a pointer report failure as null and record the reason here;
+// callers read it back with `mmap_last_error` / `mmap_last_os_error` right after the failing call.
+
+use std::cell::{Cell, RefCell};
+
+pub const MMAP_OK: i32 = 0;
+/// A required pointer was null, the path was not valid UTF-8, or an argument was out of range.
+pub const MMAP_ERR_INVALID_ARG: i32 = -1;
+/// An OS call failed; the raw errno / GetLastError value is available via `mmap_last_os_error`.
+pub const MMAP_ERR_OS: i32 = -2;
+/// The path refers to a block device and `MMAP_ALLOW_DEVICE` was not passed.
+pub const MMAP_ERR_IS_DEVICE: i32 = -3;
+/// The file has no content to map.
+pub const MMAP_ERR_EMPTY: i32 = -4;
+/// The requested range does not lie within the mapping.
+pub const MMAP_ERR_OUT_OF_BOUNDS: i32 = -5;
+/// The handle has already been closed.
+pub const MMAP_ERR_CLOSED: i32 = -6;
+/// A write was attempted through a read-only mapping.
+pub const MMAP_ERR_READ_ONLY: i32 = -7;
+/// The path is a directory.
+pub const MMAP_ERR_IS_DIRECTORY: i32 = -8;
+/// The path is a FIFO (named pipe) or pipe handle.
+pub const MMAP_ERR_IS_PIPE: i32 = -9;
+/// The path is neither a regular file nor an allowed device (socket, character device, ...).
+pub const MMAP_ERR_NOT_REGULAR: i32 = -10;
+/// The requested feature is not available on this platform.
+pub const MMAP_ERR_UNSUPPORTED: i32 = -11;
+/// `MMAP_LOCKED` could not pin the pages (e.g. RLIMIT_MEMLOCK / working-set quota);
+/// the OS reason is available via `mmap_last_os_error`.
+pub const MMAP_ERR_LOCK_FAILED: i32 = -12;
+/// `MMAP_DIRECT` was requested for a length that is not a multiple of the page size.
+pub const MMAP_ERR_UNALIGNED: i32 = -13;
+/// `mmap_flush_durable`: writing the mapped pages back to the file failed (msync /
+/// FlushViewOfFile).
+pub const MMAP_ERR_FLUSH_VIEW: i32 = -14;
+/// `mmap_flush_durable`: the pages reached the file, but flushing the file to stable storage
+/// failed (fsync / F_FULLFSYNC / FlushFileBuffers).
+pub const MMAP_ERR_FLUSH_FILE: i32 = -15;
+/// The file has been truncated below the requested range; touching it would raise SIGBUS.
+pub const MMAP_ERR_TRUNCATED: i32 = -16;
+/// The device reported an I/O error (EIO / ERROR_CRC-class failures) while syncing;
+/// previously written data may be lost.
+pub const MMAP_ERR_IO: i32 = -17;
+/// The file is larger than the cap passed to `mmap_open_capped`; `mmap_read_frame`: the
+/// payload is larger than the buffer.
+pub const MMAP_ERR_TOO_LARGE: i32 = -18;
+/// The file is on a network file system and `MMAP_LOCAL_ONLY` was passed.
+pub const MMAP_ERR_NOT_LOCAL: i32 = -19;
+/// `mmap_flush_verify`: the file contents read back after the flush differ from the mapping;
+/// `mmap_last_error_message` names the first differing offset.
+pub const MMAP_ERR_VERIFY: i32 = -20;
+/// `mmap_truncate`: the new length is shorter than the mapping, whose pages past it would
+/// raise SIGBUS; shrink the mapping first with `mmap_resize`.
+pub const MMAP_ERR_MAPPED_LARGER: i32 = -21;
+/// `mmap_preallocate`: the file system (or the user's quota) has no room for the blocks; the
+/// raw ENOSPC / EDQUOT / ERROR_DISK_FULL is available via `mmap_last_os_error`.
+pub const MMAP_ERR_NO_SPACE: i32 = -22;
+/// A non-blocking lock request conflicts with a lock held through another descriptor (possibly
+/// by another process).
+pub const MMAP_ERR_WOULD_BLOCK: i32 = -23;
+/// `mmap_lock_pages`: pinning the pages would exceed the locked-memory limit (RLIMIT_MEMLOCK, or
+/// the working-set quota on Windows); `mmap_last_error_message` gives the limit.
+pub const MMAP_ERR_QUOTA: i32 = -24;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Error {
+    pub code: i32,
+    pub os: i32,
+}
+
+impl Error {
+    pub fn new(code: i32) -> Self {
+        Error { code, os: 0 }
+    }
+
+    /// Captures errno / GetLastError as an `MMAP_ERR_OS` error.
+    pub fn last_os() -> Self {
+        let os = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        Error {
+            code: MMAP_ERR_OS,
+            os,
+        }
+    }
+
+    /// Like `From<io::Error>`, but reports a device I/O error as `MMAP_ERR_IO`.
+    pub fn io_sync(e: std::io::Error) -> Self {
+        let mut err = Error::from(e);
+        if is_device_io_error(err.os) {
+            err.code = MMAP_ERR_IO;
+        }
+        err
+    }
+
+    /// Like `Error::last_os`, but reports a full file system or quota as `MMAP_ERR_NO_SPACE`.
+    pub fn last_os_space() -> Self {
+        let mut err = Error::last_os();
+        if is_no_space_error(err.os) {
+            err.code = MMAP_ERR_NO_SPACE;
+        }
+        err
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error {
+            code: MMAP_ERR_OS,
+            os: e.raw_os_error().unwrap_or(0),
+        }
+    }
+}
+
+/// For the safe Rust API: OS failures keep their errno / GetLastError, the rest map to the
+/// closest `io::ErrorKind`.
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        use std::io::ErrorKind;
+        if e.os != 0 {
+            return std::io::Error::from_raw_os_error(e.os);
+        }
+        let kind = match e.code {
+            MMAP_ERR_INVALID_ARG | MMAP_ERR_OUT_OF_BOUNDS | MMAP_ERR_UNALIGNED => {
+                ErrorKind::InvalidInput
+            }
+            MMAP_ERR_IS_DIRECTORY => ErrorKind::IsADirectory,
+            MMAP_ERR_READ_ONLY => ErrorKind::PermissionDenied,
+            MMAP_ERR_UNSUPPORTED => ErrorKind::Unsupported,
+            MMAP_ERR_TRUNCATED => ErrorKind::UnexpectedEof,
+            MMAP_ERR_TOO_LARGE => ErrorKind::FileTooLarge,
+            MMAP_ERR_WOULD_BLOCK => ErrorKind::WouldBlock,
+            _ => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, format!("mmap error {}", e.code))
+    }
+}
+
+fn is_no_space_error(os: i32) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            os == libc::ENOSPC || os == libc::EDQUOT
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::Foundation::{ERROR_DISK_FULL, ERROR_HANDLE_DISK_FULL};
+            let os = os as u32;
+            os == ERROR_DISK_FULL || os == ERROR_HANDLE_DISK_FULL
+        }
+    }
+}
+
+fn is_device_io_error(os: i32) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            os == libc::EIO
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::Foundation::{ERROR_CRC, ERROR_IO_DEVICE, ERROR_SECTOR_NOT_FOUND};
+            let os = os as u32;
+            os == ERROR_IO_DEVICE || os == ERROR_CRC || os == ERROR_SECTOR_NOT_FOUND
+        }
+    }
+}
+
+/// Fixed description of each code, used by `mmap_last_error_message` when the failing call
+/// gave no details.
+fn describe(code: i32) -> &'static str {
+    match code {
+        MMAP_OK => "no error",
+        MMAP_ERR_INVALID_ARG => "invalid argument",
+        MMAP_ERR_OS => "OS call failed",
+        MMAP_ERR_IS_DEVICE => "path is a block device",
+        MMAP_ERR_EMPTY => "file is empty",
+        MMAP_ERR_OUT_OF_BOUNDS => "range is out of bounds of the mapping",
+        MMAP_ERR_CLOSED => "handle is closed",
+        MMAP_ERR_READ_ONLY => "mapping is read-only",
+        MMAP_ERR_IS_DIRECTORY => "path is a directory",
+        MMAP_ERR_IS_PIPE => "path is a pipe",
+        MMAP_ERR_NOT_REGULAR => "path is not a regular file",
+        MMAP_ERR_UNSUPPORTED => "not supported on this platform or file",
+        MMAP_ERR_LOCK_FAILED => "could not lock the pages in memory",
+        MMAP_ERR_UNALIGNED => "length is not a multiple of the page size",
+        MMAP_ERR_FLUSH_VIEW => "writing the mapped pages back failed",
+        MMAP_ERR_FLUSH_FILE => "flushing the file to stable storage failed",
+        MMAP_ERR_TRUNCATED => "file was truncated below the range",
+        MMAP_ERR_IO => "device I/O error",
+        MMAP_ERR_TOO_LARGE => "file is larger than the cap",
+        MMAP_ERR_NOT_LOCAL => "file is on a network file system",
+        MMAP_ERR_VERIFY => "file contents differ from the mapping",
+        MMAP_ERR_MAPPED_LARGER => "new length is shorter than the mapping",
+        MMAP_ERR_NO_SPACE => "no space left on the file system",
+        MMAP_ERR_WOULD_BLOCK => "the lock is held elsewhere",
+        MMAP_ERR_QUOTA => "the limit on locked memory was reached",
+        _ => "unknown error",
+    }
+}
+
+thread_local! {
+    static LAST: Cell<Error> = const { Cell::new(Error { code: MMAP_OK, os: 0 }) };
+    static DETAIL: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Records `e` as this thread's last error.
+pub(crate) fn set(e: Error) {
+    LAST.with(|l| l.set(e));
+    DETAIL.with(|d| d.borrow_mut().take());
+}
+
+/// Records `e` and returns -1, the failure value of functions that report an `i32` status.
+pub(crate) fn fail(e: Error) -> i32 {
+    set(e);
+    -1
+}
+
+/// Like `fail`, with a message for `mmap_last_error_message` in place of the code's fixed
+/// description.
+pub(crate) fn fail_with(e: Error, detail: String) -> i32 {
+    set(e);
+    DETAIL.with(|d| *d.borrow_mut() = Some(detail));
+    -1
+}
+
+/// Returns the error code recorded by the most recent failing call on this thread.
+/// Only meaningful immediately after a call reported failure.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_last_error() -> i32 {
+    LAST.with(|l| l.get().code)
+}
+
+/// Returns the raw OS error (errno on Unix, GetLastError on Windows) behind the last
+/// `MMAP_ERR_OS` failure on this thread, or 0 if the last error did not come from the OS.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_last_os_error() -> i32 {
+    LAST.with(|l| l.get().os)
+}
+
+/// Writes a human-readable description of this thread's last error as a NUL-terminated UTF-8
+/// string into `out` (truncated to `cap` bytes): the details recorded by the failing call
+/// (e.g. the offset `mmap_flush_verify` found differing), or the code's fixed description,
+/// followed by the OS error text if there is one.
+/// Returns the full length of the message in bytes, without the NUL, so a call with a null
+/// `out` (or a too small `cap`) tells how large a buffer is needed.
+///
+/// Safety: `out` must be null or writable for `cap` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_last_error_message(
+    out: *mut std::os::raw::c_char,
+    cap: usize,
+) -> usize {
+    let e = LAST.with(|l| l.get());
+    let mut msg = DETAIL
+        .with(|d| d.borrow().clone())
+        .unwrap_or_else(|| describe(e.code).to_owned());
+    if e.os != 0 {
+        msg.push_str(": ");
+        msg.push_str(&std::io::Error::from_raw_os_error(e.os).to_string());
+    }
+    unsafe { write_c_str(&msg, out, cap) };
+    msg.len()
+}
+
+/// Copies `s` into `out` as a NUL-terminated string, truncated at a character boundary to fit
+/// `cap` bytes. Does nothing if `out` is null or `cap` is 0.
+///
+/// Safety: `out` must be null or writable for `cap` bytes.
+pub(crate) unsafe fn write_c_str(s: &str, out: *mut std::os::raw::c_char, cap: usize) {
+    if out.is_null() || cap == 0 {
+        return;
+    }
+    let mut n = s.len().min(cap - 1);
+    while !s.is_char_boundary(n) {
+        n -= 1;
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(s.as_ptr(), out as *mut u8, n);
+        *out.add(n) = 0;
+    }
+}
@@ -0,0 +1,61 @@
+// Memory fences for protocols over shared mappings driven from JS, which has no fences of its
+// own for memory it doesn't own. The usual pattern, between threads or processes mapping the
+// same file:
+//
+//     producer                              consumer
+//     write payload (mmap_write, ...)       read flag until set
+//     mmap_fence(MMAP_FENCE_RELEASE)        mmap_fence(MMAP_FENCE_ACQUIRE)
+//     write flag                            read payload (mmap_read, ...)
+//
+// The release fence keeps the payload writes from being reordered after the flag write, and
+// the acquire fence keeps the payload reads from being reordered before the flag read. The
+// flag itself should be a single aligned word (e.g. a u32 written with `Atomics.store` on a
+// view of the mapping) so it is never observed half-written.
+
+use std::sync::atomic::{Ordering, compiler_fence, fence};
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG};
+
+pub const MMAP_FENCE_ACQUIRE: i32 = 1;
+pub const MMAP_FENCE_RELEASE: i32 = 2;
+pub const MMAP_FENCE_SEQ_CST: i32 = 3;
+pub const MMAP_FENCE_ACQ_REL: i32 = 4;
+
+fn ordering(kind: i32) -> Result<Ordering, Error> {
+    match kind {
+        MMAP_FENCE_ACQUIRE => Ok(Ordering::Acquire),
+        MMAP_FENCE_RELEASE => Ok(Ordering::Release),
+        MMAP_FENCE_SEQ_CST => Ok(Ordering::SeqCst),
+        MMAP_FENCE_ACQ_REL => Ok(Ordering::AcqRel),
+        _ => Err(Error::new(MMAP_ERR_INVALID_ARG)),
+    }
+}
+
+/// Issues a memory fence (`std::sync::atomic::fence`) of the given `MMAP_FENCE_*` kind,
+/// ordering this thread's accesses to shared mappings as seen by other threads and processes.
+/// Returns 0, or -1 with `MMAP_ERR_INVALID_ARG` for an unknown kind.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_fence(kind: i32) -> i32 {
+    match ordering(kind) {
+        Ok(order) => {
+            fence(order);
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Like `mmap_fence`, but only stops the compiler from reordering accesses across the call
+/// (`std::sync::atomic::compiler_fence`); the CPU may still reorder them. Only useful for
+/// ordering against signal handlers on the same thread.
+/// Returns 0, or -1 with `MMAP_ERR_INVALID_ARG` for an unknown kind.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_compiler_fence(kind: i32) -> i32 {
+    match ordering(kind) {
+        Ok(order) => {
+            compiler_fence(order);
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
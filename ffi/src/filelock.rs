@@ -0,0 +1,466 @@
+// Advisory locks on a handle's file, so processes sharing a read-write mapping can take
+// turns: on the whole file (`mmap_lock_file`) or on byte ranges of it (`mmap_lock_range`,
+// probed with `mmap_lock_test`).
+//
+// Locks are held through the handle's own descriptor on the file (`MmapHandle::file`), which
+// its clones share: clones hold them together, and they are released when the mapping is
+// unmapped. Other handles, in this process or another, have descriptors of their own and
+// contend for them.
+
+use std::fs::File;
+#[cfg(windows)]
+use std::sync::Mutex;
+
+use crate::error::{
+    self, Error, MMAP_ERR_CLOSED, MMAP_ERR_INVALID_ARG, MMAP_ERR_READ_ONLY, MMAP_ERR_WOULD_BLOCK,
+};
+use crate::handle::MmapHandle;
+
+/// The byte-range locks held through a handle's descriptor, for releasing them at unmap.
+/// Only Windows needs the list: a POSIX unlock of the whole file releases every range.
+#[derive(Default)]
+pub(crate) struct RangeLocks {
+    #[cfg(windows)]
+    held: Mutex<Vec<(u64, u64)>>,
+}
+
+#[cfg(windows)]
+impl RangeLocks {
+    fn held(&self) -> std::sync::MutexGuard<'_, Vec<(u64, u64)>> {
+        self.held.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// LockFileEx over `[start, start + len)`.
+#[cfg(windows)]
+fn lock_file_ex(
+    file: &File,
+    start: u64,
+    len: u64,
+    exclusive: bool,
+    block: bool,
+) -> Result<(), Error> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+    use windows_sys::Win32::Storage::FileSystem::{
+        LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, LockFileEx,
+    };
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+    let mut flags = 0;
+    if exclusive {
+        flags |= LOCKFILE_EXCLUSIVE_LOCK;
+    }
+    if !block {
+        flags |= LOCKFILE_FAIL_IMMEDIATELY;
+    }
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    overlapped.Anonymous.Anonymous.Offset = start as u32;
+    overlapped.Anonymous.Anonymous.OffsetHigh = (start >> 32) as u32;
+    let ok = unsafe {
+        LockFileEx(
+            file.as_raw_handle(),
+            flags,
+            0,
+            len as u32,
+            (len >> 32) as u32,
+            &mut overlapped,
+        )
+    };
+    if ok != 0 {
+        return Ok(());
+    }
+    let mut e = Error::last_os();
+    if e.os as u32 == ERROR_LOCK_VIOLATION {
+        e.code = MMAP_ERR_WOULD_BLOCK;
+    }
+    Err(e)
+}
+
+/// UnlockFileEx of `[start, start + len)`; Ok(false) if no lock had exactly that range.
+#[cfg(windows)]
+fn unlock_file_ex(file: &File, start: u64, len: u64) -> Result<bool, Error> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::ERROR_NOT_LOCKED;
+    use windows_sys::Win32::Storage::FileSystem::UnlockFileEx;
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    overlapped.Anonymous.Anonymous.Offset = start as u32;
+    overlapped.Anonymous.Anonymous.OffsetHigh = (start >> 32) as u32;
+    let ok = unsafe {
+        UnlockFileEx(
+            file.as_raw_handle(),
+            0,
+            len as u32,
+            (len >> 32) as u32,
+            &mut overlapped,
+        )
+    };
+    if ok != 0 {
+        return Ok(true);
+    }
+    let e = Error::last_os();
+    if e.os as u32 == ERROR_NOT_LOCKED {
+        return Ok(false);
+    }
+    Err(e)
+}
+
+/// A POSIX record lock of `[start, start + len)`, exclusive or shared as `lock` says (an unlock for
+/// `None`), `len` 0 meaning to the end of the file and beyond. Linux uses open file description
+/// locks, which belong to the descriptor like flock's; the process-wide classic locks are the
+/// fallback.
+#[cfg(unix)]
+fn fcntl_lock(
+    file: &File,
+    lock: Option<bool>,
+    start: u64,
+    len: u64,
+    block: bool,
+) -> Result<(), Error> {
+    use std::os::fd::AsRawFd;
+    let bounds = |n: u64| libc::off_t::try_from(n).map_err(|_| Error::new(MMAP_ERR_INVALID_ARG));
+    let mut req: libc::flock = unsafe { std::mem::zeroed() };
+    req.l_type = match lock {
+        Some(true) => libc::F_WRLCK,
+        Some(false) => libc::F_RDLCK,
+        None => libc::F_UNLCK,
+    } as _;
+    req.l_whence = libc::SEEK_SET as _;
+    req.l_start = bounds(start)?;
+    req.l_len = bounds(len)?;
+    let classic = if block { libc::F_SETLKW } else { libc::F_SETLK };
+    #[cfg(target_os = "linux")]
+    let mut cmd = if block {
+        libc::F_OFD_SETLKW
+    } else {
+        libc::F_OFD_SETLK
+    };
+    #[cfg(not(target_os = "linux"))]
+    let mut cmd = classic;
+    loop {
+        if unsafe { libc::fcntl(file.as_raw_fd(), cmd, &req) } == 0 {
+            return Ok(());
+        }
+        let mut e = Error::last_os();
+        match e.os {
+            libc::EINTR => continue,
+            // Kernels before 3.15 don't know open file description locks.
+            libc::EINVAL if cmd != classic => {
+                cmd = classic;
+                continue;
+            }
+            libc::EAGAIN | libc::EACCES => e.code = MMAP_ERR_WOULD_BLOCK,
+            _ => {}
+        }
+        return Err(e);
+    }
+}
+
+/// F_GETLK for an exclusive or shared lock of `[start, start + len)`: the process ID of a
+/// conflicting lock's holder (-1 for an open file description lock, which has none), or None
+/// if the lock could be taken.
+#[cfg(unix)]
+fn fcntl_test(file: &File, exclusive: bool, start: u64, len: u64) -> Result<Option<i32>, Error> {
+    use std::os::fd::AsRawFd;
+    let bounds = |n: u64| libc::off_t::try_from(n).map_err(|_| Error::new(MMAP_ERR_INVALID_ARG));
+    let mut req: libc::flock = unsafe { std::mem::zeroed() };
+    req.l_type = if exclusive {
+        libc::F_WRLCK
+    } else {
+        libc::F_RDLCK
+    } as _;
+    req.l_whence = libc::SEEK_SET as _;
+    req.l_start = bounds(start)?;
+    req.l_len = bounds(len)?;
+    #[cfg(target_os = "linux")]
+    let mut cmd = libc::F_OFD_GETLK;
+    #[cfg(not(target_os = "linux"))]
+    let mut cmd = libc::F_GETLK;
+    while unsafe { libc::fcntl(file.as_raw_fd(), cmd, &mut req) } != 0 {
+        let e = Error::last_os();
+        match e.os {
+            libc::EINTR => {}
+            libc::EINVAL if cmd != libc::F_GETLK => cmd = libc::F_GETLK,
+            _ => return Err(e),
+        }
+    }
+    if req.l_type == libc::F_UNLCK as _ {
+        return Ok(None);
+    }
+    Ok(Some(req.l_pid as i32))
+}
+
+/// Takes a lock on the whole of `file`, first dropping one held through it already.
+fn lock_whole(file: &File, exclusive: bool, block: bool) -> Result<(), Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            use std::os::fd::AsRawFd;
+            let mut op = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+            if !block {
+                op |= libc::LOCK_NB;
+            }
+            loop {
+                if unsafe { libc::flock(file.as_raw_fd(), op) } == 0 {
+                    return Ok(());
+                }
+                let mut e = Error::last_os();
+                match e.os {
+                    libc::EINTR => continue,
+                    libc::EWOULDBLOCK => e.code = MMAP_ERR_WOULD_BLOCK,
+                    _ => {}
+                }
+                return Err(e);
+            }
+        } else if #[cfg(windows)] {
+            // Windows stacks locks taken through the same handle; flock replaces them.
+            unlock_whole(file)?;
+            lock_file_ex(file, 0, u64::MAX, exclusive, block)
+        }
+    }
+}
+
+/// Drops the whole-file lock held through `file`, if any.
+fn unlock_whole(file: &File) -> Result<(), Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            use std::os::fd::AsRawFd;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+                return Err(Error::last_os());
+            }
+            Ok(())
+        } else if #[cfg(windows)] {
+            unlock_file_ex(file, 0, u64::MAX).map(|_| ())
+        }
+    }
+}
+
+/// Drops every lock held through `file`; called when the mapping is unmapped.
+pub(crate) fn release_all(file: &File, ranges: &RangeLocks) {
+    let _ = unlock_whole(file);
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let _ = ranges;
+            let _ = fcntl_lock(file, None, 0, 0, false);
+        } else if #[cfg(windows)] {
+            for (start, len) in ranges.held().drain(..) {
+                let _ = unlock_file_ex(file, start, len);
+            }
+        }
+    }
+}
+
+/// Locks the whole file behind an open handle: shared (`exclusive == 0`), so any number of
+/// handles can hold it at once, or exclusive, so only one can. With `block != 0` the call waits
+/// for conflicting locks to go; otherwise it fails with `MMAP_ERR_WOULD_BLOCK` at once. The
+/// lock is advisory: it only keeps out other callers of `mmap_lock_file`, not reads and writes
+/// (on Windows, where the locks are LockFileEx byte-range locks over the whole file, plain
+/// ReadFile / WriteFile calls on other handles are refused too, but mapped views never are).
+/// Uses flock on Unix, so locks taken on an NFS mount may be emulated with fcntl locks by the
+/// kernel. Locking again through the same handle (or a clone) converts the lock; the lock held
+/// is dropped first, so waiters may get in between, and a conversion that fails leaves no lock.
+/// The lock is released by `mmap_unlock_file`, or when the mapping is unmapped (see
+/// `mmap_handle_close`).
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_WOULD_BLOCK`, `MMAP_ERR_CLOSED`, or the OS
+/// error; a handle closed while the call waited is left unlocked again).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_lock_file(h: *const MmapHandle, exclusive: i32, block: i32) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if !h.is_open() {
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    // Not under the view lock, which a blocking wait would hold indefinitely.
+    if let Err(e) = lock_whole(h.file(), exclusive != 0, block != 0) {
+        return error::fail(e);
+    }
+    if !h.is_open() {
+        let _ = unlock_whole(h.file());
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    0
+}
+
+/// Releases the lock taken with `mmap_lock_file` through the handle or one of its clones.
+/// Returns 0 on success, including when nothing was locked, -1 on failure (`MMAP_ERR_CLOSED`
+/// for a closed handle, whose lock is gone already, or the OS error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_unlock_file(h: *const MmapHandle) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if !h.is_open() {
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    match unlock_whole(h.file()) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Checks a range lock request: a non-empty range with its end below 2^63, the POSIX limit.
+fn check_lock_range(offset: u64, len: u64) -> Result<(), Error> {
+    match offset.checked_add(len) {
+        Some(end) if len > 0 && end <= i64::MAX as u64 => Ok(()),
+        _ => Err(Error::new(MMAP_ERR_INVALID_ARG)),
+    }
+}
+
+/// Locks `[offset, offset + len)` of the file behind an open handle, shared (`exclusive ==
+/// 0`) or exclusive, waiting for conflicting locks with `block != 0` and failing with
+/// `MMAP_ERR_WOULD_BLOCK` otherwise; e.g. to let worker processes own fixed-size slots of one
+/// file. The range may extend past the end of the file. Like `mmap_lock_file` the locks are
+/// advisory; they are independent of its whole-file lock on Linux, but not on every system, so
+/// don't use both on one file. Exclusive locks need a writable handle.
+/// The OS rules for locks held through the same handle (or its clones) differ:
+/// - Unix uses fcntl record locks (open file description locks on Linux 3.15 and later): a
+///   handle's own locks never conflict with each other. Locking a range it holds converts that
+///   part to the new mode, adjacent ranges merge, and `mmap_unlock_range` can release any part
+///   of what is held. Without open file description locks (older Linux, macOS, the BSDs) locks
+///   belong to the process instead: handles of one process never conflict, and closing any
+///   handle to the file (or any other descriptor on it) releases all of the process's locks.
+/// - Windows uses LockFileEx: locks through the same handle stack rather than merge, so an
+///   exclusive request overlapping any lock the handle already holds conflicts with it (fails,
+///   or with `block != 0` waits forever), and each lock must be released by a
+///   `mmap_unlock_range` with exactly its offset and length. Locked ranges also refuse
+///   ReadFile / WriteFile through other handles, though not mapped views.
+///
+/// The locks are released by `mmap_unlock_range`, or when the mapping is unmapped.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` for an empty range or one
+/// ending past 2^63 - 1, `MMAP_ERR_READ_ONLY`, `MMAP_ERR_WOULD_BLOCK`, `MMAP_ERR_CLOSED`, or
+/// the OS error, e.g. EDEADLK where the kernel detects a deadlock between processes).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_lock_range(
+    h: *const MmapHandle,
+    offset: u64,
+    len: u64,
+    exclusive: i32,
+    block: i32,
+) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if let Err(e) = check_lock_range(offset, len) {
+        return error::fail(e);
+    }
+    if !h.is_open() {
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    if exclusive != 0 && !h.writable() {
+        return error::fail(Error::new(MMAP_ERR_READ_ONLY));
+    }
+    let (exclusive, block) = (exclusive != 0, block != 0);
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let locked = fcntl_lock(h.file(), Some(exclusive), offset, len, block);
+        } else if #[cfg(windows)] {
+            let locked = lock_file_ex(h.file(), offset, len, exclusive, block);
+            if locked.is_ok() {
+                h.range_locks().held().push((offset, len));
+            }
+        }
+    }
+    if let Err(e) = locked {
+        return error::fail(e);
+    }
+    if !h.is_open() {
+        // Taken after the close released the handle's locks, so nothing else would drop it.
+        let _ = unlock_range(h, offset, len);
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    0
+}
+
+/// Tells whether `mmap_lock_range` could lock `[offset, offset + len)` of the handle's file
+/// right now, exclusively or shared as `exclusive` says, without taking the lock: e.g. to skip
+/// a slot another worker is busy with before starting on it. The answer is stale the moment it
+/// is returned, since other processes may lock or unlock the range at any time, so use it as a
+/// hint only; `mmap_lock_range` with `block == 0` is the way to actually get the lock if free.
+/// Only range locks are seen, not `mmap_lock_file`'s whole-file locks on Linux, and the holder is
+/// "someone else" as `mmap_lock_range` sees it: on Unix the handle's own locks (and those of
+/// the rest of the process, without open file description locks) never count, while on
+/// Windows, which probes by locking through the handle and unlocking again at once, they count
+/// as for any other lock request. Exclusive probes work on read-only handles too.
+/// Where the OS says who holds the conflicting lock, its process ID is written to `pid_out`
+/// (if non-null); -1 is written where it doesn't (open file description locks, Windows), and
+/// 0 when the range is free.
+/// Returns 0 if the lock would be granted, 1 if a conflicting lock is held, -1 on failure
+/// (`MMAP_ERR_INVALID_ARG` for a bad range, `MMAP_ERR_CLOSED`, or the OS error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_lock_test(
+    h: *const MmapHandle,
+    offset: u64,
+    len: u64,
+    exclusive: i32,
+    pid_out: *mut i32,
+) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if let Err(e) = check_lock_range(offset, len) {
+        return error::fail(e);
+    }
+    if !h.is_open() {
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let holder = fcntl_test(h.file(), exclusive != 0, offset, len);
+        } else if #[cfg(windows)] {
+            let holder = match lock_file_ex(h.file(), offset, len, exclusive != 0, false) {
+                Ok(()) => unlock_file_ex(h.file(), offset, len).map(|_| None),
+                Err(e) if e.code == MMAP_ERR_WOULD_BLOCK => Ok(Some(-1)),
+                Err(e) => Err(e),
+            };
+        }
+    }
+    match holder {
+        Ok(holder) => {
+            if !pid_out.is_null() {
+                unsafe { *pid_out = holder.unwrap_or(0) };
+            }
+            holder.is_some() as i32
+        }
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Releases `[offset, offset + len)` locked with `mmap_lock_range` through the handle or one of
+/// its clones. On Unix any range may be given, and whatever part of it is locked is released;
+/// on Windows it must match a lock exactly, and releases one such lock.
+/// Returns 0 on success, including when nothing in the range was locked (on Windows: no lock
+/// with exactly this range), -1 on failure (`MMAP_ERR_INVALID_ARG` for a bad range,
+/// `MMAP_ERR_CLOSED` for a closed handle, whose locks are gone already, or the OS error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_unlock_range(h: *const MmapHandle, offset: u64, len: u64) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if let Err(e) = check_lock_range(offset, len) {
+        return error::fail(e);
+    }
+    if !h.is_open() {
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    match unlock_range(h, offset, len) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Releases `[offset, offset + len)` of the range locks held through the handle's descriptor,
+/// whether or not the handle is still open.
+fn unlock_range(h: &MmapHandle, offset: u64, len: u64) -> Result<(), Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            fcntl_lock(h.file(), None, offset, len, false)
+        } else if #[cfg(windows)] {
+            unlock_file_ex(h.file(), offset, len).map(|was_locked| {
+                let mut held = h.range_locks().held();
+                if was_locked && let Some(i) = held.iter().position(|&r| r == (offset, len)) {
+                    held.swap_remove(i);
+                }
+            })
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// Asynchronous flushes: `mmap_flush_enqueue` hands a range to a background worker and returns
+// a ticket that `mmap_flush_poll` checks, so the caller's thread (Deno's event loop) never
+// blocks in msync / FlushViewOfFile.
+//
+// The worker flushes while holding the registry lock, like the auto-flush thread, so a ticket
+// whose mapping is closed meanwhile is dropped instead of touching unmapped memory.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::os::raw::c_void;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS};
+use crate::registry::{self, Kind};
+
+struct Job {
+    ticket: u64,
+    base: usize,
+    offset: usize,
+    len: usize,
+}
+
+struct Ticket {
+    base: usize,
+    /// `None` while the flush is queued or running.
+    result: Option<Result<(), Error>>,
+}
+
+struct Queue {
+    next_ticket: u64,
+    jobs: VecDeque<Job>,
+    tickets: BTreeMap<u64, Ticket>,
+    stop: bool,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    next_ticket: 1,
+    jobs: VecDeque::new(),
+    tickets: BTreeMap::new(),
+    stop: false,
+});
+static WORK: Condvar = Condvar::new();
+static WORKER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+fn queue() -> MutexGuard<'static, Queue> {
+    QUEUE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn flush(job: &Job) -> Result<(), Error> {
+    let reg = registry::lock();
+    let Some(m) = reg.get(&job.base) else {
+        // Closed (or remapped by a grow) after enqueueing.
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if job.offset + job.len > m.len {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    if m.kind == Kind::Snapshot {
+        return Ok(());
+    }
+    unsafe { crate::sync_view(job.base + job.offset, job.len) }
+}
+
+/// Worker loop: runs queued flushes in order; on shutdown drains the queue before exiting.
+fn run() {
+    let mut q = queue();
+    loop {
+        let Some(job) = q.jobs.pop_front() else {
+            if q.stop {
+                return;
+            }
+            q = WORK.wait(q).unwrap_or_else(|e| e.into_inner());
+            continue;
+        };
+        drop(q);
+        let result = flush(&job);
+        q = queue();
+        // Absent if the mapping was closed while the flush ran.
+        if let Some(t) = q.tickets.get_mut(&job.ticket) {
+            t.result = Some(result);
+        }
+    }
+}
+
+fn enqueue(base: *mut c_void, offset: usize, len: usize) -> Result<u64, Error> {
+    if len == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let Some((total, _)) = registry::lookup(base as usize) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if offset.checked_add(len).is_none_or(|end| end > total) {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+
+    let mut worker = WORKER.lock().unwrap_or_else(|e| e.into_inner());
+    if worker.is_none() {
+        let thread = std::thread::Builder::new()
+            .name("mmap-flush".into())
+            .spawn(run)?;
+        *worker = Some(thread);
+    }
+    let mut q = queue();
+    let ticket = q.next_ticket;
+    q.next_ticket += 1;
+    let base = base as usize;
+    q.tickets.insert(ticket, Ticket { base, result: None });
+    q.jobs.push_back(Job {
+        ticket,
+        base,
+        offset,
+        len,
+    });
+    WORK.notify_one();
+    Ok(ticket)
+}
+
+/// Drops the queued flushes and tickets of the mapping at `base`. Called by `mmap_close`.
+pub(crate) fn forget(base: usize) {
+    let mut q = queue();
+    q.jobs.retain(|j| j.base != base);
+    q.tickets.retain(|_, t| t.base != base);
+}
+
+/// Stops the worker after it has run every queued flush. Results stay pollable.
+pub(crate) fn shutdown() {
+    let mut worker = WORKER.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(thread) = worker.take() else {
+        return;
+    };
+    queue().stop = true;
+    WORK.notify_all();
+    let _ = thread.join();
+    queue().stop = false;
+}
+
+/// Queues a flush of `[base + offset, base + offset + len)` (see `mmap_flush`) on a background
+/// worker and returns immediately with a ticket for `mmap_flush_poll`. Flushes run in the
+/// order they were queued.
+/// Returns 0 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base or `len` is 0,
+/// `MMAP_ERR_OUT_OF_BOUNDS` if the range exceeds the mapping).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_enqueue(base: *mut c_void, offset: usize, len: usize) -> u64 {
+    match enqueue(base, offset, len) {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            error::set(e);
+            0
+        }
+    }
+}
+
+/// Checks a ticket from `mmap_flush_enqueue`. Returns 0 while the flush is pending and 1 once
+/// it completed. If it failed, returns the negative `MMAP_ERR_*` code and records the error
+/// (with its OS code) for `mmap_last_error` / `mmap_last_os_error`.
+/// A finished ticket is reclaimed by the poll that reports it, and all tickets of a mapping
+/// are reclaimed by `mmap_close`; polling a reclaimed or unknown ticket returns
+/// `MMAP_ERR_INVALID_ARG`.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_flush_poll(ticket: u64) -> i32 {
+    let mut q = queue();
+    let Some(t) = q.tickets.get(&ticket) else {
+        error::set(Error::new(MMAP_ERR_INVALID_ARG));
+        return MMAP_ERR_INVALID_ARG;
+    };
+    let Some(result) = t.result else {
+        return 0;
+    };
+    q.tickets.remove(&ticket);
+    match result {
+        Ok(()) => 1,
+        Err(e) => {
+            error::set(e);
+            e.code
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// What kind of file system a mapped file lives on. Network file systems (and FUSE, whose
+// coherence is up to the daemon) may not keep a shared mapping coherent with other clients or
+// may fail page-ins with SIGBUS when the server goes away, so callers can check before relying
+// on one, or refuse them outright at open time with `MMAP_LOCAL_ONLY`.
+
+use std::fs::File;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_NOT_LOCAL};
+use crate::handle::MmapHandle;
+
+/// A local disk file system.
+pub const MMAP_FS_LOCAL: i32 = 0;
+/// Memory-backed (tmpfs / ramfs): the data never reaches a disk, flushing is a no-op.
+pub const MMAP_FS_TMPFS: i32 = 1;
+/// A network or userspace file system (NFS, SMB/CIFS, FUSE, ...).
+pub const MMAP_FS_NETWORK: i32 = 2;
+/// A local file system on persistent memory mounted for DAX (see `mmap_flush_pmem`).
+pub const MMAP_FS_DAX: i32 = 3;
+
+/// `out_flags` bit: the file system is mounted read-only.
+pub const MMAP_FS_READ_ONLY: u32 = 1 << 0;
+/// `out_flags` bit: the file system is served by a FUSE daemon (reported as `MMAP_FS_NETWORK`).
+pub const MMAP_FS_FUSE: u32 = 1 << 1;
+
+#[cfg(unix)]
+pub(crate) type Borrowed<'a> = std::os::fd::BorrowedFd<'a>;
+#[cfg(windows)]
+pub(crate) type Borrowed<'a> = std::os::windows::io::BorrowedHandle<'a>;
+
+pub(crate) fn borrow(file: &File) -> Borrowed<'_> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            std::os::fd::AsFd::as_fd(file)
+        } else if #[cfg(windows)] {
+            std::os::windows::io::AsHandle::as_handle(file)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct FsInfo {
+    pub kind: i32,
+    pub flags: u32,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_os = "linux", target_os = "android"))] {
+        // Not all of these are in libc; the values are from linux/magic.h and the fs sources.
+        const NETWORK_MAGIC: &[u32] = &[
+            0x6969,     // NFS
+            0x517b,     // SMB
+            0xff534d42, // CIFS
+            0xfe534d42, // SMB2
+            0x00c36400, // Ceph
+            0x5346414f, // AFS
+            0x6b414653, // kAFS
+            0x01021997, // 9p
+            0x73757245, // Coda
+            0x0bd00bd0, // Lustre
+        ];
+        const FUSE_MAGIC: u32 = 0x65735546;
+        const TMPFS_MAGIC: u32 = 0x01021994;
+        const RAMFS_MAGIC: u32 = 0x858458f6;
+
+        fn probe_fs(file: Borrowed<'_>) -> Result<FsInfo, Error> {
+            use std::os::fd::AsRawFd;
+            let mut st: libc::statfs = unsafe { core::mem::zeroed() };
+            if unsafe { libc::fstatfs(file.as_raw_fd(), &mut st) } != 0 {
+                return Err(Error::last_os());
+            }
+            let magic = st.f_type as u32;
+            // The mount flags are only exposed through statvfs.
+            let mut vfs: libc::statvfs = unsafe { core::mem::zeroed() };
+            if unsafe { libc::fstatvfs(file.as_raw_fd(), &mut vfs) } != 0 {
+                return Err(Error::last_os());
+            }
+            let mut flags = 0;
+            if vfs.f_flag & libc::ST_RDONLY != 0 {
+                flags |= MMAP_FS_READ_ONLY;
+            }
+            let kind = if magic == FUSE_MAGIC {
+                flags |= MMAP_FS_FUSE;
+                MMAP_FS_NETWORK
+            } else if NETWORK_MAGIC.contains(&magic) {
+                MMAP_FS_NETWORK
+            } else if magic == TMPFS_MAGIC || magic == RAMFS_MAGIC {
+                MMAP_FS_TMPFS
+            } else {
+                MMAP_FS_LOCAL
+            };
+            Ok(FsInfo { kind, flags })
+        }
+    } else if #[cfg(any(target_vendor = "apple", target_os = "freebsd"))] {
+        // The statfs of the file's own mount; the same record getmntinfo lists.
+        fn probe_fs(file: Borrowed<'_>) -> Result<FsInfo, Error> {
+            use std::os::fd::AsRawFd;
+            let mut st: libc::statfs = unsafe { core::mem::zeroed() };
+            if unsafe { libc::fstatfs(file.as_raw_fd(), &mut st) } != 0 {
+                return Err(Error::last_os());
+            }
+            let name = unsafe { std::ffi::CStr::from_ptr(st.f_fstypename.as_ptr()) };
+            let name = name.to_bytes();
+            let mnt = st.f_flags as u64;
+            let mut flags = 0;
+            if mnt & libc::MNT_RDONLY as u64 != 0 {
+                flags |= MMAP_FS_READ_ONLY;
+            }
+            let fuse = [&b"macfuse"[..], b"osxfuse", b"fuse"];
+            let kind = if fuse.iter().any(|f| name.starts_with(f)) {
+                flags |= MMAP_FS_FUSE;
+                MMAP_FS_NETWORK
+            } else if mnt & libc::MNT_LOCAL as u64 == 0 {
+                MMAP_FS_NETWORK
+            } else if name == b"tmpfs" {
+                MMAP_FS_TMPFS
+            } else {
+                MMAP_FS_LOCAL
+            };
+            Ok(FsInfo { kind, flags })
+        }
+    } else if #[cfg(windows)] {
+        fn probe_fs(file: Borrowed<'_>) -> Result<FsInfo, Error> {
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_REMOTE_PROTOCOL_INFO, FileRemoteProtocolInfo, GetFileInformationByHandleEx,
+                GetVolumeInformationByHandleW,
+            };
+            use windows_sys::Win32::System::SystemServices::FILE_READ_ONLY_VOLUME;
+            let h = file.as_raw_handle();
+            let mut vol_flags = 0u32;
+            let ok = unsafe {
+                GetVolumeInformationByHandleW(
+                    h,
+                    core::ptr::null_mut(),
+                    0,
+                    core::ptr::null_mut(),
+                    core::ptr::null_mut(),
+                    &mut vol_flags,
+                    core::ptr::null_mut(),
+                    0,
+                )
+            };
+            if ok == 0 {
+                return Err(Error::last_os());
+            }
+            let mut flags = 0;
+            if vol_flags & FILE_READ_ONLY_VOLUME != 0 {
+                flags |= MMAP_FS_READ_ONLY;
+            }
+            // Only files opened through a redirector (SMB, WebDAV, ...) have a remote protocol.
+            let mut remote: FILE_REMOTE_PROTOCOL_INFO = unsafe { core::mem::zeroed() };
+            let is_remote = unsafe {
+                GetFileInformationByHandleEx(
+                    h,
+                    FileRemoteProtocolInfo,
+                    (&mut remote as *mut FILE_REMOTE_PROTOCOL_INFO).cast(),
+                    size_of::<FILE_REMOTE_PROTOCOL_INFO>() as u32,
+                )
+            } != 0;
+            let kind = if is_remote { MMAP_FS_NETWORK } else { MMAP_FS_LOCAL };
+            Ok(FsInfo { kind, flags })
+        }
+    } else {
+        fn probe_fs(file: Borrowed<'_>) -> Result<FsInfo, Error> {
+            let _ = file;
+            Err(Error::new(crate::error::MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
+
+/// Classifies the file system `file` lives on.
+pub(crate) fn probe(file: Borrowed<'_>) -> Result<FsInfo, Error> {
+    let mut info = probe_fs(file)?;
+    if info.kind == MMAP_FS_LOCAL && crate::pmem::is_dax(file) {
+        info.kind = MMAP_FS_DAX;
+    }
+    Ok(info)
+}
+
+/// `MMAP_LOCAL_ONLY`: fails with `MMAP_ERR_NOT_LOCAL` if `file` is on a network file system.
+pub(crate) fn require_local(file: Borrowed<'_>) -> Result<(), Error> {
+    if probe_fs(file)?.kind == MMAP_FS_NETWORK {
+        return Err(Error::new(MMAP_ERR_NOT_LOCAL));
+    }
+    Ok(())
+}
+
+/// Reports the file system of the handle's file: `*out_kind` receives one of `MMAP_FS_LOCAL`,
+/// `MMAP_FS_TMPFS`, `MMAP_FS_NETWORK` or `MMAP_FS_DAX`, and `*out_flags` (if not null) a mask
+/// of `MMAP_FS_READ_ONLY` / `MMAP_FS_FUSE`.
+/// Uses fstatfs on Linux (the file system magic), macOS and FreeBSD (`MNT_LOCAL` and the type
+/// name) and the volume flags plus the remote-protocol query on Windows, where RAM disks
+/// report as `MMAP_FS_LOCAL`. Other platforms fail with `MMAP_ERR_UNSUPPORTED`.
+/// Returns 0 on success, -1 on failure.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_fs_info(
+    h: *const MmapHandle,
+    out_kind: *mut i32,
+    out_flags: *mut u32,
+) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if out_kind.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    match probe(borrow(h.file())) {
+        Ok(info) => {
+            unsafe {
+                *out_kind = info.kind;
+                if !out_flags.is_null() {
+                    *out_flags = info.flags;
+                }
+            }
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
@@ -0,0 +1,52 @@
+// Sizing policy for writable mappings: how big a fresh file starts and how far
+// `mmap_ensure_capacity` grows it.
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+const DEFAULT_INITIAL: usize = 1024 * 1024;
+const DEFAULT_CHUNK: usize = 1024 * 1024;
+const DEFAULT_FACTOR_X100: u32 = 150;
+
+static INITIAL: AtomicUsize = AtomicUsize::new(DEFAULT_INITIAL);
+static CHUNK: AtomicUsize = AtomicUsize::new(DEFAULT_CHUNK);
+static FACTOR_X100: AtomicU32 = AtomicU32::new(DEFAULT_FACTOR_X100);
+
+/// Length a writable open gives an empty file when no size was requested.
+pub(crate) fn initial_size() -> usize {
+    INITIAL.load(Ordering::Relaxed)
+}
+
+/// New length for a mapping of `current` bytes that must hold `needed`:
+/// the larger of `current + chunk` and `current * factor`, but never less than `needed`,
+/// rounded up to the page size.
+pub(crate) fn grown_size(current: usize, needed: usize) -> usize {
+    let chunk = CHUNK.load(Ordering::Relaxed);
+    let factor = FACTOR_X100.load(Ordering::Relaxed) as usize;
+    let by_chunk = current.saturating_add(chunk);
+    let by_factor = current.saturating_mul(factor) / 100;
+    let page = crate::page_size();
+    let size = by_chunk.max(by_factor).max(needed);
+    size.checked_next_multiple_of(page).unwrap_or(size)
+}
+
+/// Sets the growth policy for writable mappings in this process:
+/// - `initial`: size given to empty files opened for writing without an explicit size
+///   (previously a fixed 1 MiB);
+/// - `chunk`: minimum number of bytes `mmap_ensure_capacity` adds;
+/// - `factor_x100`: multiplicative growth in percent (150 = grow to 1.5x the current size).
+///
+/// Passing 0 for a parameter restores its default (1 MiB, 1 MiB, 150).
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_set_growth_policy(initial: usize, chunk: usize, factor_x100: u32) {
+    let or = |v: usize, d: usize| if v == 0 { d } else { v };
+    INITIAL.store(or(initial, DEFAULT_INITIAL), Ordering::Relaxed);
+    CHUNK.store(or(chunk, DEFAULT_CHUNK), Ordering::Relaxed);
+    FACTOR_X100.store(
+        if factor_x100 == 0 {
+            DEFAULT_FACTOR_X100
+        } else {
+            factor_x100
+        },
+        Ordering::Relaxed,
+    );
+}
@@ -0,0 +1,1721 @@
+// Handle API: an opaque, heap-allocated owner of one mapping with bounds-checked access.
+//
+// Unlike the raw pointer functions, every operation validates the handle first, so a
+// handle that has been closed (e.g. by a JS finalizer racing explicit cleanup) reports
+// `MMAP_ERR_CLOSED` instead of touching unmapped memory.
+//
+// A handle can be cloned (`mmap_handle_clone`) to give another owner, such as a Deno worker,
+// its own reference to the same mapping. Each clone is closed and freed on its own; the
+// mapping is unmapped when the last open one is closed.
+
+use std::ffi::CStr;
+use std::fs::{File, Metadata};
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::error::{
+    self, Error, MMAP_ERR_CLOSED, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_ERR_READ_ONLY,
+    MMAP_ERR_UNALIGNED, MMAP_ERR_UNSUPPORTED,
+};
+use crate::filelock::RangeLocks;
+use crate::open::OpenSpec;
+use crate::registry::{self, Kind, Registry};
+use crate::space::Prealloc;
+use crate::sync::{self, SyncMode};
+
+/// Returned by `mmap_handle_close` when the handle had already been closed.
+pub const MMAP_ALREADY_CLOSED: i32 = 1;
+/// Returned by `mmap_handle_close_secure` when the mapping was read-only, so it was unmapped
+/// without being wiped.
+pub const MMAP_CLOSED_UNWIPED: i32 = 2;
+
+/// `mmap_preallocate` modes.
+pub const MMAP_PREALLOC_SPARSE: i32 = 0;
+pub const MMAP_PREALLOC_FULL: i32 = 1;
+pub const MMAP_PREALLOC_FULL_VALID_DATA: i32 = 2;
+
+/// Per-handle I/O counters, filled in by `mmap_handle_stats`. Only successful calls count.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct MmapStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub read_count: u64,
+    pub write_count: u64,
+    pub flush_count: u64,
+}
+
+struct View {
+    base: usize,
+    len: usize,
+    closed: bool,
+    /// Where the next `mmap_handle_append` writes.
+    cursor: usize,
+    stats: MmapStats,
+    /// Set by `mmap_set_delete_on_close`: remove the file once the mapping is unmapped.
+    delete_on_close: bool,
+    /// Set by `mmap_handle_set_writethrough`: checked writes flush what they wrote.
+    writethrough: bool,
+}
+
+/// What `mmap_handle_file_changed` compares against.
+#[derive(Clone, PartialEq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(meta: &Metadata) -> Self {
+        Stamp {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+        }
+    }
+}
+
+/// What a handle and its clones share.
+struct Shared {
+    view: Mutex<View>,
+    /// Cleared when `mmap_seal` remaps the mapping read-only. Changed with the view locked.
+    writable: AtomicBool,
+    /// A separate descriptor on the mapped file, for re-stating and locking it. Subviews use
+    /// their parent's.
+    file: Arc<File>,
+    /// Byte-range locks taken through `file` (see `mmap_lock_range`); a subview's are kept in
+    /// its root's.
+    range_locks: RangeLocks,
+    /// For a subview (see `mmap_handle_subview`), the handle it was ultimately made from.
+    root: Option<Arc<Shared>>,
+    /// Where in the file the mapping starts: 0 except for subviews.
+    file_offset: u64,
+    /// A subview's share of its root's mapping descriptor (the registry's for other handles),
+    /// if the root has one.
+    mapping_file: Option<Arc<File>>,
+    /// The path the handle was opened with, for `mmap_handle_path`.
+    path: String,
+    stamp: Stamp,
+    /// The file's `mmap_stat` identity, for `mmap_is_stale`.
+    file_id: [u64; 2],
+    /// Handles (the original and its clones) not closed yet. Changed with the view locked.
+    open: AtomicUsize,
+}
+
+impl Shared {
+    fn view(&self) -> MutexGuard<'_, View> {
+        self.view.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn writable(&self) -> bool {
+        self.writable.load(Ordering::Relaxed)
+    }
+
+    /// The mapping's own descriptor on the file, writable for writable handles: the one the
+    /// registry keeps, or a subview's, which stays out of its registry entry so the pointer
+    /// functions can't take offsets into the subview for offsets into the file.
+    fn mapping_file<'a>(&'a self, view: &View, registry: &'a Registry) -> Option<&'a File> {
+        match self.root {
+            Some(_) => self.mapping_file.as_deref(),
+            None => registry.get(&view.base).and_then(|m| m.file.as_deref()),
+        }
+    }
+
+    /// Subviews map a fixed range of the file, so they can't grow or be remapped.
+    fn check_resizable(&self) -> Result<(), Error> {
+        match self.root {
+            Some(_) => Err(Error::new(MMAP_ERR_UNSUPPORTED)),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct MmapHandle {
+    shared: Arc<Shared>,
+    /// Whether this handle (as opposed to the mapping) has been closed.
+    closed: AtomicBool,
+}
+
+impl MmapHandle {
+    fn view(&self) -> MutexGuard<'_, View> {
+        self.shared.view()
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.shared.file
+    }
+
+    pub(crate) fn writable(&self) -> bool {
+        self.shared.writable()
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn range_locks(&self) -> &RangeLocks {
+        &self
+            .shared
+            .root
+            .as_deref()
+            .unwrap_or(&self.shared)
+            .range_locks
+    }
+
+    /// Whether neither this handle nor the mapping has been closed.
+    pub(crate) fn is_open(&self) -> bool {
+        !self.closed.load(Ordering::Relaxed) && !self.view().closed
+    }
+}
+
+/// Locks an open handle's view, or reports why it can't be used.
+unsafe fn open_view<'a>(h: *const MmapHandle) -> Result<(&'a Shared, MutexGuard<'a, View>), Error> {
+    let h = unsafe { h.as_ref() }.ok_or(Error::new(MMAP_ERR_INVALID_ARG))?;
+    let view = h.view();
+    if view.closed || h.closed.load(Ordering::Relaxed) {
+        return Err(Error::new(MMAP_ERR_CLOSED));
+    }
+    Ok((&h.shared, view))
+}
+
+fn check_range(offset: usize, len: usize, total: usize) -> Result<(), Error> {
+    match offset.checked_add(len) {
+        Some(end) if end <= total => Ok(()),
+        _ => Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+    }
+}
+
+/// Wraps the registered mapping at `base` in a new handle, with `file` as the handle's own
+/// descriptor on the mapped file.
+fn new_handle(
+    base: usize,
+    len: usize,
+    writable: bool,
+    file: File,
+    path: String,
+) -> Result<MmapHandle, Error> {
+    let meta = file.metadata()?;
+    let file_id = crate::stat::stat(&file)?.file_id;
+    let shared = Shared {
+        view: Mutex::new(View {
+            base,
+            len,
+            closed: false,
+            cursor: 0,
+            stats: MmapStats::default(),
+            delete_on_close: false,
+            writethrough: false,
+        }),
+        writable: AtomicBool::new(writable),
+        file: Arc::new(file),
+        range_locks: RangeLocks::default(),
+        root: None,
+        file_offset: 0,
+        mapping_file: None,
+        path,
+        stamp: Stamp::of(&meta),
+        file_id,
+        open: AtomicUsize::new(1),
+    };
+    Ok(MmapHandle {
+        shared: Arc::new(shared),
+        closed: AtomicBool::new(false),
+    })
+}
+
+unsafe fn handle_open(path: *const c_char, spec: OpenSpec) -> *mut MmapHandle {
+    let result = unsafe { crate::open_registered(path, &spec, false) }.and_then(|m| {
+        // The path was validated by open_registered.
+        let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap_or_default();
+        // Writable on Unix for writable handles, so fcntl can take write locks through it;
+        // on Windows the mapping's own handle doesn't share writing.
+        let opened = std::fs::OpenOptions::new()
+            .read(true)
+            .write(cfg!(unix) && spec.write)
+            .open(path)
+            .map_err(Error::from)
+            .and_then(|f| new_handle(m.ptr as usize, m.len, spec.write, f, path.to_owned()));
+        if opened.is_err() {
+            unsafe { crate::mmap_close(m.ptr, m.len) };
+        }
+        opened
+    });
+    match result {
+        Ok(h) => Box::into_raw(Box::new(h)),
+        Err(e) => {
+            error::set(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Maps `path` read-only and returns a handle owning the mapping, or null on failure
+/// (see `mmap_last_error`). Release it with `mmap_handle_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_open(path: *const c_char) -> *mut MmapHandle {
+    let spec = OpenSpec::read(0);
+    unsafe { handle_open(path, spec) }
+}
+
+/// Opens (or creates) `path` read-write, ensuring it is at least `size` bytes long
+/// (same sizing rules as `mmap_open_write_with_size`), and returns a handle owning the mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_open_write(
+    path: *const c_char,
+    size: usize,
+) -> *mut MmapHandle {
+    let spec = OpenSpec::write(size, 0);
+    unsafe { handle_open(path, spec) }
+}
+
+/// Creates an anonymous shared-memory file (a memfd) of `size` bytes, maps it read-write and
+/// returns a handle owning the mapping, writing its length to `len_out`. Unlike the file of
+/// `mmap_open_tmp` it lives in memory (and swap) only, and it can be sealed with `mmap_seal`.
+/// It has no name: `mmap_handle_path` reports an empty string, `mmap_commit_atomic` fails
+/// with `MMAP_ERR_UNSUPPORTED`, and the memory goes once the last reference to it does. Linux
+/// only; elsewhere it fails with `MMAP_ERR_UNSUPPORTED`. Returns null on
+/// failure (see `mmap_last_error`), `MMAP_ERR_INVALID_ARG` for a `size` of 0 or a null
+/// `len_out`. Release it with `mmap_handle_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_memfd_create(size: usize, len_out: *mut usize) -> *mut MmapHandle {
+    let result = if len_out.is_null() || size == 0 {
+        Err(Error::new(MMAP_ERR_INVALID_ARG))
+    } else {
+        unsafe { memfd_handle(size) }
+    };
+    match result {
+        Ok(h) => {
+            unsafe { *len_out = h.view().len };
+            Box::into_raw(Box::new(h))
+        }
+        Err(e) => {
+            error::set(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn memfd_handle(size: usize) -> Result<MmapHandle, Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            let m = unsafe { crate::open::open_memfd(size)? };
+            // The registry keeps the mapping's descriptor; the handle gets a duplicate.
+            let file = m.file.as_ref().map(File::try_clone);
+            let origin = registry::Origin::new(m.kind, true, None);
+            let m = unsafe { crate::register(m, 0, origin)? };
+            let handle = file
+                .expect("a memfd mapping keeps its file")
+                .map_err(Error::from)
+                .and_then(|file| new_handle(m.ptr as usize, m.len, true, file, String::new()));
+            if handle.is_err() {
+                unsafe { crate::mmap_close(m.ptr, m.len) };
+            }
+            handle
+        } else {
+            let _ = size;
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
+
+/// Returns a new handle to the same mapping as `h`, e.g. to hand to another Deno worker, which
+/// then closes and frees it independently: the mapping stays valid until every handle to it
+/// has been closed. Clones share everything else too (the `mmap_handle_append` cursor, the
+/// counters of `mmap_handle_stats`, and remaps by `mmap_ensure_capacity`), and may be cloned
+/// themselves. Returns null if `h` is null or closed (see `mmap_last_error`). Release the
+/// clone with `mmap_handle_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_clone(h: *const MmapHandle) -> *mut MmapHandle {
+    let result = unsafe { open_view(h) }.map(|(shared, _view)| {
+        // Under the view lock, so a concurrent close of the last handle can't unmap first.
+        shared.open.fetch_add(1, Ordering::Relaxed);
+        MmapHandle {
+            shared: unsafe { &*h }.shared.clone(),
+            closed: AtomicBool::new(false),
+        }
+    });
+    match result {
+        Ok(clone) => Box::into_raw(Box::new(clone)),
+        Err(e) => {
+            error::set(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Maps `len` bytes of the handle's file from `file_offset` (0 for the rest of the file) as a
+/// new handle, e.g. for one of many windows into a large file: the subview maps its range
+/// through the descriptors the handle already holds, so it opens none of its own, and it is
+/// writable if the handle is. `file_offset` must be a multiple of the mapping granularity (the
+/// page size; 64 KiB on Windows). A subview is a handle of its own, with its own mapping,
+/// clones, cursor and counters, and offsets given to it count from its start; close and free it
+/// with `mmap_handle_close` / `mmap_handle_free` as any other. It stays valid when the handle it
+/// was made from (or any other subview) is closed, and the shared descriptors stay open until
+/// the last of them is freed. Subviews can be made from subviews too; the file-level calls (the
+/// syncs, `mmap_commit`, `mmap_punch_hole`, ...) work on them, but a subview can't be grown or
+/// remapped (`mmap_ensure_capacity`, `mmap_write_grow`, `mmap_refresh` and `mmap_preallocate`
+/// fail with `MMAP_ERR_UNSUPPORTED`, as does an append that doesn't fit) or committed by
+/// `mmap_commit_atomic`. Locks taken through a subview are those of the handle it came from,
+/// held through the same descriptor and released when that handle's mapping is unmapped.
+/// Writes the subview's length to `len_out` if it is non-null. Returns null on failure (see
+/// `mmap_last_error`): `MMAP_ERR_UNALIGNED`, `MMAP_ERR_OUT_OF_BOUNDS` if the range isn't within
+/// the file as it is now (or is empty), `MMAP_ERR_CLOSED`, or the OS error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_subview(
+    h: *const MmapHandle,
+    file_offset: u64,
+    len: usize,
+    len_out: *mut usize,
+) -> *mut MmapHandle {
+    let result = unsafe { open_view(h) }.and_then(|(src, view)| {
+        if !file_offset.is_multiple_of(crate::map_granularity() as u64) {
+            return Err(Error::new(MMAP_ERR_UNALIGNED));
+        }
+        let rest = src
+            .file
+            .metadata()?
+            .len()
+            .checked_sub(file_offset)
+            .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+        let len = match len {
+            0 => usize::try_from(rest).map_err(|_| Error::new(MMAP_ERR_OUT_OF_BOUNDS))?,
+            len => len,
+        };
+        if len == 0 || len as u64 > rest {
+            return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+        }
+        let mapping_file = match src.root {
+            Some(_) => src.mapping_file.clone(),
+            None => registry::lock()
+                .get(&view.base)
+                .and_then(|m| m.file.clone()),
+        };
+        // Read-only handles on Windows keep no mapping descriptor; theirs maps read-only too.
+        let writable = src.writable() && mapping_file.is_some();
+        let through = mapping_file.as_deref().unwrap_or(&src.file);
+        let base = unsafe { crate::open::map_at(through, file_offset, len, writable)? };
+        registry::insert(
+            base as usize,
+            registry::Mapping {
+                len,
+                kind: Kind::File,
+                locked: false,
+                pinned: Vec::new(),
+                file: None,
+                dirty: Default::default(),
+                high_water: len,
+                autoflush: None,
+                sync: false,
+                writable,
+                secret: false,
+                origin: registry::Origin::new(
+                    Kind::File,
+                    writable,
+                    (!src.path.is_empty()).then_some(src.path.as_bytes()),
+                ),
+            },
+        );
+        let root = match &src.root {
+            Some(root) => root.clone(),
+            None => unsafe { &*h }.shared.clone(),
+        };
+        let shared = Shared {
+            view: Mutex::new(View {
+                base: base as usize,
+                len,
+                closed: false,
+                cursor: 0,
+                stats: MmapStats::default(),
+                delete_on_close: false,
+                writethrough: false,
+            }),
+            writable: AtomicBool::new(writable),
+            file: src.file.clone(),
+            range_locks: RangeLocks::default(),
+            root: Some(root),
+            file_offset,
+            mapping_file,
+            path: src.path.clone(),
+            stamp: src.stamp.clone(),
+            file_id: src.file_id,
+            open: AtomicUsize::new(1),
+        };
+        Ok((shared, len))
+    });
+    match result {
+        Ok((shared, len)) => {
+            if !len_out.is_null() {
+                unsafe { *len_out = len };
+            }
+            Box::into_raw(Box::new(MmapHandle {
+                shared: Arc::new(shared),
+                closed: AtomicBool::new(false),
+            }))
+        }
+        Err(e) => {
+            error::set(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Base address of the mapping, or null if the handle is null or closed.
+/// The pointer is only valid until the handle is closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_ptr(h: *const MmapHandle) -> *mut c_void {
+    match unsafe { open_view(h) } {
+        Ok((_, view)) => view.base as *mut c_void,
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Mapped length in bytes, or 0 if the handle is null or closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_len(h: *const MmapHandle) -> usize {
+    match unsafe { open_view(h) } {
+        Ok((_, view)) => view.len,
+        Err(_) => 0,
+    }
+}
+
+/// Returns 1 if `ptr` lies within `[base, base + len)` of the handle's mapping, 0 otherwise
+/// (including for a null or closed handle). Only compares addresses; `ptr` is never dereferenced.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_contains(h: *const MmapHandle, ptr: *const c_void) -> i32 {
+    match unsafe { open_view(h) } {
+        Ok((_, view)) => {
+            let addr = ptr as usize;
+            (addr >= view.base && addr - view.base < view.len) as i32
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Returns 1 if the handle's mapping was opened writable, 0 if it is read-only (writing
+/// through its pointer would fault), or -1 if the handle is null or closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_is_writable(h: *const MmapHandle) -> i32 {
+    match unsafe { open_view(h) } {
+        Ok((h, _)) => h.writable() as i32,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Copies `len` bytes at `offset` of the mapping into `dst`.
+/// Returns the number of bytes copied, or -1 if the handle is closed or the range is out of bounds.
+///
+/// Safety: `dst` must point to a writable buffer of at least `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_read(
+    h: *const MmapHandle,
+    offset: usize,
+    dst: *mut u8,
+    len: usize,
+) -> isize {
+    let result = unsafe { open_view(h) }.and_then(|(_, mut view)| {
+        if dst.is_null() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        check_range(offset, len, view.len)?;
+        unsafe {
+            ptr::copy_nonoverlapping((view.base as *const u8).add(offset), dst, len);
+        }
+        view.stats.bytes_read += len as u64;
+        view.stats.read_count += 1;
+        Ok(len as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+/// Copies `len` bytes from `src` into the mapping at `offset`, and flushes them in
+/// write-through mode (see `mmap_handle_set_writethrough`).
+/// Returns the number of bytes copied, or -1 if the handle is closed or read-only, the range
+/// is out of bounds, or the write-through flush fails (the data is in the mapping then).
+///
+/// Safety: `src` must point to a readable buffer of at least `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_write(
+    h: *const MmapHandle,
+    offset: usize,
+    src: *const u8,
+    len: usize,
+) -> isize {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        if src.is_null() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        check_range(offset, len, view.len)?;
+        unsafe {
+            ptr::copy_nonoverlapping(src, (view.base as *mut u8).add(offset), len);
+        }
+        crate::dirty::record(view.base as *mut c_void, offset, len);
+        view.stats.bytes_written += len as u64;
+        view.stats.write_count += 1;
+        write_through(&mut view, offset, len)?;
+        Ok(len as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+/// Like `mmap_handle_write`, but a range past the end of the mapping grows the file first
+/// (see `mmap_ensure_capacity`) instead of failing, all under the handle's lock so no other
+/// call sees the mapping in between. The mapping may move: `mmap_handle_ptr` returns the new
+/// base afterwards, and pointers obtained earlier are invalid.
+/// Returns the number of bytes written, or -1 if the handle is closed or read-only, the range
+/// overflows, growing fails (which leaves the mapping as it was) or the write-through flush
+/// does (see `mmap_handle_set_writethrough`).
+///
+/// Safety: `src` must point to a readable buffer of at least `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_write_grow(
+    h: *const MmapHandle,
+    offset: usize,
+    src: *const u8,
+    len: usize,
+) -> isize {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        if src.is_null() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        let end = offset
+            .checked_add(len)
+            .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+        if end > view.len {
+            h.check_resizable()?;
+            let (base, len) = unsafe { crate::grow_registered(view.base, end)? };
+            view.base = base;
+            view.len = len;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(src, (view.base as *mut u8).add(offset), len);
+        }
+        crate::dirty::record(view.base as *mut c_void, offset, len);
+        view.stats.bytes_written += len as u64;
+        view.stats.write_count += 1;
+        write_through(&mut view, offset, len)?;
+        Ok(len as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+/// Writes `len` bytes from `src` at the handle's cursor and advances the cursor past them,
+/// growing the file (see `mmap_ensure_capacity`) when the data doesn't fit, which may move the
+/// mapping. The cursor starts at 0; see `mmap_handle_seek`.
+/// Returns the offset the data was written at, or -1 if the handle is closed or read-only,
+/// growing fails, or the write-through flush does (see `mmap_handle_set_writethrough`).
+///
+/// Safety: `src` must point to a readable buffer of at least `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_append(
+    h: *const MmapHandle,
+    src: *const u8,
+    len: usize,
+) -> isize {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        if src.is_null() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        let at = view.cursor;
+        let end = at
+            .checked_add(len)
+            .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+        if end > view.len {
+            h.check_resizable()?;
+            let (base, len) = unsafe { crate::grow_registered(view.base, end)? };
+            view.base = base;
+            view.len = len;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(src, (view.base as *mut u8).add(at), len);
+        }
+        crate::dirty::record(view.base as *mut c_void, at, len);
+        view.cursor = end;
+        view.stats.bytes_written += len as u64;
+        view.stats.write_count += 1;
+        write_through(&mut view, at, len)?;
+        Ok(at as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+/// Appends the `count` records of `records` back to back at the handle's cursor, as one
+/// `mmap_handle_append` each would, e.g. to bulk-load a log in one FFI call, and writes the
+/// offset each record landed at to `offsets_out` (if non-null), giving an index in one pass.
+/// The file grows as needed (see `mmap_ensure_capacity`), which may move the mapping; records
+/// copied out of the mapping itself are read from where they are after the move, and may
+/// overlap where they are written.
+/// Returns the number of records written: fewer than `count` if growing fails partway, with
+/// the reason in `mmap_last_error`, the records before it written and the cursor just past
+/// them. -1 if the handle is null, closed or read-only, `records` is null, a record has a null
+/// `ptr` and a non-zero `len`, `offsets_cap` is less than `count`, or the write-through flush
+/// fails (see `mmap_handle_set_writethrough`; the records are in the mapping then).
+///
+/// Safety: `records` must point to `count` `MmapIoVec`s, each describing a readable buffer,
+/// and `offsets_out` must be null or writable for `offsets_cap` offsets.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_append_batch(
+    h: *const MmapHandle,
+    records: *const crate::MmapIoVec,
+    count: usize,
+    offsets_out: *mut usize,
+    offsets_cap: usize,
+) -> isize {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        if records.is_null() || (!offsets_out.is_null() && offsets_cap < count) {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        let records = unsafe { std::slice::from_raw_parts(records, count) };
+        if records.iter().any(|r| r.ptr.is_null() && r.len > 0) {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        // Where the mapping was when the call began, for records that point into it.
+        let (old_base, old_len) = (view.base, view.len);
+        let start = view.cursor;
+        let mut written = 0;
+        for r in records {
+            let at = view.cursor;
+            let Some(end) = at.checked_add(r.len) else {
+                error::set(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+                break;
+            };
+            if end > view.len {
+                let grown = h
+                    .check_resizable()
+                    .and_then(|()| unsafe { crate::grow_registered(view.base, end) });
+                match grown {
+                    Ok((base, len)) => {
+                        view.base = base;
+                        view.len = len;
+                    }
+                    Err(e) => {
+                        error::set(e);
+                        break;
+                    }
+                }
+            }
+            if r.len > 0 {
+                let src = match (r.ptr as usize).checked_sub(old_base) {
+                    Some(off) if off < old_len => (view.base + off) as *const u8,
+                    _ => r.ptr,
+                };
+                unsafe { ptr::copy(src, (view.base as *mut u8).add(at), r.len) };
+            }
+            if !offsets_out.is_null() {
+                unsafe { *offsets_out.add(written) = at };
+            }
+            view.cursor = end;
+            written += 1;
+        }
+        let len = view.cursor - start;
+        crate::dirty::record(view.base as *mut c_void, start, len);
+        view.stats.bytes_written += len as u64;
+        view.stats.write_count += 1;
+        write_through(&mut view, start, len)?;
+        Ok(written as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+/// Current append cursor of the handle, or -1 if the handle is null or closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_tell(h: *const MmapHandle) -> isize {
+    match unsafe { open_view(h) } {
+        Ok((_, view)) => view.cursor as isize,
+        Err(e) => error::fail(e) as isize,
+    }
+}
+
+/// Moves the append cursor to `offset`, e.g. to resume a log at its recorded end after
+/// reopening. Returns 0 on success, -1 if the handle is closed or `offset` is past the end of
+/// the mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_seek(h: *const MmapHandle, offset: usize) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(_, mut view)| {
+        check_range(offset, 0, view.len)?;
+        view.cursor = offset;
+        Ok(0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Flushes `[offset, offset + len)` of the mapping to its file (see `mmap_flush`).
+/// Returns 0 on success, -1 on failure.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_flush(h: *const MmapHandle, offset: usize, len: usize) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(_, mut view)| {
+        check_range(offset, len, view.len)?;
+        unsafe { crate::flush_range(view.base as *mut c_void, offset, len)? };
+        view.stats.flush_count += 1;
+        Ok(0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Turns write-through mode on (`enabled != 0`) or off (the default) for the mapping: every
+/// `mmap_handle_write`, `mmap_write_grow` and `mmap_handle_append` then flushes the pages it
+/// wrote (see `mmap_handle_flush`) before returning, so each write is on disk once the call
+/// succeeds, e.g. for a small file that must survive a crash right after any update. That
+/// costs a synchronous write-back per call, typically milliseconds on an SSD and far more on
+/// a spinning or network disk, against well under a microsecond for the copy alone, so keep
+/// it off for bulk writes and flush once at the end instead. Writes through raw pointers
+/// (`mmap_write` and friends) are not covered. The mode is shared by all clones (see
+/// `mmap_handle_clone`). Returns 0 on success, -1 if the handle is null or closed
+/// (`MMAP_ERR_CLOSED`).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_set_writethrough(h: *const MmapHandle, enabled: i32) -> i32 {
+    match unsafe { open_view(h) } {
+        Ok((_, mut view)) => {
+            view.writethrough = enabled != 0;
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Flushes `[offset, offset + len)` after a checked write in write-through mode.
+fn write_through(view: &mut View, offset: usize, len: usize) -> Result<(), Error> {
+    if view.writethrough && len > 0 {
+        unsafe { crate::flush_range(view.base as *mut c_void, offset, len)? };
+        view.stats.flush_count += 1;
+    }
+    Ok(())
+}
+
+/// Makes sure the mapping holds at least `needed` bytes. If it is smaller, the file is grown
+/// according to the growth policy (see `mmap_set_growth_policy`) and remapped; the mapping may
+/// move, and the current base address is written to `base_out` (if non-null) either way.
+/// Pointers obtained earlier from `mmap_handle_ptr` are invalid after a remap.
+/// Returns 0 on success, -1 on failure (read-only handle, closed handle, OS error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_ensure_capacity(
+    h: *const MmapHandle,
+    needed: usize,
+    base_out: *mut *mut c_void,
+) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        if needed > view.len {
+            h.check_resizable()?;
+            let (base, len) = unsafe { crate::grow_registered(view.base, needed)? };
+            view.base = base;
+            view.len = len;
+        }
+        if !base_out.is_null() {
+            unsafe {
+                *base_out = view.base as *mut c_void;
+            }
+        }
+        Ok(0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Follows a file that others append to: re-stats the mapped file and, if it has grown past
+/// the mapping, maps it again at its new size (the new mapping is created before the old one is
+/// unmapped), so the appended bytes become readable. The mapping's base and length are written
+/// to `out_new_base` / `out_new_len` (if non-null) either way; pointers obtained earlier from
+/// `mmap_handle_ptr` are invalid after a remap. A file that shrank is not followed: see
+/// `mmap_handle_file_changed`.
+/// Returns 1 if the mapping grew, 0 if the file hadn't, or -1 on failure (`MMAP_ERR_CLOSED`,
+/// `MMAP_ERR_OUT_OF_BOUNDS` if the file no longer fits in the address space, or the OS error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_refresh(
+    h: *const MmapHandle,
+    out_new_base: *mut *mut c_void,
+    out_new_len: *mut usize,
+) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        let size = h.file.metadata()?.len();
+        let size = usize::try_from(size).map_err(|_| Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+        let grew = size > view.len;
+        if grew {
+            h.check_resizable()?;
+            let read_only = (!h.writable()).then_some(&*h.file);
+            view.base = unsafe { crate::remap_registered(view.base, size, read_only)? };
+            view.len = size;
+        }
+        unsafe {
+            if !out_new_base.is_null() {
+                *out_new_base = view.base as *mut c_void;
+            }
+            if !out_new_len.is_null() {
+                *out_new_len = view.len;
+            }
+        }
+        Ok(grew as i32)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Protects checked accesses from a file truncated under the mapping, where touching pages
+/// past the new end raises SIGBUS: re-stats the mapped file and sets the handle's length (as
+/// used by `mmap_handle_read`, `mmap_handle_write` and `mmap_handle_len`) to its size, capped
+/// at the mapped length, so the truncated tail reads as out of bounds. The mapping itself is
+/// left alone, and a file that grows back is let back in up to the mapped length (see
+/// `mmap_refresh` to map beyond it). Cheap enough to call before every batch of accesses.
+/// Windows doesn't let a mapped file be truncated, so there the length only changes after an
+/// earlier clamp.
+/// Returns the new length, or -1 on failure (`MMAP_ERR_CLOSED`, or the stat error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_clamp_to_file(h: *const MmapHandle) -> isize {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        let Some((mapped, Kind::File)) = registry::lookup(view.base) else {
+            // Snapshots don't depend on the file.
+            return Ok(view.len as isize);
+        };
+        let size = h.file.metadata()?.len().saturating_sub(h.file_offset);
+        view.len = size.min(mapped as u64) as usize;
+        Ok(view.len as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+/// Re-stats the mapped file and compares its modification time and size with the values
+/// captured when the handle was opened: returns 1 if either changed, 0 if not, and -1 if the
+/// handle is null or the stat fails. Useful to invalidate data cached from the mapping.
+///
+/// Writes through the handle's own mapping count as changes once the OS updates the mtime.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_file_changed(h: *const MmapHandle) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    match h.file().metadata() {
+        Ok(meta) => (Stamp::of(&meta) != h.shared.stamp) as i32,
+        Err(e) => error::fail(e.into()),
+    }
+}
+
+/// Re-stats the path the handle was opened with and compares what it refers to now with the
+/// file mapped at open: returns 1 if the path names a different file (its `mmap_stat`
+/// identity changed, e.g. another process renamed a new version over it), no file at all, or
+/// one whose size differs from the size at open; 0 if not; and -1 if the handle is null or the
+/// stat fails. Unlike `mmap_handle_file_changed`, which follows the mapped file itself, this
+/// tells a reader when to reopen by path to see the current contents. Handles without a path
+/// (see `mmap_memfd_create`) are never stale.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_is_stale(h: *const MmapHandle) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if h.shared.path.is_empty() {
+        return 0;
+    }
+    match crate::stat::stat_path(&h.shared.path) {
+        Ok(st) => (st.file_id != h.shared.file_id || st.size != h.shared.stamp.len) as i32,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 1,
+        Err(e) => error::fail(e.into()),
+    }
+}
+
+/// Returns 1 if the two handles map the same file, whatever paths they were opened with
+/// (hard links, symlinks, relative paths), 0 if not, and -1 if either handle is null. Compares
+/// the `mmap_stat` identities captured at open (device and inode on Unix, volume serial number
+/// and file id on Windows), so it also works on closed handles and isn't fooled by a path that
+/// names another file by now. Every handle is opened on a file, so every handle has one.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_same_file(a: *const MmapHandle, b: *const MmapHandle) -> i32 {
+    match unsafe { (a.as_ref(), b.as_ref()) } {
+        (Some(a), Some(b)) => (a.shared.file_id == b.shared.file_id) as i32,
+        _ => error::fail(Error::new(MMAP_ERR_INVALID_ARG)),
+    }
+}
+
+/// Makes everything written through a writable handle durable: fdatasync (`data_only != 0`) or
+/// fsync of the mapped file (FlushFileBuffers on Windows), which also persists metadata such as the
+/// size after an extension; see `mmap_flush_full` for F_FULLFSYNC on macOS. Call it after
+/// `mmap_handle_flush` / `mmap_flush`.
+/// Returns 0 on success (and for read-only handles, which have nothing to sync), or -1 with
+/// `MMAP_ERR_IO` for a device I/O error or `MMAP_ERR_OS` otherwise.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_file_sync(h: *const MmapHandle, data_only: i32) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, view)| {
+        if !h.writable() {
+            return Ok(0);
+        }
+        let registry = registry::lock();
+        let Some(file) = h.mapping_file(&view, &registry) else {
+            return Ok(0);
+        };
+        let mode = if data_only != 0 {
+            SyncMode::Data
+        } else {
+            SyncMode::All
+        };
+        sync::sync_file(file, mode)
+            .map(|()| 0)
+            .map_err(Error::io_sync)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Flushes the whole mapping of a writable handle and then syncs the file, like
+/// `mmap_handle_flush` followed by `mmap_file_sync`. With `full != 0` the sync uses the
+/// strongest primitive available: F_FULLFSYNC on macOS (where fsync may leave data in the
+/// drive's cache), falling back to fsync if the filesystem rejects it; fsync /
+/// FlushFileBuffers elsewhere. Returns 0 on success (read-only handles only flush the view),
+/// -1 on failure (`MMAP_ERR_IO` for device I/O errors).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_full(h: *const MmapHandle, full: i32) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        unsafe { crate::flush_range(view.base as *mut c_void, 0, view.len)? };
+        view.stats.flush_count += 1;
+        if !h.writable() {
+            return Ok(0);
+        }
+        let registry = registry::lock();
+        let Some(file) = h.mapping_file(&view, &registry) else {
+            return Ok(0);
+        };
+        let mode = if full != 0 {
+            SyncMode::Full
+        } else {
+            SyncMode::All
+        };
+        sync::sync_file(file, mode)
+            .map(|()| 0)
+            .map_err(Error::io_sync)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Makes a writable handle's file crash-consistent as a whole: flushes the whole mapping (see
+/// `mmap_flush`), fsyncs the file (FlushFileBuffers on Windows), which persists its metadata
+/// such as a size grown by `mmap_ensure_capacity`, and on Unix also fsyncs the directory
+/// holding it, so a newly created file can't vanish (files without a path, see
+/// `mmap_memfd_create`, are in no directory). Once this returns 0, a crash or power
+/// loss leaves the file existing at its current size with everything written through the
+/// mapping so far; writes made after the call may or may not survive. On macOS the file sync
+/// is a plain fsync; see `mmap_flush_full` to also flush the drive's cache.
+/// Returns 0 on success (read-only handles only flush the view), -1 on failure (`MMAP_ERR_IO`
+/// for a device I/O error, `MMAP_ERR_OS` otherwise, with the OS error from
+/// `mmap_last_os_error`).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_sync_all(h: *const MmapHandle) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        unsafe { crate::flush_range(view.base as *mut c_void, 0, view.len)? };
+        view.stats.flush_count += 1;
+        if !h.writable() {
+            return Ok(0);
+        }
+        {
+            let registry = registry::lock();
+            if let Some(file) = h.mapping_file(&view, &registry) {
+                sync::sync_file(file, SyncMode::All).map_err(Error::io_sync)?;
+            }
+        }
+        if !h.path.is_empty() {
+            sync::sync_parent_dir(std::path::Path::new(&h.path)).map_err(Error::io_sync)?;
+        }
+        Ok(0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Replaces `final_path` with a writable handle's file in one step, for files that must never
+/// be seen half-written: fill a handle opened on a temporary path (in the same directory, so
+/// the rename can't cross file systems), then commit it. Makes the file durable as
+/// `mmap_handle_sync_all` does, closes the handle (see `mmap_handle_close`), renames the file
+/// over `final_path` and, on Unix, fsyncs the directory of `final_path` so the rename is
+/// durable too. Windows renames with MoveFileExW(MOVEFILE_REPLACE_EXISTING |
+/// MOVEFILE_WRITE_THROUGH). Readers see either the old file or the new one at `final_path`,
+/// before and after a crash.
+/// Returns 0 on success, -1 on failure, which never touches `final_path`:
+/// `MMAP_ERR_INVALID_ARG` for a null or non-UTF-8 `final_path` or while clones of the handle
+/// are open, `MMAP_ERR_READ_ONLY`, `MMAP_ERR_CLOSED`, `MMAP_ERR_UNSUPPORTED` for a handle
+/// without a path (see `mmap_memfd_create`), or the error of a step. The handle stays
+/// open if the flush fails; once it is closed, a failed rename leaves the file at its temporary
+/// path.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_commit_atomic(h: *mut MmapHandle, final_path: *const c_char) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let Some(final_path) = (!final_path.is_null())
+        .then(|| unsafe { CStr::from_ptr(final_path) }.to_str().ok())
+        .flatten()
+    else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let mut view = h.view();
+    if view.closed || h.closed.load(Ordering::Relaxed) {
+        return error::fail(Error::new(MMAP_ERR_CLOSED));
+    }
+    if !h.shared.writable() {
+        return error::fail(Error::new(MMAP_ERR_READ_ONLY));
+    }
+    if h.shared.open.load(Ordering::Relaxed) > 1 {
+        return error::fail_with(
+            Error::new(MMAP_ERR_INVALID_ARG),
+            "other clones of the handle are still open".into(),
+        );
+    }
+    if h.shared.root.is_some() {
+        return error::fail_with(
+            Error::new(MMAP_ERR_INVALID_ARG),
+            "a subview can't commit the file".into(),
+        );
+    }
+    if h.shared.path.is_empty() {
+        return error::fail_with(
+            Error::new(MMAP_ERR_UNSUPPORTED),
+            "the handle's file has no path to rename".into(),
+        );
+    }
+    let durable = (|| {
+        unsafe { crate::flush_range(view.base as *mut c_void, 0, view.len)? };
+        let registry = registry::lock();
+        if let Some(file) = h.shared.mapping_file(&view, &registry) {
+            sync::sync_file(file, SyncMode::All).map_err(Error::io_sync)?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = durable {
+        return error::fail(e);
+    }
+    view.stats.flush_count += 1;
+    // The mapping's own descriptor goes too, which on Windows would block the rename.
+    unsafe { crate::mmap_close(view.base as *mut c_void, view.len) };
+    view.closed = true;
+    crate::filelock::release_all(&h.shared.file, &h.shared.range_locks);
+    h.closed.store(true, Ordering::Relaxed);
+    h.shared.open.fetch_sub(1, Ordering::Relaxed);
+    drop(view);
+
+    if crate::hooks::crash_before_rename() {
+        std::process::abort();
+    }
+    let renamed = (|| {
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                std::fs::rename(&h.shared.path, final_path)?;
+                sync::sync_parent_dir(std::path::Path::new(final_path)).map_err(Error::io_sync)
+            } else if #[cfg(windows)] {
+                use std::os::windows::ffi::OsStrExt;
+                use windows_sys::Win32::Storage::FileSystem::{
+                    MOVEFILE_REPLACE_EXISTING, MOVEFILE_WRITE_THROUGH, MoveFileExW,
+                };
+                let wide = |s: &str| -> Vec<u16> {
+                    std::ffi::OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+                };
+                let (from, to) = (wide(&h.shared.path), wide(final_path));
+                let flags = MOVEFILE_REPLACE_EXISTING | MOVEFILE_WRITE_THROUGH;
+                if unsafe { MoveFileExW(from.as_ptr(), to.as_ptr(), flags) } == 0 {
+                    return Err(Error::last_os());
+                }
+                Ok(())
+            }
+        }
+    })();
+    match renamed {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Allocates disk blocks for `[offset, offset + len)` of a writable handle's file, so writes
+/// to that range can't fail for lack of space later. On a full disk the first write to an
+/// unallocated page of a shared mapping raises SIGBUS (an in-page exception on Windows) and
+/// kills the process; this call reports it as an error instead, before JS touches the range.
+/// Uses fallocate (keeping the file size) on Linux, posix_fallocate on FreeBSD, and the file's
+/// allocation size on Windows, where non-sparse files are already fully allocated. macOS and
+/// sparse files on Windows fail with `MMAP_ERR_UNSUPPORTED`, as do file systems that can't
+/// preallocate.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_OS` with ENOSPC / ERROR_DISK_FULL from
+/// `mmap_last_os_error` if the space isn't there, `MMAP_ERR_READ_ONLY` for read-only handles,
+/// `MMAP_ERR_OUT_OF_BOUNDS` if the range exceeds the mapping).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_commit(h: *const MmapHandle, offset: usize, len: usize) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, view)| {
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        if len == 0 {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        check_range(offset, len, view.len)?;
+        let registry = registry::lock();
+        let Some(file) = h.mapping_file(&view, &registry) else {
+            return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+        };
+        let at = usize::try_from(h.file_offset)
+            .ok()
+            .and_then(|start| start.checked_add(offset))
+            .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+        crate::space::reserve(file, at, len).map(|()| 0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Makes a writable handle's file at least `len` bytes long and, if the mapping is shorter,
+/// remaps it to exactly `len` (pointers from `mmap_handle_ptr` are invalid afterwards).
+/// `mode` picks how the new length is backed:
+/// - `MMAP_PREALLOC_SPARSE`: only set the length (ftruncate / SetEndOfFile), as a writable
+///   open does; blocks are allocated, and can run out, as pages are first written.
+/// - `MMAP_PREALLOC_FULL`: allocate every block of the file up front, without writing zeros:
+///   fallocate on Linux (which also fills holes below the old size), posix_fallocate on
+///   FreeBSD, F_PREALLOCATE (contiguous if possible) on macOS, and the file's allocation size
+///   (FileAllocationInfo) on Windows. On macOS and Windows only the part past the old size is
+///   allocated; holes in a sparse file stay sparse.
+/// - `MMAP_PREALLOC_FULL_VALID_DATA`: `MMAP_PREALLOC_FULL`, and on Windows also
+///   SetFileValidData, so the first writes don't have to zero-fill up to them. That needs the
+///   SE_MANAGE_VOLUME_NAME privilege (silently skipped without it) and exposes whatever the
+///   disk held in the unwritten range to readers of the file: only use it on trusted files.
+///   Elsewhere the same as `MMAP_PREALLOC_FULL`.
+///
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_NO_SPACE` if the file system or quota can't
+/// hold `len` bytes, `MMAP_ERR_UNSUPPORTED` if it can't allocate ahead of writes,
+/// `MMAP_ERR_READ_ONLY` for read-only handles, `MMAP_ERR_INVALID_ARG` for `len == 0` or an
+/// unknown mode). When the space can't be allocated the file keeps its old length.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_preallocate(h: *const MmapHandle, len: u64, mode: i32) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        h.check_resizable()?;
+        let mode = match mode {
+            MMAP_PREALLOC_SPARSE => Prealloc::Sparse,
+            MMAP_PREALLOC_FULL => Prealloc::Full,
+            MMAP_PREALLOC_FULL_VALID_DATA => Prealloc::FullValidData,
+            _ => return Err(Error::new(MMAP_ERR_INVALID_ARG)),
+        };
+        let Ok(map_len) = usize::try_from(len) else {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        };
+        if map_len == 0 {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        {
+            let registry = registry::lock();
+            let Some(file) = h.mapping_file(&view, &registry) else {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            };
+            crate::space::preallocate(file, len, mode)?;
+        }
+        if map_len > view.len {
+            let (at, new_len, result) = unsafe { crate::resize_registered(view.base, map_len) };
+            // Growing leaves the mapping in place on failure, so `at` is always set.
+            if let Some(at) = at {
+                view.base = at;
+                view.len = new_len;
+            }
+            result?;
+        }
+        Ok(0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Checks that `[offset, offset + len)` is a non-empty range of a writable handle's mapping,
+/// returning it as `usize`s.
+fn writable_range(h: &Shared, view: &View, offset: u64, len: u64) -> Result<(usize, usize), Error> {
+    if !h.writable() {
+        return Err(Error::new(MMAP_ERR_READ_ONLY));
+    }
+    if len == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let (Ok(offset), Ok(len)) = (usize::try_from(offset), usize::try_from(len)) else {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    };
+    check_range(offset, len, view.len)?;
+    Ok((offset, len))
+}
+
+/// Returns the disk space behind `[offset, offset + len)` of a writable handle's file to the
+/// file system without changing the file size, e.g. to reclaim old segments of a ring buffer;
+/// the range reads back as zeros through the mapping afterwards. Uses fallocate with
+/// FALLOC_FL_PUNCH_HOLE on Linux, F_PUNCHHOLE on macOS (which only frees whole file-system
+/// blocks; the partial blocks at either end are zeroed instead) and FSCTL_SET_ZERO_DATA on
+/// Windows, which marks the file sparse first. Use `mmap_zero_range` where this is
+/// unsupported.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_UNSUPPORTED` if the platform or file system
+/// can't punch holes, `MMAP_ERR_READ_ONLY` for read-only handles, `MMAP_ERR_INVALID_ARG` for
+/// `len == 0`, `MMAP_ERR_OUT_OF_BOUNDS` if the range exceeds the mapping).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_punch_hole(h: *const MmapHandle, offset: u64, len: u64) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, view)| {
+        let (start, n) = writable_range(h, &view, offset, len)?;
+        {
+            let registry = registry::lock();
+            let Some(file) = h.mapping_file(&view, &registry) else {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            };
+            crate::space::punch_hole(file, h.file_offset + offset, len)?;
+        }
+        // Linux drops the range from the page cache, so the mapping already reads zeros.
+        if cfg!(not(any(target_os = "linux", target_os = "android"))) {
+            let range =
+                unsafe { std::slice::from_raw_parts_mut((view.base as *mut u8).add(start), n) };
+            if range.iter().any(|&b| b != 0) {
+                range.fill(0);
+                crate::dirty::record(view.base as *mut c_void, start, n);
+            }
+        }
+        Ok(0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Portable fallback for `mmap_punch_hole`: writes zeros over `[offset, offset + len)` through
+/// the mapping and flushes the range (see `mmap_flush`). The file keeps its blocks.
+/// Returns 0 on success, -1 on failure (as for `mmap_punch_hole`, or the flush error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_zero_range(h: *const MmapHandle, offset: u64, len: u64) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        let (offset, len) = writable_range(h, &view, offset, len)?;
+        let base = view.base as *mut c_void;
+        unsafe { ptr::write_bytes((view.base as *mut u8).add(offset), 0, len) };
+        crate::dirty::record(base, offset, len);
+        view.stats.bytes_written += len as u64;
+        view.stats.write_count += 1;
+        unsafe { crate::flush_range(base, offset, len)? };
+        view.stats.flush_count += 1;
+        Ok(0)
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Copies up to `len` bytes of the file open as `src_fd`, starting at `src_offset`, into a
+/// writable handle's mapping at `dst_offset`, e.g. to assemble an output file from pieces of
+/// others without passing the data through JS. On Linux the kernel copies file to file with
+/// copy_file_range (sharing blocks where the file system supports it), and the shared mapping
+/// sees the new contents through the page cache; where that fails (older kernels, copies
+/// across file systems, `src_fd` not a regular file) and on other Unix systems the data is
+/// pread straight into the mapping. `src_fd` is not closed, and its file position is unchanged.
+/// Returns the number of bytes copied, less than `len` if the source ends first, or -1 on
+/// failure (`MMAP_ERR_READ_ONLY` for read-only handles, `MMAP_ERR_INVALID_ARG` for a negative
+/// `src_fd`, `MMAP_ERR_OUT_OF_BOUNDS` if the destination range exceeds the mapping, or the OS
+/// error).
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_splice_in(
+    h: *const MmapHandle,
+    dst_offset: usize,
+    src_fd: i32,
+    src_offset: u64,
+    len: usize,
+) -> isize {
+    let result = unsafe { open_view(h) }.and_then(|(h, mut view)| {
+        if !h.writable() {
+            return Err(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        if src_fd < 0 {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        check_range(dst_offset, len, view.len)?;
+        let copied = unsafe { splice_in(h, &view, dst_offset, src_fd, src_offset, len)? };
+        crate::dirty::record(view.base as *mut c_void, dst_offset, copied);
+        view.stats.bytes_written += copied as u64;
+        view.stats.write_count += 1;
+        Ok(copied as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+#[cfg(unix)]
+unsafe fn splice_in(
+    h: &Shared,
+    view: &View,
+    dst_offset: usize,
+    src_fd: i32,
+    src_offset: u64,
+    len: usize,
+) -> Result<usize, Error> {
+    use std::os::fd::FromRawFd;
+    use std::os::unix::fs::FileExt;
+
+    let Ok(src_start) = i64::try_from(src_offset) else {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    };
+    let mut copied = 0;
+    // Only the kernel copy needs the file.
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = h;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::fd::AsRawFd;
+        let registry = registry::lock();
+        if let Some(dst) = h.mapping_file(view, &registry) {
+            let (mut off_in, mut off_out) = (src_start, (h.file_offset + dst_offset as u64) as i64);
+            while copied < len {
+                let n = unsafe {
+                    libc::copy_file_range(
+                        src_fd,
+                        &mut off_in,
+                        dst.as_raw_fd(),
+                        &mut off_out,
+                        len - copied,
+                        0,
+                    )
+                };
+                if n == 0 {
+                    return Ok(copied);
+                }
+                if n > 0 {
+                    copied += n as usize;
+                    continue;
+                }
+                let err = std::io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // Not supported for this pair of files: read the rest instead.
+                    Some(libc::EXDEV | libc::ENOSYS | libc::EINVAL | libc::EOPNOTSUPP) => break,
+                    _ => return Err(err.into()),
+                }
+            }
+        }
+    }
+    // Borrowed, not owned: the caller keeps `src_fd`.
+    let src = std::mem::ManuallyDrop::new(unsafe { File::from_raw_fd(src_fd) });
+    let dst =
+        unsafe { std::slice::from_raw_parts_mut((view.base as *mut u8).add(dst_offset), len) };
+    while copied < len {
+        match src.read_at(&mut dst[copied..], src_start as u64 + copied as u64) {
+            Ok(0) => break,
+            Ok(n) => copied += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(copied)
+}
+
+/// Writes the data extents of the handle's file into `out` as `(offset, length)` pairs of
+/// `u64`s (`out[2 * i]`, `out[2 * i + 1]`), ascending, at most `cap_pairs` of them; the gaps
+/// between them are holes (see `mmap_punch_hole`), which read as zeros and need not be copied.
+/// Uses SEEK_DATA / SEEK_HOLE on Unix and FSCTL_QUERY_ALLOCATED_RANGES on Windows; file
+/// systems without holes report one extent covering the whole file. Pages written through the
+/// mapping count as data once the file system has allocated them, which may take a flush.
+/// Returns the total number of extents, which may exceed `cap_pairs`; call again with a larger
+/// buffer to get them all. Returns `usize::MAX` on failure (`MMAP_ERR_INVALID_ARG` for a null
+/// handle, `MMAP_ERR_CLOSED`, or the OS error).
+///
+/// Safety: `out` must be null or writable for `2 * cap_pairs` values.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_extents(
+    h: *const MmapHandle,
+    out: *mut u64,
+    cap_pairs: usize,
+) -> usize {
+    let result = unsafe { open_view(h) }.and_then(|(h, _view)| {
+        let extents = crate::space::data_extents(&h.file)?;
+        if !out.is_null() {
+            for (i, &(offset, len)) in extents.iter().take(cap_pairs).enumerate() {
+                unsafe {
+                    *out.add(2 * i) = offset;
+                    *out.add(2 * i + 1) = len;
+                }
+            }
+        }
+        Ok(extents.len())
+    });
+    result.unwrap_or_else(|e| {
+        error::set(e);
+        usize::MAX
+    })
+}
+
+/// Seals a handle's memfd (see `mmap_memfd_create`), so that the kernel holds everyone, through
+/// every descriptor and mapping, to what may still be done to it: with `allow_write` false it
+/// can't be written (F_SEAL_WRITE), with `allow_grow` false it can't be extended (F_SEAL_GROW)
+/// and with `allow_shrink` false it can't be truncated (F_SEAL_SHRINK). Seals are never lifted;
+/// later calls can only add more. The kernel refuses to seal against writes while the memfd has
+/// a shared mapping that could be made writable (any made through a descriptor open for
+/// writing), so the handle's mapping is first remapped in place through a read-only descriptor
+/// from `/proc/self/fd`: the handle and its clones become read-only (`mmap_handle_write` then
+/// fails with `MMAP_ERR_READ_ONLY`, and writing through the pointer faults), and the call fails
+/// with the OS error (EBUSY) while a subview or any other such mapping of it remains.
+/// Returns 0, or -1 on failure: `MMAP_ERR_UNSUPPORTED` on other systems and for handles of
+/// anything but a memfd, `MMAP_ERR_CLOSED`, or the OS error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_seal(
+    h: *const MmapHandle,
+    allow_write: bool,
+    allow_grow: bool,
+    allow_shrink: bool,
+) -> i32 {
+    let result = unsafe { open_view(h) }.and_then(|(h, view)| {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                use std::os::fd::AsRawFd;
+                let fd = h.file.as_raw_fd();
+                // Files that can't be sealed fail F_GET_SEALS with EINVAL.
+                if unsafe { libc::fcntl(fd, libc::F_GET_SEALS) } < 0 {
+                    let e = Error::last_os();
+                    return Err(match e.os {
+                        libc::EINVAL => Error { code: MMAP_ERR_UNSUPPORTED, os: e.os },
+                        _ => e,
+                    });
+                }
+                let mut seals = 0;
+                if !allow_write {
+                    seals |= libc::F_SEAL_WRITE;
+                }
+                if !allow_grow {
+                    seals |= libc::F_SEAL_GROW;
+                }
+                if !allow_shrink {
+                    seals |= libc::F_SEAL_SHRINK;
+                }
+                let remap = |prot, fd| unsafe {
+                    let base = view.base as *mut c_void;
+                    let flags = libc::MAP_SHARED | libc::MAP_FIXED;
+                    crate::open::sys_mmap(base, view.len, prot, flags, fd, h.file_offset as libc::off_t)?;
+                    // Replacing the pages drops their mlock too.
+                    if let Some(m) = registry::lock().get_mut(&view.base) {
+                        m.writable = prot & libc::PROT_WRITE != 0;
+                        if m.locked {
+                            crate::lock::lock_range(base, view.len)?;
+                        }
+                        crate::lock::repin(&mut m.pinned, view.base, view.len);
+                    }
+                    Ok::<_, Error>(())
+                };
+                let read_only = !allow_write && h.writable();
+                if read_only {
+                    let ro = File::open(format!("/proc/self/fd/{fd}"))?;
+                    remap(libc::PROT_READ, ro.as_raw_fd())?;
+                }
+                if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } != 0 {
+                    let e = Error::last_os();
+                    if read_only && remap(libc::PROT_READ | libc::PROT_WRITE, fd).is_err() {
+                        h.writable.store(false, Ordering::Relaxed);
+                    }
+                    return Err(e);
+                }
+                if read_only {
+                    h.writable.store(false, Ordering::Relaxed);
+                }
+                Ok(0)
+            } else {
+                let _ = (h, view, allow_write, allow_grow, allow_shrink);
+                Err(Error::new(MMAP_ERR_UNSUPPORTED))
+            }
+        }
+    });
+    result.unwrap_or_else(error::fail)
+}
+
+/// Writes the path the handle was opened with into `out` as a NUL-terminated UTF-8 string,
+/// truncated to `cap` bytes, e.g. to name the file in an error message. Handles without a
+/// path (anonymous mappings such as the memfds of `mmap_memfd_create`) report an empty string.
+/// Still works after the handle is closed.
+/// Returns the full length of the path in bytes, without the NUL, so a call with a null `out`
+/// (or a too small `cap`) tells how large a buffer is needed; -1 if `h` is null.
+///
+/// Safety: `out` must be null or writable for `cap` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_path(
+    h: *const MmapHandle,
+    out: *mut c_char,
+    cap: usize,
+) -> isize {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    };
+    unsafe { error::write_c_str(&h.shared.path, out, cap) };
+    h.shared.path.len() as isize
+}
+
+/// Copies the handle's counters into `out`. Still works after the handle is closed, so totals
+/// can be collected at cleanup. Returns 0, or -1 if `h` or `out` is null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_stats(h: *const MmapHandle, out: *mut MmapStats) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if out.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    unsafe {
+        *out = h.view().stats;
+    }
+    0
+}
+
+/// Decides whether a handle's file outlives it: with `enabled != 0` the file is removed when
+/// the mapping is unmapped, i.e. when the last open clone (see `mmap_handle_clone`) is closed
+/// by `mmap_handle_close` or `mmap_handle_close_secure`; with `enabled == 0` it is kept, which
+/// is the default. The intent is shared by all clones and the last call wins, so a scratch file
+/// can be marked for deletion up front and kept after all once its contents turn out to be
+/// worth it. Deletion happens at close rather than now, on every platform, so the path keeps
+/// naming the file while it is mapped. On Unix the path the handle was opened with is unlinked,
+/// unless it names another file by then (see `mmap_is_stale`); on Windows the file is marked
+/// with SetFileInformationByHandle(FileDispositionInfo) and disappears once the last handle to
+/// it is freed. `mmap_commit_atomic` always keeps the file. The unnamed files of
+/// `mmap_open_tmp` have no handle and are always deleted, and the memfds of
+/// `mmap_memfd_create` have no path to remove: they go with their last reference either way.
+/// Removal at close is best effort: a file that can't be removed then (e.g. one another
+/// process holds open without sharing deletion, on Windows) stays.
+/// Returns 0 on success, -1 if the handle is null or closed (`MMAP_ERR_CLOSED`).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_set_delete_on_close(h: *const MmapHandle, enabled: i32) -> i32 {
+    match unsafe { open_view(h) } {
+        Ok((_, mut view)) => {
+            view.delete_on_close = enabled != 0;
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Cleans up after unmapping a handle's mapping: releases its locks (a subview's are its
+/// root's, which keep them) and removes the file if `mmap_set_delete_on_close` asked for it.
+fn unmapped(h: &Shared, view: &View) {
+    if h.root.is_none() {
+        crate::filelock::release_all(&h.file, &h.range_locks);
+    }
+    if !view.delete_on_close || h.path.is_empty() {
+        return;
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            if crate::stat::stat_path(&h.path).is_ok_and(|st| st.file_id == h.file_id) {
+                let _ = std::fs::remove_file(&h.path);
+            }
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::{AsRawHandle, FromRawHandle};
+            use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+            use windows_sys::Win32::Storage::FileSystem::{
+                DELETE, FILE_DISPOSITION_INFO, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE,
+                FileDispositionInfo, ReOpenFile, SetFileInformationByHandle,
+            };
+            // The handle's own descriptor doesn't have DELETE access; the mapping's, which
+            // doesn't share deletion, is closed by now.
+            let share = FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE;
+            let raw = unsafe { ReOpenFile(h.file.as_raw_handle(), DELETE, share, 0) };
+            if raw == INVALID_HANDLE_VALUE {
+                return;
+            }
+            let file = unsafe { File::from_raw_handle(raw) };
+            let info = FILE_DISPOSITION_INFO { DeleteFile: true };
+            unsafe {
+                SetFileInformationByHandle(
+                    file.as_raw_handle(),
+                    FileDispositionInfo,
+                    &info as *const _ as *const c_void,
+                    size_of::<FILE_DISPOSITION_INFO>() as u32,
+                )
+            };
+        }
+    }
+}
+
+/// Closes the handle, unmapping its mapping unless clones of it (see `mmap_handle_clone`) are
+/// still open; unmapping releases the locks taken with `mmap_lock_file` and `mmap_lock_range`.
+/// Idempotent: returns 0 on the call that actually closed the handle, `MMAP_ALREADY_CLOSED` on
+/// every later call, and -1 for a null handle.
+/// The handle itself stays allocated until `mmap_handle_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_close(h: *mut MmapHandle) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let mut view = h.view();
+    if h.closed.swap(true, Ordering::Relaxed) {
+        return MMAP_ALREADY_CLOSED;
+    }
+    if h.shared.open.fetch_sub(1, Ordering::Relaxed) == 1 {
+        unsafe {
+            crate::mmap_close(view.base as *mut c_void, view.len);
+        }
+        view.closed = true;
+        unmapped(&h.shared, &view);
+    }
+    0
+}
+
+/// Overwrites every byte of a writable handle's mapping with zeros, flushes it and unmaps it,
+/// so secrets held in it don't outlive the handle in the page cache (or in swap, for pages
+/// not pinned with `MMAP_LOCKED`). The zeros are written with volatile stores, so the wipe
+/// can't be optimized away even though nothing reads it afterwards. The file is zeroed too.
+/// Returns 0 once wiped and unmapped, `MMAP_CLOSED_UNWIPED` if the mapping was read-only and
+/// was just unmapped, or if other clones (see `mmap_handle_clone`) still have it open and it
+/// was left alone, `MMAP_ALREADY_CLOSED` if the handle was closed already, or -1 for a null
+/// handle or if the flush fails; the handle then stays open (and wiped) so the call can be
+/// retried.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_close_secure(h: *mut MmapHandle) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let mut view = h.view();
+    if h.closed.load(Ordering::Relaxed) {
+        return MMAP_ALREADY_CLOSED;
+    }
+    if h.shared.open.load(Ordering::Relaxed) > 1 {
+        // Other clones still use the mapping: only this handle closes.
+        h.closed.store(true, Ordering::Relaxed);
+        h.shared.open.fetch_sub(1, Ordering::Relaxed);
+        return MMAP_CLOSED_UNWIPED;
+    }
+    let base = view.base as *mut c_void;
+    let rc = if h.shared.writable() {
+        unsafe {
+            wipe(base as *mut u8, view.len);
+            if let Err(e) = crate::flush_range(base, 0, view.len) {
+                return error::fail(e);
+            }
+        }
+        0
+    } else {
+        MMAP_CLOSED_UNWIPED
+    };
+    unsafe {
+        crate::mmap_close(base, view.len);
+    }
+    view.closed = true;
+    unmapped(&h.shared, &view);
+    h.closed.store(true, Ordering::Relaxed);
+    h.shared.open.fetch_sub(1, Ordering::Relaxed);
+    rc
+}
+
+/// Zeroes `[p, p + len)` with volatile stores, a word at a time.
+pub(crate) unsafe fn wipe(p: *mut u8, len: usize) {
+    let word = size_of::<usize>();
+    let head = p.align_offset(word).min(len);
+    let words = (len - head) / word;
+    unsafe {
+        for i in 0..head {
+            ptr::write_volatile(p.add(i), 0);
+        }
+        let w = p.add(head) as *mut usize;
+        for i in 0..words {
+            ptr::write_volatile(w.add(i), 0);
+        }
+        for i in head + words * word..len {
+            ptr::write_volatile(p.add(i), 0);
+        }
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Closes the handle if it is still open and releases it.
+///
+/// Safety: `h` must come from `mmap_handle_open*` and must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_handle_free(h: *mut MmapHandle) {
+    if h.is_null() {
+        return;
+    }
+    unsafe {
+        mmap_handle_close(h);
+        drop(Box::from_raw(h));
+    }
+}
@@ -0,0 +1,126 @@
+// Test-only introspection, compiled in with the `test-hooks` feature.
+//
+// Records the sequence of flush steps taken on this thread so tests can assert that a
+// durable flush really reaches the file-level flush, which can't be observed portably, lets
+// tests treat ordinary files as DAX so the pmem flush path runs without pmem hardware, can
+// corrupt the bytes `mmap_flush_verify` reads back to prove mismatches are caught, can
+// crash `mmap_commit_atomic` halfway to prove the target survives, and looks at the pages of
+// a `mmap_open_secret` mapping just before `mmap_close` unmaps them, which nothing else can.
+
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+thread_local! {
+    static TRACE: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+    /// Length and non-zero bytes of the last secret mapping closed on this thread.
+    static WIPED: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+static FORCE_DAX: AtomicBool = AtomicBool::new(false);
+static CRASH_BEFORE_RENAME: AtomicBool = AtomicBool::new(false);
+/// Mapping offset whose read-back byte `mmap_flush_verify` flips; `usize::MAX` = none.
+static CORRUPT_VERIFY: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Whether tests asked to treat every mapping as DAX. Always false without `test-hooks`.
+#[inline]
+pub(crate) fn force_dax() -> bool {
+    cfg!(feature = "test-hooks") && FORCE_DAX.load(Ordering::Relaxed)
+}
+
+/// Whether tests asked `mmap_commit_atomic` to abort the process between closing the handle
+/// and renaming its file. Always false without `test-hooks`.
+#[inline]
+pub(crate) fn crash_before_rename() -> bool {
+    cfg!(feature = "test-hooks") && CRASH_BEFORE_RENAME.load(Ordering::Relaxed)
+}
+
+/// The mapping offset tests asked `mmap_flush_verify` to corrupt. Always `None` without
+/// `test-hooks`.
+#[inline]
+pub(crate) fn corrupt_verify() -> Option<usize> {
+    if !cfg!(feature = "test-hooks") {
+        return None;
+    }
+    Some(CORRUPT_VERIFY.load(Ordering::Relaxed)).filter(|&off| off != usize::MAX)
+}
+
+/// Appends `step` to this thread's trace. A no-op unless built with `test-hooks`.
+#[inline]
+pub(crate) fn trace(step: &'static str) {
+    if cfg!(feature = "test-hooks") {
+        TRACE.with(|t| t.borrow_mut().push(step));
+    }
+}
+
+/// Counts the bytes of `[p, p + len)` that aren't zero, for `mmap_test_take_wipe`. Called by
+/// `mmap_close` on a secret mapping after wiping it. A no-op unless built with `test-hooks`.
+///
+/// Safety: the range must be readable.
+#[inline]
+pub(crate) unsafe fn inspect_wipe(p: *const u8, len: usize) {
+    if cfg!(feature = "test-hooks") {
+        let bytes = unsafe { core::slice::from_raw_parts(p, len) };
+        let nonzero = bytes.iter().filter(|&&b| b != 0).count();
+        WIPED.with(|w| w.set(Some((len, nonzero))));
+    }
+}
+
+/// Writes this thread's recorded steps as a comma-separated, NUL-terminated string into
+/// `out` (truncated to `cap` bytes), clears the trace and returns the bytes written.
+#[cfg(feature = "test-hooks")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_test_take_trace(out: *mut std::os::raw::c_char, cap: usize) -> usize {
+    let steps = TRACE
+        .with(|t| std::mem::take(&mut *t.borrow_mut()))
+        .join(",");
+    if out.is_null() || cap == 0 {
+        return 0;
+    }
+    let n = steps.len().min(cap - 1);
+    unsafe {
+        core::ptr::copy_nonoverlapping(steps.as_ptr(), out as *mut u8, n);
+        *out.add(n) = 0;
+    }
+    n
+}
+
+/// Makes `mmap_flush_pmem` and `mmap_persist` treat every file as DAX (`on != 0`) or detect
+/// it normally.
+#[cfg(feature = "test-hooks")]
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_test_force_dax(on: i32) {
+    FORCE_DAX.store(on != 0, Ordering::Relaxed);
+}
+
+/// Makes `mmap_flush_verify` flip the byte it reads back from the file at mapping offset
+/// `offset`, as if the disk held different data there; a negative `offset` turns it off.
+#[cfg(feature = "test-hooks")]
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_test_corrupt_verify(offset: i64) {
+    let off = usize::try_from(offset).unwrap_or(usize::MAX);
+    CORRUPT_VERIFY.store(off, Ordering::Relaxed);
+}
+
+/// Makes `mmap_commit_atomic` abort the process (`on != 0`) once the file is durable and the
+/// handle closed, just before the rename, as a crash there would.
+#[cfg(feature = "test-hooks")]
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_test_crash_before_rename(on: i32) {
+    CRASH_BEFORE_RENAME.store(on != 0, Ordering::Relaxed);
+}
+
+/// Reports the last `mmap_open_secret` mapping `mmap_close` unmapped on this thread, as its
+/// pages were right before the unmap: writes its length to `len_out` (if non-null) and returns
+/// how many of its bytes weren't zero, then forgets it. -1 if none was closed since the last
+/// call.
+#[cfg(feature = "test-hooks")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_test_take_wipe(len_out: *mut usize) -> isize {
+    let Some((len, nonzero)) = WIPED.with(Cell::take) else {
+        return -1;
+    };
+    if !len_out.is_null() {
+        unsafe { *len_out = len };
+    }
+    nonzero as isize
+}
@@ -1,272 +1,753 @@
 #![allow(non_snake_case)]
+#![allow(clippy::missing_safety_doc)]
 
 use std::ffi::CStr;
 use std::os::raw::{c_char, c_void};
 use std::ptr;
+use std::sync::Arc;
+
+mod advise;
+mod autoflush;
+mod binary;
+mod checkpoint;
+mod copy;
+#[cfg(feature = "debug-registry")]
+mod debug;
+mod dirty;
+mod error;
+mod fence;
+mod filelock;
+mod flushq;
+mod fsinfo;
+mod growth;
+mod handle;
+mod hooks;
+mod lock;
+mod log;
+mod open;
+mod pmem;
+mod prefetch;
+mod procstats;
+mod registry;
+mod reserve;
+mod resident;
+pub mod safe;
+mod secret;
+mod softdirty;
+mod space;
+mod stat;
+mod sync;
+mod text;
+mod verify;
+mod version;
+mod window;
+
+pub use advise::*;
+pub use autoflush::*;
+pub use binary::*;
+pub use checkpoint::*;
+pub use copy::*;
+#[cfg(feature = "debug-registry")]
+pub use debug::*;
+pub use dirty::*;
+pub use error::*;
+pub use fence::*;
+pub use filelock::*;
+pub use flushq::*;
+pub use fsinfo::*;
+pub use growth::*;
+pub use handle::*;
+#[cfg(feature = "test-hooks")]
+pub use hooks::*;
+pub use lock::*;
+pub use log::*;
+pub use open::{
+    MMAP_ALLOW_DEVICE, MMAP_DIRECT, MMAP_EXEC, MMAP_EXEC_CONFIRM, MMAP_LOCAL_ONLY,
+    MMAP_LOCK_BEST_EFFORT, MMAP_LOCKED, MMAP_NORESERVE, MMAP_PREFAULT,
+};
+pub use pmem::*;
+pub use procstats::*;
+pub use reserve::*;
+pub use resident::*;
+pub use secret::*;
+pub use softdirty::*;
+pub use stat::*;
+pub use text::*;
+pub use verify::*;
+pub use version::*;
+pub use window::*;
+
+use error::Error;
+use open::OpenSpec;
+use registry::{Kind, Mapping, Origin};
 
 cfg_if::cfg_if! {
     if #[cfg(unix)] {
-        use libc::{open, close, lseek, mmap, munmap, PROT_READ, MAP_PRIVATE, SEEK_END};
+        use libc::munmap;
     } else if #[cfg(windows)] {
-        use windows_sys::Win32::Foundation::{HANDLE, INVALID_HANDLE_VALUE, CloseHandle};
-        use windows_sys::Win32::System::Memory::{
-            CreateFileMappingA, MapViewOfFile, UnmapViewOfFile,
-            FILE_MAP_READ, PAGE_READONLY, MEMORY_MAPPED_VIEW_ADDRESS
-        };
-        use windows_sys::Win32::Storage::FileSystem::{
-            CreateFileA, GetFileSizeEx, OPEN_EXISTING, FILE_SHARE_READ,
-            FILE_ATTRIBUTE_NORMAL, FILE_GENERIC_READ, OPEN_ALWAYS
-        };
+        use windows_sys::Win32::System::Memory::{UnmapViewOfFile, MEMORY_MAPPED_VIEW_ADDRESS};
+    }
+}
 
+/// Size of a virtual memory page on this system.
+pub(crate) fn page_size() -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+            unsafe {
+                let mut info: SYSTEM_INFO = core::mem::zeroed();
+                GetSystemInfo(&mut info);
+                info.dwPageSize as usize
+            }
+        }
     }
 }
 
-/// Opens a file and maps it into memory for read-only access.
-/// Returns a pointer to the mapped memory, or null on failure.
-/// The file length is written to `len_out`.
-///
-/// Safety: The returned pointer is valid until `mmap_close` is called.
-/// Do not access it after closing.
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn mmap_open(path: *const c_char, len_out: *mut usize) -> *mut c_void {
+/// Alignment of mapping offsets: the page size on Unix, the allocation granularity (usually
+/// 64 KiB) on Windows.
+pub(crate) fn map_granularity() -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            page_size()
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+            unsafe {
+                let mut info: SYSTEM_INFO = core::mem::zeroed();
+                GetSystemInfo(&mut info);
+                info.dwAllocationGranularity as usize
+            }
+        }
+    }
+}
+
+/// Validates `path`, maps it per `spec` and records the mapping in the registry.
+pub(crate) unsafe fn open_registered(
+    path: *const c_char,
+    spec: &OpenSpec,
+    snapshot: bool,
+) -> Result<open::Mapped, Error> {
     unsafe {
-        if path.is_null() || len_out.is_null() {
-            return ptr::null_mut();
+        if path.is_null() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        let c_path = CStr::from_ptr(path);
+        if c_path.to_str().is_err() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
         }
 
-        let c_path = match CStr::from_ptr(path).to_str() {
-            Ok(s) => s,
-            Err(_) => return ptr::null_mut(),
+        let m = if snapshot {
+            open::snapshot(c_path, 0)?
+        } else {
+            open::open_mapping(c_path, spec)?
         };
+        let origin = Origin::new(m.kind, spec.write, Some(c_path.to_bytes()));
+        register(m, spec.flags, origin)
+    }
+}
 
-        cfg_if::cfg_if! {
-                if #[cfg(unix)] {
-                    let fd = open(c_path.as_ptr() as *const i8, libc::O_RDONLY);
-                    if fd < 0 {
-                        return ptr::null_mut();
-                    }
-
-                    let size = lseek(fd, 0, SEEK_END);
-                    if size < 0 {
-                        close(fd);
-                        return ptr::null_mut();
-                    }
-                    *len_out = size as usize;
-
-                    let addr = mmap(
-                        ptr::null_mut(),
-                        *len_out,
-                        PROT_READ,
-                        MAP_PRIVATE,
-                        fd,
-                        0
-                    );
-
-                    close(fd);
-
-                    if addr == libc::MAP_FAILED {
-                        return ptr::null_mut();
+/// Applies post-map `flags` (`MMAP_LOCKED`) to a fresh mapping and records it in the registry.
+pub(crate) unsafe fn register(
+    mut m: open::Mapped,
+    flags: u32,
+    origin: Origin,
+) -> Result<open::Mapped, Error> {
+    unsafe {
+        let mut locked = false;
+        if flags & MMAP_LOCKED != 0 {
+            match lock::lock_range(m.ptr, m.len) {
+                Ok(()) => {
+                    error::set(Error::new(MMAP_OK));
+                    locked = true;
+                }
+                Err(e) => {
+                    let e = Error {
+                        code: MMAP_ERR_LOCK_FAILED,
+                        os: e.os,
+                    };
+                    if flags & MMAP_LOCK_BEST_EFFORT == 0 {
+                        unmap(m.ptr, m.len, m.kind);
+                        return Err(e);
                     }
-
-                    addr
-                } else if #[cfg(windows)] {
-            // Convert Rust &str -> null-terminated C string
-            let c_path_bytes = std::ffi::CString::new(c_path).unwrap();
-
-            // Open file
-            let h_file: HANDLE = CreateFileA(
-                c_path_bytes.as_ptr() as *const u8,
-                FILE_GENERIC_READ,
-                FILE_SHARE_READ,
-                ptr::null_mut(),
-                OPEN_EXISTING,
-                FILE_ATTRIBUTE_NORMAL,
-                ptr::null_mut(),
-            );
-
-            if h_file == INVALID_HANDLE_VALUE {
-                return ptr::null_mut();
+                    error::set(e);
+                }
             }
+        }
+        let writable = m.kind == Kind::File && m.file.as_ref().is_some_and(open::writable);
+        registry::insert(
+            m.ptr as usize,
+            Mapping {
+                len: m.len,
+                kind: m.kind,
+                locked,
+                pinned: Vec::new(),
+                file: m.file.take().map(Arc::new),
+                dirty: Default::default(),
+                high_water: m.content_len,
+                autoflush: None,
+                sync: m.sync,
+                writable,
+                secret: false,
+                origin,
+            },
+        );
+        Ok(m)
+    }
+}
 
-            let mut size: i64 = 0;
-            if GetFileSizeEx(h_file, &mut size) == 0 {
-                CloseHandle(h_file);
-                return ptr::null_mut();
+/// Shared body of the pointer-returning open variants.
+/// On failure returns null and records the reason for `mmap_last_error`.
+unsafe fn open_into(
+    path: *const c_char,
+    len_out: *mut usize,
+    spec: OpenSpec,
+    snapshot: bool,
+) -> *mut c_void {
+    unsafe {
+        if len_out.is_null() {
+            error::set(Error::new(MMAP_ERR_INVALID_ARG));
+            return ptr::null_mut();
+        }
+        match open_registered(path, &spec, snapshot) {
+            Ok(m) => {
+                *len_out = m.len;
+                m.ptr
             }
-            *len_out = size as usize;
-
-            // Create file mapping
-            let h_map: HANDLE = CreateFileMappingA(
-                h_file,
-                ptr::null_mut(),
-                PAGE_READONLY,
-                0,
-                0,
-                ptr::null(),
-            );
-            CloseHandle(h_file);
-
-            if h_map.is_null() {
-                return ptr::null_mut();
+            Err(e) => {
+                error::set(e);
+                ptr::null_mut()
             }
+        }
+    }
+}
 
-            // Map view
-            let addr: MEMORY_MAPPED_VIEW_ADDRESS = MapViewOfFile(
-                h_map,
-                FILE_MAP_READ,
-                0,
-                0,
-                0,
-            );
-            CloseHandle(h_map);
-
-            if addr.Value.is_null() {
-                return ptr::null_mut();
-            }
+/// `open_into` for mappings of a descriptor / handle the caller already has open.
+unsafe fn adopt_into(
+    len_out: *mut usize,
+    spec: OpenSpec,
+    open: impl FnOnce(&OpenSpec) -> Result<open::Mapped, Error>,
+) -> *mut c_void {
+    if len_out.is_null() {
+        error::set(Error::new(MMAP_ERR_INVALID_ARG));
+        return ptr::null_mut();
+    }
+    let registered = open(&spec).and_then(|m| {
+        let origin = Origin::new(m.kind, spec.write, None);
+        unsafe { register(m, spec.flags, origin) }
+    });
+    match registered {
+        Ok(m) => {
+            unsafe { *len_out = m.len };
+            m.ptr
+        }
+        Err(e) => {
+            error::set(e);
+            ptr::null_mut()
+        }
+    }
+}
 
-            addr.Value
+/// The spec for `mmap_open_fd` / `mmap_open_handle`.
+fn adopt_spec(len: usize, writable: bool) -> OpenSpec {
+    if writable {
+        OpenSpec::write(len, 0)
+    } else {
+        OpenSpec {
+            size: len,
+            ..OpenSpec::read(0)
         }
-            }
     }
 }
 
-/// Unmaps a previously mapped file.
+/// Maps the file already open as `fd` (e.g. one handed over by Deno), without reopening it by
+/// path, so unnamed files (O_TMPFILE, unlinked) work and the file can't be swapped in between.
+/// With `len == 0` the whole file is mapped (its size from fstat); otherwise a read-only map
+/// covers at most `len` bytes and a writable one maps `len` bytes, extending the file first
+/// if it is shorter.
+/// `fd` must be opened for reading, and for writing too if `writable`. The mapping keeps its
+/// own duplicate of `fd`, so the caller may close `fd` right away; `mmap_close` as usual.
+/// On failure returns null; the reason is available from `mmap_last_error`.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_fd(
+    fd: i32,
+    len: usize,
+    writable: bool,
+    len_out: *mut usize,
+) -> *mut c_void {
+    unsafe {
+        adopt_into(len_out, adopt_spec(len, writable), |spec| {
+            open::open_fd(fd, spec)
+        })
+    }
+}
+
+/// `mmap_open_fd` for Windows: maps the file already open as `handle` (a raw `HANDLE`), sized
+/// from GetFileSizeEx when `len` is 0. The handle needs GENERIC_READ, and GENERIC_WRITE too
+/// if `writable`; the mapping keeps its own duplicate, so the caller may close it right away.
+/// On failure returns null; the reason is available from `mmap_last_error`.
+#[cfg(windows)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_handle(
+    handle: isize,
+    len: usize,
+    writable: bool,
+    len_out: *mut usize,
+) -> *mut c_void {
+    unsafe {
+        adopt_into(len_out, adopt_spec(len, writable), |spec| {
+            open::open_handle(handle as windows_sys::Win32::Foundation::HANDLE, spec)
+        })
+    }
+}
+
+/// Opens a file and maps it into memory for read-only access.
+/// Returns a pointer to the mapped memory, or null on failure.
+/// The file length is written to `len_out`.
 ///
-/// Safety: `ptr` must be a pointer returned by `mmap_open`
-/// with the same `length` provided by that call.
+/// Safety: The returned pointer is valid until `mmap_close` is called.
+/// Do not access it after closing.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn mmap_close(ptr: *mut c_void, _length: usize) {
+pub unsafe extern "C" fn mmap_open(path: *const c_char, len_out: *mut usize) -> *mut c_void {
+    unsafe { mmap_open_ex(path, len_out, 0) }
+}
+
+/// Like `mmap_open`, with `MMAP_*` open flags (e.g. `MMAP_ALLOW_DEVICE`).
+/// On failure returns null; the reason is available from `mmap_last_error`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_ex(
+    path: *const c_char,
+    len_out: *mut usize,
+    flags: u32,
+) -> *mut c_void {
     unsafe {
-        if ptr.is_null() {
-            return;
-        }
+        let spec = OpenSpec::read(flags);
+        open_into(path, len_out, spec, false)
+    }
+}
 
-        cfg_if::cfg_if! {
-            if #[cfg(unix)] {
-                munmap(ptr, _length);
-            } else if #[cfg(windows)] {
-                let view_addr = MEMORY_MAPPED_VIEW_ADDRESS { Value: ptr };
-                UnmapViewOfFile(view_addr);
+/// Creates an anonymous temporary file of `size` bytes and maps it read-write, as scratch
+/// space that the OS can page out under memory pressure but that never outlives the mapping.
+/// The file has no name (O_TMPFILE on Linux, mkstemp + unlink on other Unixes,
+/// FILE_FLAG_DELETE_ON_CLOSE on Windows), and `mmap_close` releases its disk space.
+/// On failure returns null; the reason is available from `mmap_last_error`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_tmp(size: usize, len_out: *mut usize) -> *mut c_void {
+    let result = if len_out.is_null() || size == 0 {
+        Err(Error::new(MMAP_ERR_INVALID_ARG))
+    } else {
+        unsafe {
+            open::open_tmp(size).and_then(|m| register(m, 0, Origin::new(Kind::File, true, None)))
+        }
+    };
+    match result {
+        Ok(m) => {
+            unsafe {
+                *len_out = m.len;
             }
+            m.ptr
+        }
+        Err(e) => {
+            error::set(e);
+            ptr::null_mut()
         }
     }
 }
 
+/// Maps only the first `min(file size, max_len)` bytes of `path` read-only, e.g. to sniff a
+/// file format from its header without mapping a huge file. The mapped length is written to
+/// `len_out`. Zero-sized pseudo-files are snapshotted up to `max_len`; an empty file fails with
+/// `MMAP_ERR_EMPTY`, and `max_len == 0` with `MMAP_ERR_INVALID_ARG`.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn mmap_open_write(path: *const c_char, len_out: *mut usize) -> *mut c_void {
+pub unsafe extern "C" fn mmap_open_head(
+    path: *const c_char,
+    max_len: usize,
+    len_out: *mut usize,
+) -> *mut c_void {
     unsafe {
-        if path.is_null() || len_out.is_null() {
+        if max_len == 0 {
+            error::set(Error::new(MMAP_ERR_INVALID_ARG));
             return ptr::null_mut();
         }
-
-        let c_path = match CStr::from_ptr(path).to_str() {
-            Ok(s) => s,
-            Err(_) => return ptr::null_mut(),
+        let spec = OpenSpec {
+            size: max_len,
+            ..OpenSpec::read(0)
         };
+        open_into(path, len_out, spec, false)
+    }
+}
 
-        cfg_if::cfg_if! {
-            if #[cfg(unix)] {
-                use libc::{ftruncate, O_RDWR, O_CREAT};
-
-                let fd = open(c_path.as_ptr() as *const i8, O_RDWR | O_CREAT, 0o644);
-                if fd < 0 {
-                    return ptr::null_mut();
-                }
+/// Like `mmap_open`, but refuses files (or devices) longer than `max_bytes` with
+/// `MMAP_ERR_TOO_LARGE` instead of mapping them, as a guard against untrusted paths that point
+/// at huge sparse files. `max_bytes == 0` fails with `MMAP_ERR_INVALID_ARG`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_capped(
+    path: *const c_char,
+    max_bytes: usize,
+    len_out: *mut usize,
+) -> *mut c_void {
+    unsafe {
+        if max_bytes == 0 {
+            error::set(Error::new(MMAP_ERR_INVALID_ARG));
+            return ptr::null_mut();
+        }
+        let spec = OpenSpec {
+            max_len: max_bytes,
+            ..OpenSpec::read(0)
+        };
+        open_into(path, len_out, spec, false)
+    }
+}
 
-                // If file is empty, pre-allocate 1MB by default (can adjust)
-                let size = lseek(fd, 0, SEEK_END);
-                let target_size = if size == 0 { 1024 * 1024 } else { size as usize };
+/// Maps `path` read-only but shared (MAP_SHARED / a PAGE_READONLY section opened with
+/// FILE_SHARE_WRITE), so writes made to the file by other processes or mappings are visible
+/// through the returned pointer. Unlike `mmap_open`, zero-sized files fail with `MMAP_ERR_EMPTY`.
+/// On failure returns null; the reason is available from `mmap_last_error`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_shared_ro(
+    path: *const c_char,
+    len_out: *mut usize,
+) -> *mut c_void {
+    unsafe {
+        let spec = OpenSpec {
+            shared: true,
+            ..OpenSpec::read(0)
+        };
+        open_into(path, len_out, spec, false)
+    }
+}
 
-                if ftruncate(fd, target_size as i64) != 0 {
-                    close(fd);
-                    return ptr::null_mut();
-                }
+/// Like `mmap_open`, for a single forward scan: the mapping is advised sequential before it is
+/// returned (MADV_SEQUENTIAL on Unix; on Windows, which has no such hint, the whole range is
+/// prefetched with PrefetchVirtualMemory instead). See "Performance notes" in the README.
+/// On failure returns null; the reason is available from `mmap_last_error`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_sequential(
+    path: *const c_char,
+    len_out: *mut usize,
+) -> *mut c_void {
+    unsafe {
+        let p = open_into(path, len_out, OpenSpec::read(0), false);
+        if !p.is_null() {
+            advise::advise_sequential(p, *len_out);
+        }
+        p
+    }
+}
 
-                *len_out = target_size;
+/// Copies the current content of the file at `path` into private anonymous memory and
+/// returns it as a read-only mapping, for pseudo-files (e.g. under /proc or /sys) that
+/// report a size of 0 but produce data when read. `mmap_open` falls back to this
+/// automatically for zero-sized regular files.
+///
+/// The snapshot does not track later changes to the file; `mmap_flush` on it is a no-op
+/// and `mmap_close` releases the anonymous memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_snapshot(
+    path: *const c_char,
+    len_out: *mut usize,
+) -> *mut c_void {
+    unsafe {
+        let spec = OpenSpec::read(0);
+        open_into(path, len_out, spec, true)
+    }
+}
 
-                let addr = mmap(
-                    ptr::null_mut(),
-                    *len_out,
-                    libc::PROT_READ | libc::PROT_WRITE,
-                    libc::MAP_SHARED,
-                    fd,
-                    0
-                );
+/// Unmaps a previously mapped file. Does nothing for null or `(void*)-1` (`MAP_FAILED`), so
+/// the result of a failed open can be passed straight through.
+///
+/// Safety: `ptr` must be a pointer returned by `mmap_open`
+/// with the same `length` provided by that call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_close(ptr: *mut c_void, _length: usize) {
+    unsafe {
+        if ptr.is_null() || ptr as usize == usize::MAX {
+            return;
+        }
 
-                close(fd);
+        prefetch::cancel_and_join(ptr as usize, _length);
+        flushq::forget(ptr as usize);
 
-                if addr == libc::MAP_FAILED {
-                    return ptr::null_mut();
+        match registry::remove(ptr as usize) {
+            Some(m) => {
+                if m.secret {
+                    handle::wipe(ptr as *mut u8, m.len);
+                    hooks::inspect_wipe(ptr as *const u8, m.len);
                 }
-
-                addr
-            } else if #[cfg(windows)] {
-                use windows_sys::Win32::Storage::FileSystem::{SetFilePointerEx, SetEndOfFile, FILE_GENERIC_WRITE};
-
-                let c_path_bytes = std::ffi::CString::new(c_path).unwrap();
-                let h_file: HANDLE = CreateFileA(
-                    c_path_bytes.as_ptr() as *const u8,
-                    FILE_GENERIC_READ | FILE_GENERIC_WRITE,
-                    FILE_SHARE_READ,
-                    ptr::null_mut(),
-                    OPEN_ALWAYS, // Create if not exists
-                    FILE_ATTRIBUTE_NORMAL,
-                    ptr::null_mut(),
-                );
-
-                if h_file == INVALID_HANDLE_VALUE {
-                    return ptr::null_mut();
+                if m.locked {
+                    lock::unlock_range(ptr, m.len);
+                }
+                lock::unpin_all(&m.pinned, ptr as usize);
+                if !reserve::release(ptr as usize) {
+                    unmap(ptr, m.len, m.kind);
                 }
+            }
+            None => unmap(ptr, _length, Kind::File),
+        }
+    }
+}
 
-                // Determine file size
-                let mut size: i64 = 0;
-                GetFileSizeEx(h_file, &mut size);
-                let target_size = if size == 0 { 1024 * 1024 } else { size as usize };
+/// Stops the library's background work: the auto-flush thread flushes every auto-flush
+/// mapping one last time and exits (auto-flush is then disabled everywhere), and the flush
+/// queue worker runs the flushes still queued and exits; their tickets stay pollable.
+/// Mappings stay open and usable, and later calls start the threads again as needed.
+/// Call this before unloading the library (e.g. `Deno.DynamicLibrary.close()`), which must not
+/// happen while these threads run.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_shutdown() {
+    autoflush::shutdown();
+    flushq::shutdown();
+}
 
-                // Expand if needed
-                let pos: i64 = target_size as i64;
-                if SetFilePointerEx(h_file, pos, ptr::null_mut(), 0) == 0 || SetEndOfFile(h_file) == 0 {
-                    CloseHandle(h_file);
-                    return ptr::null_mut();
-                }
+/// Grows the registered file mapping at `base` to at least `needed` bytes (per the growth
+/// policy), remapping it. Returns the new base and length; the old base is no longer valid.
+pub(crate) unsafe fn grow_registered(base: usize, needed: usize) -> Result<(usize, usize), Error> {
+    let mut registry = registry::lock();
+    let Some(m) = registry.get(&base) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let Some(file) = m.file.as_ref() else {
+        return Err(Error::new(MMAP_ERR_READ_ONLY));
+    };
+    let new_len = growth::grown_size(m.len, needed);
+    unsafe {
+        let new_base = open::map_grown(file, new_len)?;
+        prefetch::cancel_and_join(base, m.len);
+        let mut m = registry.remove(&base).expect("entry checked above");
+        if m.locked {
+            lock::unlock_range(base as *mut c_void, m.len);
+            m.locked = lock::lock_range(new_base, new_len).is_ok();
+        }
+        lock::unpin_all(&m.pinned, base);
+        lock::repin(&mut m.pinned, new_base as usize, new_len);
+        unmap(base as *mut c_void, m.len, m.kind);
+        m.len = new_len;
+        registry.insert(new_base as usize, m);
+        Ok((new_base as usize, new_len))
+    }
+}
 
-                *len_out = target_size;
+/// Replaces the registered file mapping at `base` with a mapping of the first `new_len` bytes
+/// of the mapped file, which must be that long already: read-write through the retained file,
+/// or read-only through `read_only` (another descriptor on the same file) if given. The new
+/// mapping is created before the old one is unmapped. Returns the new base; the old base is no
+/// longer valid.
+pub(crate) unsafe fn remap_registered(
+    base: usize,
+    new_len: usize,
+    read_only: Option<&std::fs::File>,
+) -> Result<usize, Error> {
+    let mut registry = registry::lock();
+    let Some(m) = registry.get(&base) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let (file, write) = match (read_only, m.file.as_deref()) {
+        (Some(file), _) => (file, false),
+        (None, Some(file)) => (file, true),
+        (None, None) => return Err(Error::new(MMAP_ERR_READ_ONLY)),
+    };
+    unsafe {
+        let new_base = open::map_view(file, new_len, write)?;
+        prefetch::cancel_and_join(base, m.len);
+        let mut m = registry.remove(&base).expect("entry checked above");
+        if m.locked {
+            lock::unlock_range(base as *mut c_void, m.len);
+            m.locked = lock::lock_range(new_base, new_len).is_ok();
+        }
+        lock::unpin_all(&m.pinned, base);
+        lock::repin(&mut m.pinned, new_base as usize, new_len);
+        unmap(base as *mut c_void, m.len, m.kind);
+        m.len = new_len;
+        m.kind = Kind::File;
+        registry.insert(new_base as usize, m);
+        Ok(new_base as usize)
+    }
+}
 
-                let h_map: HANDLE = CreateFileMappingA(
-                    h_file,
-                    ptr::null_mut(),
-                    windows_sys::Win32::System::Memory::PAGE_READWRITE,
-                    0,
-                    0,
-                    ptr::null(),
-                );
-                CloseHandle(h_file);
+/// Resizes the writable file mapping at `base` to `new_size` bytes: the file is extended or
+/// truncated to exactly `new_size`, and the mapping follows it (mremap on Linux, which can
+/// often grow in place; elsewhere a new mapping replaces the old one). The mapping may move
+/// either way, so its base and length are written to `out_new_base` / `out_new_len` (if
+/// non-null), also on failure, where they describe the mapping as it was left: on Windows a
+/// failed shrink can move it, or lose it (null base, length 0).
+///
+/// The registry entry stays locked for the whole resize and is then moved to the new base, so
+/// library calls still passing the old base fail with `MMAP_ERR_INVALID_ARG` instead of
+/// touching unmapped memory. Raw pointers into the old mapping are invalid. Shrinking
+/// discards the data past `new_size`; on Unix the file is truncated before the mapping shrinks.
+/// Mappings owned by an `MmapHandle` must be grown with `mmap_ensure_capacity` instead.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base
+/// or `new_size` is 0, `MMAP_ERR_READ_ONLY` for read-only mappings and snapshots,
+/// `MMAP_ERR_OS` if resizing the file or the mapping fails).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_resize(
+    base: *mut c_void,
+    new_size: usize,
+    out_new_base: *mut *mut c_void,
+    out_new_len: *mut usize,
+) -> i32 {
+    let report = |base: *mut c_void, len: usize| unsafe {
+        if !out_new_base.is_null() {
+            *out_new_base = base;
+        }
+        if !out_new_len.is_null() {
+            *out_new_len = len;
+        }
+    };
+    if registry::lookup(base as usize).is_none() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let (at, len, result) = unsafe { resize_registered(base as usize, new_size) };
+    report(at.map_or(ptr::null_mut(), |at| at as *mut c_void), len);
+    match result {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
 
-                if h_map.is_null() {
-                    return ptr::null_mut();
+/// `mmap_resize` for the registered file mapping at `base`. Returns where the mapping is left
+/// (`None` if it was lost) and its length, with the outcome.
+pub(crate) unsafe fn resize_registered(
+    base: usize,
+    new_size: usize,
+) -> (Option<usize>, usize, Result<(), Error>) {
+    let mut registry = registry::lock();
+    let Some(m) = registry.get(&base) else {
+        return (Some(base), 0, Err(Error::new(MMAP_ERR_INVALID_ARG)));
+    };
+    let old_len = m.len;
+    if new_size == 0 {
+        return (Some(base), old_len, Err(Error::new(MMAP_ERR_INVALID_ARG)));
+    }
+    if reserve::is_reserved(base) {
+        return (Some(base), old_len, Err(Error::new(MMAP_ERR_UNSUPPORTED)));
+    }
+    let Some(file) = m.file.as_ref() else {
+        return (Some(base), old_len, Err(Error::new(MMAP_ERR_READ_ONLY)));
+    };
+    unsafe {
+        prefetch::cancel_and_join(base, old_len);
+        if m.locked {
+            lock::unlock_range(base as *mut c_void, old_len);
+        }
+        lock::unpin_all(&m.pinned, base);
+        let (at, len, result) = match open::remap(file, base as *mut c_void, old_len, new_size) {
+            Ok(new_base) => (Some(new_base), new_size, Ok(())),
+            Err((e, at)) => (at, old_len, Err(e)),
+        };
+        let mut m = registry.remove(&base).expect("entry checked above");
+        match at {
+            Some(at) => {
+                m.len = len;
+                m.dirty.truncate(len);
+                m.high_water = m.high_water.min(len);
+                if m.locked {
+                    m.locked = lock::lock_range(at, len).is_ok();
                 }
+                lock::repin(&mut m.pinned, at as usize, len);
+                registry.insert(at as usize, m);
+                (Some(at as usize), len, result)
+            }
+            None => {
+                flushq::forget(base);
+                (None, 0, result)
+            }
+        }
+    }
+}
 
-                let addr: MEMORY_MAPPED_VIEW_ADDRESS = MapViewOfFile(
-                    h_map,
-                    windows_sys::Win32::System::Memory::FILE_MAP_WRITE,
-                    0,
-                    0,
-                    0,
-                );
-                CloseHandle(h_map);
-
-                if addr.Value.is_null() {
-                    return ptr::null_mut();
-                }
+/// Sets the length of the file behind the writable mapping at `base` to `new_len` bytes
+/// without touching the mapping, e.g. to trim preallocated space before closing. Growing is
+/// always allowed. Shrinking below the mapped length is rejected, since touching mapped pages
+/// past the new end of the file raises SIGBUS (and Windows can't truncate a mapped file at all);
+/// shrink the mapping first with `mmap_resize`.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base,
+/// `MMAP_ERR_READ_ONLY` for read-only mappings and snapshots, `MMAP_ERR_MAPPED_LARGER` if
+/// `new_len` is below the mapped length, `MMAP_ERR_OS` if the OS refuses).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_truncate(base: *mut c_void, new_len: u64) -> i32 {
+    let registry = registry::lock();
+    let Some(m) = registry.get(&(base as usize)) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let file = match m.file.as_ref() {
+        Some(file) if open::writable(file) => file,
+        _ => return error::fail(Error::new(MMAP_ERR_READ_ONLY)),
+    };
+    if new_len < m.len as u64 {
+        return error::fail(Error::new(MMAP_ERR_MAPPED_LARGER));
+    }
+    match file.set_len(new_len) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e.into()),
+    }
+}
+
+/// Flushes and unmaps the writable mapping at `base`, then truncates its file to the mapping's
+/// high-water mark, so the zero padding left by preallocating opens doesn't reach the output.
+/// The mark is the end of the file's content when it was opened, pushed out by every write
+/// through the library (`mmap_write`, `mmap_writev`, `mmap_copy_between`, handle writes) and
+/// by `mmap_mark_dirty`, which is how stores through a raw pointer must be reported to count.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base,
+/// `MMAP_ERR_READ_ONLY` for read-only mappings and snapshots, or the OS error). If the flush
+/// fails the mapping stays open; if only the truncate fails it has been unmapped regardless.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_close_trim(base: *mut c_void) -> i32 {
+    let (len, high_water, file) = {
+        let registry = registry::lock();
+        let Some(m) = registry.get(&(base as usize)) else {
+            return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+        };
+        let file = match m.file.as_ref() {
+            Some(file) if open::writable(file) => file.try_clone(),
+            _ => return error::fail(Error::new(MMAP_ERR_READ_ONLY)),
+        };
+        match file {
+            Ok(file) => (m.len, m.high_water, file),
+            Err(e) => return error::fail(e.into()),
+        }
+    };
+    unsafe {
+        if let Err(e) = flush_range(base, 0, len) {
+            return error::fail(e);
+        }
+        mmap_close(base, len);
+    }
+    match file.set_len(high_water as u64) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e.into()),
+    }
+}
 
-                addr.Value
+/// Releases a mapping created by `open::open_mapping` or `open::snapshot`.
+unsafe fn unmap(ptr: *mut c_void, len: usize, kind: Kind) {
+    unsafe {
+        if kind == Kind::Snapshot {
+            open::anon_free(ptr, len);
+            return;
+        }
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                munmap(ptr, len);
+            } else if #[cfg(windows)] {
+                let _ = len;
+                let view_addr = MEMORY_MAPPED_VIEW_ADDRESS { Value: ptr };
+                UnmapViewOfFile(view_addr);
             }
         }
     }
 }
 
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_write(path: *const c_char, len_out: *mut usize) -> *mut c_void {
+    unsafe { mmap_open_write_ex(path, len_out, 0, 0) }
+}
+
 /// Write `len` bytes from `src_ptr` into (dst_ptr + offset).
-/// Returns number of bytes written, or 0 on invalid args.
+/// Returns number of bytes written, or 0 if `len` is 0 or a pointer is null (recording
+/// `MMAP_ERR_INVALID_ARG`).
+/// The range is recorded for `mmap_flush_dirty`.
 /// Safety: caller must ensure mapping is large enough for [offset, offset+len).
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn mmap_write(
@@ -276,17 +757,66 @@ pub unsafe extern "C" fn mmap_write(
     len: usize,
 ) -> usize {
     unsafe {
-        if dst_ptr.is_null() || src_ptr.is_null() || len == 0 {
+        if dst_ptr.is_null() || src_ptr.is_null() {
+            error::set(Error::new(MMAP_ERR_INVALID_ARG));
+            return 0;
+        }
+        if len == 0 {
             return 0;
         }
         let dst = (dst_ptr as *mut u8).add(offset);
         core::ptr::copy_nonoverlapping(src_ptr, dst, len);
+        dirty::record(dst_ptr, offset, len);
         len
     }
 }
 
+/// One source buffer for `mmap_writev`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MmapIoVec {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+/// Copies the `count` buffers of `iov` back to back into (dst + offset), e.g. a record header
+/// followed by its payload, in one FFI call.
+/// Returns the total number of bytes written. Stops at the first buffer whose pointer is null,
+/// returning the bytes written so far; returns 0 if `dst` or `iov` is null (recording
+/// `MMAP_ERR_INVALID_ARG`).
+///
+/// Safety: `iov` must point to `count` readable `MmapIoVec`s, each describing a readable
+/// buffer, and the mapping must be large enough for `offset` plus the sum of their lengths.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_writev(
+    dst: *mut c_void,
+    offset: usize,
+    iov: *const MmapIoVec,
+    count: usize,
+) -> usize {
+    if dst.is_null() || iov.is_null() {
+        error::set(Error::new(MMAP_ERR_INVALID_ARG));
+        return 0;
+    }
+    let iov = unsafe { std::slice::from_raw_parts(iov, count) };
+    let mut written = 0;
+    for v in iov {
+        if v.ptr.is_null() {
+            break;
+        }
+        unsafe {
+            let at = (dst as *mut u8).add(offset + written);
+            core::ptr::copy_nonoverlapping(v.ptr, at, v.len);
+        }
+        written += v.len;
+    }
+    dirty::record(dst, offset, written);
+    written
+}
+
 /// Copies `len` bytes from (src_base + offset) into `dst_ptr`.
-/// Returns number of bytes copied (len) or 0 on invalid args.
+/// Returns number of bytes copied (len), or 0 if `len` is 0 or a pointer is null (recording
+/// `MMAP_ERR_INVALID_ARG`).
 ///
 /// Safety:
 /// - `src_base` must be a valid pointer returned by mmap_open / mmap_open_write(_with_size).
@@ -300,7 +830,11 @@ pub unsafe extern "C" fn mmap_read(
     len: usize,
 ) -> usize {
     unsafe {
-        if dst_ptr.is_null() || src_base.is_null() || len == 0 {
+        if dst_ptr.is_null() || src_base.is_null() {
+            error::set(Error::new(MMAP_ERR_INVALID_ARG));
+            return 0;
+        }
+        if len == 0 {
             return 0;
         }
         let src = (src_base as *const u8).add(offset);
@@ -309,32 +843,226 @@ pub unsafe extern "C" fn mmap_read(
     }
 }
 
+/// Copies `len` bytes from (src_base + src_offset) into (dst_base + dst_offset), e.g. to
+/// merge a region of one mapping into another without bouncing through a JS buffer.
+/// Returns number of bytes copied (len), or 0 if `len` is 0 or a pointer is null (recording
+/// `MMAP_ERR_INVALID_ARG`).
+///
+/// Safety:
+/// - Both ranges must lie within their mappings, and `dst_base` must be writable.
+/// - The two ranges must not overlap; passing the same base for both is undefined behavior.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_copy_between(
+    dst_base: *mut c_void,
+    dst_offset: usize,
+    src_base: *const c_void,
+    src_offset: usize,
+    len: usize,
+) -> usize {
+    unsafe {
+        if dst_base.is_null() || src_base.is_null() {
+            error::set(Error::new(MMAP_ERR_INVALID_ARG));
+            return 0;
+        }
+        if len == 0 {
+            return 0;
+        }
+        let src = (src_base as *const u8).add(src_offset);
+        let dst = (dst_base as *mut u8).add(dst_offset);
+        core::ptr::copy_nonoverlapping(src, dst, len);
+        dirty::record(dst_base, dst_offset, len);
+        len
+    }
+}
+
+/// Writes the pages covering `[base + offset, base + offset + len)` back to the file and
+/// waits for completion. Ranges of registered mappings are checked against their bounds;
+/// snapshots have nothing to write.
+pub(crate) unsafe fn flush_range(
+    base: *mut c_void,
+    offset: usize,
+    len: usize,
+) -> Result<(), Error> {
+    if base.is_null() || len == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let end = offset
+        .checked_add(len)
+        .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+    match registry::lookup(base as usize) {
+        Some((total, _)) if end > total => return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+        Some((_, Kind::Snapshot)) => return Ok(()),
+        _ => {}
+    }
+    unsafe { sync_view(base as usize + offset, len) }
+}
+
+/// msync / FlushViewOfFile for `[addr, addr + len)`, without any registry checks.
+pub(crate) unsafe fn sync_view(addr: usize, len: usize) -> Result<(), Error> {
+    // msync needs a page-aligned address: flush the pages covering the range.
+    let start = addr & !(page_size() - 1);
+    let len = addr + len - start;
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let ok = unsafe { libc::msync(start as *mut c_void, len, libc::MS_SYNC) } == 0;
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::FlushViewOfFile;
+            let ok = unsafe { FlushViewOfFile(start as *const c_void, len) } != 0;
+        }
+    }
+    if ok { Ok(()) } else { Err(Error::last_os()) }
+}
+
+/// Writes modified pages covering `[base_ptr + offset, base_ptr + offset + len)` back to the
+/// file and waits for completion. `offset` need not be page-aligned.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_OUT_OF_BOUNDS` if the range exceeds the
+/// mapping).
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn mmap_flush(
     base_ptr: *mut core::ffi::c_void,
     offset: usize,
     len: usize,
+) -> i32 {
+    match unsafe { flush_range(base_ptr, offset, len) } {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Flushes the whole mapping starting at `base`, using the length recorded when it was opened.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_all(base: *mut c_void) -> i32 {
+    let Some((len, _)) = registry::lookup(base as usize) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    match unsafe { flush_range(base, 0, len) } {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// A byte range `[offset, offset + len)` within a mapping.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MmapRange {
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Flushes each of `count` ranges of the mapping at `base` in order (see `mmap_flush`), so a
+/// transaction touching scattered regions can be flushed in one FFI call.
+/// Returns -1 if every range was flushed, otherwise the index of the first range that failed
+/// (later ranges are not attempted; the OS error is in `mmap_last_os_error`).
+/// Returns -2 if `base` is null, or `ranges` is null while `count > 0`.
+///
+/// Safety: `ranges` must point to `count` readable `MmapRange`s, each within the mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_ranges(
+    base: *mut c_void,
+    ranges: *const MmapRange,
+    count: usize,
+) -> isize {
+    if base.is_null() || (ranges.is_null() && count > 0) {
+        error::set(Error::new(MMAP_ERR_INVALID_ARG));
+        return -2;
+    }
+    if count == 0 {
+        return -1;
+    }
+    let ranges = unsafe { core::slice::from_raw_parts(ranges, count) };
+    for (i, r) in ranges.iter().enumerate() {
+        if r.len > 0
+            && let Err(e) = unsafe { flush_range(base, r.offset, r.len) }
+        {
+            error::set(e);
+            return i as isize;
+        }
+    }
+    -1
+}
+
+/// Checks that `[base + offset, base + offset + len)` is safe to access: it must lie within
+/// the mapping starting at `base`, and (on Unix) the file must still be long enough to back
+/// every page of it. Returns 0 if so, or -1 with `MMAP_ERR_OUT_OF_BOUNDS` /
+/// `MMAP_ERR_TRUNCATED` (or `MMAP_ERR_INVALID_ARG` if `base` is not a mapping base).
+///
+/// On Unix, touching a page of a shared or private file mapping that lies wholly past the
+/// end of the file raises SIGBUS, which kills the whole process; this happens when another
+/// process truncates a file while it is mapped. Probe before reading files that may shrink.
+/// The check is inherently racy against a concurrent truncate, so it narrows rather than
+/// closes the window. Windows does not allow truncating a mapped file.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_probe(base: *const c_void, offset: usize, len: usize) -> i32 {
+    let registry = registry::lock();
+    let Some(m) = registry.get(&(base as usize)) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let end = match offset.checked_add(len) {
+        Some(end) if end <= m.len => end,
+        _ => return error::fail(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+    };
+    let Some(file) = m.file.as_ref() else {
+        return 0;
+    };
+    match file.metadata() {
+        // Block devices report a size of 0 and can't shrink.
+        Ok(meta) if !meta.is_file() => 0,
+        Ok(meta) => {
+            let backed = (meta.len() as usize).next_multiple_of(page_size());
+            if end <= backed {
+                0
+            } else {
+                error::fail(Error::new(MMAP_ERR_TRUNCATED))
+            }
+        }
+        Err(e) => error::fail(e.into()),
+    }
+}
+
+/// Like `mmap_flush`, then also flushes the file itself to stable storage (fsync, F_FULLFSYNC on
+/// macOS, FlushFileBuffers on Windows), so the data survives a power loss. On Windows
+/// `mmap_flush` alone only reaches the file system cache.
+///
+/// `base_ptr` must be the base of a writable mapping for the file-level step; read-only
+/// mappings and snapshots have nothing to flush there. Returns 0 on success, or -1 with
+/// `MMAP_ERR_FLUSH_VIEW` / `MMAP_ERR_FLUSH_FILE` identifying the step that failed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_durable(
+    base_ptr: *mut c_void,
+    offset: usize,
+    len: usize,
 ) -> i32 {
     unsafe {
         if base_ptr.is_null() || len == 0 {
-            return -1;
-        }
-        #[cfg(unix)]
-        {
-            use libc::{MS_SYNC, msync};
-            let p = (base_ptr as *mut u8).add(offset) as *mut core::ffi::c_void;
-            let rc = msync(p, len, MS_SYNC);
-            return if rc == 0 { 0 } else { -1 };
+            return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
         }
-        #[cfg(windows)]
-        {
-            use core::ffi::c_void;
-            use windows_sys::Win32::System::Memory::FlushViewOfFile;
-            let p = (base_ptr as *const u8).add(offset) as *const c_void;
-            let ok = FlushViewOfFile(p, len);
-            return if ok != 0 { 0 } else { -1 };
+        hooks::trace("flush_view");
+        if let Err(e) = flush_range(base_ptr, offset, len) {
+            // Bad arguments keep their own code; only a failed write-back is FLUSH_VIEW.
+            let code = if e.code == MMAP_ERR_OS {
+                MMAP_ERR_FLUSH_VIEW
+            } else {
+                e.code
+            };
+            return error::fail(Error { code, os: e.os });
         }
     }
+    let registry = registry::lock();
+    let Some(file) = registry
+        .get(&(base_ptr as usize))
+        .and_then(|m| m.file.as_ref())
+    else {
+        return 0;
+    };
+    hooks::trace("flush_file");
+    match sync::sync_file(file, sync::SyncMode::Full) {
+        Ok(()) => 0,
+        Err(e) => error::fail(Error {
+            code: MMAP_ERR_FLUSH_FILE,
+            os: e.raw_os_error().unwrap_or(0),
+        }),
+    }
 }
 
 /// Open (or create) a file and map it read-write, ensuring file size >= `size` if `size > 0`.
@@ -345,120 +1073,20 @@ pub unsafe extern "C" fn mmap_open_write_with_size(
     len_out: *mut usize,
     size: usize,
 ) -> *mut core::ffi::c_void {
-    unsafe {
-        use core::ptr;
-        if path.is_null() || len_out.is_null() {
-            return ptr::null_mut();
-        }
-
-        let c_path = match core::ffi::CStr::from_ptr(path).to_str() {
-            Ok(s) => s,
-            Err(_) => return ptr::null_mut(),
-        };
-
-        #[cfg(unix)]
-        {
-            use libc::{
-                MAP_SHARED, O_CREAT, O_RDWR, PROT_READ, PROT_WRITE, SEEK_END, close, ftruncate,
-                lseek, mmap, open,
-            };
-            let fd = open(c_path.as_ptr() as *const i8, O_RDWR | O_CREAT, 0o644);
-            if fd < 0 {
-                return ptr::null_mut();
-            }
-
-            let cur = lseek(fd, 0, SEEK_END);
-            if cur < 0 {
-                close(fd);
-                return ptr::null_mut();
-            }
-
-            let target = if size > 0 { size } else { cur as usize };
-
-            let target = if target == 0 { 1024 * 1024 } else { target };
-
-            if (cur as usize) < target {
-                if ftruncate(fd, target as i64) != 0 {
-                    close(fd);
-                    return ptr::null_mut();
-                }
-            }
-            *len_out = target;
-
-            let addr = mmap(
-                ptr::null_mut(),
-                *len_out,
-                PROT_READ | PROT_WRITE,
-                MAP_SHARED,
-                fd,
-                0,
-            );
-            close(fd);
-            if addr == libc::MAP_FAILED {
-                return ptr::null_mut();
-            }
-            addr
-        }
-
-        #[cfg(windows)]
-        {
-            use core::ptr;
-            use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
-            use windows_sys::Win32::Storage::FileSystem::{
-                CreateFileA, FILE_ATTRIBUTE_NORMAL, FILE_GENERIC_READ, FILE_GENERIC_WRITE,
-                FILE_SHARE_READ, GetFileSizeEx, OPEN_ALWAYS, SetEndOfFile, SetFilePointerEx,
-            };
-            use windows_sys::Win32::System::Memory::{
-                CreateFileMappingA, FILE_MAP_WRITE, MEMORY_MAPPED_VIEW_ADDRESS, MapViewOfFile,
-                PAGE_READWRITE,
-            };
-
-            let c_path_bytes = std::ffi::CString::new(c_path).unwrap();
-            let h_file: HANDLE = CreateFileA(
-                c_path_bytes.as_ptr() as *const u8,
-                FILE_GENERIC_READ | FILE_GENERIC_WRITE,
-                FILE_SHARE_READ,
-                ptr::null_mut(),
-                OPEN_ALWAYS,
-                FILE_ATTRIBUTE_NORMAL,
-                ptr::null_mut(),
-            );
-            if h_file == INVALID_HANDLE_VALUE {
-                return ptr::null_mut();
-            }
-
-            let mut cur: i64 = 0;
-            let _ = GetFileSizeEx(h_file, &mut cur);
-
-            let mut target = if size > 0 { size as i64 } else { cur };
-            if target == 0 {
-                target = 1024 * 1024;
-            } // default 1 MiB
-
-            if cur < target {
-                // extend file to target
-                if SetFilePointerEx(h_file, target, ptr::null_mut(), 0) == 0
-                    || SetEndOfFile(h_file) == 0
-                {
-                    CloseHandle(h_file);
-                    return ptr::null_mut();
-                }
-            }
-            *len_out = target as usize;
-
-            let h_map: HANDLE =
-                CreateFileMappingA(h_file, ptr::null_mut(), PAGE_READWRITE, 0, 0, ptr::null());
-            CloseHandle(h_file);
-            if h_map.is_null() {
-                return ptr::null_mut();
-            }
+    unsafe { mmap_open_write_ex(path, len_out, size, 0) }
+}
 
-            let addr: MEMORY_MAPPED_VIEW_ADDRESS = MapViewOfFile(h_map, FILE_MAP_WRITE, 0, 0, 0);
-            CloseHandle(h_map);
-            if addr.Value.is_null() {
-                return ptr::null_mut();
-            }
-            addr.Value
-        }
+/// Like `mmap_open_write_with_size`, with `MMAP_*` open flags.
+/// On failure returns null; the reason is available from `mmap_last_error`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_write_ex(
+    path: *const c_char,
+    len_out: *mut usize,
+    size: usize,
+    flags: u32,
+) -> *mut c_void {
+    unsafe {
+        let spec = OpenSpec::write(size, flags);
+        open_into(path, len_out, spec, false)
     }
 }
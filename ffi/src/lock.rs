@@ -0,0 +1,225 @@
+// Pinning mapped pages in RAM: whole mappings for `MMAP_LOCKED` opens, and ranges of them with
+// `mmap_lock_pages`.
+
+use std::ops::Range;
+use std::os::raw::c_void;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_ERR_QUOTA};
+use crate::registry;
+
+/// Locks `[ptr, ptr + len)` into physical memory.
+///
+/// Unix uses mlock rather than MAP_LOCKED: MAP_LOCKED silently leaves pages unlocked when the
+/// RLIMIT_MEMLOCK quota is exceeded, while mlock reports it. On Windows a quota failure grows
+/// the working set by `len` and retries once.
+pub(crate) unsafe fn lock_range(ptr: *mut c_void, len: usize) -> Result<(), Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            if unsafe { libc::mlock(ptr, len) } != 0 {
+                return Err(Error::last_os());
+            }
+            Ok(())
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::Foundation::{ERROR_WORKING_SET_QUOTA, GetLastError};
+            use windows_sys::Win32::System::Memory::{SetProcessWorkingSetSizeEx, VirtualLock};
+            use windows_sys::Win32::System::Threading::{GetCurrentProcess, GetProcessWorkingSetSize};
+            unsafe {
+                if VirtualLock(ptr, len) != 0 {
+                    return Ok(());
+                }
+                if GetLastError() != ERROR_WORKING_SET_QUOTA {
+                    return Err(Error::last_os());
+                }
+                let process = GetCurrentProcess();
+                let (mut min, mut max) = (0usize, 0usize);
+                if GetProcessWorkingSetSize(process, &mut min, &mut max) == 0
+                    || SetProcessWorkingSetSizeEx(process, min + len, max + len, 0) == 0
+                    || VirtualLock(ptr, len) == 0
+                {
+                    return Err(Error::last_os());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Undoes `lock_range`; called by `mmap_close` before unmapping.
+pub(crate) unsafe fn unlock_range(ptr: *mut c_void, len: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            unsafe {
+                libc::munlock(ptr, len);
+            }
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::VirtualUnlock;
+            unsafe {
+                VirtualUnlock(ptr, len);
+            }
+        }
+    }
+}
+
+/// Pins `[offset, offset + len)` of the mapping at `base` in RAM (mlock / VirtualLock), e.g. a
+/// latency-critical index in the middle of a larger mapping, so that reading it never waits on
+/// the disk. The range is widened to whole pages. Pins don't nest: a page is pinned once however
+/// many ranges cover it, and one `mmap_unlock_pages` over it releases it. `mmap_close` unpins
+/// whatever is still pinned, and the pins move along when the mapping is remapped (by
+/// `mmap_resize`, `mmap_ensure_capacity`, ...), dropping those past its new end or that the OS
+/// won't pin again. A mapping opened with `MMAP_LOCKED` is pinned whole already; both calls leave
+/// it so.
+///
+/// Returns 0, or -1 on failure: `MMAP_ERR_QUOTA` if the pages would exceed RLIMIT_MEMLOCK (or,
+/// on Windows, the working-set quota even after raising it by `len`), with the limit in
+/// `mmap_last_error_message`; `MMAP_ERR_INVALID_ARG` for a `base` that isn't a mapping base or a
+/// `len` of 0, `MMAP_ERR_OUT_OF_BOUNDS` for a range past the end of the mapping, or the OS error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_lock_pages(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    match lock_pages(base as usize, offset, len) {
+        Ok(()) => 0,
+        Err(e) if e.code == MMAP_ERR_QUOTA => error::fail_with(e, quota_detail()),
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Unpins the pages of `[offset, offset + len)` (widened to whole pages) pinned with
+/// `mmap_lock_pages`, so the OS may page them out again. Pages in the range that aren't pinned
+/// are left alone. Returns 0, including when nothing in the range was pinned, or -1 on failure
+/// (`MMAP_ERR_INVALID_ARG`, `MMAP_ERR_OUT_OF_BOUNDS`, as `mmap_lock_pages`).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_unlock_pages(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    let base = base as usize;
+    let mut registry = registry::lock();
+    let Some(m) = registry.get_mut(&base) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let span = match page_span(m.len, offset, len) {
+        Ok(span) => span,
+        Err(e) => return error::fail(e),
+    };
+    for r in unpin(&mut m.pinned, &span) {
+        unsafe { unlock_range((base + r.start) as *mut c_void, r.len()) };
+    }
+    0
+}
+
+fn lock_pages(base: usize, offset: usize, len: usize) -> Result<(), Error> {
+    let mut registry = registry::lock();
+    let Some(m) = registry.get_mut(&base) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let span = page_span(m.len, offset, len)?;
+    if m.locked {
+        return Ok(());
+    }
+    if let Err(mut e) = unsafe { lock_range((base + span.start) as *mut c_void, span.len()) } {
+        if is_quota_error(e.os) {
+            e.code = MMAP_ERR_QUOTA;
+        }
+        return Err(e);
+    }
+    pin(&mut m.pinned, span);
+    Ok(())
+}
+
+/// `[offset, offset + len)` of a mapping of `total` bytes, widened to whole pages.
+fn page_span(total: usize, offset: usize, len: usize) -> Result<Range<usize>, Error> {
+    if len == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let end = match offset.checked_add(len) {
+        Some(end) if end <= total => end,
+        _ => return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+    };
+    let page = crate::page_size();
+    Ok(offset & !(page - 1)..end.next_multiple_of(page))
+}
+
+/// Adds `r` to the sorted, disjoint ranges of `set`, merged with those it overlaps or touches.
+fn pin(set: &mut Vec<Range<usize>>, mut r: Range<usize>) {
+    set.retain(|p| {
+        if p.start > r.end || p.end < r.start {
+            return true;
+        }
+        r = r.start.min(p.start)..r.end.max(p.end);
+        false
+    });
+    let at = set.partition_point(|p| p.start < r.start);
+    set.insert(at, r);
+}
+
+/// Removes `r` from `set`, returning the parts of it that were in it.
+fn unpin(set: &mut Vec<Range<usize>>, r: &Range<usize>) -> Vec<Range<usize>> {
+    let mut removed = Vec::new();
+    let mut kept = Vec::with_capacity(set.len() + 1);
+    for p in set.drain(..) {
+        let (start, end) = (p.start.max(r.start), p.end.min(r.end));
+        if start >= end {
+            kept.push(p);
+            continue;
+        }
+        removed.push(start..end);
+        if p.start < start {
+            kept.push(p.start..start);
+        }
+        if end < p.end {
+            kept.push(end..p.end);
+        }
+    }
+    *set = kept;
+    removed
+}
+
+/// Unpins the `pinned` ranges of the mapping at `base`, before it is unmapped or remapped.
+pub(crate) unsafe fn unpin_all(pinned: &[Range<usize>], base: usize) {
+    for r in pinned {
+        unsafe { unlock_range((base + r.start) as *mut c_void, r.len()) };
+    }
+}
+
+/// Pins the `pinned` ranges again in the mapping of `len` bytes now at `base`, dropping those
+/// past its end and those the OS refuses.
+pub(crate) unsafe fn repin(pinned: &mut Vec<Range<usize>>, base: usize, len: usize) {
+    let end = len.next_multiple_of(crate::page_size());
+    pinned.retain_mut(|r| {
+        r.end = r.end.min(end);
+        r.start < r.end && unsafe { lock_range((base + r.start) as *mut c_void, r.len()) }.is_ok()
+    });
+}
+
+/// Whether a failed mlock / VirtualLock hit the limit on locked memory.
+fn is_quota_error(os: i32) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            // EPERM: an unprivileged process with a limit of 0.
+            os == libc::ENOMEM || os == libc::EAGAIN || os == libc::EPERM
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::Foundation::{ERROR_NOT_ENOUGH_QUOTA, ERROR_WORKING_SET_QUOTA};
+            let os = os as u32;
+            os == ERROR_WORKING_SET_QUOTA || os == ERROR_NOT_ENOUGH_QUOTA
+        }
+    }
+}
+
+/// The limit behind an `MMAP_ERR_QUOTA`, for `mmap_last_error_message`.
+fn quota_detail() -> String {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let mut limit: libc::rlimit = unsafe { core::mem::zeroed() };
+            if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+                return "the limit on locked memory was reached".into();
+            }
+            match limit.rlim_cur {
+                libc::RLIM_INFINITY => "the limit on locked memory (RLIMIT_MEMLOCK unlimited) was reached".into(),
+                n => format!("the limit on locked memory (RLIMIT_MEMLOCK {n} bytes) was reached"),
+            }
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Threading::{GetCurrentProcess, GetProcessWorkingSetSize};
+            let (mut min, mut max) = (0usize, 0usize);
+            if unsafe { GetProcessWorkingSetSize(GetCurrentProcess(), &mut min, &mut max) } == 0 {
+                return "the working-set quota was reached".into();
+            }
+            format!("the working-set quota (minimum {min} bytes, maximum {max} bytes) was reached")
+        }
+    }
+}
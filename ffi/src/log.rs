@@ -0,0 +1,274 @@
+// Append-only logs: a file holding a small header with the committed length, followed by the
+// records appended so far, addressed by 64-bit ids instead of raw pointers.
+//
+// `mmap_log_append` copies a record past the committed end and only then publishes the new
+// length with a release store, so a writer killed mid-append leaves the header pointing at the
+// last complete record; `mmap_log_open` resumes from there and ignores the bytes past it. The
+// mapping is shared, so appended records survive the writer process dying without a flush
+// (not a power loss: use `mmap_log_close` or `mmap_flush_durable` for that).
+//
+// The header layout is part of the ABI: logs written by one version of the library must reopen
+// with the next.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_OK};
+use crate::open::OpenSpec;
+
+/// `MmapLogHeader::magic`: "MMLG" in file order.
+pub const MMAP_LOG_MAGIC: u32 = u32::from_le_bytes(*b"MMLG");
+/// `MmapLogHeader::version` of the layout below.
+pub const MMAP_LOG_VERSION: u32 = 1;
+/// Bytes reserved for the header at offset 0; records start right after it.
+pub const MMAP_LOG_HEADER_SIZE: usize = 64;
+/// Returned by the `u64` log functions on failure (see `mmap_last_error`).
+pub const MMAP_LOG_ERROR: u64 = u64::MAX;
+
+/// The log header at offset 0 of the file, in host byte order. Record offsets are relative to
+/// the end of the header.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MmapLogHeader {
+    pub magic: u32,
+    pub version: u32,
+    /// Bytes of records that were completely appended.
+    pub committed_len: u64,
+}
+
+const _: () = assert!(size_of::<MmapLogHeader>() <= MMAP_LOG_HEADER_SIZE);
+
+struct Log {
+    /// The log's registered mapping, which moves when an append grows the file.
+    base: usize,
+    len: usize,
+}
+
+impl Log {
+    fn header(&self) -> *mut MmapLogHeader {
+        self.base as *mut MmapLogHeader
+    }
+
+    fn committed(&self) -> &AtomicU64 {
+        unsafe { AtomicU64::from_ptr(&raw mut (*self.header()).committed_len) }
+    }
+
+    fn data(&self) -> *mut u8 {
+        (self.base + MMAP_LOG_HEADER_SIZE) as *mut u8
+    }
+}
+
+struct Logs {
+    next_id: u64,
+    open: BTreeMap<u64, Arc<Mutex<Log>>>,
+}
+
+static LOGS: Mutex<Logs> = Mutex::new(Logs {
+    next_id: 1,
+    open: BTreeMap::new(),
+});
+
+fn logs() -> MutexGuard<'static, Logs> {
+    LOGS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn get(id: u64) -> Result<Arc<Mutex<Log>>, Error> {
+    logs()
+        .open
+        .get(&id)
+        .cloned()
+        .ok_or(Error::new(MMAP_ERR_INVALID_ARG))
+}
+
+fn lock(log: &Mutex<Log>) -> MutexGuard<'_, Log> {
+    log.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Checks the header of a log reopened with `content_len` bytes; formats a new one if the file
+/// was empty.
+fn init_or_validate(log: &Log, content_len: usize) -> Result<(), (Error, String)> {
+    let h = log.header();
+    if content_len == 0 {
+        let hdr = MmapLogHeader {
+            magic: MMAP_LOG_MAGIC,
+            version: MMAP_LOG_VERSION,
+            committed_len: 0,
+        };
+        unsafe { ptr::write_volatile(h, hdr) };
+        return Ok(());
+    }
+    let invalid = |msg: String| (Error::new(MMAP_ERR_INVALID_ARG), msg);
+    let hdr = unsafe { ptr::read_volatile(h) };
+    if content_len < MMAP_LOG_HEADER_SIZE || hdr.magic != MMAP_LOG_MAGIC {
+        return Err(invalid("not a log (bad magic)".into()));
+    }
+    if hdr.version != MMAP_LOG_VERSION {
+        return Err(invalid(format!("unsupported log version {}", hdr.version)));
+    }
+    let committed = log.committed().load(Ordering::Acquire);
+    if committed > (content_len - MMAP_LOG_HEADER_SIZE) as u64 {
+        return Err(invalid(format!(
+            "committed length {committed} exceeds the file"
+        )));
+    }
+    Ok(())
+}
+
+/// Opens the log at `path`, creating it if it doesn't exist, with room for at least `capacity`
+/// bytes of records before the file has to grow. An existing log keeps its records: its header
+/// is validated, and appends resume at its committed length.
+/// Returns the log's id, or 0 on failure, with the error code also written to `err_out` if it
+/// is non-null (`MMAP_OK` on success): `MMAP_ERR_INVALID_ARG` if the file is not a log (see
+/// `mmap_last_error_message`), or the open error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_log_open(
+    path: *const c_char,
+    capacity: usize,
+    err_out: *mut i32,
+) -> u64 {
+    let result = unsafe { open_log(path, capacity) };
+    let code = match &result {
+        Ok(_) => MMAP_OK,
+        Err((e, _)) => e.code,
+    };
+    if !err_out.is_null() {
+        unsafe { *err_out = code };
+    }
+    match result {
+        Ok(id) => id,
+        Err((e, detail)) if detail.is_empty() => {
+            error::set(e);
+            0
+        }
+        Err((e, detail)) => {
+            error::fail_with(e, detail);
+            0
+        }
+    }
+}
+
+unsafe fn open_log(path: *const c_char, capacity: usize) -> Result<u64, (Error, String)> {
+    let plain = |e: Error| (e, String::new());
+    if path.is_null() {
+        return Err(plain(Error::new(MMAP_ERR_INVALID_ARG)));
+    }
+    let path_str = unsafe { CStr::from_ptr(path) }
+        .to_str()
+        .map_err(|_| plain(Error::new(MMAP_ERR_INVALID_ARG)))?;
+    let wanted = MMAP_LOG_HEADER_SIZE.saturating_add(capacity);
+    // An existing file is mapped as it is, so one that turns out not to be a log is left alone.
+    let existing = std::fs::metadata(path_str).map_or(0, |m| m.len() as usize);
+    let size = if existing > 0 { 0 } else { wanted };
+    let m =
+        unsafe { crate::open_registered(path, &OpenSpec::write(size, 0), false) }.map_err(plain)?;
+    let mut log = Log {
+        base: m.ptr as usize,
+        len: m.len,
+    };
+    let ready = init_or_validate(&log, m.content_len).and_then(|()| {
+        if log.len < wanted {
+            (log.base, log.len) =
+                unsafe { crate::grow_registered(log.base, wanted) }.map_err(plain)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = ready {
+        unsafe { crate::mmap_close(log.base as *mut c_void, log.len) };
+        return Err(e);
+    }
+    let mut logs = logs();
+    let id = logs.next_id;
+    logs.next_id += 1;
+    logs.open.insert(id, Arc::new(Mutex::new(log)));
+    Ok(id)
+}
+
+/// Appends the `len` bytes at `src` to the log, growing the file (see `mmap_set_growth_policy`)
+/// when they don't fit, and commits them.
+/// Returns the record's offset (relative to the first record), or `MMAP_LOG_ERROR` on failure
+/// (`MMAP_ERR_INVALID_ARG` for an unknown id or a null `src`, or the error growing the file).
+///
+/// Safety: `src` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_log_append(id: u64, src: *const u8, len: usize) -> u64 {
+    let result = get(id).and_then(|log| {
+        if src.is_null() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        let mut log = lock(&log);
+        let at = log.committed().load(Ordering::Relaxed) as usize;
+        let end = (MMAP_LOG_HEADER_SIZE + at)
+            .checked_add(len)
+            .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+        if end > log.len {
+            let (base, new_len) = unsafe { crate::grow_registered(log.base, end)? };
+            log.base = base;
+            log.len = new_len;
+        }
+        unsafe { ptr::copy_nonoverlapping(src, log.data().add(at), len) };
+        crate::dirty::record(log.base as *mut c_void, MMAP_LOG_HEADER_SIZE + at, len);
+        crate::dirty::record(log.base as *mut c_void, 0, MMAP_LOG_HEADER_SIZE);
+        log.committed().store((at + len) as u64, Ordering::Release);
+        Ok(at as u64)
+    });
+    result.unwrap_or_else(|e| {
+        error::set(e);
+        MMAP_LOG_ERROR
+    })
+}
+
+/// The log's committed length in bytes, or `MMAP_LOG_ERROR` for an unknown id.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_log_len(id: u64) -> u64 {
+    match get(id) {
+        Ok(log) => lock(&log).committed().load(Ordering::Acquire),
+        Err(e) => {
+            error::set(e);
+            MMAP_LOG_ERROR
+        }
+    }
+}
+
+/// Copies `len` committed bytes at record offset `offset` into `dst`.
+/// Returns the number of bytes copied, or -1 on failure (`MMAP_ERR_INVALID_ARG` for an unknown
+/// id or a null `dst`, `MMAP_ERR_OUT_OF_BOUNDS` if the range reaches past the committed length).
+///
+/// Safety: `dst` must point to a writable buffer of at least `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_log_read(id: u64, offset: u64, dst: *mut u8, len: usize) -> isize {
+    let result = get(id).and_then(|log| {
+        if dst.is_null() {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        }
+        let log = lock(&log);
+        let committed = log.committed().load(Ordering::Acquire);
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= committed => {}
+            _ => return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+        }
+        unsafe { ptr::copy_nonoverlapping(log.data().add(offset as usize), dst, len) };
+        Ok(len as isize)
+    });
+    result.unwrap_or_else(|e| error::fail(e) as isize)
+}
+
+/// Makes the log durable (see `mmap_flush_durable`) and closes it; the id is invalid afterwards.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` for an unknown id). The log is
+/// closed even if the flush fails.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_log_close(id: u64) -> i32 {
+    let Some(log) = logs().open.remove(&id) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let log = lock(&log);
+    let base = log.base as *mut c_void;
+    unsafe {
+        let rc = crate::mmap_flush_durable(base, 0, log.len);
+        crate::mmap_close(base, log.len);
+        rc
+    }
+}
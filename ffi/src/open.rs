@@ -0,0 +1,1068 @@
+// Shared open-and-map path behind every `mmap_open*` variant.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::os::raw::c_void;
+
+use crate::error::{
+    Error, MMAP_ERR_EMPTY, MMAP_ERR_INVALID_ARG, MMAP_ERR_IS_DEVICE, MMAP_ERR_IS_DIRECTORY,
+    MMAP_ERR_IS_PIPE, MMAP_ERR_NOT_REGULAR, MMAP_ERR_TOO_LARGE, MMAP_ERR_UNALIGNED,
+    MMAP_ERR_UNSUPPORTED,
+};
+use crate::registry::Kind;
+
+/// Allow mapping block devices (raw disks/volumes). Sizes come from the device
+/// (BLKGETSIZE64 / IOCTL_DISK_GET_LENGTH_INFO) rather than the file length.
+pub const MMAP_ALLOW_DEVICE: u32 = 1 << 0;
+/// Map read-only pages as executable (PROT_EXEC / PAGE_EXECUTE_READ). Only honoured together
+/// with `MMAP_EXEC_CONFIRM` and never for writable opens; not available on macOS, whose
+/// W^X policy requires MAP_JIT for executable memory.
+pub const MMAP_EXEC: u32 = 1 << 1;
+/// Must accompany `MMAP_EXEC`, so executable code can't be mapped by a stray flag bit.
+pub const MMAP_EXEC_CONFIRM: u32 = 1 << 2;
+/// Page the whole mapping in at open time (MAP_POPULATE on Linux, touching each page on other
+/// Unixes, PrefetchVirtualMemory on Windows) instead of faulting on first access.
+pub const MMAP_PREFAULT: u32 = 1 << 3;
+/// Writable opens: don't reserve swap for the mapping (MAP_NORESERVE), for huge sparse
+/// files. Accepted as a no-op on Windows, where sections over sparse files commit nothing
+/// up front anyway, and ignored for read-only opens.
+/// Nothing backs the untouched pages: if the file system fills up (or memory runs out under
+/// strict overcommit) when a page is first written, the write faults with SIGBUS instead of
+/// the open failing.
+pub const MMAP_NORESERVE: u32 = 1 << 4;
+/// Lock the whole mapping into RAM (mlock / VirtualLock) so it is never paged out; `mmap_close`
+/// unlocks it. If locking fails the open fails with `MMAP_ERR_LOCK_FAILED`, unless
+/// `MMAP_LOCK_BEST_EFFORT` is also set.
+pub const MMAP_LOCKED: u32 = 1 << 5;
+/// With `MMAP_LOCKED`: keep the mapping unlocked instead of failing when locking fails.
+/// The open then succeeds and `mmap_last_error` reports `MMAP_ERR_LOCK_FAILED`
+/// (or `MMAP_OK` if the pages were locked).
+pub const MMAP_LOCK_BEST_EFFORT: u32 = 1 << 6;
+/// Advanced, platform-sensitive: open the file bypassing the page cache (O_DIRECT on Linux,
+/// F_NOCACHE on macOS, FILE_FLAG_NO_BUFFERING on Windows) to avoid double-buffering under a
+/// database-style cache. The mapped length must be a multiple of the page size (which covers
+/// the device sector size), otherwise the open fails with `MMAP_ERR_UNALIGNED`. Other Unixes
+/// report `MMAP_ERR_UNSUPPORTED`.
+pub const MMAP_DIRECT: u32 = 1 << 7;
+/// Refuse files on network file systems (NFS, SMB/CIFS, FUSE, ...; see `mmap_fs_info`) with
+/// `MMAP_ERR_NOT_LOCAL`, where a shared mapping may be incoherent with other clients and
+/// page-ins can fault with SIGBUS if the server goes away.
+pub const MMAP_LOCAL_ONLY: u32 = 1 << 8;
+
+pub(crate) struct OpenSpec {
+    pub write: bool,
+    /// Read-only opens: map MAP_SHARED (and share writes on Windows) so changes made to the
+    /// file elsewhere stay visible, instead of a private view.
+    pub shared: bool,
+    /// Writable opens: ensure the file is at least this long (0 = keep current size).
+    /// Read-only opens: map at most this many bytes from the start (0 = the whole file).
+    pub size: usize,
+    /// Refuse files (or devices) longer than this with `MMAP_ERR_TOO_LARGE` (0 = no limit).
+    pub max_len: usize,
+    pub flags: u32,
+    /// Writable opens: map the file for persistent memory, with MAP_SYNC where it is on DAX
+    /// (see `mmap_open_pmem`).
+    #[cfg_attr(
+        not(any(windows, all(target_os = "linux", target_arch = "x86_64"))),
+        allow(dead_code)
+    )]
+    pub pmem: bool,
+}
+
+impl OpenSpec {
+    pub fn read(flags: u32) -> Self {
+        OpenSpec {
+            write: false,
+            shared: false,
+            size: 0,
+            max_len: 0,
+            flags,
+            pmem: false,
+        }
+    }
+
+    pub fn write(size: usize, flags: u32) -> Self {
+        OpenSpec {
+            write: true,
+            shared: true,
+            size,
+            max_len: 0,
+            flags,
+            pmem: false,
+        }
+    }
+}
+
+pub(crate) struct Mapped {
+    pub ptr: *mut c_void,
+    pub len: usize,
+    pub kind: Kind,
+    /// The mapped file, kept open so the mapping can later be grown and remapped, or checked
+    /// for truncation. Windows keeps it for writable mappings only, since the open handle's
+    /// share mode would otherwise lock out other writers.
+    pub file: Option<File>,
+    /// How much of the mapping held file data before the open extended the file: where
+    /// `mmap_close_trim`'s high-water mark starts.
+    pub content_len: usize,
+    /// Stores reach the file once they leave the CPU caches (see `mmap_persist`).
+    pub sync: bool,
+}
+
+/// Resolves the length a writable open should map from the current size and the requested one.
+fn write_target(cur: usize, size: usize) -> usize {
+    let target = if size > 0 { size } else { cur };
+    if target == 0 {
+        crate::growth::initial_size()
+    } else {
+        target
+    }
+}
+
+/// Whether `spec` asks for an executable mapping, rejecting requests that aren't allowed.
+fn exec_requested(spec: &OpenSpec) -> Result<bool, Error> {
+    if spec.flags & MMAP_EXEC == 0 {
+        return Ok(false);
+    }
+    if spec.flags & MMAP_EXEC_CONFIRM == 0 || spec.write {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    if cfg!(target_vendor = "apple") {
+        return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+    }
+    Ok(true)
+}
+
+/// Rejects files longer than `spec.max_len`.
+fn check_cap(spec: &OpenSpec, len: usize) -> Result<(), Error> {
+    if spec.max_len > 0 && len > spec.max_len {
+        return Err(Error::new(MMAP_ERR_TOO_LARGE));
+    }
+    Ok(())
+}
+
+/// Copies a pseudo-file that reports size 0 (see `snapshot`), applying the size cap to what
+/// was actually read.
+unsafe fn snapshot_capped(path: &CStr, spec: &OpenSpec) -> Result<Mapped, Error> {
+    let m = unsafe { snapshot(path, spec.size)? };
+    if let Err(e) = check_cap(spec, m.len) {
+        unsafe { anon_free(m.ptr, m.len) };
+        return Err(e);
+    }
+    Ok(m)
+}
+
+/// Rejects `MMAP_DIRECT` opens whose mapped length isn't page-aligned.
+fn check_direct(spec: &OpenSpec, len: usize) -> Result<(), Error> {
+    if spec.flags & MMAP_DIRECT != 0 && !len.is_multiple_of(crate::page_size()) {
+        return Err(Error::new(MMAP_ERR_UNALIGNED));
+    }
+    Ok(())
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+        use libc::{MAP_FAILED, MAP_PRIVATE, MAP_SHARED, O_CLOEXEC, O_CREAT, O_RDONLY, O_RDWR, PROT_READ, PROT_WRITE};
+
+        /// Closes the descriptor on drop.
+        struct Fd(libc::c_int);
+
+        impl Drop for Fd {
+            fn drop(&mut self) {
+                unsafe {
+                    libc::close(self.0);
+                }
+            }
+        }
+
+        impl Fd {
+            fn into_file(self) -> File {
+                use std::os::fd::FromRawFd;
+                let fd = self.0;
+                core::mem::forget(self);
+                unsafe { File::from_raw_fd(fd) }
+            }
+        }
+
+        /// mmap(2) that fails instead of returning MAP_FAILED. Shared mappings are asked for with
+        /// MAP_SHARED_VALIDATE on Linux, so a flag the kernel doesn't know fails the call with
+        /// EOPNOTSUPP (reported as `MMAP_ERR_UNSUPPORTED`, the errno kept) rather than being
+        /// ignored. Kernels before 4.15 refuse MAP_SHARED_VALIDATE itself with EINVAL; the call
+        /// is then retried with plain MAP_SHARED, which is all that is used from then on.
+        pub(crate) unsafe fn sys_mmap(addr: *mut c_void, len: usize, prot: libc::c_int, flags: libc::c_int, fd: libc::c_int, offset: libc::off_t) -> Result<*mut c_void, Error> {
+            #[cfg(target_os = "linux")]
+            {
+                use std::sync::atomic::{AtomicBool, Ordering};
+                static NO_VALIDATE: AtomicBool = AtomicBool::new(false);
+                // Only MAP_SHARED_VALIDATE makes the kernel refuse MAP_SYNC rather than ignore it.
+                #[cfg(target_arch = "x86_64")]
+                let needs_validate = flags & libc::MAP_SYNC != 0;
+                #[cfg(not(target_arch = "x86_64"))]
+                let needs_validate = false;
+                if flags & MAP_SHARED != 0 && !NO_VALIDATE.load(Ordering::Relaxed) {
+                    let validated = (flags & !MAP_SHARED) | libc::MAP_SHARED_VALIDATE;
+                    let p = unsafe { libc::mmap(addr, len, prot, validated, fd, offset) };
+                    if p != MAP_FAILED {
+                        return Ok(p);
+                    }
+                    let mut e = Error::last_os();
+                    match e.os {
+                        libc::EOPNOTSUPP => e.code = MMAP_ERR_UNSUPPORTED,
+                        libc::EINVAL if needs_validate => e.code = MMAP_ERR_UNSUPPORTED,
+                        libc::EINVAL => {
+                            let p = unsafe { libc::mmap(addr, len, prot, flags, fd, offset) };
+                            if p == MAP_FAILED {
+                                return Err(Error::last_os());
+                            }
+                            NO_VALIDATE.store(true, Ordering::Relaxed);
+                            return Ok(p);
+                        }
+                        _ => {}
+                    }
+                    return Err(e);
+                }
+                if needs_validate {
+                    return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+                }
+            }
+            let p = unsafe { libc::mmap(addr, len, prot, flags, fd, offset) };
+            if p == MAP_FAILED {
+                return Err(Error::last_os());
+            }
+            Ok(p)
+        }
+
+        /// Accepts regular files (and block devices when `MMAP_ALLOW_DEVICE` is set), returning
+        /// whether it is a device; everything else fails with a specific error code.
+        fn classify(mode: libc::mode_t, flags: u32) -> Result<bool, Error> {
+            match mode & libc::S_IFMT {
+                libc::S_IFREG => Ok(false),
+                libc::S_IFBLK if flags & MMAP_ALLOW_DEVICE != 0 => Ok(true),
+                libc::S_IFBLK => Err(Error::new(MMAP_ERR_IS_DEVICE)),
+                libc::S_IFDIR => Err(Error::new(MMAP_ERR_IS_DIRECTORY)),
+                libc::S_IFIFO => Err(Error::new(MMAP_ERR_IS_PIPE)),
+                _ => Err(Error::new(MMAP_ERR_NOT_REGULAR)),
+            }
+        }
+
+        /// Classifies `path` before it is opened, so devices are never opened without the flag
+        /// and FIFOs can't block the open. A missing path is fine (writable opens create it).
+        pub(crate) unsafe fn precheck(path: &CStr, flags: u32) -> Result<(), Error> {
+            let mut st: libc::stat = unsafe { core::mem::zeroed() };
+            if unsafe { libc::stat(path.as_ptr(), &mut st) } == 0 {
+                classify(st.st_mode, flags)?;
+            }
+            Ok(())
+        }
+
+        #[cfg(target_os = "linux")]
+        unsafe fn device_size(fd: libc::c_int) -> Result<usize, Error> {
+            // _IOR(0x12, 114, size_t). The direction bits (read: 2) sit above a 13-bit size
+            // field on these architectures and a 14-bit one everywhere else.
+            #[cfg(any(
+                target_arch = "powerpc",
+                target_arch = "powerpc64",
+                target_arch = "mips",
+                target_arch = "mips64",
+                target_arch = "mips32r6",
+                target_arch = "mips64r6",
+                target_arch = "sparc",
+                target_arch = "sparc64",
+            ))]
+            const DIR_SHIFT: u32 = 29;
+            #[cfg(not(any(
+                target_arch = "powerpc",
+                target_arch = "powerpc64",
+                target_arch = "mips",
+                target_arch = "mips64",
+                target_arch = "mips32r6",
+                target_arch = "mips64r6",
+                target_arch = "sparc",
+                target_arch = "sparc64",
+            )))]
+            const DIR_SHIFT: u32 = 30;
+            const BLKGETSIZE64: u32 =
+                (2 << DIR_SHIFT) | ((size_of::<usize>() as u32) << 16) | (0x12 << 8) | 114;
+            // The kernel writes a u64, whatever size the request number encodes.
+            let mut size: u64 = 0;
+            if unsafe { libc::ioctl(fd, BLKGETSIZE64 as libc::Ioctl, &mut size) } != 0 {
+                return Err(Error::last_os());
+            }
+            usize::try_from(size).map_err(|_| Error::new(MMAP_ERR_TOO_LARGE))
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        unsafe fn device_size(fd: libc::c_int) -> Result<usize, Error> {
+            let size = unsafe { libc::lseek(fd, 0, libc::SEEK_END) };
+            if size < 0 {
+                return Err(Error::last_os());
+            }
+            usize::try_from(size).map_err(|_| Error::new(MMAP_ERR_TOO_LARGE))
+        }
+
+        pub(crate) unsafe fn open_mapping(path: &CStr, spec: &OpenSpec) -> Result<Mapped, Error> {
+            unsafe {
+                // The fstat below re-checks in case the path was swapped in between;
+                // O_NONBLOCK keeps a FIFO swapped in from blocking the open itself.
+                let exec = exec_requested(spec)?;
+                precheck(path, spec.flags)?;
+
+                let direct = spec.flags & MMAP_DIRECT != 0;
+                if direct && !cfg!(any(target_os = "linux", target_vendor = "apple")) {
+                    return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+                }
+
+                let oflag = if spec.write { O_RDWR | O_CREAT } else { O_RDONLY };
+                #[cfg(target_os = "linux")]
+                let oflag = if direct { oflag | libc::O_DIRECT } else { oflag };
+                let fd = libc::open(path.as_ptr(), oflag | O_CLOEXEC | libc::O_NONBLOCK, 0o644 as libc::c_uint);
+                if fd < 0 {
+                    return Err(Error::last_os());
+                }
+                let fd = Fd(fd);
+                #[cfg(target_vendor = "apple")]
+                if direct && libc::fcntl(fd.0, libc::F_NOCACHE, 1) != 0 {
+                    return Err(Error::last_os());
+                }
+                map_fd(fd, spec, exec, Some(path))
+            }
+        }
+
+        /// Maps an already-open descriptor that the mapping takes over. `path` is what it was
+        /// opened from, if anything, for copying pseudo-files that report a size of 0.
+        unsafe fn map_fd(fd: Fd, spec: &OpenSpec, exec: bool, path: Option<&CStr>) -> Result<Mapped, Error> {
+            unsafe {
+                let mut st: libc::stat = core::mem::zeroed();
+                if libc::fstat(fd.0, &mut st) != 0 {
+                    return Err(Error::last_os());
+                }
+                let is_device = classify(st.st_mode, spec.flags)?;
+                if spec.flags & MMAP_LOCAL_ONLY != 0 && !is_device {
+                    crate::fsinfo::require_local(std::os::fd::BorrowedFd::borrow_raw(fd.0))?;
+                }
+                let cur = if is_device { device_size(fd.0)? } else { st.st_size as usize };
+                check_cap(spec, cur)?;
+
+                // /proc, /sys and friends report size 0 but have content: copy it instead.
+                // Snapshots are never executable and can't observe later writes.
+                if !spec.write && cur == 0 && !is_device {
+                    let Some(path) = path.filter(|_| !exec && !spec.shared) else {
+                        return Err(Error::new(MMAP_ERR_EMPTY));
+                    };
+                    return snapshot_capped(path, spec);
+                }
+                check_direct(spec, if spec.write { write_target(cur, spec.size) } else { cur })?;
+
+                let len = if spec.write {
+                    let target = write_target(cur, spec.size);
+                    if cur < target {
+                        // A device can't be extended.
+                        if is_device {
+                            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+                        }
+                        if libc::ftruncate(fd.0, target as libc::off_t) != 0 {
+                            return Err(Error::last_os());
+                        }
+                    }
+                    target
+                } else if spec.size > 0 {
+                    cur.min(spec.size)
+                } else {
+                    cur
+                };
+
+                let (prot, flags) = if spec.write {
+                    let noreserve = if spec.flags & MMAP_NORESERVE != 0 { libc::MAP_NORESERVE } else { 0 };
+                    (PROT_READ | PROT_WRITE, MAP_SHARED | noreserve)
+                } else if exec {
+                    (PROT_READ | libc::PROT_EXEC, MAP_PRIVATE)
+                } else if spec.shared {
+                    (PROT_READ, MAP_SHARED)
+                } else {
+                    (PROT_READ, MAP_PRIVATE)
+                };
+                let prefault = spec.flags & MMAP_PREFAULT != 0;
+                #[cfg(target_os = "linux")]
+                let flags = if prefault { flags | libc::MAP_POPULATE } else { flags };
+                // MAP_SYNC keeps the file's metadata in step with stores to a DAX mapping, so
+                // flushing the CPU caches makes them durable; other files refuse it.
+                #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+                let (addr, sync) = match spec.pmem {
+                    true => match sys_mmap(core::ptr::null_mut(), len, prot, flags | libc::MAP_SYNC, fd.0, 0) {
+                        Err(e) if e.code == MMAP_ERR_UNSUPPORTED => (sys_mmap(core::ptr::null_mut(), len, prot, flags, fd.0, 0)?, false),
+                        mapped => (mapped?, true),
+                    },
+                    false => (sys_mmap(core::ptr::null_mut(), len, prot, flags, fd.0, 0)?, false),
+                };
+                #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+                let (addr, sync) = (sys_mmap(core::ptr::null_mut(), len, prot, flags, fd.0, 0)?, false);
+                if prefault && cfg!(not(target_os = "linux")) {
+                    crate::prefetch::prefault(addr as usize, len);
+                }
+                Ok(Mapped {
+                    ptr: addr,
+                    len,
+                    kind: Kind::File,
+                    file: Some(fd.into_file()),
+                    content_len: cur.min(len),
+                    sync,
+                })
+            }
+        }
+
+        /// Maps the file open as `fd`. The mapping keeps a duplicate, so the caller still owns
+        /// `fd` and may close it at any time.
+        pub(crate) unsafe fn open_fd(fd: libc::c_int, spec: &OpenSpec) -> Result<Mapped, Error> {
+            let exec = exec_requested(spec)?;
+            // O_DIRECT / F_NOCACHE would change the caller's open file description too.
+            if spec.flags & MMAP_DIRECT != 0 {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            }
+            let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+            if dup < 0 {
+                return Err(Error::last_os());
+            }
+            unsafe { map_fd(Fd(dup), spec, exec, None) }
+        }
+
+        /// Creates a nameless temporary file in the system temp directory.
+        unsafe fn create_tmp() -> Result<File, Error> {
+            use std::os::unix::ffi::OsStrExt;
+            let dir = std::env::temp_dir();
+            #[cfg(target_os = "linux")]
+            {
+                let c_dir = std::ffi::CString::new(dir.as_os_str().as_bytes())
+                    .map_err(|_| Error::new(MMAP_ERR_INVALID_ARG))?;
+                let fd = unsafe { libc::open(c_dir.as_ptr(), libc::O_TMPFILE | O_RDWR | O_CLOEXEC, 0o600 as libc::c_uint) };
+                if fd >= 0 {
+                    return Ok(Fd(fd).into_file());
+                }
+                // Not every filesystem supports O_TMPFILE: fall through to mkstemp.
+            }
+            let mut template = dir.join("mmap-XXXXXX").as_os_str().as_bytes().to_vec();
+            template.push(0);
+            unsafe {
+                let fd = libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char);
+                if fd < 0 {
+                    return Err(Error::last_os());
+                }
+                let fd = Fd(fd);
+                libc::unlink(template.as_ptr() as *const libc::c_char);
+                libc::fcntl(fd.0, libc::F_SETFD, libc::FD_CLOEXEC);
+                Ok(fd.into_file())
+            }
+        }
+
+        /// Extends `file` to `new_len` and maps it shared read-write at a (possibly) new address.
+        /// The old mapping is left alone so the caller can unmap it once the new one exists.
+        pub(crate) unsafe fn map_grown(file: &File, new_len: usize) -> Result<*mut c_void, Error> {
+            use std::os::fd::AsRawFd;
+            unsafe {
+                let fd = file.as_raw_fd();
+                if libc::ftruncate(fd, new_len as libc::off_t) != 0 {
+                    return Err(Error::last_os());
+                }
+                sys_mmap(core::ptr::null_mut(), new_len, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0)
+            }
+        }
+
+        /// Maps the first `len` bytes of `file` shared, read-write if `write`, without resizing
+        /// it; the file must already be that long.
+        pub(crate) unsafe fn map_view(file: &File, len: usize, write: bool) -> Result<*mut c_void, Error> {
+            use std::os::fd::AsRawFd;
+            let prot = if write { PROT_READ | PROT_WRITE } else { PROT_READ };
+            unsafe { sys_mmap(core::ptr::null_mut(), len, prot, MAP_SHARED, file.as_raw_fd(), 0) }
+        }
+
+        /// Maps `len` bytes of `file` from `offset` (a multiple of the mapping granularity)
+        /// shared, read-write if `write`; the file must already be that long.
+        pub(crate) unsafe fn map_at(file: &File, offset: u64, len: usize, write: bool) -> Result<*mut c_void, Error> {
+            use std::os::fd::AsRawFd;
+            let prot = if write { PROT_READ | PROT_WRITE } else { PROT_READ };
+            let offset = libc::off_t::try_from(offset).map_err(|_| Error::new(crate::error::MMAP_ERR_OUT_OF_BOUNDS))?;
+            unsafe { sys_mmap(core::ptr::null_mut(), len, prot, MAP_SHARED, file.as_raw_fd(), offset) }
+        }
+
+        /// Whether `file` (a retained mapping file) was opened for writing. Read-only mappings
+        /// keep theirs too, for truncation checks.
+        pub(crate) fn writable(file: &File) -> bool {
+            use std::os::fd::AsRawFd;
+            unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) & libc::O_ACCMODE != O_RDONLY }
+        }
+
+        /// Resizes `file` to `new_len` and the shared read-write mapping of it at `base` from
+        /// `old_len` to match, returning its (possibly new) address. The file is resized first,
+        /// so a shrunk mapping never covers pages past the end of the file for longer than the
+        /// call. On failure the error comes with the mapping's address, which is still `base`:
+        /// the mapping is left as it was.
+        pub(crate) unsafe fn remap(file: &File, base: *mut c_void, old_len: usize, new_len: usize) -> Result<*mut c_void, (Error, Option<*mut c_void>)> {
+            use std::os::fd::AsRawFd;
+            let fd = file.as_raw_fd();
+            unsafe {
+                if !writable(file) {
+                    return Err((Error::new(crate::error::MMAP_ERR_READ_ONLY), Some(base)));
+                }
+                if libc::ftruncate(fd, new_len as libc::off_t) != 0 {
+                    return Err((Error::last_os(), Some(base)));
+                }
+                cfg_if::cfg_if! {
+                    if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                        let addr = libc::mremap(base, old_len, new_len, libc::MREMAP_MAYMOVE);
+                        if addr == MAP_FAILED {
+                            return Err((Error::last_os(), Some(base)));
+                        }
+                        Ok(addr)
+                    } else {
+                        if new_len <= old_len {
+                            // Shrink in place: drop the whole pages past the new end.
+                            let page = crate::page_size();
+                            let keep = new_len.next_multiple_of(page);
+                            let mapped = old_len.next_multiple_of(page);
+                            if mapped > keep {
+                                libc::munmap((base as *mut u8).add(keep) as *mut c_void, mapped - keep);
+                            }
+                            return Ok(base);
+                        }
+                        let addr = sys_mmap(core::ptr::null_mut(), new_len, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0).map_err(|e| (e, Some(base)))?;
+                        libc::munmap(base, old_len);
+                        Ok(addr)
+                    }
+                }
+            }
+        }
+    } else if #[cfg(windows)] {
+        use windows_sys::Win32::Foundation::{CloseHandle, ERROR_SHARING_VIOLATION, HANDLE, INVALID_HANDLE_VALUE};
+        use windows_sys::Win32::Storage::FileSystem::{
+            CreateFileA, FILE_ATTRIBUTE_NORMAL, FILE_FLAG_NO_BUFFERING, FILE_GENERIC_EXECUTE, FILE_GENERIC_READ, FILE_GENERIC_WRITE, FILE_SHARE_READ,
+            FILE_SHARE_WRITE, GetFileSizeEx, OPEN_ALWAYS, OPEN_EXISTING, SetEndOfFile, SetFilePointerEx,
+        };
+        use windows_sys::Win32::System::Memory::{
+            CreateFileMappingA, FILE_MAP_EXECUTE, FILE_MAP_READ, FILE_MAP_WRITE, MEMORY_MAPPED_VIEW_ADDRESS, MapViewOfFile,
+            PAGE_EXECUTE_READ, PAGE_READONLY, PAGE_READWRITE, UnmapViewOfFile,
+        };
+
+        /// Closes the handle on drop.
+        struct Handle(HANDLE);
+
+        impl Drop for Handle {
+            fn drop(&mut self) {
+                unsafe {
+                    CloseHandle(self.0);
+                }
+            }
+        }
+
+        impl Handle {
+            fn into_file(self) -> File {
+                use std::os::windows::io::FromRawHandle;
+                let h = self.0;
+                core::mem::forget(self);
+                unsafe { File::from_raw_handle(h as _) }
+            }
+        }
+
+        unsafe fn device_size(h: HANDLE) -> Result<usize, Error> {
+            use windows_sys::Win32::System::IO::DeviceIoControl;
+            use windows_sys::Win32::System::Ioctl::{GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO};
+            unsafe {
+                let mut info: GET_LENGTH_INFORMATION = core::mem::zeroed();
+                let mut returned = 0u32;
+                let ok = DeviceIoControl(
+                    h,
+                    IOCTL_DISK_GET_LENGTH_INFO,
+                    core::ptr::null(),
+                    0,
+                    &mut info as *mut _ as *mut c_void,
+                    core::mem::size_of::<GET_LENGTH_INFORMATION>() as u32,
+                    &mut returned,
+                    core::ptr::null_mut(),
+                );
+                if ok == 0 {
+                    return Err(Error::last_os());
+                }
+                usize::try_from(info.Length).map_err(|_| Error::new(MMAP_ERR_TOO_LARGE))
+            }
+        }
+
+        /// Whether the open `h` is a disk or volume rather than a file, whatever path named it
+        /// (`\\.\PhysicalDrive0`, `\\.\C:`, `\\?\GLOBALROOT\Device\...`): a disk-type handle
+        /// that answers IOCTL_DISK_GET_LENGTH_INFO, which file systems refuse for their files.
+        unsafe fn is_device(h: HANDLE) -> bool {
+            use windows_sys::Win32::Storage::FileSystem::{FILE_TYPE_DISK, GetFileType};
+            unsafe { GetFileType(h) == FILE_TYPE_DISK && device_size(h).is_ok() }
+        }
+
+        /// Rejects directories before opening `path`. Devices can only be told apart once open
+        /// (see `is_device`).
+        pub(crate) unsafe fn precheck(path: &CStr, flags: u32) -> Result<(), Error> {
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_ATTRIBUTE_DIRECTORY, GetFileAttributesA, INVALID_FILE_ATTRIBUTES,
+            };
+            let _ = flags;
+            let attrs = unsafe { GetFileAttributesA(path.as_ptr() as *const u8) };
+            if attrs != INVALID_FILE_ATTRIBUTES && attrs & FILE_ATTRIBUTE_DIRECTORY != 0 {
+                return Err(Error::new(MMAP_ERR_IS_DIRECTORY));
+            }
+            Ok(())
+        }
+
+        /// Only disk files (which include volumes) can be mapped.
+        unsafe fn check_file_type(h: HANDLE) -> Result<(), Error> {
+            use windows_sys::Win32::Storage::FileSystem::{FILE_TYPE_DISK, FILE_TYPE_PIPE, GetFileType};
+            match unsafe { GetFileType(h) } {
+                FILE_TYPE_DISK => Ok(()),
+                FILE_TYPE_PIPE => Err(Error::new(MMAP_ERR_IS_PIPE)),
+                _ => Err(Error::new(MMAP_ERR_NOT_REGULAR)),
+            }
+        }
+
+        pub(crate) unsafe fn open_mapping(path: &CStr, spec: &OpenSpec) -> Result<Mapped, Error> {
+            unsafe {
+                let exec = exec_requested(spec)?;
+                precheck(path, spec.flags)?;
+
+                let (access, share, disposition) = if spec.write {
+                    (FILE_GENERIC_READ | FILE_GENERIC_WRITE, FILE_SHARE_READ, OPEN_ALWAYS)
+                } else if exec {
+                    (FILE_GENERIC_READ | FILE_GENERIC_EXECUTE, FILE_SHARE_READ, OPEN_EXISTING)
+                } else {
+                    (FILE_GENERIC_READ, FILE_SHARE_READ, OPEN_EXISTING)
+                };
+                // Shared read-only views must let other writers keep the file open too.
+                let share = if spec.shared && !spec.write { share | FILE_SHARE_WRITE } else { share };
+                let attrs = if spec.flags & MMAP_DIRECT != 0 { FILE_ATTRIBUTE_NORMAL | FILE_FLAG_NO_BUFFERING } else { FILE_ATTRIBUTE_NORMAL };
+                let create = |share, disposition| {
+                    let h = CreateFileA(path.as_ptr() as *const u8, access, share, core::ptr::null_mut(), disposition, attrs, core::ptr::null_mut());
+                    if h == INVALID_HANDLE_VALUE { Err(Error::last_os()) } else { Ok(Handle(h)) }
+                };
+                let h_file = match create(share, disposition) {
+                    // Volumes stay open for writing by the system; they can't be opened without
+                    // sharing writes. Only a device may keep the more lenient sharing.
+                    Err(e) if e.os as u32 == ERROR_SHARING_VIOLATION && spec.flags & MMAP_ALLOW_DEVICE != 0 => {
+                        match create(share | FILE_SHARE_WRITE, OPEN_EXISTING) {
+                            Ok(h) if is_device(h.0) => h,
+                            _ => return Err(e),
+                        }
+                    }
+                    result => result?,
+                };
+                let is_device = is_device(h_file.0);
+                if is_device && spec.flags & MMAP_ALLOW_DEVICE == 0 {
+                    return Err(Error::new(MMAP_ERR_IS_DEVICE));
+                }
+                map_handle(h_file, spec, exec, is_device, Some(path))
+            }
+        }
+
+        /// Maps an already-open handle that the mapping takes over. `path` is what it was
+        /// opened from, if anything, for copying pseudo-files that report a size of 0.
+        unsafe fn map_handle(
+            h_file: Handle,
+            spec: &OpenSpec,
+            exec: bool,
+            is_device: bool,
+            path: Option<&CStr>,
+        ) -> Result<Mapped, Error> {
+            unsafe {
+                check_file_type(h_file.0)?;
+                if spec.flags & MMAP_LOCAL_ONLY != 0 && !is_device {
+                    crate::fsinfo::require_local(std::os::windows::io::BorrowedHandle::borrow_raw(h_file.0))?;
+                }
+
+                let cur = if is_device {
+                    device_size(h_file.0)?
+                } else {
+                    let mut size: i64 = 0;
+                    if GetFileSizeEx(h_file.0, &mut size) == 0 {
+                        return Err(Error::last_os());
+                    }
+                    size as usize
+                };
+                check_cap(spec, cur)?;
+
+                if !spec.write && cur == 0 && !is_device {
+                    let Some(path) = path.filter(|_| !exec && !spec.shared) else {
+                        return Err(Error::new(MMAP_ERR_EMPTY));
+                    };
+                    return snapshot_capped(path, spec);
+                }
+                check_direct(spec, if spec.write { write_target(cur, spec.size) } else { cur })?;
+
+                let len = if spec.write {
+                    let target = write_target(cur, spec.size);
+                    if cur < target {
+                        if is_device {
+                            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+                        }
+                        if SetFilePointerEx(h_file.0, target as i64, core::ptr::null_mut(), 0) == 0
+                            || SetEndOfFile(h_file.0) == 0
+                        {
+                            return Err(Error::last_os());
+                        }
+                    }
+                    target
+                } else if spec.size > 0 {
+                    cur.min(spec.size)
+                } else {
+                    cur
+                };
+
+                let (protect, access) = if spec.write {
+                    (PAGE_READWRITE, FILE_MAP_WRITE)
+                } else if exec {
+                    (PAGE_EXECUTE_READ, FILE_MAP_READ | FILE_MAP_EXECUTE)
+                } else {
+                    (PAGE_READONLY, FILE_MAP_READ)
+                };
+                let h_map = CreateFileMappingA(h_file.0, core::ptr::null_mut(), protect, 0, 0, core::ptr::null());
+                if h_map.is_null() {
+                    return Err(Error::last_os());
+                }
+                let h_map = Handle(h_map);
+
+                let addr: MEMORY_MAPPED_VIEW_ADDRESS = MapViewOfFile(h_map.0, access, 0, 0, len);
+                if addr.Value.is_null() {
+                    return Err(Error::last_os());
+                }
+                if spec.flags & MMAP_PREFAULT != 0 {
+                    crate::prefetch::prefault(addr.Value as usize, len);
+                }
+                // Views of files on DAX volumes are the persistent memory itself.
+                let sync = spec.write && spec.pmem && crate::pmem::is_dax(std::os::windows::io::BorrowedHandle::borrow_raw(h_file.0));
+                Ok(Mapped {
+                    ptr: addr.Value,
+                    len,
+                    kind: Kind::File,
+                    file: spec.write.then(|| h_file.into_file()),
+                    content_len: cur.min(len),
+                    sync,
+                })
+            }
+        }
+
+        /// Maps the file open as `h`. The mapping keeps a duplicate, so the caller still owns
+        /// `h` and may close it at any time. Handles are always treated as files, not volumes.
+        pub(crate) unsafe fn open_handle(h: HANDLE, spec: &OpenSpec) -> Result<Mapped, Error> {
+            use windows_sys::Win32::Foundation::{DUPLICATE_SAME_ACCESS, DuplicateHandle};
+            use windows_sys::Win32::System::Threading::GetCurrentProcess;
+            let exec = exec_requested(spec)?;
+            // The handle's buffering mode is fixed when it is opened.
+            if spec.flags & MMAP_DIRECT != 0 {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            }
+            let mut dup: HANDLE = core::ptr::null_mut();
+            let ok = unsafe {
+                DuplicateHandle(GetCurrentProcess(), h, GetCurrentProcess(), &mut dup, 0, 0, DUPLICATE_SAME_ACCESS)
+            };
+            if ok == 0 {
+                return Err(Error::last_os());
+            }
+            unsafe { map_handle(Handle(dup), spec, exec, false, None) }
+        }
+
+        /// Creates a temporary file in the system temp directory that the OS deletes once the
+        /// last handle to it (including the mapping's section) is closed.
+        unsafe fn create_tmp() -> Result<File, Error> {
+            use std::sync::atomic::{AtomicU32, Ordering};
+            use windows_sys::Win32::Storage::FileSystem::{
+                CREATE_NEW, FILE_ATTRIBUTE_TEMPORARY, FILE_FLAG_DELETE_ON_CLOSE, FILE_SHARE_DELETE,
+            };
+            static SEQ: AtomicU32 = AtomicU32::new(0);
+            let name = format!("mmap-{}-{}.tmp", std::process::id(), SEQ.fetch_add(1, Ordering::Relaxed));
+            let path = std::env::temp_dir().join(name);
+            let c_path = std::ffi::CString::new(path.to_string_lossy().into_owned())
+                .map_err(|_| Error::new(MMAP_ERR_INVALID_ARG))?;
+            unsafe {
+                let h = CreateFileA(
+                    c_path.as_ptr() as *const u8,
+                    FILE_GENERIC_READ | FILE_GENERIC_WRITE,
+                    FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                    core::ptr::null_mut(),
+                    CREATE_NEW,
+                    FILE_ATTRIBUTE_TEMPORARY | FILE_FLAG_DELETE_ON_CLOSE,
+                    core::ptr::null_mut(),
+                );
+                if h == INVALID_HANDLE_VALUE {
+                    return Err(Error::last_os());
+                }
+                Ok(Handle(h).into_file())
+            }
+        }
+
+        /// Extends `file` to `new_len` and maps it read-write at a (possibly) new address.
+        /// Sizing the new section to `new_len` extends the file; the old view is left alone so
+        /// the caller can unmap it once the new one exists.
+        pub(crate) unsafe fn map_grown(file: &File, new_len: usize) -> Result<*mut c_void, Error> {
+            use std::os::windows::io::AsRawHandle;
+            unsafe {
+                let size = new_len as u64;
+                let h_map = CreateFileMappingA(
+                    file.as_raw_handle() as HANDLE,
+                    core::ptr::null_mut(),
+                    PAGE_READWRITE,
+                    (size >> 32) as u32,
+                    size as u32,
+                    core::ptr::null(),
+                );
+                if h_map.is_null() {
+                    return Err(Error::last_os());
+                }
+                let h_map = Handle(h_map);
+                let addr = MapViewOfFile(h_map.0, FILE_MAP_WRITE, 0, 0, new_len);
+                if addr.Value.is_null() {
+                    return Err(Error::last_os());
+                }
+                Ok(addr.Value)
+            }
+        }
+
+        /// Maps the first `len` bytes of `file`, read-write if `write`, without resizing it; the
+        /// file must already be that long.
+        pub(crate) unsafe fn map_view(file: &File, len: usize, write: bool) -> Result<*mut c_void, Error> {
+            use std::os::windows::io::AsRawHandle;
+            let (protect, access) = if write { (PAGE_READWRITE, FILE_MAP_WRITE) } else { (PAGE_READONLY, FILE_MAP_READ) };
+            unsafe {
+                let size = len as u64;
+                let h_map = CreateFileMappingA(
+                    file.as_raw_handle() as HANDLE,
+                    core::ptr::null_mut(),
+                    protect,
+                    (size >> 32) as u32,
+                    size as u32,
+                    core::ptr::null(),
+                );
+                if h_map.is_null() {
+                    return Err(Error::last_os());
+                }
+                let h_map = Handle(h_map);
+                let addr = MapViewOfFile(h_map.0, access, 0, 0, len);
+                if addr.Value.is_null() {
+                    return Err(Error::last_os());
+                }
+                Ok(addr.Value)
+            }
+        }
+
+        /// Maps `len` bytes of `file` from `offset` (a multiple of the allocation granularity),
+        /// read-write if `write`; the file must already be that long.
+        pub(crate) unsafe fn map_at(file: &File, offset: u64, len: usize, write: bool) -> Result<*mut c_void, Error> {
+            use std::os::windows::io::AsRawHandle;
+            let (protect, access) = if write { (PAGE_READWRITE, FILE_MAP_WRITE) } else { (PAGE_READONLY, FILE_MAP_READ) };
+            unsafe {
+                // A section over the whole file, which the view then picks the range from.
+                let h_map = CreateFileMappingA(
+                    file.as_raw_handle() as HANDLE,
+                    core::ptr::null_mut(),
+                    protect,
+                    0,
+                    0,
+                    core::ptr::null(),
+                );
+                if h_map.is_null() {
+                    return Err(Error::last_os());
+                }
+                let h_map = Handle(h_map);
+                let addr = MapViewOfFile(h_map.0, access, (offset >> 32) as u32, offset as u32, len);
+                if addr.Value.is_null() {
+                    return Err(Error::last_os());
+                }
+                Ok(addr.Value)
+            }
+        }
+
+        /// Whether `file` (a retained mapping file) was opened for writing, which on Windows
+        /// every retained file is.
+        pub(crate) fn writable(_file: &File) -> bool {
+            true
+        }
+
+        /// Resizes `file` to `new_len` and the read-write view of it at `base` from `old_len` to
+        /// match, returning its (possibly new) address. Growing maps the larger view before
+        /// unmapping the old one. A mapped file can't be truncated, so shrinking unmaps the view
+        /// first and maps it again afterwards; if the truncate is refused (e.g. another process
+        /// maps the file too) the view is mapped again at `old_len`, possibly elsewhere. On
+        /// failure the error comes with the view's address, or `None` if it could not be restored.
+        pub(crate) unsafe fn remap(file: &File, base: *mut c_void, old_len: usize, new_len: usize) -> Result<*mut c_void, (Error, Option<*mut c_void>)> {
+            unsafe {
+                let unmap = |addr: *mut c_void| UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: addr });
+                if new_len > old_len {
+                    let addr = map_grown(file, new_len).map_err(|e| (e, Some(base)))?;
+                    unmap(base);
+                    return Ok(addr);
+                }
+                unmap(base);
+                if let Err(e) = file.set_len(new_len as u64) {
+                    return Err((e.into(), map_grown(file, old_len).ok()));
+                }
+                map_grown(file, new_len).map_err(|e| (e, None))
+            }
+        }
+    }
+}
+
+/// Creates an unnamed temporary file of `size` bytes and maps it read-write.
+pub(crate) unsafe fn open_tmp(size: usize) -> Result<Mapped, Error> {
+    unsafe {
+        let file = create_tmp()?;
+        let ptr = map_grown(&file, size)?;
+        Ok(Mapped {
+            ptr,
+            len: size,
+            kind: Kind::File,
+            file: Some(file),
+            content_len: 0,
+            sync: false,
+        })
+    }
+}
+
+/// Creates a sealable memfd of `size` bytes and maps it read-write.
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn open_memfd(size: usize) -> Result<Mapped, Error> {
+    unsafe {
+        let fd = libc::memfd_create(
+            c"mmap".as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        );
+        if fd < 0 {
+            return Err(Error::last_os());
+        }
+        let file = Fd(fd).into_file();
+        let ptr = map_grown(&file, size)?;
+        Ok(Mapped {
+            ptr,
+            len: size,
+            kind: Kind::File,
+            file: Some(file),
+            content_len: 0,
+            sync: false,
+        })
+    }
+}
+
+/// Reads the whole file at `path` into a fresh read-only anonymous mapping.
+/// Used for pseudo-files whose reported size is 0; fails with `MMAP_ERR_EMPTY` if
+/// there really is no content.
+pub(crate) unsafe fn snapshot(path: &CStr, max_len: usize) -> Result<Mapped, Error> {
+    unsafe {
+        precheck(path, 0)?;
+    }
+    let path = path
+        .to_str()
+        .map_err(|_| Error::new(MMAP_ERR_INVALID_ARG))?;
+    let mut data = std::fs::read(path)?;
+    if max_len > 0 {
+        data.truncate(max_len);
+    }
+    if data.is_empty() {
+        return Err(Error::new(MMAP_ERR_EMPTY));
+    }
+    unsafe {
+        let ptr = anon_alloc(data.len())?;
+        core::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len());
+        anon_protect_readonly(ptr, data.len());
+        Ok(Mapped {
+            ptr,
+            len: data.len(),
+            kind: Kind::Snapshot,
+            file: None,
+            content_len: data.len(),
+            sync: false,
+        })
+    }
+}
+
+/// Allocates `len` bytes of zeroed, private, read-write anonymous memory.
+pub(crate) unsafe fn anon_alloc(len: usize) -> Result<*mut c_void, Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let ptr = unsafe {
+                libc::mmap(
+                    core::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANON,
+                    -1,
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(Error::last_os());
+            }
+            Ok(ptr)
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::{MEM_COMMIT, MEM_RESERVE, VirtualAlloc};
+            let ptr = unsafe { VirtualAlloc(core::ptr::null(), len, MEM_COMMIT | MEM_RESERVE, PAGE_READWRITE) };
+            if ptr.is_null() {
+                return Err(Error::last_os());
+            }
+            Ok(ptr)
+        }
+    }
+}
+
+unsafe fn anon_protect_readonly(ptr: *mut c_void, len: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            unsafe {
+                libc::mprotect(ptr, len, libc::PROT_READ);
+            }
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::{PAGE_PROTECTION_FLAGS, VirtualProtect};
+            let mut old: PAGE_PROTECTION_FLAGS = 0;
+            unsafe {
+                VirtualProtect(ptr, len, PAGE_READONLY, &mut old);
+            }
+        }
+    }
+}
+
+/// Releases memory obtained from `anon_alloc`.
+pub(crate) unsafe fn anon_free(ptr: *mut c_void, len: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            unsafe {
+                libc::munmap(ptr, len);
+            }
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::{MEM_RELEASE, VirtualFree};
+            let _ = len;
+            unsafe {
+                VirtualFree(ptr, 0, MEM_RELEASE);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// `classify` of a stat with file type `fmt`, with and without `MMAP_ALLOW_DEVICE`.
+    fn both(fmt: libc::mode_t) -> [Result<bool, i32>; 2] {
+        let mode = fmt | 0o644;
+        [0, MMAP_ALLOW_DEVICE].map(|flags| classify(mode, flags).map_err(|e| e.code))
+    }
+
+    #[test]
+    fn regular_files_are_mapped_as_files() {
+        assert_eq!(both(libc::S_IFREG), [Ok(false), Ok(false)]);
+    }
+
+    #[test]
+    fn block_devices_need_the_flag() {
+        assert_eq!(both(libc::S_IFBLK), [Err(MMAP_ERR_IS_DEVICE), Ok(true)]);
+    }
+
+    #[test]
+    fn other_file_types_are_refused_even_with_the_flag() {
+        let cases = [
+            (libc::S_IFCHR, MMAP_ERR_NOT_REGULAR),
+            (libc::S_IFSOCK, MMAP_ERR_NOT_REGULAR),
+            (libc::S_IFIFO, MMAP_ERR_IS_PIPE),
+            (libc::S_IFDIR, MMAP_ERR_IS_DIRECTORY),
+        ];
+        for (fmt, code) in cases {
+            assert_eq!(both(fmt), [Err(code), Err(code)], "mode {fmt:o}");
+        }
+    }
+}
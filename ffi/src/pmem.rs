@@ -0,0 +1,223 @@
+// Cache-line write-back for mappings of files on persistent memory (DAX). There the mapping is
+// the media itself: stores are durable once they leave the CPU caches, so msync's page-cache
+// work is wasted and CLWB (or CLFLUSHOPT / CLFLUSH) plus a store fence is enough.
+
+use std::os::raw::{c_char, c_void};
+
+use crate::error::{
+    self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_ERR_UNSUPPORTED, MMAP_OK,
+};
+use crate::fsinfo::Borrowed;
+use crate::open::OpenSpec;
+use crate::registry;
+
+/// Whether `file` lives on a DAX file system / volume.
+pub(crate) fn is_dax(file: Borrowed<'_>) -> bool {
+    if crate::hooks::force_dax() {
+        return true;
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            use std::os::fd::AsRawFd;
+            let mut stx: libc::statx = unsafe { core::mem::zeroed() };
+            let rc = unsafe {
+                libc::statx(file.as_raw_fd(), c"".as_ptr(), libc::AT_EMPTY_PATH, 0, &mut stx)
+            };
+            let dax = libc::STATX_ATTR_DAX as u64;
+            rc == 0 && stx.stx_attributes_mask & dax != 0 && stx.stx_attributes & dax != 0
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Storage::FileSystem::GetVolumeInformationByHandleW;
+            use windows_sys::Win32::System::SystemServices::FILE_DAX_VOLUME;
+            let mut flags = 0u32;
+            let ok = unsafe {
+                GetVolumeInformationByHandleW(
+                    file.as_raw_handle(),
+                    core::ptr::null_mut(),
+                    0,
+                    core::ptr::null_mut(),
+                    core::ptr::null_mut(),
+                    &mut flags,
+                    core::ptr::null_mut(),
+                    0,
+                )
+            };
+            ok != 0 && flags & FILE_DAX_VOLUME != 0
+        } else {
+            let _ = file;
+            false
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use core::arch::asm;
+    use core::arch::x86_64::{__cpuid, __cpuid_count, _mm_sfence};
+    use std::sync::OnceLock;
+
+    #[derive(Clone, Copy)]
+    enum Insn {
+        Clwb,
+        Clflushopt,
+        Clflush,
+    }
+
+    /// The best available write-back instruction and the cache line size, from CPUID.
+    fn detect() -> (Insn, usize) {
+        let leaf1 = __cpuid(1);
+        let line = match ((leaf1.ebx >> 8) & 0xff) as usize * 8 {
+            0 => 64,
+            n => n,
+        };
+        let ebx7 = if __cpuid(0).eax >= 7 {
+            __cpuid_count(7, 0).ebx
+        } else {
+            0
+        };
+        let insn = if ebx7 & (1 << 24) != 0 {
+            Insn::Clwb
+        } else if ebx7 & (1 << 23) != 0 {
+            Insn::Clflushopt
+        } else {
+            Insn::Clflush
+        };
+        (insn, line)
+    }
+
+    /// Writes the cache lines covering `[start, end)` back to memory and fences.
+    pub(super) unsafe fn write_back(start: usize, end: usize) {
+        static CPU: OnceLock<(Insn, usize)> = OnceLock::new();
+        let (insn, line) = *CPU.get_or_init(detect);
+        let mut p = start & !(line - 1);
+        while p < end {
+            unsafe {
+                match insn {
+                    Insn::Clwb => asm!("clwb [{}]", in(reg) p, options(nostack, preserves_flags)),
+                    Insn::Clflushopt => {
+                        asm!("clflushopt [{}]", in(reg) p, options(nostack, preserves_flags))
+                    }
+                    Insn::Clflush => {
+                        asm!("clflush [{}]", in(reg) p, options(nostack, preserves_flags))
+                    }
+                }
+            }
+            p += line;
+        }
+        unsafe { _mm_sfence() };
+    }
+}
+
+fn flush(base: *mut c_void, offset: usize, len: usize) -> Result<(), Error> {
+    if len == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    // Held across the write-back so the mapping can't be unmapped underneath it.
+    let reg = registry::lock();
+    let Some(m) = reg.get(&(base as usize)) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let end = offset
+        .checked_add(len)
+        .filter(|&end| end <= m.len)
+        .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            if !m.file.as_ref().is_some_and(|f| is_dax(crate::fsinfo::borrow(f))) {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            }
+            unsafe { x86::write_back(base as usize + offset, base as usize + end) };
+            Ok(())
+        } else {
+            let _ = end;
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
+
+/// Makes `[base + offset, base + offset + len)` durable on persistent memory by writing the
+/// CPU cache lines back (CLWB, or CLFLUSHOPT / CLFLUSH on older CPUs, picked at runtime)
+/// followed by a store fence, instead of msync.
+/// Only meaningful for files on a DAX file system (Linux `-o dax`, Windows DAX volumes): for
+/// any other mapping, and on non-x86_64 CPUs, nothing is done and the call fails with
+/// `MMAP_ERR_UNSUPPORTED`, so callers can fall back to `mmap_flush`.
+/// Returns 0 on success, -1 on failure.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_pmem(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    match flush(base, offset, len) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Opens (or creates) `path` read-write like `mmap_open_write`, mapped for byte-addressable
+/// persistence with `mmap_persist`. On Linux x86_64 the mapping asks for MAP_SYNC (through
+/// MAP_SHARED_VALIDATE), which a file on a DAX file system grants: the kernel then keeps the
+/// file's metadata durable before any store can land in a newly allocated block, so flushing
+/// the CPU caches is all a store needs to be persistent. Views of files on Windows DAX volumes
+/// are the same without asking. Anywhere else (other file systems, CPUs and platforms) the
+/// file is mapped as by `mmap_open_write`, and `mmap_persist` falls back to msync.
+/// On success `mmap_last_error` tells which it got: `MMAP_OK` for a synchronous mapping,
+/// `MMAP_ERR_UNSUPPORTED` for an ordinary one. On failure returns null (see `mmap_last_error`).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_pmem(path: *const c_char, len_out: *mut usize) -> *mut c_void {
+    let spec = OpenSpec {
+        pmem: true,
+        ..OpenSpec::write(0, 0)
+    };
+    let p = unsafe { crate::open_into(path, len_out, spec, false) };
+    if !p.is_null() {
+        let sync = registry::lock().get(&(p as usize)).is_some_and(|m| m.sync);
+        error::set(Error::new(if sync {
+            MMAP_OK
+        } else {
+            MMAP_ERR_UNSUPPORTED
+        }));
+    }
+    p
+}
+
+fn persist(base: *mut c_void, offset: usize, len: usize) -> Result<(), Error> {
+    if len == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    {
+        // Held across the write-back so the mapping can't be unmapped underneath it.
+        let reg = registry::lock();
+        let Some(m) = reg.get(&(base as usize)) else {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        };
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= m.len)
+            .ok_or(Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "x86_64")] {
+                if m.sync || crate::hooks::force_dax() {
+                    unsafe { x86::write_back(base as usize + offset, base as usize + end) };
+                    return Ok(());
+                }
+            } else {
+                let _ = (m, end);
+            }
+        }
+    }
+    unsafe { crate::flush_range(base, offset, len) }
+}
+
+/// Makes `[base + offset, base + offset + len)` of a mapping from `mmap_open_pmem` durable.
+/// For a synchronous mapping (see `mmap_open_pmem`) on x86_64 this writes the CPU cache lines
+/// back (CLWB, or CLFLUSHOPT / CLFLUSH on older CPUs) and fences, in user space and at the
+/// cost of the bytes written; for any other mapping it is `mmap_flush`, an msync that writes
+/// whole pages back through the kernel. Unlike `mmap_flush_pmem`, which refuses mappings it
+/// can't flush that way, it works on every mapping, so code written for persistent memory
+/// runs unchanged elsewhere.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` for a zero `len` or a `base`
+/// that isn't a mapping base, `MMAP_ERR_OUT_OF_BOUNDS`, or the OS error of the msync).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_persist(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    match persist(base, offset, len) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
@@ -0,0 +1,281 @@
+// Background page-cache warming for mapped ranges.
+
+use std::os::raw::c_void;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_OK};
+use crate::{MmapRange, page_size, registry};
+
+struct Task {
+    base: usize,
+    start: usize,
+    end: usize,
+    cancel: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+static TASKS: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+
+/// Faults in `[start, end)` by reading one byte per page, stopping early once `cancel` is set.
+fn warm(start: usize, end: usize, cancel: &AtomicBool) {
+    #[cfg(unix)]
+    unsafe {
+        let page = page_size();
+        let aligned = start & !(page - 1);
+        libc::madvise(aligned as *mut c_void, end - aligned, libc::MADV_WILLNEED);
+    }
+
+    let page = page_size();
+    let mut p = start;
+    while p < end {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        unsafe {
+            core::ptr::read_volatile(p as *const u8);
+        }
+        p = (p & !(page - 1)) + page;
+    }
+}
+
+/// Synchronously faults in `[start, start + len)` for `MMAP_PREFAULT` opens on platforms
+/// without MAP_POPULATE.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+pub(crate) fn prefault(start: usize, len: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::{PrefetchVirtualMemory, WIN32_MEMORY_RANGE_ENTRY};
+            use windows_sys::Win32::System::Threading::GetCurrentProcess;
+            let range = WIN32_MEMORY_RANGE_ENTRY {
+                VirtualAddress: start as *mut c_void,
+                NumberOfBytes: len,
+            };
+            // Best effort: if prefetching is unavailable the pages simply fault in on first use.
+            unsafe {
+                PrefetchVirtualMemory(GetCurrentProcess(), 1, &range, 0);
+            }
+        } else {
+            warm(start, start + len, &AtomicBool::new(false));
+        }
+    }
+}
+
+/// Asks the OS to read `[start, start + len)` of each range in ahead of use, without waiting
+/// for it: MADV_WILLNEED on Unix, one PrefetchVirtualMemory call for all of them on Windows,
+/// falling back to touching every page where that fails (before Windows 8).
+fn prefetch_now(ranges: &[(usize, usize)]) -> Result<(), Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let page = page_size();
+            for &(start, len) in ranges {
+                let aligned = start & !(page - 1);
+                if unsafe { libc::madvise(aligned as *mut c_void, start + len - aligned, libc::MADV_WILLNEED) } != 0 {
+                    return Err(Error::last_os());
+                }
+            }
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::Memory::{PrefetchVirtualMemory, WIN32_MEMORY_RANGE_ENTRY};
+            use windows_sys::Win32::System::Threading::GetCurrentProcess;
+            let entries: Vec<WIN32_MEMORY_RANGE_ENTRY> = ranges
+                .iter()
+                .map(|&(start, len)| WIN32_MEMORY_RANGE_ENTRY {
+                    VirtualAddress: start as *mut c_void,
+                    NumberOfBytes: len,
+                })
+                .collect();
+            let ok = unsafe { PrefetchVirtualMemory(GetCurrentProcess(), entries.len(), entries.as_ptr(), 0) };
+            if ok == 0 {
+                let done = AtomicBool::new(false);
+                for &(start, len) in ranges {
+                    warm(start, start + len, &done);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks `[offset, offset + len)` against the length of the mapping at `base` (`total`) and
+/// returns it as an address range; None for an empty range.
+fn check_range(
+    base: usize,
+    total: usize,
+    offset: usize,
+    len: usize,
+) -> Result<Option<(usize, usize)>, Error> {
+    match offset.checked_add(len) {
+        Some(end) if end <= total => Ok((len > 0).then_some((base + offset, len))),
+        _ => Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+    }
+}
+
+/// Starts reading `[base + offset, base + offset + len)` in from the file and returns without
+/// waiting for it, e.g. ahead of a burst of random reads in a known region, so they don't each
+/// stall on a page fault: MADV_WILLNEED on Unix, PrefetchVirtualMemory on Windows, where pages
+/// are touched one by one instead if that fails (before Windows 8). Unlike
+/// `mmap_prefetch_async` no thread is started, and the pages are only read into the page
+/// cache, not necessarily mapped yet. For many regions at once see `mmap_prefetch_v`.
+/// Returns 0 on success (also for `len == 0`), -1 on failure (`MMAP_ERR_INVALID_ARG` if `base`
+/// is not a mapping base, `MMAP_ERR_OUT_OF_BOUNDS` if the range exceeds the mapping, or the OS
+/// error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_prefetch(base: *const c_void, offset: usize, len: usize) -> i32 {
+    unsafe { mmap_prefetch_v(base, &MmapRange { offset, len }, 1) }
+}
+
+/// `mmap_prefetch` for `count` ranges of the mapping at `base` in one call (on Windows, one
+/// PrefetchVirtualMemory call). Every range is checked before any is prefetched, so nothing
+/// is prefetched if one is out of bounds. Returns as `mmap_prefetch`; `MMAP_ERR_INVALID_ARG`
+/// also if `ranges` is null while `count > 0`.
+///
+/// Safety: `ranges` must point to `count` readable `MmapRange`s.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_prefetch_v(
+    base: *const c_void,
+    ranges: *const MmapRange,
+    count: usize,
+) -> i32 {
+    let Some((total, _)) = registry::lookup(base as usize) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if ranges.is_null() && count > 0 {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    if count == 0 {
+        return 0;
+    }
+    let ranges = unsafe { core::slice::from_raw_parts(ranges, count) };
+    let checked: Result<Vec<_>, _> = ranges
+        .iter()
+        .filter_map(|r| check_range(base as usize, total, r.offset, r.len).transpose())
+        .collect();
+    match checked.and_then(|ranges| prefetch_now(&ranges)) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Faults in exactly the page holding `base + offset` by reading the byte at its start, which
+/// it returns so the read can't be optimized away: a micro-benchmark helper for timing a single
+/// page fault (time the first call for each page of a fresh mapping), where
+/// `mmap_prefetch_async` and `MMAP_PREFAULT` warm whole ranges. No read-ahead is asked for, but
+/// the kernel may still bring in neighbouring pages on its own.
+/// Returns the byte, or 0 on failure (`MMAP_ERR_INVALID_ARG` for a null `base`,
+/// `MMAP_ERR_OUT_OF_BOUNDS` if `offset` is past the end of a mapping opened by this library).
+/// Since 0 is also a valid byte, `mmap_last_error` is set to `MMAP_OK` on success to tell them
+/// apart.
+///
+/// Safety: `base` must be null or point into a mapping holding the page at `base + offset`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_touch_page(base: *const c_void, offset: usize) -> u8 {
+    if base.is_null() {
+        error::set(Error::new(MMAP_ERR_INVALID_ARG));
+        return 0;
+    }
+    let addr = match registry::lookup(base as usize) {
+        Some((len, _)) if offset >= len => None,
+        _ => (base as usize).checked_add(offset),
+    };
+    let Some(addr) = addr else {
+        error::set(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+        return 0;
+    };
+    let byte = unsafe { core::ptr::read_volatile((addr & !(page_size() - 1)) as *const u8) };
+    error::set(Error::new(MMAP_OK));
+    byte
+}
+
+/// Takes every task overlapping `[start, end)` out of the table, signalling them to stop.
+fn take_overlapping(start: usize, end: usize) -> Vec<Task> {
+    let mut tasks = TASKS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut taken = Vec::new();
+    let mut i = 0;
+    while i < tasks.len() {
+        if tasks[i].start < end && start < tasks[i].end {
+            let task = tasks.swap_remove(i);
+            task.cancel.store(true, Ordering::Relaxed);
+            taken.push(task);
+        } else {
+            i += 1;
+        }
+    }
+    taken
+}
+
+/// Cancels and joins any prefetch still touching `[start, start + len)`.
+/// Called by `mmap_close` before the range is unmapped.
+pub(crate) fn cancel_and_join(start: usize, len: usize) {
+    for task in take_overlapping(start, start.saturating_add(len.max(1))) {
+        let _ = task.thread.join();
+    }
+}
+
+/// Starts warming `[base + offset, base + offset + len)` on a background thread and returns
+/// immediately.
+/// Returns 0 if the thread was started, -1 on failure (`MMAP_ERR_INVALID_ARG` for a null `base`
+/// or a zero `len`, `MMAP_ERR_OS` if the thread can't be spawned).
+///
+/// The thread is tracked: `mmap_close` cancels and joins any prefetch overlapping the
+/// range it unmaps, so closing while a prefetch is in flight is safe.
+/// Use `mmap_prefetch_join` to wait for completion explicitly.
+///
+/// Safety: `base` must be a pointer returned by one of the `mmap_open*` functions and the
+/// mapping must be at least `offset + len` bytes long.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_prefetch_async(
+    base: *const c_void,
+    offset: usize,
+    len: usize,
+) -> i32 {
+    if base.is_null() || len == 0 {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let start = base as usize + offset;
+    let end = start + len;
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    let mut tasks = TASKS.lock().unwrap_or_else(|e| e.into_inner());
+    // Reap finished threads so the table doesn't grow without bound.
+    tasks.retain(|t| !t.thread.is_finished());
+
+    let flag = cancel.clone();
+    let spawned = std::thread::Builder::new()
+        .name("mmap-prefetch".into())
+        .spawn(move || warm(start, end, &flag));
+    match spawned {
+        Ok(thread) => {
+            tasks.push(Task {
+                base: base as usize,
+                start,
+                end,
+                cancel,
+                thread,
+            });
+            0
+        }
+        Err(e) => error::fail(e.into()),
+    }
+}
+
+/// Blocks until every prefetch started against the mapping at `base` has finished.
+/// Returns 0 on success, -1 with `MMAP_ERR_INVALID_ARG` if `base` is null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_prefetch_join(base: *const c_void) -> i32 {
+    if base.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let base = base as usize;
+    let mine: Vec<Task> = {
+        let mut tasks = TASKS.lock().unwrap_or_else(|e| e.into_inner());
+        let (mine, rest) = tasks.drain(..).partition(|t| t.base == base);
+        *tasks = rest;
+        mine
+    };
+    for task in mine {
+        let _ = task.thread.join();
+    }
+    0
+}
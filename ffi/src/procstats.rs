@@ -0,0 +1,96 @@
+// Process-wide memory counters, to correlate mapping use with memory pressure after a workload.
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG};
+use crate::registry;
+
+/// Filled in by `mmap_process_stats`. The fault counts are cumulative since the process
+/// started, across all of its memory, not just this library's mappings.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MmapProcStats {
+    /// Resident set size in bytes (the working set on Windows); 0 where the platform has no
+    /// cheap way to tell (Unix systems other than Linux).
+    pub rss_bytes: u64,
+    /// Faults served without I/O. Windows doesn't tell the two kinds apart and counts every
+    /// fault here.
+    pub minor_faults: u64,
+    /// Faults that had to read from disk.
+    pub major_faults: u64,
+    /// Mappings opened through this library and not closed yet.
+    pub mappings: u64,
+}
+
+fn os_stats() -> Result<MmapProcStats, Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            let stat = std::fs::read_to_string("/proc/self/stat")?;
+            // The command name in parentheses may contain spaces; fields resume after it, the
+            // first being field 3 (state).
+            let rest = stat.rsplit_once(')').map_or("", |(_, rest)| rest);
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            let field = |n: usize| -> Result<u64, Error> {
+                fields
+                    .get(n - 3)
+                    .and_then(|f| f.parse().ok())
+                    .ok_or(Error::new(crate::error::MMAP_ERR_OS))
+            };
+            Ok(MmapProcStats {
+                rss_bytes: field(24)? * crate::page_size() as u64,
+                minor_faults: field(10)?,
+                major_faults: field(12)?,
+                mappings: 0,
+            })
+        } else if #[cfg(unix)] {
+            let mut usage: libc::rusage = unsafe { core::mem::zeroed() };
+            if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+                return Err(Error::last_os());
+            }
+            Ok(MmapProcStats {
+                rss_bytes: 0,
+                minor_faults: usage.ru_minflt as u64,
+                major_faults: usage.ru_majflt as u64,
+                mappings: 0,
+            })
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::System::ProcessStatus::{
+                GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS,
+            };
+            use windows_sys::Win32::System::Threading::GetCurrentProcess;
+            let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { core::mem::zeroed() };
+            let cb = size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+            if unsafe { GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, cb) } == 0 {
+                return Err(Error::last_os());
+            }
+            Ok(MmapProcStats {
+                rss_bytes: counters.WorkingSetSize as u64,
+                minor_faults: counters.PageFaultCount as u64,
+                major_faults: 0,
+                mappings: 0,
+            })
+        }
+    }
+}
+
+/// Writes the process's resident size and page fault counts, read from /proc/self/stat on
+/// Linux, getrusage on other Unix systems and GetProcessMemoryInfo on Windows, together with
+/// the number of mappings open through this library, to `out`.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `out` is null, or the OS
+/// error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_process_stats(out: *mut MmapProcStats) -> i32 {
+    if out.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    match os_stats() {
+        Ok(stats) => {
+            unsafe {
+                *out = MmapProcStats {
+                    mappings: registry::active() as u64,
+                    ..stats
+                }
+            };
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
@@ -0,0 +1,109 @@
+// Book-keeping for every mapping handed out by this library, keyed by base address.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::autoflush::AutoFlush;
+use crate::dirty::DirtySet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    /// A view of a file (or device) created by mmap / MapViewOfFile.
+    File,
+    /// A private anonymous copy of a file's content (pseudo-files that report size 0).
+    /// Flushing is a no-op and closing releases anonymous memory.
+    Snapshot,
+}
+
+pub(crate) struct Mapping {
+    pub len: usize,
+    pub kind: Kind,
+    /// Pages are mlock()ed / VirtualLock()ed and must be unlocked before unmapping.
+    pub locked: bool,
+    /// Page-aligned offset ranges pinned with `mmap_lock_pages`, sorted and disjoint; unpinned
+    /// before unmapping.
+    pub pinned: Vec<Range<usize>>,
+    /// The mapped file (see `open::Mapped::file`), shared with the handle subviews mapped
+    /// through it (see `mmap_handle_subview`).
+    pub file: Option<Arc<File>>,
+    /// Pages written through the library since the last `mmap_flush_dirty`.
+    pub dirty: DirtySet,
+    /// End of the furthest byte known to hold data: the file's content at open, extended by
+    /// every write through the library and every `mmap_mark_dirty`. See `mmap_close_trim`.
+    pub high_water: usize,
+    /// Set while a background auto-flush is enabled for the mapping.
+    pub autoflush: Option<AutoFlush>,
+    /// Opened with `mmap_open_pmem` and mapped MAP_SYNC (on a DAX volume, on Windows).
+    pub sync: bool,
+    /// Mapped with write access.
+    pub writable: bool,
+    /// Opened with `mmap_open_secret`: `mmap_close` zeroes it before unmapping it.
+    pub secret: bool,
+    /// How the mapping was opened, for `mmap_debug_list`.
+    #[cfg_attr(not(feature = "debug-registry"), allow(dead_code))]
+    pub origin: Origin,
+}
+
+/// The open mode and path hash `mmap_debug_list` reports for a mapping. Empty without the
+/// `debug-registry` feature.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Origin {
+    #[cfg(feature = "debug-registry")]
+    pub mode: i32,
+    #[cfg(feature = "debug-registry")]
+    pub path_hash: u64,
+}
+
+impl Origin {
+    /// A mapping of `kind`, opened writable or not, from `path` if it was opened by name.
+    #[cfg_attr(not(feature = "debug-registry"), allow(unused_variables))]
+    pub fn new(kind: Kind, write: bool, path: Option<&[u8]>) -> Self {
+        Origin {
+            #[cfg(feature = "debug-registry")]
+            mode: match (kind, write) {
+                (Kind::Snapshot, _) => crate::debug::MMAP_DEBUG_SNAPSHOT,
+                (Kind::File, true) => crate::debug::MMAP_DEBUG_WRITE,
+                (Kind::File, false) => crate::debug::MMAP_DEBUG_READ,
+            },
+            #[cfg(feature = "debug-registry")]
+            path_hash: path.map_or(0, crate::debug::path_hash),
+        }
+    }
+}
+
+/// Every mapping, by base address.
+pub(crate) type Registry = BTreeMap<usize, Mapping>;
+
+static REGISTRY: Mutex<Registry> = Mutex::new(BTreeMap::new());
+/// Mappings opened and not closed yet, readable without the registry lock. Remaps move an
+/// entry under the lock and leave it unchanged.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn lock() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) fn insert(base: usize, mapping: Mapping) {
+    lock().insert(base, mapping);
+    ACTIVE.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn remove(base: usize) -> Option<Mapping> {
+    let m = lock().remove(&base);
+    if m.is_some() {
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+    m
+}
+
+pub(crate) fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Length and kind of the mapping registered at `base`.
+pub(crate) fn lookup(base: usize) -> Option<(usize, Kind)> {
+    lock().get(&base).map(|m| (m.len, m.kind))
+}
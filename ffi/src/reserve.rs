@@ -0,0 +1,413 @@
+// Arena mappings: a large range of address space is reserved up front and the file is mapped
+// into its start, so the file can grow into the rest of the range (`mmap_extend_in_place`)
+// without the mapping ever moving, and pointers into it stay valid for its whole life.
+//
+// On Unix the reservation is a PROT_NONE anonymous mapping that file pages are mapped over with
+// MAP_FIXED. On Windows it is a placeholder (VirtualAlloc2, Windows 10 1803 and later), split
+// off and replaced by a view of the file (MapViewOfFile3) for every extension.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fs::File;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::{
+    self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_TOO_LARGE, MMAP_ERR_UNSUPPORTED, MMAP_OK,
+};
+use crate::registry::{self, Kind, Mapping, Origin};
+
+struct Reservation {
+    /// Bytes of address space reserved at the base, a multiple of the mapping granularity.
+    size: usize,
+    /// Offsets of the views mapped into the reservation so far, one per extension.
+    #[cfg(windows)]
+    views: Vec<usize>,
+    /// End of the last view; the placeholder left spans from here to `size`.
+    #[cfg(windows)]
+    mapped: usize,
+}
+
+static RESERVATIONS: Mutex<BTreeMap<usize, Reservation>> = Mutex::new(BTreeMap::new());
+
+fn reservations() -> MutexGuard<'static, BTreeMap<usize, Reservation>> {
+    RESERVATIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether the mapping at `base` was opened with `mmap_open_reserve`, so it must not move.
+pub(crate) fn is_reserved(base: usize) -> bool {
+    reservations().contains_key(&base)
+}
+
+/// Unmaps the file views and releases the address space of the reservation at `base`, if
+/// there is one. Returns whether there was.
+pub(crate) unsafe fn release(base: usize) -> bool {
+    let Some(r) = reservations().remove(&base) else {
+        return false;
+    };
+    unsafe { platform::release(base, &r) };
+    true
+}
+
+/// Opens (or creates) `path` read-write, reserves `reserve_size` bytes of address space (rounded
+/// up to the page size, or the allocation granularity on Windows) and maps the first
+/// `initial_size` bytes of the file at its start, extending the file first if it is shorter;
+/// with `initial_size == 0` the file is mapped at its current size. The mapping then grows
+/// within the reservation with `mmap_extend_in_place`, never moving: the returned base stays
+/// valid until `mmap_close`, which releases the whole reservation.
+/// The reservation only takes address space, not memory. On Windows views are mapped in steps
+/// of the allocation granularity (64 KiB), and the file grows to the end of the last one.
+/// Returns the base, with the mapped length written to `len_out`, or null on failure, with the
+/// error code also written to `err_out` if it is non-null (`MMAP_OK` on success):
+/// `MMAP_ERR_INVALID_ARG` for a null path or `len_out`, or `reserve_size == 0`,
+/// `MMAP_ERR_TOO_LARGE` if the mapping wouldn't fit in the reservation, `MMAP_ERR_UNSUPPORTED`
+/// on Windows versions without placeholders, the file type errors of `mmap_open`, or the OS
+/// error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_reserve(
+    path: *const c_char,
+    reserve_size: usize,
+    initial_size: usize,
+    len_out: *mut usize,
+    err_out: *mut i32,
+) -> *mut c_void {
+    let result = unsafe { open_reserve(path, reserve_size, initial_size, len_out) };
+    if !err_out.is_null() {
+        unsafe { *err_out = result.as_ref().map_or_else(|e| e.code, |_| MMAP_OK) };
+    }
+    result.unwrap_or_else(|e| {
+        error::set(e);
+        ptr::null_mut()
+    })
+}
+
+unsafe fn open_reserve(
+    path: *const c_char,
+    reserve_size: usize,
+    initial_size: usize,
+    len_out: *mut usize,
+) -> Result<*mut c_void, Error> {
+    if path.is_null() || len_out.is_null() || reserve_size == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let path = unsafe { CStr::from_ptr(path) };
+    unsafe { crate::open::precheck(path, 0)? };
+    let path = path
+        .to_str()
+        .map_err(|_| Error::new(MMAP_ERR_INVALID_ARG))?;
+    let size = reserve_size
+        .checked_next_multiple_of(crate::map_granularity())
+        .ok_or(Error::new(MMAP_ERR_TOO_LARGE))?;
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let on_disk = file.metadata()?.len();
+    let len = match initial_size {
+        0 => usize::try_from(on_disk).map_err(|_| Error::new(MMAP_ERR_TOO_LARGE))?,
+        n => n,
+    };
+    if len > size {
+        return Err(Error::new(MMAP_ERR_TOO_LARGE));
+    }
+    if on_disk < len as u64 {
+        file.set_len(len as u64)?;
+    }
+    let mut r = Reservation {
+        size,
+        #[cfg(windows)]
+        views: Vec::new(),
+        #[cfg(windows)]
+        mapped: 0,
+    };
+    let base = unsafe { platform::reserve(size)? };
+    if let Err(e) = unsafe { platform::map(base, &mut r, &file, 0, len) } {
+        unsafe { platform::release(base, &r) };
+        return Err(e);
+    }
+    reservations().insert(base, r);
+    registry::insert(
+        base,
+        Mapping {
+            len,
+            kind: Kind::File,
+            locked: false,
+            pinned: Vec::new(),
+            file: Some(Arc::new(file)),
+            dirty: Default::default(),
+            high_water: (on_disk as usize).min(len),
+            autoflush: None,
+            sync: false,
+            writable: true,
+            secret: false,
+            origin: Origin::new(Kind::File, true, Some(path.as_bytes())),
+        },
+    );
+    unsafe { *len_out = len };
+    Ok(base as *mut c_void)
+}
+
+/// Grows the mapping at `base`, opened by `mmap_open_reserve`, to `new_size` bytes: the file is
+/// extended to `new_size` if it is shorter, and the new pages are mapped right after the old
+/// ones, so the base and every pointer into the mapping stay valid.
+/// Returns 0 on success (also when `new_size` is the current length), -1 on failure
+/// (`MMAP_ERR_INVALID_ARG` if `base` is not a mapping base or `new_size` is below the current
+/// length, `MMAP_ERR_UNSUPPORTED` if the mapping wasn't opened with `mmap_open_reserve`, so
+/// growing it would move it (see `mmap_resize`), `MMAP_ERR_TOO_LARGE` past the reservation, or
+/// the OS error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_extend_in_place(base: *mut c_void, new_size: usize) -> i32 {
+    let base = base as usize;
+    let mut registry = registry::lock();
+    let Some(m) = registry.get_mut(&base) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let mut reservations = reservations();
+    let Some(r) = reservations.get_mut(&base) else {
+        return error::fail(Error::new(MMAP_ERR_UNSUPPORTED));
+    };
+    if new_size < m.len {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    if new_size > r.size {
+        return error::fail(Error::new(MMAP_ERR_TOO_LARGE));
+    }
+    let file = m.file.as_ref().expect("reserved mappings keep their file");
+    let grown = file
+        .metadata()
+        .and_then(|meta| {
+            if meta.len() < new_size as u64 {
+                file.set_len(new_size as u64)?;
+            }
+            Ok(())
+        })
+        .map_err(Error::from)
+        .and_then(|()| unsafe { platform::map(base, r, file, m.len, new_size) });
+    match grown {
+        Ok(()) => {
+            m.len = new_size;
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+    use std::os::raw::c_void;
+
+    use super::Reservation;
+    use crate::error::Error;
+
+    pub(super) unsafe fn reserve(size: usize) -> Result<usize, Error> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_NORESERVE;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+        let addr = unsafe { libc::mmap(std::ptr::null_mut(), size, libc::PROT_NONE, flags, -1, 0) };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::last_os());
+        }
+        Ok(addr as usize)
+    }
+
+    /// Maps the file from `old_len` to `new_len` over the reservation. The page holding
+    /// `old_len` is mapped already: it shows the file's new bytes once the file has them.
+    pub(super) unsafe fn map(
+        base: usize,
+        _r: &mut Reservation,
+        file: &File,
+        old_len: usize,
+        new_len: usize,
+    ) -> Result<(), Error> {
+        let from = old_len.next_multiple_of(crate::page_size());
+        if new_len <= from {
+            return Ok(());
+        }
+        unsafe {
+            crate::open::sys_mmap(
+                (base + from) as *mut c_void,
+                new_len - from,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                file.as_raw_fd(),
+                from as libc::off_t,
+            )?
+        };
+        Ok(())
+    }
+
+    pub(super) unsafe fn release(base: usize, r: &Reservation) {
+        unsafe { libc::munmap(base as *mut c_void, r.size) };
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::File;
+    use std::os::raw::c_void;
+    use std::os::windows::io::AsRawHandle;
+    use std::sync::OnceLock;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
+    use windows_sys::Win32::System::Memory::{
+        CreateFileMappingA, MEM_PRESERVE_PLACEHOLDER, MEM_RELEASE, MEM_REPLACE_PLACEHOLDER,
+        MEM_RESERVE, MEM_RESERVE_PLACEHOLDER, MEMORY_MAPPED_VIEW_ADDRESS, PAGE_NOACCESS,
+        PAGE_READWRITE, UnmapViewOfFile, VirtualFree,
+    };
+    use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+    use super::Reservation;
+    use crate::error::{Error, MMAP_ERR_UNSUPPORTED};
+
+    const MEM_COALESCE_PLACEHOLDERS: u32 = 0x1;
+
+    type VirtualAlloc2 = unsafe extern "system" fn(
+        HANDLE,
+        *const c_void,
+        usize,
+        u32,
+        u32,
+        *mut c_void,
+        u32,
+    ) -> *mut c_void;
+    type MapViewOfFile3 = unsafe extern "system" fn(
+        HANDLE,
+        HANDLE,
+        *const c_void,
+        u64,
+        usize,
+        u32,
+        u32,
+        *mut c_void,
+        u32,
+    ) -> *mut c_void;
+
+    /// The placeholder functions, looked up at run time so the library still loads on Windows
+    /// versions without them.
+    fn api() -> Result<(VirtualAlloc2, MapViewOfFile3), Error> {
+        static API: OnceLock<Option<(VirtualAlloc2, MapViewOfFile3)>> = OnceLock::new();
+        let api = API.get_or_init(|| unsafe {
+            let module = GetModuleHandleA(c"kernelbase.dll".as_ptr().cast());
+            if module.is_null() {
+                return None;
+            }
+            let alloc = GetProcAddress(module, c"VirtualAlloc2".as_ptr().cast())?;
+            let map = GetProcAddress(module, c"MapViewOfFile3".as_ptr().cast())?;
+            Some((
+                core::mem::transmute::<unsafe extern "system" fn() -> isize, VirtualAlloc2>(alloc),
+                core::mem::transmute::<unsafe extern "system" fn() -> isize, MapViewOfFile3>(map),
+            ))
+        });
+        api.ok_or(Error::new(MMAP_ERR_UNSUPPORTED))
+    }
+
+    pub(super) unsafe fn reserve(size: usize) -> Result<usize, Error> {
+        let (virtual_alloc2, _) = api()?;
+        let addr = unsafe {
+            virtual_alloc2(
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                size,
+                MEM_RESERVE | MEM_RESERVE_PLACEHOLDER,
+                PAGE_NOACCESS,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if addr.is_null() {
+            return Err(Error::last_os());
+        }
+        Ok(addr as usize)
+    }
+
+    /// Maps a view of the file over the placeholder from the end of the last view to
+    /// `new_len` rounded up to the granularity, which the file grows to.
+    pub(super) unsafe fn map(
+        base: usize,
+        r: &mut Reservation,
+        file: &File,
+        _old_len: usize,
+        new_len: usize,
+    ) -> Result<(), Error> {
+        let (_, map_view_of_file3) = api()?;
+        let end = new_len
+            .next_multiple_of(crate::map_granularity())
+            .min(r.size);
+        if end <= r.mapped {
+            return Ok(());
+        }
+        let (at, len) = (base + r.mapped, end - r.mapped);
+        unsafe {
+            // Split the view's part off the placeholder.
+            if end < r.size
+                && VirtualFree(
+                    at as *mut c_void,
+                    len,
+                    MEM_RELEASE | MEM_PRESERVE_PLACEHOLDER,
+                ) == 0
+            {
+                return Err(Error::last_os());
+            }
+            let section = CreateFileMappingA(
+                file.as_raw_handle(),
+                std::ptr::null_mut(),
+                PAGE_READWRITE,
+                (end as u64 >> 32) as u32,
+                end as u32,
+                std::ptr::null(),
+            );
+            let (view, e) = if section.is_null() {
+                (std::ptr::null_mut(), Error::last_os())
+            } else {
+                let view = map_view_of_file3(
+                    section,
+                    GetCurrentProcess(),
+                    at as *const c_void,
+                    r.mapped as u64,
+                    len,
+                    MEM_REPLACE_PLACEHOLDER,
+                    PAGE_READWRITE,
+                    std::ptr::null_mut(),
+                    0,
+                );
+                let e = Error::last_os();
+                // The view keeps the section alive.
+                CloseHandle(section);
+                (view, e)
+            };
+            if view.is_null() {
+                if end < r.size {
+                    VirtualFree(
+                        at as *mut c_void,
+                        r.size - r.mapped,
+                        MEM_RELEASE | MEM_COALESCE_PLACEHOLDERS,
+                    );
+                }
+                return Err(e);
+            }
+        }
+        r.views.push(r.mapped);
+        r.mapped = end;
+        Ok(())
+    }
+
+    pub(super) unsafe fn release(base: usize, r: &Reservation) {
+        unsafe {
+            for &offset in &r.views {
+                UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS {
+                    Value: (base + offset) as *mut c_void,
+                });
+            }
+            if r.mapped < r.size {
+                VirtualFree((base + r.mapped) as *mut c_void, 0, MEM_RELEASE);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// Which pages of a mapping are in memory.
+
+use std::os::raw::c_void;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS};
+use crate::{page_size, registry};
+
+/// Pages asked about per OS call, so huge mappings don't need a huge buffer.
+const CHUNK: usize = 64 * 1024;
+
+/// Reports which pages of `[offset, offset + len)` of the mapping at `base` are in memory
+/// (`len == 0` means "to the end of the mapping"), e.g. to decide whether scanning a range is
+/// cheap or would fault it in from disk. The range is widened to start on a page boundary; bit
+/// `i % 8` of `out_bitmap[i / 8]` is set if its `i`-th page is resident, and bits past the last
+/// page are cleared. `out_bitmap` may be null to only count the pages.
+///
+/// Uses mincore on Unix, which for file mappings tells whether a page is in the page cache,
+/// even if this process hasn't touched it yet. Windows has no such query; QueryWorkingSetEx
+/// reports the pages in the process's working set, so pages that are cached but not mapped
+/// (e.g. after `mmap_evict`, or read through another mapping) count as not resident there.
+///
+/// Returns the number of resident pages, or -1 on failure: `MMAP_ERR_INVALID_ARG` if `base` is
+/// not a mapping base or `bitmap_len` has fewer than one bit per page, `MMAP_ERR_OUT_OF_BOUNDS`
+/// for a range past the end of the mapping, or the OS error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_residency(
+    base: *const c_void,
+    offset: usize,
+    len: usize,
+    out_bitmap: *mut u8,
+    bitmap_len: usize,
+) -> i64 {
+    let result = range(base as usize, offset, len).and_then(|(start, len)| {
+        let pages = len.div_ceil(page_size());
+        let mut bitmap = if out_bitmap.is_null() {
+            None
+        } else if bitmap_len < pages.div_ceil(8) {
+            return Err(Error::new(MMAP_ERR_INVALID_ARG));
+        } else {
+            let bitmap = unsafe { core::slice::from_raw_parts_mut(out_bitmap, bitmap_len) };
+            bitmap.fill(0);
+            Some(bitmap)
+        };
+        let mut resident = 0;
+        scan(start, len, |i| {
+            if let Some(bitmap) = bitmap.as_deref_mut() {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+            resident += 1;
+        })?;
+        Ok(resident)
+    });
+    match result {
+        Ok(n) => n,
+        Err(e) => error::fail(e) as i64,
+    }
+}
+
+/// Returns how many bytes of the mapping at `base` are in resident pages, as `mmap_residency`
+/// over the whole mapping counts them (a resident last page counts only as far as the mapping
+/// reaches), or -1 on failure: `MMAP_ERR_INVALID_ARG` if `base` is not a mapping base, or the
+/// OS error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_resident_bytes(base: *const c_void) -> i64 {
+    let result = range(base as usize, 0, 0).and_then(|(start, len)| {
+        let page = page_size();
+        let mut bytes = 0;
+        scan(start, len, |i| bytes += page.min(len - i * page))?;
+        Ok(bytes as i64)
+    });
+    match result {
+        Ok(n) => n,
+        Err(e) => error::fail(e) as i64,
+    }
+}
+
+/// Returns the size of a virtual memory page in bytes, the unit of `mmap_residency`'s bitmap:
+/// usually 4 KiB, 16 KiB on Apple silicon.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_page_size() -> usize {
+    page_size()
+}
+
+/// Checks `[offset, offset + len)` (`len == 0`: to the end) against the mapping at `base` and
+/// widens it to start on a page boundary.
+fn range(base: usize, offset: usize, len: usize) -> Result<(usize, usize), Error> {
+    let Some((total, _)) = registry::lookup(base) else {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let end = match len {
+        0 => total,
+        len => offset.saturating_add(len),
+    };
+    if offset > total || end > total {
+        return Err(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    let start = (base + offset) & !(page_size() - 1);
+    Ok((start, base + end - start))
+}
+
+/// Calls `f` with the index of every resident page of `[start, start + len)`, `start` being
+/// page-aligned.
+fn scan(start: usize, len: usize, mut f: impl FnMut(usize)) -> Result<(), Error> {
+    let page = page_size();
+    let pages = len.div_ceil(page);
+    let mut first = 0;
+    while first < pages {
+        let n = CHUNK.min(pages - first);
+        let addr = start + first * page;
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                let mut vec = vec![0u8; n];
+                if unsafe { libc::mincore(addr as _, n * page, vec.as_mut_ptr().cast()) } != 0 {
+                    return Err(Error::last_os());
+                }
+                // The other bits mean other things on some systems (e.g. modified on macOS).
+                let resident = |i: usize| vec[i] & 1 != 0;
+            } else if #[cfg(windows)] {
+                use windows_sys::Win32::System::ProcessStatus::{
+                    PSAPI_WORKING_SET_EX_INFORMATION, QueryWorkingSetEx,
+                };
+                use windows_sys::Win32::System::Threading::GetCurrentProcess;
+                let mut info: Vec<_> = (0..n)
+                    .map(|i| PSAPI_WORKING_SET_EX_INFORMATION {
+                        VirtualAddress: (addr + i * page) as *mut c_void,
+                        ..Default::default()
+                    })
+                    .collect();
+                let cb = size_of_val(info.as_slice()) as u32;
+                if unsafe { QueryWorkingSetEx(GetCurrentProcess(), info.as_mut_ptr().cast(), cb) } == 0 {
+                    return Err(Error::last_os());
+                }
+                // Bit 0 of the attributes is Valid: the page is in the working set.
+                let resident = |i: usize| unsafe { info[i].VirtualAttributes.Flags } & 1 != 0;
+            }
+        }
+        (0..n).filter(|&i| resident(i)).for_each(|i| f(first + i));
+        first += n;
+    }
+    Ok(())
+}
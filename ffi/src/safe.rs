@@ -0,0 +1,166 @@
+// Safe Rust API over the same open / close / flush paths the FFI exports use, for hosts that
+// link the crate as a regular Rust library instead of loading it through Deno.
+//
+//     use deno_mmap_ffi::safe::{Mmap, MmapMut};
+//
+//     let index = Mmap::open("index.bin")?;
+//     let mut log = MmapMut::open_with_size("log.bin", 1 << 20)?;
+//     log[..index.len().min(16)].copy_from_slice(&index[..index.len().min(16)]);
+//     log.flush()?;
+//
+// Mappings are registered like the ones handed to Deno, so the raw `mmap_*` functions also
+// accept `as_ptr()` / `as_mut_ptr()` (e.g. `mmap_flush_dirty`). Don't `mmap_close` them; drop
+// the wrapper instead.
+
+use std::ffi::CString;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::raw::c_void;
+use std::path::Path;
+
+use crate::open::OpenSpec;
+
+/// A read-only view of a file. Unmapped on drop.
+pub struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+/// A writable, shared view of a file: changes reach the file. Unmapped on drop.
+pub struct MmapMut {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// The views are plain memory owned by the wrapper; the registry they live in is synchronized.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+unsafe impl Send for MmapMut {}
+unsafe impl Sync for MmapMut {}
+
+fn map(path: &Path, spec: OpenSpec) -> io::Result<(*mut c_void, usize)> {
+    let path = path
+        .to_str()
+        .and_then(|s| CString::new(s).ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path is not valid UTF-8 or contains NUL",
+            )
+        })?;
+    let m = unsafe { crate::open_registered(path.as_ptr(), &spec, false)? };
+    Ok((m.ptr, m.len))
+}
+
+/// # Safety
+/// `ptr` must be null or valid for reads of `len` bytes.
+unsafe fn bytes<'a>(ptr: *mut c_void, len: usize) -> &'a [u8] {
+    if ptr.is_null() || len == 0 {
+        return &[];
+    }
+    unsafe { std::slice::from_raw_parts(ptr.cast(), len) }
+}
+
+/// # Safety
+/// `ptr` must be null or valid for reads and writes of `len` bytes.
+unsafe fn bytes_mut<'a>(ptr: *mut c_void, len: usize) -> &'a mut [u8] {
+    if ptr.is_null() || len == 0 {
+        return &mut [];
+    }
+    unsafe { std::slice::from_raw_parts_mut(ptr.cast(), len) }
+}
+
+impl Mmap {
+    /// Maps the whole file read-only (see `mmap_open`).
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Mmap> {
+        Mmap::open_ex(path, 0)
+    }
+
+    /// Like `open`, with `MMAP_*` open flags (see `mmap_open_ex`).
+    pub fn open_ex(path: impl AsRef<Path>, flags: u32) -> io::Result<Mmap> {
+        let (ptr, len) = map(path.as_ref(), OpenSpec::read(flags))?;
+        Ok(Mmap { ptr, len })
+    }
+
+    /// Base address of the view.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr.cast()
+    }
+}
+
+impl MmapMut {
+    /// Maps the whole file read-write (see `mmap_open_write`).
+    pub fn open(path: impl AsRef<Path>) -> io::Result<MmapMut> {
+        MmapMut::open_ex(path, 0, 0)
+    }
+
+    /// Maps the file read-write, first extending it to at least `size` bytes
+    /// (see `mmap_open_write_with_size`).
+    pub fn open_with_size(path: impl AsRef<Path>, size: usize) -> io::Result<MmapMut> {
+        MmapMut::open_ex(path, size, 0)
+    }
+
+    /// Like `open_with_size`, with `MMAP_*` open flags (see `mmap_open_write_ex`).
+    pub fn open_ex(path: impl AsRef<Path>, size: usize, flags: u32) -> io::Result<MmapMut> {
+        let (ptr, len) = map(path.as_ref(), OpenSpec::write(size, flags))?;
+        Ok(MmapMut { ptr, len })
+    }
+
+    /// Writes the whole view back to the file and waits for completion (see `mmap_flush`).
+    pub fn flush(&self) -> io::Result<()> {
+        self.flush_range(0, self.len)
+    }
+
+    /// Writes `[offset, offset + len)` back to the file and waits for completion.
+    pub fn flush_range(&self, offset: usize, len: usize) -> io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        unsafe { crate::flush_range(self.ptr, offset, len)? };
+        Ok(())
+    }
+
+    /// Base address of the view.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr.cast()
+    }
+
+    /// Base address of the view, for writing.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.cast()
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { bytes(self.ptr, self.len) }
+    }
+}
+
+impl Deref for MmapMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { bytes(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for MmapMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { bytes_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { crate::mmap_close(self.ptr, self.len) }
+    }
+}
+
+impl Drop for MmapMut {
+    fn drop(&mut self) {
+        unsafe { crate::mmap_close(self.ptr, self.len) }
+    }
+}
@@ -0,0 +1,152 @@
+// Mappings for key material: locked in RAM, kept out of core dumps and wiped when closed, and
+// scrubbing of secrets that passed through ordinary mappings.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::Read;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+use crate::error::{
+    self, Error, MMAP_ERR_EMPTY, MMAP_ERR_INVALID_ARG, MMAP_ERR_LOCK_FAILED,
+    MMAP_ERR_OUT_OF_BOUNDS, MMAP_ERR_READ_ONLY, MMAP_ERR_TOO_LARGE, MMAP_OK,
+};
+use crate::open::{self, MMAP_LOCK_BEST_EFFORT, MMAP_LOCKED};
+use crate::registry::{self, Kind, Origin};
+
+/// Reads `path` into fresh private, read-write memory meant for secrets such as key material,
+/// and writes its length to `len_out`. The pages are locked in RAM (mlock / VirtualLock)
+/// before the file is read in, so its content never reaches swap; on Linux they are also left
+/// out of core dumps (MADV_DONTDUMP) and zeroed in forked children (MADV_WIPEONFORK, 4.14 and
+/// later). `mmap_close` overwrites them with zeros before unmapping them, with volatile stores
+/// that can't be optimized away. Writes go to the copy only, never to the file.
+/// `flags` may hold `MMAP_LOCK_BEST_EFFORT` to keep a mapping that can't be locked (e.g. past
+/// RLIMIT_MEMLOCK) instead of failing with `MMAP_ERR_LOCK_FAILED`; `mmap_last_error` then
+/// reports `MMAP_ERR_LOCK_FAILED` although the call succeeded. `err_out`, if non-null,
+/// receives the same code: `MMAP_OK`, `MMAP_ERR_LOCK_FAILED` for a best-effort lock that
+/// failed, or the error. On failure returns null: `MMAP_ERR_INVALID_ARG` for a null pointer
+/// or any other flag, `MMAP_ERR_EMPTY` for an empty file, the file type errors of `mmap_open`,
+/// or the OS error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_open_secret(
+    path: *const c_char,
+    len_out: *mut usize,
+    flags: u32,
+    err_out: *mut i32,
+) -> *mut c_void {
+    let result = unsafe { open_secret(path, len_out, flags) };
+    let code = match result {
+        Ok((_, true)) => MMAP_OK,
+        Ok((_, false)) => MMAP_ERR_LOCK_FAILED,
+        Err(e) => {
+            error::set(e);
+            e.code
+        }
+    };
+    if !err_out.is_null() {
+        unsafe { *err_out = code };
+    }
+    result.map_or(ptr::null_mut(), |(p, _)| p)
+}
+
+/// The mapping and whether it is locked.
+unsafe fn open_secret(
+    path: *const c_char,
+    len_out: *mut usize,
+    flags: u32,
+) -> Result<(*mut c_void, bool), Error> {
+    if path.is_null() || len_out.is_null() || flags & !MMAP_LOCK_BEST_EFFORT != 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let path = unsafe { CStr::from_ptr(path) };
+    unsafe { open::precheck(path, 0)? };
+    let mut file = File::open(
+        path.to_str()
+            .map_err(|_| Error::new(MMAP_ERR_INVALID_ARG))?,
+    )?;
+    let len =
+        usize::try_from(file.metadata()?.len()).map_err(|_| Error::new(MMAP_ERR_TOO_LARGE))?;
+    if len == 0 {
+        return Err(Error::new(MMAP_ERR_EMPTY));
+    }
+    let base = unsafe { open::anon_alloc(len)? };
+    unsafe { exclude(base, len) };
+    let m = open::Mapped {
+        ptr: base,
+        len,
+        kind: Kind::Snapshot,
+        file: None,
+        content_len: len,
+        sync: false,
+    };
+    let origin = Origin::new(Kind::Snapshot, true, Some(path.to_bytes()));
+    unsafe { crate::register(m, MMAP_LOCKED | flags, origin)? };
+    let locked = {
+        let mut registry = registry::lock();
+        let m = registry.get_mut(&(base as usize)).expect("just registered");
+        // From here on, closing wipes it.
+        m.secret = true;
+        m.writable = true;
+        m.locked
+    };
+    let content = unsafe { core::slice::from_raw_parts_mut(base as *mut u8, len) };
+    if let Err(e) = file.read_exact(content) {
+        unsafe { crate::mmap_close(base, len) };
+        return Err(e.into());
+    }
+    unsafe { *len_out = len };
+    Ok((base, locked))
+}
+
+/// Overwrites `[base + offset, base + offset + len)` (`len == 0`: to the end of the mapping)
+/// with zeros, e.g. to scrub a credential that briefly lived in an ordinary writable mapping.
+/// The zeros are written with volatile stores, so the wipe happens even though nothing reads
+/// the range afterwards. File mappings are then flushed, so the file holds the zeros too and
+/// the old bytes don't linger in dirty pages.
+/// Returns 0, or -1: `MMAP_ERR_INVALID_ARG` if `base` is not a mapping base,
+/// `MMAP_ERR_OUT_OF_BOUNDS` if the range extends past the mapping, `MMAP_ERR_READ_ONLY` for
+/// read-only mappings and snapshots (other than `mmap_open_secret` ones), or the flush error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_zero_secure(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    let total = {
+        let registry = registry::lock();
+        let Some(m) = registry.get(&(base as usize)) else {
+            return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+        };
+        if !m.writable {
+            return error::fail(Error::new(MMAP_ERR_READ_ONLY));
+        }
+        m.len
+    };
+    if offset > total {
+        return error::fail(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    let len = match len {
+        0 => total - offset,
+        n if n > total - offset => return error::fail(Error::new(MMAP_ERR_OUT_OF_BOUNDS)),
+        n => n,
+    };
+    if len == 0 {
+        return 0;
+    }
+    unsafe { crate::handle::wipe((base as *mut u8).add(offset), len) };
+    match unsafe { crate::flush_range(base, offset, len) } {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Keeps `[p, p + len)` out of core dumps and forked children where the OS can.
+unsafe fn exclude(p: *mut c_void, len: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            unsafe {
+                libc::madvise(p, len, libc::MADV_DONTDUMP);
+                // EINVAL before Linux 4.14.
+                libc::madvise(p, len, libc::MADV_WIPEONFORK);
+            }
+        } else {
+            let _ = (p, len);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Kernel-side dirty page detection, for mappings written directly through raw pointers where
+// the library's own tracking (see dirty.rs) sees nothing.
+//
+// Linux only: `mmap_clear_dirty` writes "4" to /proc/self/clear_refs, which clears the
+// soft-dirty bit of every page, and `mmap_dirty_pages` reads bit 55 of each page's
+// /proc/self/pagemap entry. Needs a kernel built with CONFIG_MEM_SOFT_DIRTY; without it, and on
+// other platforms (Windows' GetWriteWatch only covers VirtualAlloc memory, not file views),
+// both functions fail with `MMAP_ERR_UNSUPPORTED`.
+
+use std::os::raw::c_void;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG};
+use crate::registry;
+
+/// Length of the mapping at `base`, or `MMAP_ERR_INVALID_ARG` if it isn't a mapping base.
+fn mapping_len(base: *mut c_void) -> Result<usize, Error> {
+    registry::lookup(base as usize)
+        .map(|(len, _)| len)
+        .ok_or(Error::new(MMAP_ERR_INVALID_ARG))
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        use std::fs::File;
+        use std::io::Write;
+        use std::os::unix::fs::FileExt;
+        use std::sync::OnceLock;
+
+        use crate::error::MMAP_ERR_UNSUPPORTED;
+
+        const SOFT_DIRTY: u64 = 1 << 55;
+        /// Pagemap entries read per syscall.
+        const CHUNK: usize = 4096;
+
+        /// True if the kernel reports soft-dirty bits. A fresh VMA starts out soft-dirty, so a
+        /// just-touched anonymous page without the bit means the feature is compiled out.
+        fn supported() -> bool {
+            static SUPPORTED: OnceLock<bool> = OnceLock::new();
+            *SUPPORTED.get_or_init(|| unsafe {
+                let page = crate::page_size();
+                let Ok(p) = crate::open::anon_alloc(page) else {
+                    return false;
+                };
+                p.cast::<u8>().write_volatile(1);
+                let mut entry = [0u64];
+                let ok = read_entries(p as usize / page, &mut entry).is_ok();
+                crate::open::anon_free(p, page);
+                ok && entry[0] & SOFT_DIRTY != 0
+            })
+        }
+
+        /// Reads the pagemap entries for `out.len()` pages starting at virtual page `first`.
+        fn read_entries(first: usize, out: &mut [u64]) -> std::io::Result<()> {
+            let pagemap = File::open("/proc/self/pagemap")?;
+            let bytes = unsafe {
+                std::slice::from_raw_parts_mut(out.as_mut_ptr().cast::<u8>(), out.len() * 8)
+            };
+            pagemap.read_exact_at(bytes, first as u64 * 8)
+        }
+
+        fn clear(base: *mut c_void) -> Result<(), Error> {
+            mapping_len(base)?;
+            if !supported() {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            }
+            File::options().write(true).open("/proc/self/clear_refs")?.write_all(b"4")?;
+            Ok(())
+        }
+
+        fn collect(base: *mut c_void, out: *mut u64, cap: usize) -> Result<usize, Error> {
+            let len = mapping_len(base)?;
+            if !supported() {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            }
+            let page = crate::page_size();
+            let first = base as usize / page;
+            let pages = len.div_ceil(page);
+            let mut entries = vec![0u64; CHUNK.min(pages)];
+            let mut found = 0;
+            for start in (0..pages).step_by(CHUNK) {
+                let chunk = &mut entries[..CHUNK.min(pages - start)];
+                read_entries(first + start, chunk)?;
+                for (i, e) in chunk.iter().enumerate() {
+                    if e & SOFT_DIRTY == 0 {
+                        continue;
+                    }
+                    if found < cap && !out.is_null() {
+                        unsafe { *out.add(found) = ((start + i) * page) as u64 };
+                    }
+                    found += 1;
+                }
+            }
+            Ok(found)
+        }
+    } else {
+        use crate::error::MMAP_ERR_UNSUPPORTED;
+
+        fn clear(base: *mut c_void) -> Result<(), Error> {
+            mapping_len(base)?;
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+
+        fn collect(base: *mut c_void, _out: *mut u64, _cap: usize) -> Result<usize, Error> {
+            mapping_len(base)?;
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
+
+/// Starts a new dirty-tracking interval: afterwards `mmap_dirty_pages` reports only pages
+/// written from now on.
+/// The kernel clears the bits for the whole process, so this resets the interval of every
+/// mapping, not just the one at `base`.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_UNSUPPORTED` without soft-dirty support).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_clear_dirty(base: *mut c_void) -> i32 {
+    match clear(base) {
+        Ok(()) => 0,
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Writes the byte offsets (relative to `base`, ascending) of the pages modified since the last
+/// `mmap_clear_dirty` into `out_offsets`, at most `cap` of them.
+/// Returns the total number of dirty pages, which may exceed `cap`; call again with a larger
+/// buffer to get them all. Returns `usize::MAX` on failure (`MMAP_ERR_INVALID_ARG` if `base`
+/// is not a mapping base, `MMAP_ERR_UNSUPPORTED` without soft-dirty support).
+///
+/// Pages the kernel wrote back and evicted in the meantime are no longer reported.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_dirty_pages(
+    base: *mut c_void,
+    out_offsets: *mut u64,
+    cap: usize,
+) -> usize {
+    match collect(base, out_offsets, cap) {
+        Ok(n) => n,
+        Err(e) => {
+            error::set(e);
+            usize::MAX
+        }
+    }
+}
@@ -0,0 +1,387 @@
+// Reserving disk blocks behind a shared mapping. On a full (or quota-limited) file system the
+// first write to an unallocated page of a MAP_SHARED mapping can't report ENOSPC: the fault
+// raises SIGBUS (an in-page exception on Windows) instead. Allocating the blocks up front moves
+// that failure to a call that can return an error.
+
+use std::fs::File;
+
+use crate::error::{Error, MMAP_ERR_UNSUPPORTED};
+
+/// Allocates the file blocks behind `[offset, offset + len)` without changing the file size
+/// or its contents. Fails with the OS error (ENOSPC / ERROR_DISK_FULL) if they can't be backed.
+pub(crate) fn reserve(file: &File, offset: usize, len: usize) -> Result<(), Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            use std::os::fd::AsRawFd;
+            let rc = unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_KEEP_SIZE,
+                    offset as libc::off_t,
+                    len as libc::off_t,
+                )
+            };
+            if rc == 0 {
+                return Ok(());
+            }
+            let e = Error::last_os();
+            Err(if e.os == libc::EOPNOTSUPP { Error::new(MMAP_ERR_UNSUPPORTED) } else { e })
+        } else if #[cfg(target_os = "freebsd")] {
+            use std::os::fd::AsRawFd;
+            // The range lies inside the file, so posix_fallocate can't change its size.
+            match unsafe { libc::posix_fallocate(file.as_raw_fd(), offset as libc::off_t, len as libc::off_t) } {
+                0 => Ok(()),
+                libc::EINVAL | libc::EOPNOTSUPP => Err(Error::new(MMAP_ERR_UNSUPPORTED)),
+                os => Err(Error { code: crate::error::MMAP_ERR_OS, os }),
+            }
+        } else if #[cfg(windows)] {
+            use std::os::windows::fs::MetadataExt;
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_ALLOCATION_INFO, FILE_ATTRIBUTE_SPARSE_FILE, FILE_STANDARD_INFO,
+                FileAllocationInfo, FileStandardInfo, GetFileInformationByHandleEx,
+                SetFileInformationByHandle,
+            };
+            // Clusters of a non-sparse file are allocated when SetEndOfFile extends it (only
+            // the zero-filling is lazy), so what remains is making sure the allocation covers
+            // the range. Holes in a sparse file can't be filled this way.
+            if file.metadata()?.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE != 0 {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            }
+            let h = file.as_raw_handle();
+            let mut std_info: FILE_STANDARD_INFO = unsafe { core::mem::zeroed() };
+            let ok = unsafe {
+                GetFileInformationByHandleEx(
+                    h,
+                    FileStandardInfo,
+                    (&mut std_info as *mut FILE_STANDARD_INFO).cast(),
+                    size_of::<FILE_STANDARD_INFO>() as u32,
+                )
+            };
+            if ok == 0 {
+                return Err(Error::last_os());
+            }
+            let end = (offset + len) as i64;
+            if std_info.AllocationSize >= end {
+                return Ok(());
+            }
+            let alloc = FILE_ALLOCATION_INFO { AllocationSize: end };
+            let ok = unsafe {
+                SetFileInformationByHandle(
+                    h,
+                    FileAllocationInfo,
+                    (&alloc as *const FILE_ALLOCATION_INFO).cast(),
+                    size_of::<FILE_ALLOCATION_INFO>() as u32,
+                )
+            };
+            if ok == 0 {
+                return Err(Error::last_os());
+            }
+            Ok(())
+        } else {
+            // macOS's F_PREALLOCATE only allocates past the end of the file, not holes in it.
+            let _ = (file, offset, len);
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
+
+/// How `preallocate` backs the new length; see `mmap_preallocate`.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Prealloc {
+    /// Just set the length, leaving a hole.
+    Sparse,
+    /// Allocate every block up to the length.
+    Full,
+    /// `Full`, and on Windows also skip the lazy zero-fill with SetFileValidData.
+    FullValidData,
+}
+
+/// Makes `file` at least `len` bytes long (it never shrinks), allocating the blocks behind the
+/// whole file unless `mode` is `Sparse`. A full file system or quota fails with
+/// `MMAP_ERR_NO_SPACE`; one that can't allocate ahead of writes with `MMAP_ERR_UNSUPPORTED`.
+pub(crate) fn preallocate(file: &File, len: u64, mode: Prealloc) -> Result<(), Error> {
+    let cur = file.metadata()?.len();
+    if mode == Prealloc::Sparse {
+        if len > cur {
+            file.set_len(len)?;
+        }
+        return Ok(());
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            use std::os::fd::AsRawFd;
+            // Mode 0 fills the holes below the current size too, and extends the size to `len`.
+            let rc = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) };
+            if rc == 0 {
+                return Ok(());
+            }
+            let e = Error::last_os_space();
+            // A failed fallocate can keep what it allocated, size included (ext4 does).
+            restore_len(file, cur);
+            Err(if e.os == libc::EOPNOTSUPP { Error::new(MMAP_ERR_UNSUPPORTED) } else { e })
+        } else if #[cfg(target_os = "freebsd")] {
+            use std::os::fd::AsRawFd;
+            match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) } {
+                0 => Ok(()),
+                libc::EINVAL | libc::EOPNOTSUPP => Err(Error::new(MMAP_ERR_UNSUPPORTED)),
+                os => {
+                    restore_len(file, cur);
+                    let code = if os == libc::ENOSPC || os == libc::EDQUOT {
+                        crate::error::MMAP_ERR_NO_SPACE
+                    } else {
+                        crate::error::MMAP_ERR_OS
+                    };
+                    Err(Error { code, os })
+                }
+            }
+        } else if #[cfg(target_vendor = "apple")] {
+            use std::os::fd::AsRawFd;
+            let _ = mode;
+            if len <= cur {
+                // F_PREALLOCATE only allocates past the end of the file (see `reserve`).
+                return Ok(());
+            }
+            let mut store = libc::fstore_t {
+                fst_flags: libc::F_ALLOCATECONTIG,
+                fst_posmode: libc::F_PEOFPOSMODE,
+                fst_offset: 0,
+                fst_length: (len - cur) as libc::off_t,
+                fst_bytesalloc: 0,
+            };
+            let fd = file.as_raw_fd();
+            // A contiguous run is preferred but not required.
+            if unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &store) } == -1 {
+                store.fst_flags = libc::F_ALLOCATEALL;
+                if unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &store) } == -1 {
+                    let e = Error::last_os_space();
+                    return Err(if e.os == libc::ENOTSUP { Error::new(MMAP_ERR_UNSUPPORTED) } else { e });
+                }
+            }
+            file.set_len(len)?;
+            Ok(())
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_ALLOCATION_INFO, FileAllocationInfo, SetFileInformationByHandle,
+                SetFileValidData,
+            };
+            if len <= cur {
+                // Non-sparse files are already allocated up to their size (see `reserve`).
+                return Ok(());
+            }
+            let h = file.as_raw_handle();
+            let alloc = FILE_ALLOCATION_INFO { AllocationSize: len as i64 };
+            let ok = unsafe {
+                SetFileInformationByHandle(
+                    h,
+                    FileAllocationInfo,
+                    (&alloc as *const FILE_ALLOCATION_INFO).cast(),
+                    size_of::<FILE_ALLOCATION_INFO>() as u32,
+                )
+            };
+            if ok == 0 {
+                return Err(Error::last_os_space());
+            }
+            file.set_len(len)?;
+            // Needs SE_MANAGE_VOLUME_NAME; without it the range is zero-filled lazily, as usual.
+            if mode == Prealloc::FullValidData {
+                unsafe { SetFileValidData(h, len as i64) };
+            }
+            Ok(())
+        } else {
+            let _ = (file, len, cur);
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
+
+/// Shrinks `file` back to `len` after a preallocation that failed partway, releasing the
+/// blocks it allocated past the old end.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn restore_len(file: &File, len: u64) {
+    if file.metadata().is_ok_and(|m| m.len() > len) {
+        let _ = file.set_len(len);
+    }
+}
+
+/// Deallocates the file blocks behind `[offset, offset + len)`, keeping the file size: the range
+/// reads back as zeros. On Linux this is exact and the shared mapping sees the zeros at once.
+/// macOS only punches whole file-system blocks and Windows zeroes through its own cache, so
+/// there the caller must zero whatever the mapping still shows in the range.
+pub(crate) fn punch_hole(file: &File, offset: u64, len: u64) -> Result<(), Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            use std::os::fd::AsRawFd;
+            let rc = unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    offset as libc::off_t,
+                    len as libc::off_t,
+                )
+            };
+            if rc == 0 {
+                return Ok(());
+            }
+            let e = Error::last_os();
+            Err(if e.os == libc::EOPNOTSUPP { Error::new(MMAP_ERR_UNSUPPORTED) } else { e })
+        } else if #[cfg(target_vendor = "apple")] {
+            use std::os::fd::AsRawFd;
+            use std::os::unix::fs::MetadataExt;
+            // F_PUNCHHOLE wants block-aligned ranges: punch the whole blocks inside this one.
+            let block = file.metadata()?.blksize().max(1);
+            let start = offset.next_multiple_of(block);
+            let end = (offset + len) / block * block;
+            if start >= end {
+                return Ok(());
+            }
+            let hole = libc::fpunchhole_t {
+                fp_flags: 0,
+                reserved: 0,
+                fp_offset: start as libc::off_t,
+                fp_length: (end - start) as libc::off_t,
+            };
+            if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PUNCHHOLE, &hole) } == -1 {
+                let e = Error::last_os();
+                return Err(if e.os == libc::ENOTSUP { Error::new(MMAP_ERR_UNSUPPORTED) } else { e });
+            }
+            Ok(())
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::System::IO::DeviceIoControl;
+            use windows_sys::Win32::System::Ioctl::{
+                FILE_ZERO_DATA_INFORMATION, FSCTL_SET_SPARSE, FSCTL_SET_ZERO_DATA,
+            };
+            let h = file.as_raw_handle();
+            let mut returned = 0u32;
+            // Zeroing a range of a non-sparse file writes zeros instead of deallocating it.
+            let ok = unsafe {
+                DeviceIoControl(
+                    h,
+                    FSCTL_SET_SPARSE,
+                    core::ptr::null(),
+                    0,
+                    core::ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    core::ptr::null_mut(),
+                )
+            };
+            if ok == 0 {
+                return Err(Error::new(MMAP_ERR_UNSUPPORTED));
+            }
+            let zero = FILE_ZERO_DATA_INFORMATION {
+                FileOffset: offset as i64,
+                BeyondFinalZero: (offset + len) as i64,
+            };
+            let ok = unsafe {
+                DeviceIoControl(
+                    h,
+                    FSCTL_SET_ZERO_DATA,
+                    (&zero as *const FILE_ZERO_DATA_INFORMATION).cast(),
+                    size_of::<FILE_ZERO_DATA_INFORMATION>() as u32,
+                    core::ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    core::ptr::null_mut(),
+                )
+            };
+            if ok == 0 {
+                return Err(Error::last_os());
+            }
+            Ok(())
+        } else {
+            let _ = (file, offset, len);
+            Err(Error::new(MMAP_ERR_UNSUPPORTED))
+        }
+    }
+}
+
+/// The data extents of `file` as ascending, non-overlapping `(offset, len)` pairs; the gaps
+/// between them are holes. File systems that don't track holes report the whole file as data.
+pub(crate) fn data_extents(file: &File) -> Result<Vec<(u64, u64)>, Error> {
+    let len = file.metadata()?.len();
+    let whole = || if len == 0 { Vec::new() } else { vec![(0, len)] };
+    cfg_if::cfg_if! {
+        if #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_vendor = "apple"
+        ))] {
+            use std::os::fd::AsRawFd;
+            let fd = file.as_raw_fd();
+            let mut extents = Vec::new();
+            let mut pos = 0u64;
+            while pos < len {
+                let data = unsafe { libc::lseek(fd, pos as libc::off_t, libc::SEEK_DATA) };
+                if data < 0 {
+                    let e = Error::last_os();
+                    match e.os {
+                        // Only holes past `pos`.
+                        libc::ENXIO => break,
+                        libc::EINVAL | libc::ENOTSUP => return Ok(whole()),
+                        _ => return Err(e),
+                    }
+                }
+                let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+                if hole < 0 {
+                    return Err(Error::last_os());
+                }
+                // Another process may have shrunk the file since `len` was read.
+                let (data, hole) = (data as u64, (hole as u64).min(len));
+                if hole <= data {
+                    break;
+                }
+                extents.push((data, hole - data));
+                pos = hole;
+            }
+            Ok(extents)
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Foundation::ERROR_MORE_DATA;
+            use windows_sys::Win32::System::IO::DeviceIoControl;
+            use windows_sys::Win32::System::Ioctl::{
+                FILE_ALLOCATED_RANGE_BUFFER, FSCTL_QUERY_ALLOCATED_RANGES,
+            };
+            let mut extents = Vec::new();
+            let mut query = FILE_ALLOCATED_RANGE_BUFFER { FileOffset: 0, Length: len as i64 };
+            let mut ranges = [FILE_ALLOCATED_RANGE_BUFFER { FileOffset: 0, Length: 0 }; 64];
+            loop {
+                let mut returned = 0u32;
+                let ok = unsafe {
+                    DeviceIoControl(
+                        file.as_raw_handle(),
+                        FSCTL_QUERY_ALLOCATED_RANGES,
+                        (&query as *const FILE_ALLOCATED_RANGE_BUFFER).cast(),
+                        size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as u32,
+                        ranges.as_mut_ptr().cast(),
+                        size_of_val(&ranges) as u32,
+                        &mut returned,
+                        core::ptr::null_mut(),
+                    )
+                };
+                let more = ok == 0 && Error::last_os().os as u32 == ERROR_MORE_DATA;
+                if ok == 0 && !more {
+                    // Not a sparse-capable file system (FAT, network shares, ...).
+                    return Ok(whole());
+                }
+                let got = returned as usize / size_of::<FILE_ALLOCATED_RANGE_BUFFER>();
+                extents.extend(
+                    ranges[..got].iter().map(|r| (r.FileOffset as u64, r.Length as u64)),
+                );
+                if !more || got == 0 {
+                    return Ok(extents);
+                }
+                // Carry on after the last range returned.
+                let last = ranges[got - 1];
+                let next = last.FileOffset + last.Length;
+                query = FILE_ALLOCATED_RANGE_BUFFER { FileOffset: next, Length: len as i64 - next };
+            }
+        } else {
+            let _ = file;
+            Ok(whole())
+        }
+    }
+}
@@ -0,0 +1,257 @@
+// Metadata of the file behind a handle, e.g. to build cache keys from its identity and
+// modification time, and its timestamps, e.g. to keep an asset's mtime across a rewrite.
+//
+// `MmapStat` is part of the ABI: fields are only ever appended, and the offsets documented on
+// them are checked at compile time.
+
+use std::fs::{File, FileTimes};
+use std::mem::offset_of;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG};
+use crate::handle::MmapHandle;
+
+/// `MmapStat::flags` bit: the file has holes (Unix: fewer blocks allocated than its size
+/// needs) or is marked sparse (Windows).
+pub const MMAP_STAT_SPARSE: u32 = 1 << 0;
+/// `MmapStat::flags` bit: the file is read-only by its permissions (no write bit on Unix,
+/// FILE_ATTRIBUTE_READONLY on Windows), whatever the mapping's access.
+pub const MMAP_STAT_READ_ONLY: u32 = 1 << 1;
+
+/// Filled in by `mmap_stat`; 48 bytes, in host byte order.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MmapStat {
+    /// Offset 0: the file size in bytes.
+    pub size: u64,
+    /// Offset 8: last modification of the contents, in nanoseconds since the Unix epoch.
+    pub mtime_ns: i64,
+    /// Offset 16: last change of the contents or metadata (st_ctime / ChangeTime), in
+    /// nanoseconds since the Unix epoch.
+    pub ctime_ns: i64,
+    /// Offset 24: a 128-bit identity of the file, equal for every path and hard link to it:
+    /// `[st_dev, st_ino]` on Unix, `[VolumeSerialNumber, FileId]` on Windows, where the
+    /// FileId's two halves are XORed together (the upper one is 0 on NTFS).
+    pub file_id: [u64; 2],
+    /// Offset 40: `MMAP_STAT_*` bits.
+    pub flags: u32,
+    /// Offset 44: always 0.
+    pub reserved: u32,
+}
+
+const _: () = {
+    assert!(offset_of!(MmapStat, size) == 0);
+    assert!(offset_of!(MmapStat, mtime_ns) == 8);
+    assert!(offset_of!(MmapStat, ctime_ns) == 16);
+    assert!(offset_of!(MmapStat, file_id) == 24);
+    assert!(offset_of!(MmapStat, flags) == 40);
+    assert!(offset_of!(MmapStat, reserved) == 44);
+    assert!(size_of::<MmapStat>() == 48);
+};
+
+pub(crate) fn stat(file: &File) -> Result<MmapStat, Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            Ok(from_metadata(&file.metadata()?))
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SPARSE_FILE, FILE_BASIC_INFO, FILE_ID_INFO,
+                FileBasicInfo, FileIdInfo, GetFileInformationByHandleEx,
+            };
+            unsafe fn query<T>(file: &File, class: i32) -> Result<T, Error> {
+                let mut info: T = unsafe { core::mem::zeroed() };
+                let ok = unsafe {
+                    GetFileInformationByHandleEx(
+                        file.as_raw_handle(),
+                        class,
+                        (&mut info as *mut T).cast(),
+                        size_of::<T>() as u32,
+                    )
+                };
+                if ok == 0 {
+                    return Err(Error::last_os());
+                }
+                Ok(info)
+            }
+            let meta = file.metadata()?;
+            let basic: FILE_BASIC_INFO = unsafe { query(file, FileBasicInfo)? };
+            let id: FILE_ID_INFO = unsafe { query(file, FileIdInfo)? };
+            // FILETIMEs count 100 ns intervals since 1601.
+            const EPOCH_DIFF: i64 = 11_644_473_600 * 10_000_000;
+            let ns = |t: i64| (t - EPOCH_DIFF).saturating_mul(100);
+            let bytes = id.FileId.Identifier;
+            let lo = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+            let hi = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+            let mut flags = 0;
+            if basic.FileAttributes & FILE_ATTRIBUTE_SPARSE_FILE != 0 {
+                flags |= MMAP_STAT_SPARSE;
+            }
+            if basic.FileAttributes & FILE_ATTRIBUTE_READONLY != 0 {
+                flags |= MMAP_STAT_READ_ONLY;
+            }
+            Ok(MmapStat {
+                size: meta.len(),
+                mtime_ns: ns(basic.LastWriteTime),
+                ctime_ns: ns(basic.ChangeTime),
+                file_id: [id.VolumeSerialNumber, lo ^ hi],
+                flags,
+                reserved: 0,
+            })
+        }
+    }
+}
+
+/// Stats whatever `path` refers to now, without opening it where the platform allows.
+pub(crate) fn stat_path(path: &str) -> Result<MmapStat, std::io::Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            Ok(from_metadata(&std::fs::metadata(path)?))
+        } else if #[cfg(windows)] {
+            Ok(stat(&File::open(path)?)?)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn from_metadata(meta: &std::fs::Metadata) -> MmapStat {
+    use std::os::unix::fs::MetadataExt;
+    let ns = |s: i64, ns: i64| s.saturating_mul(1_000_000_000).saturating_add(ns);
+    let mut flags = 0;
+    if (meta.blocks() as u128) * 512 < meta.size() as u128 {
+        flags |= MMAP_STAT_SPARSE;
+    }
+    if meta.permissions().readonly() {
+        flags |= MMAP_STAT_READ_ONLY;
+    }
+    MmapStat {
+        size: meta.size(),
+        mtime_ns: ns(meta.mtime(), meta.mtime_nsec()),
+        ctime_ns: ns(meta.ctime(), meta.ctime_nsec()),
+        file_id: [meta.dev(), meta.ino()],
+        flags,
+        reserved: 0,
+    }
+}
+
+/// Stats the file behind the handle now (not as it was at open, unlike
+/// `mmap_handle_file_changed`) and writes the result to `out`. Works on closed handles too.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` if `h` or `out` is null, or the
+/// OS error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_stat(h: *const MmapHandle, out: *mut MmapStat) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if out.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    match stat(h.file()) {
+        Ok(st) => {
+            unsafe { *out = st };
+            0
+        }
+        Err(e) => error::fail(e),
+    }
+}
+
+/// Nanoseconds since the Unix epoch, negative before it.
+fn to_ns(t: SystemTime) -> i64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => i64::try_from(d.as_nanos()).unwrap_or(i64::MAX),
+        Err(e) => i64::try_from(e.duration().as_nanos()).map_or(i64::MIN, |ns| -ns),
+    }
+}
+
+fn from_ns(ns: i64) -> SystemTime {
+    let d = Duration::from_nanos(ns.unsigned_abs());
+    if ns < 0 {
+        UNIX_EPOCH - d
+    } else {
+        UNIX_EPOCH + d
+    }
+}
+
+/// Writes the last access and modification times of the file behind the handle, in
+/// nanoseconds since the Unix epoch, to `atime_out` and `mtime_out` (either may be null). The
+/// precision is the file system's: nanoseconds on most Unix ones, 100 ns on NTFS, 2 s for the
+/// mtime on FAT. Works on closed handles too.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` for a null handle, or the OS
+/// error).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_get_times(
+    h: *const MmapHandle,
+    atime_out: *mut i64,
+    mtime_out: *mut i64,
+) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let times = h
+        .file()
+        .metadata()
+        .and_then(|meta| Ok((to_ns(meta.accessed()?), to_ns(meta.modified()?))));
+    match times {
+        Ok((atime, mtime)) => {
+            unsafe {
+                if !atime_out.is_null() {
+                    *atime_out = atime;
+                }
+                if !mtime_out.is_null() {
+                    *mtime_out = mtime;
+                }
+            }
+            0
+        }
+        Err(e) => error::fail(e.into()),
+    }
+}
+
+/// Sets the last access and modification times of the file behind the handle to `atime_ns`
+/// and `mtime_ns`, in nanoseconds since the Unix epoch; -1 leaves that time as it is. E.g. to
+/// give a rewritten asset back its old mtime (from `mmap_get_times`) so caches keyed on it stay
+/// valid. Writes through a mapping update the mtime whenever the OS notices them, which may be
+/// as late as a flush or the unmap, so set the times after `mmap_handle_close`: this works on
+/// closed handles too, through the descriptor kept until `mmap_handle_free`. The new mtime
+/// counts as a change for `mmap_handle_file_changed`. Uses futimens on Unix and SetFileTime
+/// on Windows, with the precision of the file system (see `mmap_get_times`); the OS's own
+/// rules decide who may set the times (the file's owner always may, also through a read-only
+/// handle).
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` for a null handle, or the OS
+/// error, e.g. EPERM).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_set_times(h: *const MmapHandle, atime_ns: i64, mtime_ns: i64) -> i32 {
+    let Some(h) = (unsafe { h.as_ref() }) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let mut times = FileTimes::new();
+    if atime_ns != -1 {
+        times = times.set_accessed(from_ns(atime_ns));
+    }
+    if mtime_ns != -1 {
+        times = times.set_modified(from_ns(mtime_ns));
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let set = h.file().set_times(times);
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::{AsRawHandle, FromRawHandle};
+            use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, FILE_WRITE_ATTRIBUTES,
+                ReOpenFile,
+            };
+            // The handle's own descriptor is opened for reading only.
+            let share = FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE;
+            let raw = unsafe { ReOpenFile(h.file().as_raw_handle(), FILE_WRITE_ATTRIBUTES, share, 0) };
+            if raw == INVALID_HANDLE_VALUE {
+                return error::fail(Error::last_os());
+            }
+            let set = unsafe { File::from_raw_handle(raw) }.set_times(times);
+        }
+    }
+    match set {
+        Ok(()) => 0,
+        Err(e) => error::fail(e.into()),
+    }
+}
@@ -0,0 +1,74 @@
+// File-level durability primitives behind `mmap_file_sync`, `mmap_flush_full`,
+// `mmap_handle_sync_all` and `mmap_flush_durable`.
+
+use std::fs::File;
+use std::io;
+
+#[derive(Clone, Copy)]
+pub(crate) enum SyncMode {
+    /// fdatasync: file data plus the metadata needed to read it back.
+    Data,
+    /// fsync / FlushFileBuffers.
+    All,
+    /// The strongest primitive available: F_FULLFSYNC on macOS, which also flushes the
+    /// drive's write cache; the same as `All` elsewhere.
+    Full,
+}
+
+pub(crate) fn sync_file(file: &File, mode: SyncMode) -> io::Result<()> {
+    match mode {
+        SyncMode::Data => file.sync_data(),
+        SyncMode::All => fsync(file),
+        SyncMode::Full => {
+            cfg_if::cfg_if! {
+                if #[cfg(target_vendor = "apple")] {
+                    use std::os::fd::AsRawFd;
+                    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_FULLFSYNC) } == 0 {
+                        return Ok(());
+                    }
+                    let e = io::Error::last_os_error();
+                    // Some filesystems (network mounts in particular) don't implement it.
+                    match e.raw_os_error() {
+                        Some(libc::ENOTSUP) | Some(libc::EINVAL) => fsync(file),
+                        _ => Err(e),
+                    }
+                } else {
+                    fsync(file)
+                }
+            }
+        }
+    }
+}
+
+/// A plain fsync. std's `sync_all` is F_FULLFSYNC on Apple platforms, so it is only used
+/// directly elsewhere.
+fn fsync(file: &File) -> io::Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_vendor = "apple")] {
+            use std::os::fd::AsRawFd;
+            if unsafe { libc::fsync(file.as_raw_fd()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        } else {
+            file.sync_all()
+        }
+    }
+}
+
+/// Fsyncs the directory holding `path`, so the directory entry of a newly created file is
+/// durable too. Windows has no equivalent (NTFS journals the entry itself); it does nothing.
+pub(crate) fn sync_parent_dir(path: &std::path::Path) -> io::Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => std::path::Path::new("."),
+            };
+            fsync(&File::open(dir)?)
+        } else {
+            let _ = path;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// Text helpers that inspect mapped bytes in place, so JS doesn't have to copy them out first.
+
+use std::os::raw::c_void;
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG};
+
+/// Checks whether `[base + offset, base + offset + len)` is valid UTF-8.
+/// Returns 0 if it is, 1 if not (writing the mapping offset of the first invalid sequence,
+/// i.e. `offset + valid bytes`, to `err_offset_out` if non-null), or -1 with
+/// `MMAP_ERR_INVALID_ARG` if `base` is null.
+///
+/// Safety: the range must lie within the mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_validate_utf8(
+    base: *const c_void,
+    offset: usize,
+    len: usize,
+    err_offset_out: *mut usize,
+) -> i32 {
+    if base.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let bytes = unsafe { core::slice::from_raw_parts((base as *const u8).add(offset), len) };
+    match std::str::from_utf8(bytes) {
+        Ok(_) => 0,
+        Err(e) => {
+            if !err_offset_out.is_null() {
+                unsafe {
+                    *err_offset_out = offset + e.valid_up_to();
+                }
+            }
+            1
+        }
+    }
+}
+
+/// Finds the line starting at `cursor` in `[base, base + len)`: writes its offset to
+/// `line_start_out` and its length, excluding the `\n` and a preceding `\r`, to
+/// `line_len_out`. A final line without a trailing newline is returned too.
+/// Returns the cursor for the next call (just past the newline), or -1 once `cursor`
+/// reaches `len` (and, recording `MMAP_ERR_INVALID_ARG`, if any pointer is null).
+///
+/// Safety: `[base, base + len)` must lie within a mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_next_line(
+    base: *const c_void,
+    len: usize,
+    cursor: usize,
+    line_start_out: *mut usize,
+    line_len_out: *mut usize,
+) -> isize {
+    if base.is_null() || line_start_out.is_null() || line_len_out.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    }
+    if cursor >= len {
+        return -1;
+    }
+    let rest =
+        unsafe { core::slice::from_raw_parts((base as *const u8).add(cursor), len - cursor) };
+    let (mut line_len, next) = match rest.iter().position(|&b| b == b'\n') {
+        Some(i) => (i, cursor + i + 1),
+        None => (rest.len(), len),
+    };
+    if line_len > 0 && rest[line_len - 1] == b'\r' {
+        line_len -= 1;
+    }
+    unsafe {
+        *line_start_out = cursor;
+        *line_len_out = line_len;
+    }
+    next as isize
+}
@@ -0,0 +1,298 @@
+// Comparing a mapping with the file behind it: `mmap_flush_verify` reads a flushed range back
+// to prove it reached the disk, `mmap_diff_count` measures how much a private (copy-on-write)
+// view has drifted from the file.
+//
+// A plain read would be served from the page cache the mapping itself lives in and always
+// match, so where possible the read-back goes through a second, unbuffered descriptor
+// (O_DIRECT on Linux, FILE_FLAG_NO_BUFFERING on Windows) and really reaches the device. File
+// systems that refuse unbuffered I/O (tmpfs, some network mounts) and other platforms fall back
+// to positional reads on the mapping's own descriptor, which still catch a range that never
+// made it into the file (a truncate, a failed write-back).
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::raw::{c_char, c_void};
+
+use crate::error::{
+    self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_TRUNCATED, MMAP_ERR_UNSUPPORTED, MMAP_ERR_VERIFY,
+};
+use crate::registry::{self, Kind};
+
+/// Bytes read back per call; a multiple of any page size.
+const CHUNK: usize = 1 << 20;
+
+/// A page-aligned buffer, as unbuffered reads require.
+struct Scratch(*mut u8);
+
+impl Scratch {
+    fn new() -> Result<Scratch, Error> {
+        Ok(Scratch(unsafe { crate::open::anon_alloc(CHUNK)? }.cast()))
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.0, CHUNK) }
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        unsafe { crate::open::anon_free(self.0.cast(), CHUNK) }
+    }
+}
+
+/// Opens a second descriptor on `file` that bypasses the page cache, if the platform and the
+/// file system allow it.
+fn reopen_unbuffered(file: &File) -> Option<File> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            use std::os::fd::{AsRawFd, FromRawFd};
+            let path = format!("/proc/self/fd/{}\0", file.as_raw_fd());
+            let fd = unsafe {
+                libc::open(path.as_ptr().cast(), libc::O_RDONLY | libc::O_DIRECT | libc::O_CLOEXEC)
+            };
+            (fd >= 0).then(|| unsafe { File::from_raw_fd(fd) })
+        } else if #[cfg(windows)] {
+            use std::os::windows::io::{AsRawHandle, FromRawHandle};
+            use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_FLAG_NO_BUFFERING, FILE_GENERIC_READ, FILE_SHARE_READ, FILE_SHARE_WRITE, ReOpenFile,
+            };
+            let h = unsafe {
+                ReOpenFile(
+                    file.as_raw_handle(),
+                    FILE_GENERIC_READ,
+                    FILE_SHARE_READ | FILE_SHARE_WRITE,
+                    FILE_FLAG_NO_BUFFERING,
+                )
+            };
+            (h != INVALID_HANDLE_VALUE).then(|| unsafe { File::from_raw_handle(h) })
+        } else {
+            let _ = file;
+            None
+        }
+    }
+}
+
+fn read_at(file: &File, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            std::os::unix::fs::FileExt::read_at(file, buf, pos)
+        } else if #[cfg(windows)] {
+            std::os::windows::fs::FileExt::seek_read(file, buf, pos)
+        }
+    }
+}
+
+/// Fills `buf` from `pos` up to the end of the file. An unbuffered read stops at the first
+/// short read, since the next position would no longer be aligned.
+fn read_chunk(file: &File, buf: &mut [u8], pos: u64, unbuffered: bool) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = read_at(file, &mut buf[filled..], pos + filled as u64)?;
+        filled += n;
+        if n == 0 || unbuffered {
+            break;
+        }
+    }
+    Ok(filled)
+}
+
+/// Compares `[offset, end)` of the mapping at `base` with the file contents.
+fn compare(base: usize, offset: usize, end: usize, file: &File) -> Result<(), (Error, String)> {
+    let plain = |e: Error| (e, String::new());
+    let direct = reopen_unbuffered(file);
+    let source = direct.as_ref().unwrap_or(file);
+    let mut scratch = Scratch::new().map_err(plain)?;
+    let buf = scratch.bytes();
+    let mut pos = offset & !(crate::page_size() - 1);
+    while pos < end {
+        let n = read_chunk(source, buf, pos as u64, direct.is_some())
+            .map_err(|e| plain(Error::io_sync(e)))?;
+        let lo = pos.max(offset);
+        let hi = (pos + n).min(end);
+        if hi <= lo {
+            return Err((
+                Error::new(MMAP_ERR_TRUNCATED),
+                format!(
+                    "file ends at {}, before the end of the range at {end}",
+                    pos + n
+                ),
+            ));
+        }
+        if let Some(off) = crate::hooks::corrupt_verify().filter(|off| (lo..hi).contains(off)) {
+            buf[off - pos] ^= 0xff;
+        }
+        let disk = &buf[lo - pos..hi - pos];
+        let mem = unsafe { std::slice::from_raw_parts((base + lo) as *const u8, hi - lo) };
+        if let Some(i) = mem.iter().zip(disk).position(|(a, b)| a != b) {
+            return Err((
+                Error::new(MMAP_ERR_VERIFY),
+                format!("flushed data differs from the mapping at offset {}", lo + i),
+            ));
+        }
+        pos += n;
+    }
+    Ok(())
+}
+
+/// Like `mmap_flush_durable`, then reads `[base + offset, base + offset + len)` back from the
+/// file, bypassing the page cache where the platform and file system allow it (O_DIRECT on
+/// Linux, FILE_FLAG_NO_BUFFERING on Windows; plain positional reads elsewhere), and compares
+/// it with the mapping. Nothing may write to the range while this runs.
+/// Returns 0 if the file matches, or -1 with `MMAP_ERR_VERIFY` if a byte differs (the first
+/// differing mapping offset is in `mmap_last_error_message`), `MMAP_ERR_TRUNCATED` if the file
+/// ends inside the range, the `mmap_flush_durable` error if the flush fails, or
+/// `MMAP_ERR_UNSUPPORTED` for mappings without a file to read back (snapshots, and read-only
+/// mappings on Windows).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_flush_verify(base: *mut c_void, offset: usize, len: usize) -> i32 {
+    if unsafe { crate::mmap_flush_durable(base, offset, len) } != 0 {
+        return -1;
+    }
+    // Held across the read-back so the mapping can't be unmapped underneath it.
+    let reg = registry::lock();
+    let Some(m) = reg.get(&(base as usize)) else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let Some(file) = m.file.as_ref().filter(|_| m.kind != Kind::Snapshot) else {
+        return error::fail(Error::new(MMAP_ERR_UNSUPPORTED));
+    };
+    // The range was checked against the mapping by the flush.
+    match compare(base as usize, offset, offset + len, file) {
+        Ok(()) => 0,
+        Err((e, detail)) if detail.is_empty() => error::fail(e),
+        Err((e, detail)) => error::fail_with(e, detail),
+    }
+}
+
+/// Counts the bytes of `[base, base + len)` that differ from the first `len` bytes of the file
+/// at `path`, e.g. to decide whether a private mapping written to since it was opened is worth
+/// persisting. Bytes past the end of the file count as different. The file is streamed in
+/// fixed-size chunks rather than mapped, so memory use stays bounded.
+/// Returns the count, or -1 on failure (`MMAP_ERR_INVALID_ARG` for a null pointer or a path
+/// that isn't UTF-8, `MMAP_ERR_OS` if the file can't be read).
+///
+/// Safety: `[base, base + len)` must lie within a mapping.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_diff_count(
+    base: *const c_void,
+    path: *const c_char,
+    len: usize,
+) -> isize {
+    if base.is_null() || path.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    }
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG)) as isize;
+    };
+    let mem = unsafe { std::slice::from_raw_parts(base as *const u8, len) };
+    let count = || -> io::Result<usize> {
+        let mut file = File::open(path)?;
+        let mut buf = vec![0u8; CHUNK.min(len)];
+        let mut diff = 0;
+        let mut pos = 0;
+        while pos < len {
+            let want = buf.len().min(len - pos);
+            let n = file.read(&mut buf[..want])?;
+            if n == 0 {
+                // The file ended: the rest of the mapping has no counterpart.
+                return Ok(diff + len - pos);
+            }
+            diff += mem[pos..pos + n]
+                .iter()
+                .zip(&buf[..n])
+                .filter(|(a, b)| a != b)
+                .count();
+            pos += n;
+        }
+        Ok(diff)
+    };
+    match count() {
+        Ok(diff) => diff as isize,
+        Err(e) => error::fail(e.into()) as isize,
+    }
+}
+
+/// Checks whether writes through a shared mapping really reach files in the directory `path`
+/// (or the directory holding it, if `path` names a file): some NFS and overlayfs mounts accept
+/// the mapping but don't propagate its writes. Creates a one-page probe file there, writes a
+/// sentinel through a shared mapping of it, flushes, and reads it back through a second
+/// descriptor (unbuffered when the file system allows), then deletes the probe.
+/// Returns 1 if the sentinel came back, 0 if it didn't (fall back to buffered I/O), or -1 on
+/// failure (`MMAP_ERR_INVALID_ARG` for a null or non-UTF-8 path, `MMAP_ERR_OS` if the probe
+/// can't be created, mapped or read, e.g. in a read-only directory).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_supports_shared_write(path: *const c_char) -> i32 {
+    if path.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    let path = std::path::Path::new(path);
+    let dir = if path.is_dir() {
+        path
+    } else {
+        path.parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(std::path::Path::new("."))
+    };
+    match probe_shared_write(dir) {
+        Ok(persisted) => persisted as i32,
+        Err(e) => error::fail(e),
+    }
+}
+
+fn probe_shared_write(dir: &std::path::Path) -> Result<bool, Error> {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    static SEQ: AtomicU32 = AtomicU32::new(0);
+
+    /// Deletes the probe file on drop.
+    struct Probe(std::path::PathBuf);
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    let name = format!(
+        ".mmap-probe-{}-{}",
+        std::process::id(),
+        SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+    let probe = Probe(dir.join(name));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&probe.0)?;
+    let page = crate::page_size();
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let sentinel = (nanos ^ (std::process::id() as u64) << 32).to_le_bytes();
+
+    let written = unsafe {
+        let addr = crate::open::map_grown(&file, page)?;
+        let p = addr as *mut u8;
+        // At both ends of the page, so a partially written-back page doesn't pass.
+        std::ptr::copy_nonoverlapping(sentinel.as_ptr(), p, sentinel.len());
+        std::ptr::copy_nonoverlapping(sentinel.as_ptr(), p.add(page - sentinel.len()), 8);
+        let synced = crate::sync_view(addr as usize, page);
+        crate::mmap_close(addr, page);
+        synced
+    };
+    written?;
+    drop(file);
+
+    let reader = File::open(&probe.0)?;
+    let (reader, unbuffered) = match reopen_unbuffered(&reader) {
+        Some(direct) => (direct, true),
+        None => (reader, false),
+    };
+    let mut scratch = Scratch::new()?;
+    let buf = &mut scratch.bytes()[..page];
+    let n = read_chunk(&reader, buf, 0, unbuffered)?;
+    Ok(n == page && buf[..8] == sentinel && buf[page - 8..] == sentinel)
+}
@@ -0,0 +1,46 @@
+// Build identification, so a loader can reject a mismatched binary before calling into it.
+
+use std::os::raw::c_char;
+
+/// Bumped whenever an exported function signature, struct layout or error code changes.
+/// 2: null pointers passed to the byte-count and prefetch/text functions are reported through
+/// `mmap_last_error` like everywhere else.
+/// 3: error codes -21 to -24 (`MMAP_ERR_MAPPED_LARGER` to `MMAP_ERR_QUOTA`), and
+/// `mmap_handle_close` leaves the mapping in place while clones of the handle are open.
+pub const MMAP_ABI_VERSION: u32 = 3;
+
+/// "<crate version> abi=<n> <os>-<arch>", e.g. "0.1.0 abi=1 linux-x86_64".
+fn build_info() -> String {
+    format!(
+        "{} abi={} {}-{}",
+        env!("CARGO_PKG_VERSION"),
+        MMAP_ABI_VERSION,
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+/// Writes the build-info string into `out` as a NUL-terminated string, truncated to fit
+/// `cap` bytes. Returns the number of bytes written excluding the NUL, or 0 if `out` is
+/// null or `cap` is 0.
+///
+/// Safety: `out` must point to a writable buffer of at least `cap` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_version(out: *mut c_char, cap: usize) -> usize {
+    if out.is_null() || cap == 0 {
+        return 0;
+    }
+    let info = build_info();
+    let n = info.len().min(cap - 1);
+    unsafe {
+        core::ptr::copy_nonoverlapping(info.as_ptr(), out as *mut u8, n);
+        *out.add(n) = 0;
+    }
+    n
+}
+
+/// Returns `MMAP_ABI_VERSION` of this build.
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_abi_version() -> u32 {
+    MMAP_ABI_VERSION
+}
@@ -0,0 +1,262 @@
+// Sliding windows over files too large to map at once, or whose mappings must stay small (e.g.
+// on 32-bit systems): a window maps an aligned piece of the file around the offset last
+// sought to, and seeking outside it replaces the mapping, so address space use stays bounded
+// by the window size whatever the file size. Windows are read-only and addressed by 64-bit
+// ids, like logs.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fs::File;
+use std::os::raw::{c_char, c_void};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::{self, Error, MMAP_ERR_INVALID_ARG, MMAP_ERR_OUT_OF_BOUNDS, MMAP_OK};
+
+struct Window {
+    #[cfg(unix)]
+    file: File,
+    /// A read-only file mapping object covering the whole file, which views are mapped from;
+    /// it keeps the file open.
+    #[cfg(windows)]
+    section: windows_sys::Win32::Foundation::HANDLE,
+    /// The file size at open; later growth is not followed.
+    size: u64,
+    /// The `window_size` asked for: every seek leaves at least this many bytes (or the rest of
+    /// the file) mapped from the offset on.
+    min_len: usize,
+    /// What gets mapped: `min_len` rounded up to the alignment, plus the alignment, so any
+    /// offset's aligned start still covers `min_len` bytes from it.
+    span: usize,
+    /// The current mapping, if any (`base` 0 if not).
+    start: u64,
+    base: usize,
+    len: usize,
+}
+
+// The section handle is only used under the window's lock.
+#[cfg(windows)]
+unsafe impl Send for Window {}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        self.unmap();
+        #[cfg(windows)]
+        if !self.section.is_null() {
+            unsafe { windows_sys::Win32::Foundation::CloseHandle(self.section) };
+        }
+    }
+}
+
+impl Window {
+    fn unmap(&mut self) {
+        if self.base == 0 {
+            return;
+        }
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                unsafe { libc::munmap(self.base as *mut c_void, self.len) };
+            } else if #[cfg(windows)] {
+                use windows_sys::Win32::System::Memory::{MEMORY_MAPPED_VIEW_ADDRESS, UnmapViewOfFile};
+                unsafe { UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: self.base as *mut c_void }) };
+            }
+        }
+        self.base = 0;
+    }
+
+    /// Maps `len` bytes of the file from the aligned offset `start`.
+    fn map(&mut self, start: u64, len: usize) -> Result<(), Error> {
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                use std::os::fd::AsRawFd;
+                let (prot, flags, fd) = (libc::PROT_READ, libc::MAP_SHARED, self.file.as_raw_fd());
+                #[cfg(all(target_os = "linux", target_env = "gnu"))]
+                let addr = unsafe { libc::mmap64(core::ptr::null_mut(), len, prot, flags, fd, start as i64) };
+                #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+                let addr = {
+                    let off = libc::off_t::try_from(start).map_err(|_| Error::new(MMAP_ERR_OUT_OF_BOUNDS))?;
+                    unsafe { libc::mmap(core::ptr::null_mut(), len, prot, flags, fd, off) }
+                };
+                if addr == libc::MAP_FAILED {
+                    return Err(Error::last_os());
+                }
+            } else if #[cfg(windows)] {
+                use windows_sys::Win32::System::Memory::{FILE_MAP_READ, MapViewOfFile};
+                let view = unsafe {
+                    MapViewOfFile(self.section, FILE_MAP_READ, (start >> 32) as u32, start as u32, len)
+                };
+                if view.Value.is_null() {
+                    return Err(Error::last_os());
+                }
+                let addr = view.Value;
+            }
+        }
+        self.start = start;
+        self.base = addr as usize;
+        self.len = len;
+        Ok(())
+    }
+}
+
+struct Windows {
+    next_id: u64,
+    open: BTreeMap<u64, Arc<Mutex<Window>>>,
+}
+
+static WINDOWS: Mutex<Windows> = Mutex::new(Windows {
+    next_id: 1,
+    open: BTreeMap::new(),
+});
+
+fn windows() -> MutexGuard<'static, Windows> {
+    WINDOWS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn lock(window: &Mutex<Window>) -> MutexGuard<'_, Window> {
+    window.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Opens `path` for windowed reading with windows of at least `window_size` bytes; nothing is
+/// mapped until the first `mmap_window_seek`. Each window maps up to `window_size` plus twice
+/// the mapping alignment (see `mmap_window_seek`).
+/// Returns the window's id, or 0 on failure, with the error code also written to `err_out` if
+/// it is non-null (`MMAP_OK` on success): `MMAP_ERR_INVALID_ARG` for a null path or
+/// `window_size == 0`, the file type errors of `mmap_open`, or the open error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_window_open(
+    path: *const c_char,
+    window_size: usize,
+    err_out: *mut i32,
+) -> u64 {
+    let result = unsafe { open_window(path, window_size) };
+    if !err_out.is_null() {
+        unsafe { *err_out = result.as_ref().map_or_else(|e| e.code, |_| MMAP_OK) };
+    }
+    match result {
+        Ok(window) => {
+            let mut windows = windows();
+            let id = windows.next_id;
+            windows.next_id += 1;
+            windows.open.insert(id, Arc::new(Mutex::new(window)));
+            id
+        }
+        Err(e) => {
+            error::set(e);
+            0
+        }
+    }
+}
+
+unsafe fn open_window(path: *const c_char, window_size: usize) -> Result<Window, Error> {
+    if path.is_null() || window_size == 0 {
+        return Err(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let path = unsafe { CStr::from_ptr(path) };
+    unsafe { crate::open::precheck(path, 0)? };
+    let path_str = path
+        .to_str()
+        .map_err(|_| Error::new(MMAP_ERR_INVALID_ARG))?;
+    let align = crate::map_granularity();
+    let span = window_size
+        .checked_next_multiple_of(align)
+        .and_then(|n| n.checked_add(align))
+        .ok_or(Error::new(MMAP_ERR_INVALID_ARG))?;
+    let file = File::open(path_str)?;
+    let size = file.metadata()?.len();
+    #[cfg(windows)]
+    let section = if size == 0 {
+        core::ptr::null_mut()
+    } else {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::System::Memory::{CreateFileMappingA, PAGE_READONLY};
+        let h = unsafe {
+            CreateFileMappingA(
+                file.as_raw_handle(),
+                core::ptr::null_mut(),
+                PAGE_READONLY,
+                0,
+                0,
+                core::ptr::null(),
+            )
+        };
+        if h.is_null() {
+            return Err(Error::last_os());
+        }
+        h
+    };
+    Ok(Window {
+        #[cfg(unix)]
+        file,
+        #[cfg(windows)]
+        section,
+        size,
+        min_len: window_size,
+        span,
+        start: 0,
+        base: 0,
+        len: 0,
+    })
+}
+
+/// Makes `file_offset` readable: if the current window doesn't hold `window_size` bytes from
+/// it (or the rest of the file, if less), the window is unmapped and the aligned piece of the
+/// file starting at or just before `file_offset` is mapped instead. Writes a pointer to the
+/// byte at `file_offset` to `out_ptr` and the number of bytes readable from it to
+/// `out_valid_len`, at least `window_size` unless the file ends first; seek again to read past
+/// them. At the end of the file the length is 0 (and the pointer null). The pointer is valid
+/// until the next seek or close of the window.
+/// Returns 0 on success, -1 on failure (`MMAP_ERR_INVALID_ARG` for an unknown id or null
+/// outputs, `MMAP_ERR_OUT_OF_BOUNDS` if `file_offset` is past the end of the file as it was at
+/// open, or the mapping error, which leaves nothing mapped).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mmap_window_seek(
+    id: u64,
+    file_offset: u64,
+    out_ptr: *mut *const u8,
+    out_valid_len: *mut usize,
+) -> i32 {
+    let Some(window) = windows().open.get(&id).cloned() else {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    };
+    if out_ptr.is_null() || out_valid_len.is_null() {
+        return error::fail(Error::new(MMAP_ERR_INVALID_ARG));
+    }
+    let mut w = lock(&window);
+    if file_offset > w.size {
+        return error::fail(Error::new(MMAP_ERR_OUT_OF_BOUNDS));
+    }
+    let needed = (w.min_len as u64).min(w.size - file_offset);
+    if needed == 0 {
+        unsafe {
+            *out_ptr = std::ptr::null();
+            *out_valid_len = 0;
+        }
+        return 0;
+    }
+    let end = w.start + w.len as u64;
+    if w.base == 0 || file_offset < w.start || file_offset + needed > end {
+        let align = crate::map_granularity() as u64;
+        let start = file_offset / align * align;
+        let len = (w.span as u64).min(w.size - start) as usize;
+        // Unmapped first, so there is never more than one window's worth mapped.
+        w.unmap();
+        if let Err(e) = w.map(start, len) {
+            return error::fail(e);
+        }
+    }
+    let skip = (file_offset - w.start) as usize;
+    unsafe {
+        *out_ptr = (w.base as *const u8).add(skip);
+        *out_valid_len = w.len - skip;
+    }
+    0
+}
+
+/// Unmaps the window and closes its file; the id is invalid afterwards.
+/// Returns 0 on success, -1 for an unknown id (`MMAP_ERR_INVALID_ARG`).
+#[unsafe(no_mangle)]
+pub extern "C" fn mmap_window_close(id: u64) -> i32 {
+    match windows().open.remove(&id) {
+        Some(_) => 0,
+        None => error::fail(Error::new(MMAP_ERR_INVALID_ARG)),
+    }
+}
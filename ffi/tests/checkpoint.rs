@@ -0,0 +1,133 @@
+// Crash consistency of the checkpoint protocol: a child process (this test binary, re-run as
+// `checkpoint_writer`) rewrites a region and commits checkpoints in a loop until it is killed
+// at a random moment; the parent then reopens the file and checks what survived.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use deno_mmap_ffi::{
+    MMAP_CHECKPOINT_HEADER_SIZE, MmapCheckpointHeader, mmap_checkpoint_begin,
+    mmap_checkpoint_commit, mmap_checkpoint_init, mmap_checkpoint_verify, mmap_close,
+    mmap_open_write, mmap_open_write_with_size, mmap_write,
+};
+
+const REGION: usize = 256 * 1024;
+const CHILD_ENV: &str = "MMAP_CHECKPOINT_CHILD";
+
+/// The region contents the writer commits as checkpoint `seq`.
+fn pattern(seq: u64) -> Vec<u8> {
+    (0..REGION)
+        .map(|i| (seq.wrapping_mul(31) as usize).wrapping_add(i) as u8)
+        .collect()
+}
+
+fn open(path: &str) -> (*mut std::os::raw::c_void, usize) {
+    let path = std::ffi::CString::new(path).unwrap();
+    let mut len = 0;
+    let base = unsafe { mmap_open_write(path.as_ptr(), &mut len) };
+    assert!(!base.is_null(), "mmap_open_write failed");
+    (base, len)
+}
+
+/// Not a test by itself: the child side, which only runs when the parent sets `CHILD_ENV`.
+#[test]
+fn checkpoint_writer() {
+    let Ok(path) = std::env::var(CHILD_ENV) else {
+        return;
+    };
+    let (base, _) = open(&path);
+    let mut seq = 0u64;
+    loop {
+        seq += 1;
+        // Rewrite the region in pieces, so kills also land halfway through it.
+        let data = pattern(seq);
+        for (i, piece) in data.chunks(REGION / 16).enumerate() {
+            let offset = MMAP_CHECKPOINT_HEADER_SIZE + i * piece.len();
+            unsafe { mmap_write(base, offset, piece.as_ptr(), piece.len()) };
+        }
+        assert_eq!(unsafe { mmap_checkpoint_begin(base) }, seq as i64);
+        assert_eq!(unsafe { mmap_checkpoint_commit(base) }, 0);
+        println!("committed {seq}");
+    }
+}
+
+#[test]
+fn last_committed_checkpoint_survives_kill() {
+    let dir = std::env::temp_dir();
+    let mut rng = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+        | 1;
+    for round in 0..6 {
+        let path = dir.join(format!("mmap-checkpoint-{}-{round}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let c_path = std::ffi::CString::new(path.clone()).unwrap();
+        let mut len = 0;
+        let size = MMAP_CHECKPOINT_HEADER_SIZE + REGION;
+        let base = unsafe { mmap_open_write_with_size(c_path.as_ptr(), &mut len, size) };
+        assert!(!base.is_null());
+        assert_eq!(
+            unsafe { mmap_checkpoint_init(base, MMAP_CHECKPOINT_HEADER_SIZE, REGION) },
+            0
+        );
+        unsafe { mmap_close(base, len) };
+
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "checkpoint_writer",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(CHILD_ENV, &path)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let reported = Arc::new(Mutex::new(0u64));
+        let reader = {
+            let reported = reported.clone();
+            let stdout = child.stdout.take().unwrap();
+            std::thread::spawn(move || {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if let Some(seq) = line.strip_prefix("committed ") {
+                        *reported.lock().unwrap() = seq.parse().unwrap();
+                    }
+                }
+            })
+        };
+        // xorshift: a kill time between 20 and 270 ms.
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        std::thread::sleep(Duration::from_millis(20 + rng % 250));
+        child.kill().unwrap(); // SIGKILL / TerminateProcess
+        child.wait().unwrap();
+        reader.join().unwrap();
+        let reported = *reported.lock().unwrap();
+
+        let (base, len) = open(&path);
+        let mut hdr = MmapCheckpointHeader::default();
+        let intact = unsafe { mmap_checkpoint_verify(base, &mut hdr) };
+        assert!(intact >= 0, "header unreadable after the crash");
+        // Every checkpoint the child saw committed is on file, and at most one more was staged.
+        assert!(hdr.committed_seq >= reported, "lost checkpoint {reported}");
+        assert!(hdr.pending_seq - hdr.committed_seq <= 1);
+        let region = (base as *const u8).wrapping_add(MMAP_CHECKPOINT_HEADER_SIZE);
+        let region = unsafe { std::slice::from_raw_parts(region, REGION) };
+        if intact == 1 {
+            assert_eq!(
+                region,
+                pattern(hdr.committed_seq),
+                "intact checkpoint with wrong data"
+            );
+        } else {
+            // Killed while rewriting the region (or before the first commit).
+            assert!(hdr.committed_seq == 0 || region != pattern(hdr.committed_seq));
+        }
+        unsafe { mmap_close(base, len) };
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+// mmap_copy_file: contents, holes and flushing of mapped sources.
+
+use std::ffi::CString;
+use std::fs::OpenOptions;
+
+use deno_mmap_ffi::{
+    MMAP_COPY_REQUIRE_MAPPED, MMAP_ERR_INVALID_ARG, MMAP_ERR_UNSUPPORTED, mmap_clone_file,
+    mmap_close, mmap_copy_file, mmap_last_error, mmap_open_write_with_size, mmap_write,
+};
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("mmap-copy-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+fn copy(src: &str, dst: &str, flags: u32) -> i64 {
+    let (src, dst) = (CString::new(src).unwrap(), CString::new(dst).unwrap());
+    unsafe { mmap_copy_file(src.as_ptr(), dst.as_ptr(), flags) }
+}
+
+#[test]
+fn copies_a_sparse_file_and_keeps_its_hole() {
+    let (src, dst) = (temp_path("sparse-src"), temp_path("sparse-dst"));
+    const SIZE: u64 = 16 << 20;
+    let head: Vec<u8> = (0..100_000u32).map(|i| (i * 13) as u8).collect();
+    let tail = b"the end";
+    {
+        let f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&src)
+            .unwrap();
+        f.set_len(SIZE).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileExt;
+            f.write_all_at(&head, 0).unwrap();
+            f.write_all_at(tail, SIZE - tail.len() as u64).unwrap();
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::FileExt;
+            f.seek_write(&head, 0).unwrap();
+            f.seek_write(tail, SIZE - tail.len() as u64).unwrap();
+        }
+    }
+    // An existing, longer destination is replaced.
+    std::fs::write(&dst, vec![0xffu8; (SIZE + 4096) as usize]).unwrap();
+
+    assert_eq!(copy(&src, &dst, 0), SIZE as i64);
+    let (a, b) = (std::fs::read(&src).unwrap(), std::fs::read(&dst).unwrap());
+    assert_eq!(a.len(), b.len());
+    assert!(a == b, "contents differ");
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let blocks = |p: &str| std::fs::metadata(p).unwrap().blocks();
+        // Where the source has a hole, so does the copy (give or take block rounding).
+        if blocks(&src) * 512 < SIZE / 2 {
+            assert!(
+                blocks(&dst) <= blocks(&src) + 64,
+                "copy has {} blocks, source {}",
+                blocks(&dst),
+                blocks(&src)
+            );
+        }
+    }
+    for p in [src, dst] {
+        std::fs::remove_file(p).unwrap();
+    }
+}
+
+#[test]
+fn flushes_mappings_of_the_source() {
+    let (src, dst) = (temp_path("mapped-src"), temp_path("mapped-dst"));
+    std::fs::write(&src, b"unmapped").unwrap();
+    assert_eq!(copy(&src, &dst, MMAP_COPY_REQUIRE_MAPPED), -1);
+    assert_eq!(mmap_last_error(), MMAP_ERR_INVALID_ARG);
+    std::fs::remove_file(&src).unwrap();
+
+    let c_src = CString::new(src.clone()).unwrap();
+    let mut len = 0;
+    let base = unsafe { mmap_open_write_with_size(c_src.as_ptr(), &mut len, 8192) };
+    assert!(!base.is_null());
+    let data = b"written through the mapping";
+    assert_eq!(
+        unsafe { mmap_write(base, 5000, data.as_ptr(), data.len()) },
+        data.len()
+    );
+    assert_eq!(copy(&src, &dst, MMAP_COPY_REQUIRE_MAPPED), 8192);
+    let copied = std::fs::read(&dst).unwrap();
+    assert_eq!(&copied[5000..5000 + data.len()], data);
+    assert_eq!(&copied[..5000], &[0u8; 5000][..]);
+
+    // Copying a file onto itself would truncate it first.
+    assert_eq!(copy(&src, &src, 0), -1);
+    assert_eq!(mmap_last_error(), MMAP_ERR_INVALID_ARG);
+    unsafe { mmap_close(base, len) };
+    assert_eq!(std::fs::metadata(&src).unwrap().len(), 8192);
+    for p in [src, dst] {
+        std::fs::remove_file(p).unwrap();
+    }
+}
+
+fn clone(src: &str, dst: &str) -> i32 {
+    let (src, dst) = (CString::new(src).unwrap(), CString::new(dst).unwrap());
+    unsafe { mmap_clone_file(src.as_ptr(), dst.as_ptr()) }
+}
+
+#[test]
+fn clones_or_reports_unsupported() {
+    let (src, dst) = (temp_path("clone-src"), temp_path("clone-dst"));
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i * 31) as u8).collect();
+    std::fs::write(&src, &data).unwrap();
+
+    // The temp directory may or may not be on a file system that clones; either way the
+    // caller ends up with a copy.
+    if clone(&src, &dst) == 0 {
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+        // A longer destination is replaced, not overwritten in place.
+        std::fs::write(&dst, vec![1u8; 500_000]).unwrap();
+        assert_eq!(clone(&src, &dst), 0);
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+    } else {
+        assert_eq!(mmap_last_error(), MMAP_ERR_UNSUPPORTED);
+        assert!(
+            !std::fs::exists(&dst).unwrap(),
+            "a failed clone left a file behind"
+        );
+        std::fs::write(&dst, b"kept").unwrap();
+        assert_eq!(clone(&src, &dst), -1);
+        assert_eq!(std::fs::read(&dst).unwrap(), b"kept");
+        assert_eq!(copy(&src, &dst, 0), data.len() as i64);
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+    }
+
+    assert_eq!(clone(&src, &src), -1);
+    assert_eq!(mmap_last_error(), MMAP_ERR_INVALID_ARG);
+    for p in [src, dst] {
+        std::fs::remove_file(p).unwrap();
+    }
+}
+
+#[test]
+fn rejects_bad_arguments() {
+    let (src, dst) = (temp_path("args-src"), temp_path("args-dst"));
+    let c_dst = CString::new(dst.clone()).unwrap();
+    assert_eq!(
+        unsafe { mmap_copy_file(std::ptr::null(), c_dst.as_ptr(), 0) },
+        -1
+    );
+    assert_eq!(mmap_last_error(), MMAP_ERR_INVALID_ARG);
+    std::fs::write(&src, b"x").unwrap();
+    assert_eq!(copy(&src, &dst, 1 << 7), -1);
+    assert_eq!(mmap_last_error(), MMAP_ERR_INVALID_ARG);
+    // A missing source is the OS error, and creates nothing.
+    std::fs::remove_file(&src).unwrap();
+    assert_eq!(copy(&src, &dst, 0), -1);
+    assert!(!std::fs::exists(&dst).unwrap());
+}
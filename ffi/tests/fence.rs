@@ -0,0 +1,73 @@
+// Publish / consume through a shared mapping, ordered only by `mmap_fence` around relaxed flag
+// accesses, the way a JS producer and consumer would drive it.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use deno_mmap_ffi::{
+    MMAP_ERR_INVALID_ARG, MMAP_FENCE_ACQUIRE, MMAP_FENCE_RELEASE, mmap_close, mmap_compiler_fence,
+    mmap_fence, mmap_last_error, mmap_open_tmp, mmap_read, mmap_write,
+};
+
+const PAYLOAD: usize = 64;
+const PAYLOAD_LEN: usize = 4096;
+const ROUNDS: u32 = 2_000;
+
+/// The u32 at `offset` of the mapping at `base`.
+fn word(base: usize, offset: usize) -> &'static AtomicU32 {
+    unsafe { AtomicU32::from_ptr((base + offset) as *mut u32) }
+}
+
+/// Spins until the word at `offset` reaches `value`.
+fn wait_for(base: usize, offset: usize, value: u32) {
+    while word(base, offset).load(Ordering::Relaxed) != value {
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn fences_order_payload_and_flag() {
+    let mut len = 0usize;
+    let base = unsafe { mmap_open_tmp(PAYLOAD + PAYLOAD_LEN, &mut len) };
+    assert!(!base.is_null(), "mmap_open_tmp failed");
+    let addr = base as usize;
+    // Offset 0: round published by the producer; offset 4: round consumed by the consumer.
+    let producer = std::thread::spawn(move || {
+        for round in 1..=ROUNDS {
+            wait_for(addr, 4, round - 1);
+            assert_eq!(mmap_fence(MMAP_FENCE_ACQUIRE), 0);
+            let payload = [round as u8; PAYLOAD_LEN];
+            let n = unsafe { mmap_write(addr as *mut _, PAYLOAD, payload.as_ptr(), PAYLOAD_LEN) };
+            assert_eq!(n, PAYLOAD_LEN);
+            assert_eq!(mmap_fence(MMAP_FENCE_RELEASE), 0);
+            word(addr, 0).store(round, Ordering::Relaxed);
+        }
+    });
+    let consumer = std::thread::spawn(move || {
+        let mut payload = [0u8; PAYLOAD_LEN];
+        for round in 1..=ROUNDS {
+            wait_for(addr, 0, round);
+            assert_eq!(mmap_fence(MMAP_FENCE_ACQUIRE), 0);
+            let n =
+                unsafe { mmap_read(payload.as_mut_ptr(), addr as *const _, PAYLOAD, PAYLOAD_LEN) };
+            assert_eq!(n, PAYLOAD_LEN);
+            assert!(
+                payload.iter().all(|&b| b == round as u8),
+                "torn payload in round {round}"
+            );
+            assert_eq!(mmap_fence(MMAP_FENCE_RELEASE), 0);
+            word(addr, 4).store(round, Ordering::Relaxed);
+        }
+    });
+    producer.join().unwrap();
+    consumer.join().unwrap();
+    unsafe { mmap_close(base, len) };
+}
+
+#[test]
+fn unknown_fence_kinds_are_rejected() {
+    assert_eq!(mmap_fence(0), -1);
+    assert_eq!(mmap_last_error(), MMAP_ERR_INVALID_ARG);
+    assert_eq!(mmap_compiler_fence(5), -1);
+    assert_eq!(mmap_last_error(), MMAP_ERR_INVALID_ARG);
+    assert_eq!(mmap_compiler_fence(MMAP_FENCE_RELEASE), 0);
+}
@@ -0,0 +1,143 @@
+// Append-only logs across a close/reopen, and after the writer is killed mid-stream: a child
+// process (this test binary, re-run as `log_writer`) appends records until it is killed, then
+// the parent reopens the log and checks it resumes at the last complete record.
+
+use std::ffi::CString;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use deno_mmap_ffi::{
+    MMAP_LOG_HEADER_SIZE, MMAP_OK, mmap_close, mmap_log_append, mmap_log_close, mmap_log_len,
+    mmap_log_open, mmap_log_read, mmap_open_write_with_size,
+};
+
+const CHILD_ENV: &str = "MMAP_LOG_CHILD";
+
+/// Record `seq`: a little-endian u32 length followed by that many pattern bytes.
+fn record(seq: u64) -> Vec<u8> {
+    let len = 1 + (seq % 200) as usize;
+    let mut r = (len as u32).to_le_bytes().to_vec();
+    r.extend((0..len).map(|i| (seq as usize + i) as u8));
+    r
+}
+
+fn temp_path(name: &str) -> CString {
+    let path = std::env::temp_dir().join(format!("mmap-log-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    CString::new(path.to_str().unwrap()).unwrap()
+}
+
+fn open(path: &CString, capacity: usize) -> u64 {
+    let mut err = -1;
+    let id = unsafe { mmap_log_open(path.as_ptr(), capacity, &mut err) };
+    assert_eq!(err, MMAP_OK);
+    assert_ne!(id, 0);
+    id
+}
+
+fn append(id: u64, seq: u64) -> u64 {
+    let r = record(seq);
+    unsafe { mmap_log_append(id, r.as_ptr(), r.len()) }
+}
+
+/// Reads every record of the log, checking each against `record(seq)`; returns their count.
+fn check_records(id: u64) -> u64 {
+    let len = mmap_log_len(id);
+    let (mut at, mut seq) = (0u64, 1u64);
+    while at < len {
+        let expected = record(seq);
+        let mut got = vec![0u8; expected.len()];
+        let n = unsafe { mmap_log_read(id, at, got.as_mut_ptr(), got.len()) };
+        assert_eq!(n, got.len() as isize, "record {seq} cut short");
+        assert_eq!(got, expected, "record {seq} corrupted");
+        at += got.len() as u64;
+        seq += 1;
+    }
+    assert_eq!(at, len);
+    seq - 1
+}
+
+#[test]
+fn appends_resume_after_reopen() {
+    let path = temp_path("reopen");
+    let id = open(&path, 4096);
+    let mut offsets = Vec::new();
+    for seq in 1..=100 {
+        offsets.push(append(id, seq));
+    }
+    assert_eq!(offsets[0], 0);
+    assert_eq!(offsets[1], record(1).len() as u64);
+    assert_eq!(mmap_log_close(id), 0);
+
+    let id = open(&path, 0);
+    assert_eq!(check_records(id), 100);
+    let len = mmap_log_len(id);
+    assert_eq!(append(id, 101), len);
+    assert_eq!(check_records(id), 101);
+    assert_eq!(mmap_log_close(id), 0);
+    assert_eq!(mmap_log_len(id), u64::MAX);
+    std::fs::remove_file(path.to_str().unwrap()).unwrap();
+}
+
+/// Not a test by itself: the child side, which only runs when the parent sets `CHILD_ENV`.
+#[test]
+fn log_writer() {
+    let Ok(path) = std::env::var(CHILD_ENV) else {
+        return;
+    };
+    let id = open(&CString::new(path).unwrap(), 1 << 16);
+    for seq in 1.. {
+        assert_ne!(append(id, seq), u64::MAX);
+        if seq % 64 == 0 {
+            println!("appended {seq}");
+        }
+    }
+}
+
+#[test]
+fn killed_writer_leaves_complete_records() {
+    let path = temp_path("killed");
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "log_writer", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, path.to_str().unwrap())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    // Let it get going (and grow the file at least once) before killing it, unflushed.
+    let mut reported = 0u64;
+    while reported < 2048 {
+        let line = lines.next().unwrap().unwrap();
+        if let Some(seq) = line.strip_prefix("appended ") {
+            reported = seq.parse().unwrap();
+        }
+    }
+    std::thread::sleep(Duration::from_millis(20));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let id = open(&path, 0);
+    let count = check_records(id);
+    assert!(count >= reported, "lost records: {count} < {reported}");
+    let len = mmap_log_len(id);
+    assert_eq!(mmap_log_close(id), 0);
+
+    // A torn record: bytes past the committed length, as if the writer died mid-copy.
+    let torn = MMAP_LOG_HEADER_SIZE + len as usize;
+    let mut mapped = 0;
+    let base = unsafe { mmap_open_write_with_size(path.as_ptr(), &mut mapped, torn + 3) };
+    let base = base as *mut u8;
+    assert!(!base.is_null());
+    unsafe {
+        std::ptr::write_bytes(base.add(torn), 0xee, 3);
+        mmap_close(base.cast(), mapped);
+    }
+
+    let id = open(&path, 0);
+    assert_eq!(mmap_log_len(id), len);
+    assert_eq!(append(id, count + 1), len);
+    assert_eq!(check_records(id), count + 1);
+    assert_eq!(mmap_log_close(id), 0);
+    std::fs::remove_file(path.to_str().unwrap()).unwrap();
+}
@@ -0,0 +1,92 @@
+// mmap_splice_in: copying from another file's descriptor into a handle's mapping.
+#![cfg(unix)]
+
+use std::ffi::CString;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+
+use deno_mmap_ffi::{
+    MMAP_ERR_OUT_OF_BOUNDS, MMAP_ERR_READ_ONLY, MmapHandle, mmap_handle_close, mmap_handle_free,
+    mmap_handle_open, mmap_handle_open_write, mmap_handle_read, mmap_last_error, mmap_splice_in,
+};
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("mmap-splice-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+fn read(h: *const MmapHandle, offset: usize, len: usize) -> Vec<u8> {
+    let mut out = vec![0u8; len];
+    assert_eq!(
+        unsafe { mmap_handle_read(h, offset, out.as_mut_ptr(), len) },
+        len as isize
+    );
+    out
+}
+
+fn close(h: *mut MmapHandle) {
+    unsafe {
+        mmap_handle_close(h);
+        mmap_handle_free(h);
+    }
+}
+
+#[test]
+fn copies_a_range_of_another_file() {
+    let (src_path, dst_path) = (temp_path("src"), temp_path("dst"));
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7) as u8).collect();
+    std::fs::write(&src_path, &data).unwrap();
+    let src = File::open(&src_path).unwrap();
+    let c_dst = CString::new(dst_path.clone()).unwrap();
+    let h = unsafe { mmap_handle_open_write(c_dst.as_ptr(), 200_000) };
+    assert!(!h.is_null());
+
+    let n = unsafe { mmap_splice_in(h, 4096, src.as_raw_fd(), 1000, 50_000) };
+    assert_eq!(n, 50_000);
+    assert_eq!(read(h, 4096, 50_000), &data[1000..51_000]);
+    assert_eq!(read(h, 0, 4096), vec![0; 4096]);
+    // The source ends first: a short copy.
+    let n = unsafe { mmap_splice_in(h, 100_000, src.as_raw_fd(), 90_000, 50_000) };
+    assert_eq!(n, 10_000);
+    assert_eq!(read(h, 100_000, 10_000), &data[90_000..]);
+    assert_eq!(
+        unsafe { mmap_splice_in(h, 0, src.as_raw_fd(), 200_000, 10) },
+        0
+    );
+    close(h);
+
+    // The copies reached the file, not just the mapping.
+    let written = std::fs::read(&dst_path).unwrap();
+    assert_eq!(&written[4096..54_096], &data[1000..51_000]);
+    assert_eq!(&written[100_000..110_000], &data[90_000..]);
+    for p in [src_path, dst_path] {
+        std::fs::remove_file(p).unwrap();
+    }
+}
+
+#[test]
+fn rejects_read_only_handles_and_out_of_range_copies() {
+    let (src_path, dst_path) = (temp_path("src2"), temp_path("dst2"));
+    std::fs::write(&src_path, [1u8; 4096]).unwrap();
+    std::fs::write(&dst_path, [0u8; 4096]).unwrap();
+    let src = File::open(&src_path).unwrap();
+    let c_dst = CString::new(dst_path.clone()).unwrap();
+
+    let h = unsafe { mmap_handle_open(c_dst.as_ptr()) };
+    assert_eq!(unsafe { mmap_splice_in(h, 0, src.as_raw_fd(), 0, 10) }, -1);
+    assert_eq!(mmap_last_error(), MMAP_ERR_READ_ONLY);
+    close(h);
+
+    let h = unsafe { mmap_handle_open_write(c_dst.as_ptr(), 0) };
+    assert_eq!(
+        unsafe { mmap_splice_in(h, 4000, src.as_raw_fd(), 0, 100) },
+        -1
+    );
+    assert_eq!(mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS);
+    assert_eq!(read(h, 0, 4096), vec![0; 4096]);
+    close(h);
+    for p in [src_path, dst_path] {
+        std::fs::remove_file(p).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+// mmap_stat and mmap_get_times / mmap_set_times against std::fs::metadata of the same file,
+// before and after it changes.
+
+use std::ffi::CString;
+use std::time::UNIX_EPOCH;
+
+use deno_mmap_ffi::{
+    MmapStat, mmap_get_times, mmap_handle_close, mmap_handle_free, mmap_handle_open,
+    mmap_handle_open_write, mmap_handle_write, mmap_set_times, mmap_stat,
+};
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("mmap-stat-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+fn stat_of(h: *const deno_mmap_ffi::MmapHandle) -> MmapStat {
+    let mut st = MmapStat::default();
+    assert_eq!(unsafe { mmap_stat(h, &mut st) }, 0);
+    st
+}
+
+fn mtime_ns(path: &str) -> i64 {
+    let mtime = std::fs::metadata(path).unwrap().modified().unwrap();
+    mtime.duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64
+}
+
+fn atime_ns(path: &str) -> i64 {
+    let atime = std::fs::metadata(path).unwrap().accessed().unwrap();
+    atime.duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64
+}
+
+fn times_of(h: *const deno_mmap_ffi::MmapHandle) -> (i64, i64) {
+    let (mut atime, mut mtime) = (0, 0);
+    assert_eq!(unsafe { mmap_get_times(h, &mut atime, &mut mtime) }, 0);
+    (atime, mtime)
+}
+
+#[test]
+fn size_and_mtime_match_metadata() {
+    let path = temp_path("meta");
+    let c_path = CString::new(path.clone()).unwrap();
+    let h = unsafe { mmap_handle_open_write(c_path.as_ptr(), 10_000) };
+    assert!(!h.is_null());
+
+    let st = stat_of(h);
+    assert_eq!(st.size, std::fs::metadata(&path).unwrap().len());
+    assert_eq!(st.size, 10_000);
+    assert_eq!(st.mtime_ns, mtime_ns(&path));
+    assert_ne!(st.ctime_ns, 0);
+    assert_eq!(st.reserved, 0);
+
+    // Changes made through another descriptor show up: mmap_stat is not a snapshot.
+    let earlier = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(20_000).unwrap();
+    file.set_modified(earlier).unwrap();
+    drop(file);
+    let st2 = stat_of(h);
+    assert_eq!(st2.size, 20_000);
+    assert_eq!(st2.mtime_ns, 1_000_000_000 * 1_000_000_000);
+    assert_eq!(st2.mtime_ns, mtime_ns(&path));
+    assert_eq!(st2.file_id, st.file_id);
+
+    // Still answers once the mapping is gone.
+    assert_eq!(unsafe { mmap_handle_close(h) }, 0);
+    assert_eq!(stat_of(h).size, 20_000);
+    unsafe { mmap_handle_free(h) };
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_id_identifies_the_file() {
+    let (a, b) = (temp_path("a"), temp_path("b"));
+    std::fs::write(&a, b"a").unwrap();
+    std::fs::write(&b, b"b").unwrap();
+    let link = temp_path("link");
+    std::fs::hard_link(&a, &link).unwrap();
+    let open = |p: &str| {
+        let c = CString::new(p).unwrap();
+        let h = unsafe { mmap_handle_open(c.as_ptr()) };
+        assert!(!h.is_null());
+        h
+    };
+    let handles = [open(&a), open(&link), open(&b)];
+    let ids = handles.map(|h| stat_of(h).file_id);
+    assert_eq!(ids[0], ids[1]);
+    assert_ne!(ids[0], ids[2]);
+    for h in handles {
+        unsafe {
+            mmap_handle_close(h);
+            mmap_handle_free(h);
+        }
+    }
+    for p in [a, b, link] {
+        std::fs::remove_file(p).unwrap();
+    }
+}
+
+#[test]
+fn null_arguments_are_rejected() {
+    let mut st = MmapStat::default();
+    assert_eq!(unsafe { mmap_stat(std::ptr::null(), &mut st) }, -1);
+    let path = temp_path("null");
+    std::fs::write(&path, b"x").unwrap();
+    let c_path = CString::new(path.clone()).unwrap();
+    let h = unsafe { mmap_handle_open(c_path.as_ptr()) };
+    assert_eq!(unsafe { mmap_stat(h, std::ptr::null_mut()) }, -1);
+    unsafe {
+        mmap_handle_close(h);
+        mmap_handle_free(h);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn set_times_restores_the_mtime_after_a_rewrite() {
+    let path = temp_path("times");
+    std::fs::write(&path, vec![0u8; 4096]).unwrap();
+    // A multiple of 100 ns, which NTFS keeps too.
+    const OLD: i64 = 1_500_000_000_123_456_700;
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_modified(UNIX_EPOCH + std::time::Duration::from_nanos(OLD as u64))
+        .unwrap();
+    drop(file);
+
+    let c_path = CString::new(path.clone()).unwrap();
+    let h = unsafe { mmap_handle_open_write(c_path.as_ptr(), 0) };
+    assert!(!h.is_null());
+    let (atime, mtime) = times_of(h);
+    assert_eq!(mtime, OLD);
+    assert_eq!(atime, atime_ns(&path));
+    let data = b"rewritten";
+    assert_eq!(
+        unsafe { mmap_handle_write(h, 0, data.as_ptr(), data.len()) },
+        data.len() as isize
+    );
+    assert_eq!(unsafe { mmap_handle_close(h) }, 0);
+
+    // After the close, so no write-back of the mapping can touch the mtime again.
+    assert_eq!(unsafe { mmap_set_times(h, -1, mtime) }, 0);
+    assert_eq!(mtime_ns(&path), OLD);
+    assert_eq!(atime_ns(&path), atime);
+    assert_eq!(&std::fs::read(&path).unwrap()[..data.len()], data);
+
+    // Each time is set on its own, down to the file system's precision.
+    const ACCESSED: i64 = 1_600_000_000_987_654_300;
+    assert_eq!(unsafe { mmap_set_times(h, ACCESSED, -1) }, 0);
+    assert_eq!(atime_ns(&path), ACCESSED);
+    assert_eq!(mtime_ns(&path), OLD);
+    let mut only_mtime = 0;
+    assert_eq!(
+        unsafe { mmap_get_times(h, std::ptr::null_mut(), &mut only_mtime) },
+        0
+    );
+    assert_eq!(only_mtime, OLD);
+    assert_eq!(times_of(h), (ACCESSED, OLD));
+
+    assert_eq!(unsafe { mmap_set_times(std::ptr::null(), 0, 0) }, -1);
+    unsafe { mmap_handle_free(h) };
+
+    // Read-only handles set them too, as the file's owner.
+    let ro = unsafe { mmap_handle_open(c_path.as_ptr()) };
+    assert!(!ro.is_null());
+    assert_eq!(unsafe { mmap_set_times(ro, OLD, -1) }, 0);
+    assert_eq!(atime_ns(&path), OLD);
+    unsafe {
+        mmap_handle_close(ro);
+        mmap_handle_free(ro);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
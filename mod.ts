@@ -7,4 +7,5 @@ export {
     flush,
     close,
     type MmapHandle,
+    type OpenWriteOptions,
 } from "./src/ffi_api.ts"
@@ -37,10 +37,31 @@ export async function openWrite(path: string): Promise<MmapHandle> {
   return { ptr: p, len: Number(lenBuf[0]), path }
 }
 
+export type OpenWriteOptions = {
+  /** Don't reserve swap for the mapping (Linux `MAP_NORESERVE`); for huge sparse files. */
+  noReserve?: boolean
+}
+
+const MMAP_NORESERVE = 1 << 4
+
 /** Open for write ensuring file size >= `size`. If the native symbol is missing, fallback to Deno.truncate then openWrite. */
-export async function openWriteWithSize(path: string, size: number | bigint): Promise<MmapHandle> {
+export async function openWriteWithSize(
+  path: string,
+  size: number | bigint,
+  opts: OpenWriteOptions = {},
+): Promise<MmapHandle> {
   const lib = await getLib()
   const want = BigInt(size)
+  if (opts.noReserve) {
+    // No fallback: silently mapping without the flag is what the caller is trying to avoid.
+    const openEx = (lib.symbols as any).mmap_open_write_ex
+    if (!openEx) throw new Error("noReserve requires a native library with mmap_open_write_ex")
+    const lenBuf = new BigUint64Array(1)
+    const lenPtr = Deno.UnsafePointer.of(lenBuf)
+    const p = openEx(toCStringPath(path), lenPtr, want, MMAP_NORESERVE) as Deno.PointerValue | null
+    if (!p || ptrValue(p) === 0n) throw new Error(`mmap_open_write_ex failed: ${path}`)
+    return { ptr: p, len: Number(lenBuf[0]), path }
+  }
   // Try native if available
   const hasNative = (lib.symbols as any).mmap_open_write_with_size?.parameters
   if (hasNative) {
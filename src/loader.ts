@@ -13,6 +13,7 @@ export type SymbolsV2 = {
   mmap_open: (p: Uint8Array, len: Deno.PointerValue) => Deno.PointerValue | null
   mmap_open_write: (p: Uint8Array, len: Deno.PointerValue) => Deno.PointerValue | null
   mmap_open_write_with_size?: (p: Uint8Array, len: Deno.PointerValue, size: bigint) => Deno.PointerValue | null
+  mmap_open_write_ex?: (p: Uint8Array, len: Deno.PointerValue, size: bigint, flags: number) => Deno.PointerValue | null
   mmap_write: (dst: Deno.PointerValue, off: bigint, src: Deno.PointerValue, len: bigint) => bigint
   mmap_read: (dst: Deno.PointerValue, base: Deno.PointerValue, off: bigint, len: bigint) => bigint
   mmap_flush: (base: Deno.PointerValue, off: bigint, len: bigint) => number // 0 = success
@@ -83,6 +84,17 @@ async function downloadAsset(asset: string): Promise<Uint8Array> {
   return new Uint8Array(ab)
 }
 
+const symbolsV3 = {
+  mmap_open: { parameters: ["buffer", "pointer"], result: "pointer" },
+  mmap_open_write: { parameters: ["buffer", "pointer"], result: "pointer" },
+  mmap_open_write_with_size: { parameters: ["buffer", "pointer", "usize"], result: "pointer" },
+  mmap_open_write_ex: { parameters: ["buffer", "pointer", "usize", "u32"], result: "pointer" },
+  mmap_write: { parameters: ["pointer", "usize", "pointer", "usize"], result: "usize" },
+  mmap_read: { parameters: ["pointer", "pointer", "usize", "usize"], result: "usize" },
+  mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+  mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+} as const
+
 const symbolsV2 = {
   mmap_open: { parameters: ["buffer", "pointer"], result: "pointer" },
   mmap_open_write: { parameters: ["buffer", "pointer"], result: "pointer" },
@@ -137,10 +149,14 @@ export async function loadLibrary(): Promise<Loaded & { symbols: SymbolsV2 }> {
     }
   }
 
-  // Try loading with V2 (has mmap_open_write_with_size); fall back to V1.
+  // Try loading with V3 (has mmap_open_write_ex), then V2 (has mmap_open_write_with_size), then V1.
   try {
-    return Deno.dlopen(libPath!, symbolsV2) as any
+    return Deno.dlopen(libPath!, symbolsV3) as any
   } catch {
-    return Deno.dlopen(libPath!, symbolsV1) as any
+    try {
+      return Deno.dlopen(libPath!, symbolsV2) as any
+    } catch {
+      return Deno.dlopen(libPath!, symbolsV1) as any
+    }
   }
 }
@@ -0,0 +1,24 @@
+// Shared helpers for the FFI tests: locate the locally built library and encode paths.
+
+export const libPath: string = Deno.env.get("MMAP_LIB_PATH") ?? defaultLibPath()
+
+function defaultLibPath(): string {
+    switch (Deno.build.os) {
+        case "windows":
+            return "./dist/windows-x86_64/mmap_ffi.dll"
+        case "linux":
+            return "./dist/linux-x86_64/libmmap_ffi.so"
+        case "darwin":
+            return "./dist/macos-aarch64/libmmap_ffi.dylib"
+        default:
+            throw new Error(`Unsupported OS: ${Deno.build.os}`)
+    }
+}
+
+export function cString(str: string): Uint8Array {
+    return new TextEncoder().encode(str + "\0")
+}
+
+export function isNull(p: Deno.PointerValue): boolean {
+    return !p || Deno.UnsafePointer.value(p) === 0n
+}
@@ -0,0 +1,73 @@
+// mmap_advise: access-pattern hints for a mapping, applied or accepted-but-ignored per platform.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ADVICE_NORMAL = 0
+const MMAP_ADVICE_SEQUENTIAL = 1
+const MMAP_ADVICE_RANDOM = 2
+const MMAP_ADVICE_WILLNEED = 3
+const MMAP_ADVICE_DONTNEED = 4
+const MMAP_HINT_IGNORED = 1
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const SIZE = 1024 * 1024
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_open_snapshot: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_advise: { parameters: ["pointer", "usize", "usize", "i32"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+Deno.test("mmap_advise accepts every advice on a file mapping", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(SIZE))
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const data = new TextEncoder().encode("kept")
+    lib.symbols.mmap_write(p, 5000n, data, 4n)
+
+    const windows = Deno.build.os === "windows"
+    const expected = [
+        [MMAP_ADVICE_NORMAL, windows ? MMAP_HINT_IGNORED : 0],
+        [MMAP_ADVICE_SEQUENTIAL, windows ? MMAP_HINT_IGNORED : 0],
+        [MMAP_ADVICE_RANDOM, windows ? MMAP_HINT_IGNORED : 0],
+        [MMAP_ADVICE_WILLNEED, 0],
+        [MMAP_ADVICE_DONTNEED, 0],
+    ]
+    // An unaligned range is widened to whole pages; len 0 runs to the end of the mapping.
+    for (const [advice, rc] of expected) {
+        assertEquals(lib.symbols.mmap_advise(p, 100n, 5000n, advice), rc, `advice ${advice}`)
+        assertEquals(lib.symbols.mmap_advise(p, 0n, 0n, advice), rc, `advice ${advice}`)
+    }
+    // Dropped pages of a shared mapping come back from the file.
+    const out = new Uint8Array(4)
+    lib.symbols.mmap_read(out, p, 5000n, 4n)
+    assertEquals(out, data)
+
+    assertEquals(lib.symbols.mmap_advise(p, 0n, 0n, 5), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_advise(p, BigInt(SIZE), 1n, MMAP_ADVICE_NORMAL), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("MMAP_ADVICE_DONTNEED leaves a snapshot's private copy alone", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "snapshot contents")
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_snapshot(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_snapshot failed")
+
+    assertEquals(lib.symbols.mmap_advise(p, 0n, 0n, MMAP_ADVICE_DONTNEED), MMAP_HINT_IGNORED)
+    const out = new Uint8Array(Number(lenBuf[0]))
+    lib.symbols.mmap_read(out, p, 0n, lenBuf[0])
+    assertEquals(new TextDecoder().decode(out), "snapshot contents")
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
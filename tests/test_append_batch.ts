@@ -0,0 +1,99 @@
+// mmap_append_batch: appending many records in one call and getting back where each landed.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_READ_ONLY = -7
+const MMAP_ERR_UNSUPPORTED = -11
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_subview: { parameters: ["pointer", "u64", "usize", "buffer"], result: "pointer" },
+    mmap_handle_ptr: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_read: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_tell: { parameters: ["pointer"], result: "isize" },
+    mmap_append_batch: { parameters: ["pointer", "buffer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+})
+
+/** An `MmapIoVec` array for `records`, each a buffer or a pointer and length. */
+function ioVecs(records: (Uint8Array | [Deno.PointerValue, number])[]): Uint8Array {
+    const vecs = new BigUint64Array(records.length * 2)
+    records.forEach((r, i) => {
+        const [p, len] = r instanceof Uint8Array ? [Deno.UnsafePointer.of(r), r.length] : r
+        vecs[2 * i] = BigInt(Deno.UnsafePointer.value(p))
+        vecs[2 * i + 1] = BigInt(len)
+    })
+    return new Uint8Array(vecs.buffer)
+}
+
+function read(h: Deno.PointerValue, offset: bigint, len: number): string {
+    const out = new Uint8Array(len)
+    assertEquals(lib.symbols.mmap_handle_read(h, offset, out, BigInt(len)), BigInt(len))
+    return new TextDecoder().decode(out)
+}
+
+Deno.test("mmap_append_batch appends records back to back and reports their offsets", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 16n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const enc = new TextEncoder()
+    const big = new Uint8Array(100000).fill(0x67)
+    const offsets = new BigUint64Array(3)
+
+    // The third record doesn't fit, so the file grows.
+    const records = [enc.encode("alpha"), new Uint8Array(0), big]
+    const n = lib.symbols.mmap_append_batch(h, ioVecs(records), 3n, new Uint8Array(offsets.buffer), 3n)
+    assertEquals(n, 3n)
+    assertEquals([...offsets], [0n, 5n, 5n])
+    assertEquals(lib.symbols.mmap_handle_tell(h), 100005n)
+    assertEquals(read(h, 0n, 6), "alphag")
+
+    // A record taken from the mapping itself is still found if growing moves the mapping.
+    const alpha: [Deno.PointerValue, number] = [lib.symbols.mmap_handle_ptr(h), 5]
+    const more = new Uint8Array(200000)
+    assertEquals(lib.symbols.mmap_append_batch(h, ioVecs([more, alpha]), 2n, new Uint8Array(offsets.buffer), 3n), 2n)
+    assertEquals([...offsets.subarray(0, 2)], [100005n, 300005n])
+    assertEquals(read(h, 300005n, 5), "alpha")
+    // Offsets are optional.
+    assertEquals(lib.symbols.mmap_append_batch(h, ioVecs([enc.encode("tail")]), 1n, null, 0n), 1n)
+    assertEquals(read(h, 300010n, 4), "tail")
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_append_batch stops at the record it can't grow the file for", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(4096))
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 0n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    // Subviews can't grow.
+    const sub = lib.symbols.mmap_handle_subview(h, 0n, 4096n, null)
+    assert(!isNull(sub), "mmap_handle_subview failed")
+    const offsets = new BigUint64Array(3)
+
+    const records = [new Uint8Array(3000), new Uint8Array(1000), new Uint8Array(200)]
+    const n = lib.symbols.mmap_append_batch(sub, ioVecs(records), 3n, new Uint8Array(offsets.buffer), 3n)
+    assertEquals(n, 2n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+    assertEquals([...offsets.subarray(0, 2)], [0n, 3000n])
+    assertEquals(lib.symbols.mmap_handle_tell(sub), 4000n)
+
+    assertEquals(lib.symbols.mmap_append_batch(sub, ioVecs(records), 3n, new Uint8Array(offsets.buffer), 2n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_append_batch(sub, ioVecs([[null, 1]]), 1n, null, 0n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    lib.symbols.mmap_handle_free(sub)
+    lib.symbols.mmap_handle_free(h)
+
+    const ro = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(ro), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_append_batch(ro, ioVecs(records), 3n, null, 0n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    lib.symbols.mmap_handle_free(ro)
+    await Deno.remove(path)
+})
@@ -0,0 +1,64 @@
+// mmap_autoflush_enable / mmap_shutdown: background flushing without explicit flush calls.
+// On Linux the mapping's dirty page count in /proc/self/smaps shows the write-back; elsewhere
+// the test checks the file contents only.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_autoflush_enable: { parameters: ["pointer", "u32"], result: "i32" },
+    mmap_autoflush_disable: { parameters: ["pointer"], result: "i32" },
+    mmap_shutdown: { parameters: [], result: "void" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+/** Dirty bytes of the mapping of `path`, or null where smaps isn't available. */
+function dirtyBytes(path: string): number | null {
+    if (Deno.build.os !== "linux") return null
+    const lines = Deno.readTextFileSync("/proc/self/smaps").split("\n")
+    const start = lines.findIndex((l) => l.endsWith(path))
+    assert(start >= 0, "mapping not found in /proc/self/smaps")
+    const next = lines.findIndex((l, i) => i > start && l.startsWith("VmFlags:"))
+    return lines.slice(start + 1, next)
+        .filter((l) => l.startsWith("Shared_Dirty:") || l.startsWith("Private_Dirty:"))
+        .reduce((sum, l) => sum + parseInt(l.split(/\s+/)[1]) * 1024, 0)
+}
+
+Deno.test("auto-flush writes data back without an explicit flush", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 1024n * 1024n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_autoflush_enable(p, 50), 0)
+    lib.symbols.mmap_write(p, 5000n, new TextEncoder().encode("metrics"), 7n)
+    const before = dirtyBytes(path)
+    if (before !== null) assert(before > 0, "write should leave a dirty page")
+
+    await new Promise((resolve) => setTimeout(resolve, 300))
+    assertEquals(dirtyBytes(path) ?? 0, 0)
+    assertEquals(new TextDecoder().decode((await Deno.readFile(path)).subarray(5000, 5007)), "metrics")
+
+    assertEquals(lib.symbols.mmap_autoflush_disable(p), 0)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    lib.symbols.mmap_shutdown()
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_shutdown flushes pending writes before stopping", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 64n * 1024n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    // Long interval: only the shutdown pass can flush this write.
+    assertEquals(lib.symbols.mmap_autoflush_enable(p, 60_000), 0)
+    lib.symbols.mmap_write(p, 100n, new TextEncoder().encode("last"), 4n)
+    lib.symbols.mmap_shutdown()
+    assertEquals(dirtyBytes(path) ?? 0, 0)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
@@ -0,0 +1,36 @@
+// mmap_open_capped: refusing files larger than a caller-supplied limit.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_capped: { parameters: ["buffer", "usize", "buffer"], result: "pointer" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_TOO_LARGE = -18
+const CAP = 64 * 1024
+
+Deno.test("mmap_open_capped maps a file just under the cap", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(CAP - 1).fill(1))
+    const lenBuf = new BigUint64Array(1)
+
+    const p = lib.symbols.mmap_open_capped(cString(path), BigInt(CAP), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_capped failed")
+    assertEquals(lenBuf[0], BigInt(CAP - 1))
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_open_capped rejects a sparse file just over the cap", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.truncate(path, CAP + 1)
+    const lenBuf = new BigUint64Array(1)
+
+    const p = lib.symbols.mmap_open_capped(cString(path), BigInt(CAP), new Uint8Array(lenBuf.buffer))
+    assert(isNull(p), "file over the cap should not be mapped")
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_TOO_LARGE)
+    await Deno.remove(path)
+})
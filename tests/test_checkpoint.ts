@@ -0,0 +1,76 @@
+// mmap_checkpoint_*: committing recovery points for a region and detecting writes made after
+// the last one. The kill-at-random-times crash test lives in ffi/tests/checkpoint.rs.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_checkpoint_init: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_checkpoint_begin: { parameters: ["pointer"], result: "i64" },
+    mmap_checkpoint_commit: { parameters: ["pointer"], result: "i32" },
+    mmap_checkpoint_verify: { parameters: ["pointer", "buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const HEADER_SIZE = 64n
+
+Deno.test("a committed checkpoint verifies until the region changes", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_checkpoint_init(p, HEADER_SIZE, 1024n), 0)
+    // magic, version, region_offset, region_len, pending_seq, pending_crc, committed_crc, committed_seq
+    const hdr = new DataView(new ArrayBuffer(48))
+    const out = new Uint8Array(hdr.buffer)
+    assertEquals(lib.symbols.mmap_checkpoint_verify(p, out), 0)
+    assertEquals(new TextDecoder().decode(out.subarray(0, 4)), "MMCP")
+    assertEquals(hdr.getBigUint64(40, true), 0n)
+
+    // Nothing staged yet.
+    assertEquals(lib.symbols.mmap_checkpoint_commit(p), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    const enc = new TextEncoder()
+    lib.symbols.mmap_write(p, 100n, enc.encode("state 1"), 7n)
+    assertEquals(lib.symbols.mmap_checkpoint_begin(p), 1n)
+    assertEquals(lib.symbols.mmap_checkpoint_commit(p), 0)
+    assertEquals(lib.symbols.mmap_checkpoint_verify(p, out), 1)
+    assertEquals(hdr.getBigUint64(40, true), 1n)
+
+    // Writes after the commit are detected; outside the region they don't matter.
+    lib.symbols.mmap_write(p, 2000n, enc.encode("x"), 1n)
+    assertEquals(lib.symbols.mmap_checkpoint_verify(p, null), 1)
+    lib.symbols.mmap_write(p, 100n, enc.encode("state 2"), 7n)
+    assertEquals(lib.symbols.mmap_checkpoint_verify(p, null), 0)
+    assertEquals(lib.symbols.mmap_checkpoint_begin(p), 2n)
+    assertEquals(lib.symbols.mmap_checkpoint_commit(p), 0)
+    assertEquals(lib.symbols.mmap_checkpoint_verify(p, null), 1)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("checkpoint calls reject bad regions and unformatted mappings", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_checkpoint_begin(p), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    // Overlapping the header, then past the end.
+    assertEquals(lib.symbols.mmap_checkpoint_init(p, 0n, 16n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_checkpoint_init(p, HEADER_SIZE, 4096n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
@@ -0,0 +1,74 @@
+// mmap_handle_close_secure: wiping a writable mapping before unmapping it.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ALREADY_CLOSED = 1
+const MMAP_CLOSED_UNWIPED = 2
+const MMAP_ERR_CLOSED = -6
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_read: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_close_secure: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_clone: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+Deno.test("close_secure zeroes a writable mapping and its file", async () => {
+    const path = await Deno.makeTempFile()
+    // Odd length, so the wipe has a partial word at the end.
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4099n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const secret = new TextEncoder().encode("hunter2")
+    assertEquals(lib.symbols.mmap_handle_write(h, 4092n, secret, 7n), 7n)
+
+    assertEquals(lib.symbols.mmap_handle_close_secure(h), 0)
+    assertEquals(lib.symbols.mmap_handle_close_secure(h), MMAP_ALREADY_CLOSED)
+    assertEquals(lib.symbols.mmap_handle_read(h, 0n, new Uint8Array(1), 1n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    lib.symbols.mmap_handle_free(h)
+
+    const data = await Deno.readFile(path)
+    assertEquals(data.length, 4099)
+    assert(data.every((b) => b === 0), "secret bytes survived the wipe")
+    await Deno.remove(path)
+})
+
+Deno.test("close_secure unmaps read-only mappings without wiping", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "public")
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+
+    assertEquals(lib.symbols.mmap_handle_close_secure(h), MMAP_CLOSED_UNWIPED)
+    assertEquals(lib.symbols.mmap_handle_close_secure(h), MMAP_ALREADY_CLOSED)
+    lib.symbols.mmap_handle_free(h)
+    assertEquals(await Deno.readTextFile(path), "public")
+    await Deno.remove(path)
+})
+
+Deno.test("close_secure leaves a mapping that clones still use", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const secret = new TextEncoder().encode("hunter2")
+    assertEquals(lib.symbols.mmap_handle_write(h, 0n, secret, 7n), 7n)
+    const clone = lib.symbols.mmap_handle_clone(h)
+    assert(!isNull(clone), "mmap_handle_clone failed")
+
+    assertEquals(lib.symbols.mmap_handle_close_secure(h), MMAP_CLOSED_UNWIPED)
+    const out = new Uint8Array(7)
+    assertEquals(lib.symbols.mmap_handle_read(clone, 0n, out, 7n), 7n)
+    assertEquals(out, secret)
+    // The last handle wipes it.
+    assertEquals(lib.symbols.mmap_handle_close_secure(clone), 0)
+    lib.symbols.mmap_handle_free(h)
+    lib.symbols.mmap_handle_free(clone)
+
+    assert((await Deno.readFile(path)).every((b) => b === 0), "secret bytes survived the wipe")
+    await Deno.remove(path)
+})
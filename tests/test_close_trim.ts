@@ -0,0 +1,75 @@
+// mmap_close_trim: cutting the preallocated tail off a written file on close.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_mark_dirty: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_close_trim: { parameters: ["pointer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_READ_ONLY = -7
+const MiB = 1024n * 1024n
+
+function openPreallocated(path: string, size: bigint): Deno.PointerValue {
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), size)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    return p
+}
+
+Deno.test("close_trim truncates to the furthest write", async () => {
+    const path = await Deno.makeTempFile()
+    const p = openPreallocated(path, MiB)
+    lib.symbols.mmap_write(p, 0n, new Uint8Array(1234).fill(1), 1234n)
+    lib.symbols.mmap_write(p, 5000n, new Uint8Array(100).fill(2), 100n)
+
+    assertEquals(lib.symbols.mmap_close_trim(p), 0)
+    const data = await Deno.readFile(path)
+    assertEquals(data.length, 5100)
+    assertEquals(data[1233], 1)
+    assertEquals(data[5099], 2)
+    await Deno.remove(path)
+})
+
+Deno.test("raw stores count once reported with mmap_mark_dirty", async () => {
+    const path = await Deno.makeTempFile()
+    const p = openPreallocated(path, MiB)
+    const view = new Uint8Array(Deno.UnsafePointerView.getArrayBuffer(p!, 8192))
+    view[8000] = 7
+    assertEquals(lib.symbols.mmap_mark_dirty(p, 8000n, 1n), 0)
+
+    assertEquals(lib.symbols.mmap_close_trim(p), 0)
+    const data = await Deno.readFile(path)
+    assertEquals(data.length, 8001)
+    assertEquals(data[8000], 7)
+    await Deno.remove(path)
+})
+
+Deno.test("content the file had before the open is kept", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "existing content")
+    const p = openPreallocated(path, MiB)
+    lib.symbols.mmap_write(p, 0n, new TextEncoder().encode("E"), 1n)
+
+    assertEquals(lib.symbols.mmap_close_trim(p), 0)
+    assertEquals(await Deno.readTextFile(path), "Existing content")
+    await Deno.remove(path)
+})
+
+Deno.test("close_trim rejects read-only mappings and leaves them open", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "hello")
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    assertEquals(lib.symbols.mmap_close_trim(p), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
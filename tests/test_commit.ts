@@ -0,0 +1,56 @@
+// mmap_commit: reserving disk blocks behind a writable mapping. Running out of space can't be
+// staged portably here; on Linux the test checks that a sparse range becomes allocated.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_commit: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_READ_ONLY = -7
+const MMAP_ERR_UNSUPPORTED = -11
+
+Deno.test("mmap_commit allocates the blocks of a sparse range", async () => {
+    const path = await Deno.makeTempFile()
+    const size = 1024 * 1024
+    const h = lib.symbols.mmap_handle_open_write(cString(path), BigInt(size))
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    const rc = lib.symbols.mmap_commit(h, 0n, BigInt(size))
+    if (Deno.build.os === "darwin") {
+        assertEquals(rc, -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+    } else {
+        assertEquals(rc, 0)
+    }
+    const st = await Deno.stat(path)
+    assertEquals(st.size, size, "mmap_commit must not change the file size")
+    if (Deno.build.os === "linux" && st.blocks !== null) {
+        assert(st.blocks * 512 >= size, `only ${st.blocks} blocks allocated`)
+    }
+
+    assertEquals(lib.symbols.mmap_commit(h, BigInt(size - 1), 2n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_commit refuses read-only handles", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(4096))
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+
+    assertEquals(lib.symbols.mmap_commit(h, 0n, 4096n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
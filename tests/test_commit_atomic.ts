@@ -0,0 +1,124 @@
+// mmap_commit_atomic: replacing a file with a filled-in temporary one, crash-safely.
+// The crash test needs a build with `--features test-hooks`, which can abort the process
+// between the flush and the rename.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const symbols = {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_read: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_clone: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_commit_atomic: { parameters: ["pointer", "buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_test_crash_before_rename: { parameters: ["i32"], result: "void", optional: true },
+} as const
+const lib = Deno.dlopen(libPath, symbols)
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_CLOSED = -6
+const MMAP_ERR_READ_ONLY = -7
+
+const encode = (s: string) => new TextEncoder().encode(s)
+const decode = (b: Uint8Array) => new TextDecoder().decode(b)
+
+function openFilled(path: string, text: string): Deno.PointerValue {
+    const h = lib.symbols.mmap_handle_open_write(cString(path), BigInt(text.length))
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    assertEquals(lib.symbols.mmap_handle_write(h, 0n, encode(text), BigInt(text.length)), BigInt(text.length))
+    return h
+}
+
+async function exists(path: string): Promise<boolean> {
+    try {
+        await Deno.stat(path)
+        return true
+    } catch (e) {
+        if (e instanceof Deno.errors.NotFound) return false
+        throw e
+    }
+}
+
+Deno.test("mmap_commit_atomic renames the filled file over the target", async () => {
+    const dir = await Deno.makeTempDir()
+    const target = `${dir}/config.json`
+    const tmp = `${target}.tmp`
+    await Deno.writeTextFile(target, "old contents")
+
+    const h = openFilled(tmp, `{"version":2}`)
+    assertEquals(lib.symbols.mmap_commit_atomic(h, cString(target)), 0)
+    assertEquals(await Deno.readTextFile(target), `{"version":2}`)
+    assert(!(await exists(tmp)), "the temporary file should be gone")
+
+    // The commit closed the handle.
+    assertEquals(lib.symbols.mmap_handle_read(h, 0n, new Uint8Array(1), 1n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    assertEquals(lib.symbols.mmap_commit_atomic(h, cString(target)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(dir, { recursive: true })
+})
+
+Deno.test("mmap_commit_atomic refuses read-only and cloned handles", async () => {
+    const dir = await Deno.makeTempDir()
+    const target = `${dir}/data`
+    const tmp = `${target}.tmp`
+    await Deno.writeTextFile(target, "old contents")
+    await Deno.writeTextFile(tmp, "new contents")
+
+    const ro = lib.symbols.mmap_handle_open(cString(tmp))
+    assert(!isNull(ro), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_commit_atomic(ro, cString(target)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    lib.symbols.mmap_handle_free(ro)
+
+    const h = openFilled(tmp, "new contents")
+    const clone = lib.symbols.mmap_handle_clone(h)
+    assertEquals(lib.symbols.mmap_commit_atomic(h, cString(target)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    lib.symbols.mmap_handle_free(clone)
+    assertEquals(lib.symbols.mmap_commit_atomic(h, null), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    // A rename that fails leaves the target alone and the file at its temporary path.
+    assertEquals(lib.symbols.mmap_commit_atomic(h, cString(`${dir}/missing/data`)), -1)
+    assertEquals(await Deno.readTextFile(target), "old contents")
+    assertEquals(await Deno.readTextFile(tmp), "new contents")
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(dir, { recursive: true })
+})
+
+Deno.test({
+    name: "a crash between the flush and the rename leaves the old target",
+    ignore: lib.symbols.mmap_test_crash_before_rename === null,
+    fn: async () => {
+        const dir = await Deno.makeTempDir()
+        const target = `${dir}/state`
+        const tmp = `${target}.tmp`
+        await Deno.writeTextFile(target, "committed state")
+
+        const script = `
+            const lib = Deno.dlopen(${JSON.stringify(libPath)}, ${JSON.stringify(symbols)})
+            const cString = (s) => new TextEncoder().encode(s + "\\0")
+            const text = new TextEncoder().encode("half-done state")
+            const h = lib.symbols.mmap_handle_open_write(cString(${JSON.stringify(tmp)}), BigInt(text.length))
+            lib.symbols.mmap_handle_write(h, 0n, text, BigInt(text.length))
+            lib.symbols.mmap_test_crash_before_rename(1)
+            lib.symbols.mmap_commit_atomic(h, cString(${JSON.stringify(target)}))
+            console.log("survived")
+        `
+        const child = await new Deno.Command(Deno.execPath(), {
+            args: ["eval", "--unstable-ffi", script],
+        }).output()
+        assert(!child.success, "the child should have crashed")
+        assertEquals(decode(child.stdout), "")
+
+        assertEquals(await Deno.readTextFile(target), "committed state")
+        // The flush happened before the crash: the new file is complete, just not in place.
+        assertEquals(await Deno.readTextFile(tmp), "half-done state")
+        await Deno.remove(dir, { recursive: true })
+    },
+})
@@ -0,0 +1,37 @@
+// mmap_copy_between: copy a region from one mapping straight into another.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_copy_between: { parameters: ["pointer", "usize", "pointer", "usize", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+Deno.test("mmap_copy_between copies from a read-only mapping into a writable one", async () => {
+    const srcPath = await Deno.makeTempFile()
+    const dstPath = await Deno.makeTempFile()
+    await Deno.writeFile(srcPath, new TextEncoder().encode("0123456789abcdef"))
+
+    const srcLen = new BigUint64Array(1)
+    const src = lib.symbols.mmap_open(cString(srcPath), new Uint8Array(srcLen.buffer))
+    assert(!isNull(src), "mmap_open failed")
+    const dstLen = new BigUint64Array(1)
+    const dst = lib.symbols.mmap_open_write_with_size(cString(dstPath), new Uint8Array(dstLen.buffer), 4096n)
+    assert(!isNull(dst), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_copy_between(dst, 100n, src, 10n, 6n), 6n)
+    assertEquals(lib.symbols.mmap_copy_between(null, 0n, src, 0n, 6n), 0n)
+
+    const out = new Uint8Array(6)
+    lib.symbols.mmap_read(out, dst, 100n, 6n)
+    assertEquals(new TextDecoder().decode(out), "abcdef")
+
+    lib.symbols.mmap_close(src, srcLen[0])
+    lib.symbols.mmap_close(dst, dstLen[0])
+    await Deno.remove(srcPath)
+    await Deno.remove(dstPath)
+})
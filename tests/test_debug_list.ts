@@ -0,0 +1,117 @@
+// mmap_debug_list: enumerating the mappings still open, for leak hunting. Needs a build with
+// `--features debug-registry`; the tests are skipped otherwise. Other test files may leave
+// mappings open in the same process, so only the ones opened here are checked.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_DEBUG_READ = 0
+const MMAP_DEBUG_WRITE = 1
+const MMAP_DEBUG_SNAPSHOT = 2
+/** `MmapDebugEntry` on 64-bit targets: base, len, mode (padded to 8), path_hash. */
+const ENTRY_SIZE = 32
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_open_snapshot: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_tmp: { parameters: ["usize", "buffer"], result: "pointer" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_debug_list: { parameters: ["buffer", "usize"], result: "isize", optional: true },
+})
+
+interface Entry {
+    len: bigint
+    mode: number
+    pathHash: bigint
+}
+
+/** Every open mapping, by base address. */
+function list(): Map<bigint, Entry> {
+    const count = Number(lib.symbols.mmap_debug_list!(null, 0n))
+    const buf = new ArrayBuffer(count * ENTRY_SIZE)
+    assertEquals(lib.symbols.mmap_debug_list!(new Uint8Array(buf), BigInt(count)), BigInt(count))
+    const view = new DataView(buf)
+    const entries = new Map<bigint, Entry>()
+    for (let i = 0; i < count; i++) {
+        const at = i * ENTRY_SIZE
+        entries.set(view.getBigUint64(at, true), {
+            len: view.getBigUint64(at + 8, true),
+            mode: view.getInt32(at + 16, true),
+            pathHash: view.getBigUint64(at + 24, true),
+        })
+    }
+    return entries
+}
+
+/** 64-bit FNV-1a of the path's bytes, as `mmap_debug_list` reports it. */
+function pathHash(path: string): bigint {
+    let h = 0xcbf29ce484222325n
+    for (const b of new TextEncoder().encode(path)) {
+        h = ((h ^ BigInt(b)) * 0x100000001b3n) & 0xffffffffffffffffn
+    }
+    return h
+}
+
+const base = (p: Deno.PointerValue) => BigInt(Deno.UnsafePointer.value(p))
+
+Deno.test({
+    name: "mmap_debug_list reports every open mapping until it is closed",
+    ignore: lib.symbols.mmap_debug_list === null,
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        await Deno.writeTextFile(path, "listed")
+        const before = list().size
+        const lenBuf = new BigUint64Array(1)
+        const w = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 8192n)
+        assert(!isNull(w), "mmap_open_write_with_size failed")
+        const r = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+        assert(!isNull(r), "mmap_open failed")
+        const s = lib.symbols.mmap_open_snapshot(cString(path), new Uint8Array(lenBuf.buffer))
+        assert(!isNull(s), "mmap_open_snapshot failed")
+        const t = lib.symbols.mmap_open_tmp(4096n, new Uint8Array(lenBuf.buffer))
+        assert(!isNull(t), "mmap_open_tmp failed")
+
+        const entries = list()
+        assertEquals(entries.size, before + 4)
+        const hash = pathHash(path)
+        assertEquals(entries.get(base(w)), { len: 8192n, mode: MMAP_DEBUG_WRITE, pathHash: hash })
+        assertEquals(entries.get(base(r)), { len: 8192n, mode: MMAP_DEBUG_READ, pathHash: hash })
+        assertEquals(entries.get(base(s))?.mode, MMAP_DEBUG_SNAPSHOT)
+        assertEquals(entries.get(base(s))?.pathHash, hash)
+        assertEquals(entries.get(base(t)), { len: 4096n, mode: MMAP_DEBUG_WRITE, pathHash: 0n })
+
+        lib.symbols.mmap_close(w, 8192n)
+        lib.symbols.mmap_close(r, 8192n)
+        lib.symbols.mmap_close(s, entries.get(base(s))!.len)
+        lib.symbols.mmap_close(t, 4096n)
+        const after = list()
+        assertEquals(after.size, before)
+        assert(!after.has(base(w)) && !after.has(base(t)))
+        await Deno.remove(path)
+    },
+})
+
+Deno.test({
+    name: "mmap_debug_list fills at most cap entries and still returns the count",
+    ignore: lib.symbols.mmap_debug_list === null,
+    fn: () => {
+        const lenBuf = new BigUint64Array(1)
+        const a = lib.symbols.mmap_open_tmp(4096n, new Uint8Array(lenBuf.buffer))
+        const b = lib.symbols.mmap_open_tmp(4096n, new Uint8Array(lenBuf.buffer))
+        assert(!isNull(a) && !isNull(b), "mmap_open_tmp failed")
+        const count = lib.symbols.mmap_debug_list!(null, 0n)
+        assert(count >= 2n)
+
+        const out = new Uint8Array(2 * ENTRY_SIZE).fill(0xee)
+        assertEquals(lib.symbols.mmap_debug_list!(out, 1n), count)
+        assert(out.subarray(ENTRY_SIZE).every((b) => b === 0xee), "wrote past cap")
+
+        assertEquals(lib.symbols.mmap_debug_list!(null, 1n), -1n)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+        lib.symbols.mmap_close(a, 4096n)
+        lib.symbols.mmap_close(b, 4096n)
+    },
+})
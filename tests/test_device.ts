@@ -0,0 +1,50 @@
+// Block device detection for the open path (Linux).
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ALLOW_DEVICE = 1
+const MMAP_ERR_IS_DEVICE = -3
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_ex: { parameters: ["buffer", "pointer", "u32"], result: "pointer" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const isLinux = Deno.build.os === "linux"
+
+// First block device with a non-zero size, e.g. "vda" -> (path, bytes).
+function findBlockDevice(): { path: string; size: number } | null {
+    if (!isLinux) return null
+    for (const e of Deno.readDirSync("/sys/block")) {
+        const sectors = Number(Deno.readTextFileSync(`/sys/block/${e.name}/size`).trim())
+        if (sectors > 0) return { path: `/dev/${e.name}`, size: sectors * 512 }
+    }
+    return null
+}
+
+const dev = findBlockDevice()
+
+Deno.test({
+    name: "opening a block device without MMAP_ALLOW_DEVICE fails with MMAP_ERR_IS_DEVICE",
+    ignore: dev === null,
+    fn() {
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open_ex(cString(dev!.path), Deno.UnsafePointer.of(lenBuf), 0)
+        assert(isNull(p))
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_IS_DEVICE)
+    },
+})
+
+Deno.test({
+    name: "MMAP_ALLOW_DEVICE maps the full device length (root only)",
+    ignore: dev === null || Deno.uid() !== 0,
+    fn() {
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open_ex(cString(dev!.path), Deno.UnsafePointer.of(lenBuf), MMAP_ALLOW_DEVICE)
+        assert(!isNull(p), `mmap_open_ex failed: ${lib.symbols.mmap_last_error()}`)
+        assertEquals(Number(lenBuf[0]), dev!.size)
+        lib.symbols.mmap_close(p, lenBuf[0])
+    },
+})
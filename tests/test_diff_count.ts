@@ -0,0 +1,60 @@
+// mmap_diff_count: counting the bytes a mapping changed relative to a file on disk.
+// The mapping here is a writable copy; its original stays next to it as the reference.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_diff_count: { parameters: ["pointer", "buffer", "usize"], result: "isize" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_OS = -2
+
+Deno.test("mmap_diff_count counts changed bytes across chunks", async () => {
+    const size = 3 * 1024 * 1024
+    const original = await Deno.makeTempFile()
+    const copy = await Deno.makeTempFile()
+    await Deno.writeFile(original, new Uint8Array(size))
+    await Deno.writeFile(copy, new Uint8Array(size))
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write(cString(copy), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_write failed")
+    assertEquals(lib.symbols.mmap_diff_count(p, cString(original), lenBuf[0]), 0n)
+
+    const enc = new TextEncoder()
+    lib.symbols.mmap_write(p, 5n, enc.encode("abc"), 3n)
+    // Past the first read chunk.
+    lib.symbols.mmap_write(p, BigInt(2 * 1024 * 1024 + 7), enc.encode("xyz"), 3n)
+    assertEquals(lib.symbols.mmap_diff_count(p, cString(original), lenBuf[0]), 6n)
+    assertEquals(lib.symbols.mmap_diff_count(p, cString(original), 1024n), 3n)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(original)
+    await Deno.remove(copy)
+})
+
+Deno.test("bytes past the end of the file count as different", async () => {
+    const short = await Deno.makeTempFile()
+    const long = await Deno.makeTempFile()
+    await Deno.writeFile(short, new Uint8Array(1000))
+    await Deno.writeFile(long, new Uint8Array(4096))
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write(cString(long), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_write failed")
+    assertEquals(lib.symbols.mmap_diff_count(p, cString(short), 4096n), 3096n)
+
+    const missing = await Deno.makeTempDir()
+    assertEquals(lib.symbols.mmap_diff_count(p, cString(`${missing}/nope`), 4096n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(short)
+    await Deno.remove(long)
+    await Deno.remove(missing)
+})
@@ -0,0 +1,49 @@
+// MMAP_DIRECT: page-cache-bypassing opens with length alignment checks.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_DIRECT = 1 << 7
+const MMAP_ERR_OS = -2
+const MMAP_ERR_UNALIGNED = -13
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_ex: { parameters: ["buffer", "buffer", "u32"], result: "pointer" },
+    mmap_open_write_ex: { parameters: ["buffer", "buffer", "usize", "u32"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+Deno.test("MMAP_DIRECT rejects unaligned lengths", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(100))
+    const len = new Uint8Array(8)
+
+    assert(isNull(lib.symbols.mmap_open_ex(cString(path), len, MMAP_DIRECT)))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNALIGNED)
+    assert(isNull(lib.symbols.mmap_open_write_ex(cString(path), len, 1000n, MMAP_DIRECT)))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNALIGNED)
+    await Deno.remove(path)
+})
+
+Deno.test({
+    name: "MMAP_DIRECT maps page-aligned files",
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open_write_ex(cString(path), new Uint8Array(lenBuf.buffer), 65536n, MMAP_DIRECT)
+        if (isNull(p)) {
+            // Some filesystems (e.g. tmpfs) don't support direct I/O at all.
+            assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+        } else {
+            assertEquals(lenBuf[0], 65536n)
+            assertEquals(lib.symbols.mmap_write(p, 0n, new Uint8Array([1, 2, 3]), 3n), 3n)
+            assertEquals(lib.symbols.mmap_flush(p, 0n, 65536n), 0)
+            lib.symbols.mmap_close(p, lenBuf[0])
+            assertEquals((await Deno.readFile(path)).subarray(0, 3), new Uint8Array([1, 2, 3]))
+        }
+        await Deno.remove(path)
+    },
+})
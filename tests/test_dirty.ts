@@ -0,0 +1,54 @@
+// mmap_flush_dirty / mmap_mark_dirty: flushing only the pages written since the last flush.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush_dirty: { parameters: ["pointer"], result: "i32" },
+    mmap_mark_dirty: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const SIZE = 64 * 1024 * 1024
+
+Deno.test("mmap_flush_dirty writes back scattered writes", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(SIZE))
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    // Start, a page-straddling write in the middle, and the very end.
+    const offsets = [5, SIZE / 2 + 4093, SIZE - 3]
+    const data = new TextEncoder().encode("xyz")
+    for (const off of offsets) assertEquals(lib.symbols.mmap_write(p, BigInt(off), data, 3n), 3n)
+    assertEquals(lib.symbols.mmap_flush_dirty(p), 0)
+
+    const file = await Deno.readFile(path)
+    for (const off of offsets) assertEquals(file.subarray(off, off + 3), data)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_mark_dirty registers writes made through the raw pointer", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 8192n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    const view = new Uint8Array(Deno.UnsafePointerView.getArrayBuffer(p!, 8192))
+    view.set(new TextEncoder().encode("raw"), 5000)
+    assertEquals(lib.symbols.mmap_mark_dirty(p, 5000n, 3n), 0)
+    assertEquals(lib.symbols.mmap_flush_dirty(p), 0)
+    assertEquals(new TextDecoder().decode((await Deno.readFile(path)).subarray(5000, 5003)), "raw")
+
+    assertEquals(lib.symbols.mmap_mark_dirty(p, 8190n, 3n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
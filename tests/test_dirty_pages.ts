@@ -0,0 +1,53 @@
+// mmap_dirty_pages / mmap_clear_dirty: kernel soft-dirty tracking of raw pointer writes (Linux).
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_clear_dirty: { parameters: ["pointer"], result: "i32" },
+    mmap_dirty_pages: { parameters: ["pointer", "buffer", "usize"], result: "usize" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_page_size: { parameters: [], result: "usize" },
+})
+
+const MMAP_ERR_UNSUPPORTED = -11
+const PAGE = Number(lib.symbols.mmap_page_size())
+const PAGES = 16
+
+Deno.test({
+    name: "mmap_dirty_pages reports exactly the pages written through the raw pointer",
+    ignore: Deno.build.os !== "linux",
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(PAGES * PAGE))
+        assert(!isNull(p), "mmap_open_write_with_size failed")
+        const view = new Uint8Array(Deno.UnsafePointerView.getArrayBuffer(p!, PAGES * PAGE))
+
+        // Fault every page in first, so the clear below has something to reset.
+        for (let i = 0; i < PAGES; i++) view[i * PAGE] = 1
+
+        if (lib.symbols.mmap_clear_dirty(p) !== 0) {
+            // Kernel built without CONFIG_MEM_SOFT_DIRTY: nothing to check.
+            assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+            lib.symbols.mmap_close(p, lenBuf[0])
+            await Deno.remove(path)
+            return
+        }
+
+        for (const i of [2, 7, 15]) view[i * PAGE + 5] = 9
+
+        const out = new BigUint64Array(PAGES)
+        const n = lib.symbols.mmap_dirty_pages(p, new Uint8Array(out.buffer), BigInt(PAGES))
+        assertEquals(n, 3n)
+        assertEquals([...out.subarray(0, 3)].map((off) => Number(off) / PAGE), [2, 7, 15])
+
+        // A short buffer still reports the full count.
+        assertEquals(lib.symbols.mmap_dirty_pages(p, new Uint8Array(8), 1n), 3n)
+
+        lib.symbols.mmap_close(p, lenBuf[0])
+        await Deno.remove(path)
+    },
+})
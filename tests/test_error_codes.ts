@@ -0,0 +1,47 @@
+// Uniform failure reporting: every function records the same code for the same class of error.
+
+import { assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_prefetch_join: { parameters: ["pointer"], result: "i32" },
+    mmap_validate_utf8: { parameters: ["pointer", "usize", "usize", "buffer"], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_abi_version: { parameters: [], result: "u32" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OS = -2
+
+/** Leaves a different last error behind, so each check sees only its own failure. */
+function reset() {
+    // Flushing an address that isn't mapped fails in the OS.
+    assertEquals(lib.symbols.mmap_flush(Deno.UnsafePointer.create(4096n), 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+}
+
+Deno.test("a null mapping is MMAP_ERR_INVALID_ARG for every kind of result", () => {
+    const buf = new Uint8Array(4)
+    const calls: [string, () => unknown, unknown][] = [
+        ["mmap_write", () => lib.symbols.mmap_write(null, 0n, buf, 4n), 0n],
+        ["mmap_read", () => lib.symbols.mmap_read(buf, null, 0n, 4n), 0n],
+        ["mmap_flush", () => lib.symbols.mmap_flush(null, 0n, 4n), -1],
+        ["mmap_prefetch_join", () => lib.symbols.mmap_prefetch_join(null), -1],
+        ["mmap_validate_utf8", () => lib.symbols.mmap_validate_utf8(null, 0n, 4n, null), -1],
+    ]
+    for (const [name, call, failed] of calls) {
+        reset()
+        assertEquals(call(), failed, name)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG, name)
+    }
+    assertEquals(lib.symbols.mmap_abi_version(), 3)
+})
+
+Deno.test("mmap_close ignores null and MAP_FAILED", () => {
+    lib.symbols.mmap_close(null, 0n)
+    lib.symbols.mmap_close(Deno.UnsafePointer.create(0xffff_ffff_ffff_ffffn), 4096n)
+})
@@ -0,0 +1,118 @@
+// mmap_evict: dropping a processed range from memory without losing what was written to it.
+// Residency is checked with mincore on Linux; elsewhere the test only exercises the calls.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MIB = 1024 * 1024
+const SIZE = 16 * MIB
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_open_snapshot: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_evict: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_page_size: { parameters: [], result: "usize" },
+})
+const PAGE = Number(lib.symbols.mmap_page_size())
+
+const libc = Deno.build.os === "linux"
+    ? Deno.dlopen("libc.so.6", {
+        mincore: { parameters: ["pointer", "usize", "buffer"], result: "i32" },
+    })
+    : null
+
+/** Whether temporary files are in tmpfs: their pages have no file to be dropped back to. */
+function tempInTmpfs(): boolean {
+    const tmp = Deno.realPathSync(Deno.env.get("TMPDIR") ?? "/tmp")
+    const mount = Deno.readTextFileSync("/proc/self/mounts").split("\n")
+        .map((line) => line.split(" "))
+        .filter(([, dir]) => dir === "/" || tmp === dir || tmp.startsWith(dir + "/"))
+        .sort((a, b) => b[1].length - a[1].length)[0]
+    return mount[2] === "tmpfs"
+}
+
+const checkResidency = libc !== null && !tempInTmpfs()
+
+/** Number of resident pages in `[offset, offset + len)` of the mapping, or null. */
+function residentPages(p: Deno.PointerValue, offset: number, len: number): number | null {
+    if (!checkResidency) return null
+    const vec = new Uint8Array(len / PAGE)
+    const at = Deno.UnsafePointer.create(Deno.UnsafePointer.value(p) + BigInt(offset))
+    assertEquals(libc!.symbols.mincore(at, BigInt(len), vec), 0)
+    return vec.reduce((n, v) => n + (v & 1), 0)
+}
+
+Deno.test("mmap_evict drops a range and keeps what was written to it", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(SIZE).fill(7))
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 0n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    lib.symbols.mmap_read(new Uint8Array(SIZE), p, 0n, BigInt(SIZE))
+    const data = new TextEncoder().encode("not flushed yet")
+    lib.symbols.mmap_write(p, BigInt(5 * MIB + 3), data, BigInt(data.length))
+
+    assertEquals(lib.symbols.mmap_evict(p, BigInt(4 * MIB), BigInt(4 * MIB)), 0)
+    assertEquals(residentPages(p, 4 * MIB, 4 * MIB) ?? 0, 0)
+    const kept = residentPages(p, 0, 4 * MIB)
+    if (kept !== null) assertEquals(kept, 4 * MIB / PAGE)
+
+    // The modified page was written back before it was dropped.
+    const onDisk = await Deno.readFile(path)
+    assertEquals(onDisk.subarray(5 * MIB + 3, 5 * MIB + 3 + data.length), data)
+    const out = new Uint8Array(data.length)
+    lib.symbols.mmap_read(out, p, BigInt(5 * MIB + 3), BigInt(out.length))
+    assertEquals(out, data)
+
+    assertEquals(lib.symbols.mmap_evict(p, 0n, 0n), 0)
+    assertEquals(residentPages(p, 0, SIZE) ?? 0, 0)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_evict works on read-only mappings and snapshots", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(MIB).fill(3))
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    lib.symbols.mmap_read(new Uint8Array(MIB), p, 0n, BigInt(MIB))
+    assertEquals(lib.symbols.mmap_evict(p, 0n, 0n), 0)
+    assertEquals(residentPages(p, 0, MIB) ?? 0, 0)
+    lib.symbols.mmap_close(p, lenBuf[0])
+
+    // A snapshot's pages are swapped out, not discarded (or left alone without swap support).
+    await Deno.writeTextFile(path, "snapshot contents")
+    const s = lib.symbols.mmap_open_snapshot(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(s), "mmap_open_snapshot failed")
+    assert(lib.symbols.mmap_evict(s, 0n, 0n) >= 0)
+    const out = new Uint8Array(Number(lenBuf[0]))
+    lib.symbols.mmap_read(out, s, 0n, lenBuf[0])
+    assertEquals(new TextDecoder().decode(out), "snapshot contents")
+    lib.symbols.mmap_close(s, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_evict checks the range", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(8 * PAGE))
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+
+    assertEquals(lib.symbols.mmap_evict(p, BigInt(8 * PAGE), 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    const inside = Deno.UnsafePointer.create(Deno.UnsafePointer.value(p) + BigInt(PAGE))
+    assertEquals(lib.symbols.mmap_evict(inside, 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
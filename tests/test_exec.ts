@@ -0,0 +1,64 @@
+// MMAP_EXEC: opt-in executable read-only mappings. The protection is checked through the OS
+// (/proc/self/maps on Linux, VirtualQuery on Windows); the code is never executed.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_EXEC = 1 << 1
+const MMAP_EXEC_CONFIRM = 1 << 2
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_UNSUPPORTED = -11
+const PAGE_EXECUTE_READ = 0x20
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_ex: { parameters: ["buffer", "buffer", "u32"], result: "pointer" },
+    mmap_open_write_ex: { parameters: ["buffer", "buffer", "usize", "u32"], result: "pointer" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+function protectionOf(p: Deno.PointerObject, path: string): string | number {
+    if (Deno.build.os === "windows") {
+        const k32 = Deno.dlopen("kernel32.dll", {
+            VirtualQuery: { parameters: ["pointer", "buffer", "usize"], result: "usize" },
+        })
+        // MEMORY_BASIC_INFORMATION (x64): Protect is the u32 at offset 36.
+        const info = new Uint8Array(48)
+        k32.symbols.VirtualQuery(p, info, BigInt(info.length))
+        k32.close()
+        return new DataView(info.buffer).getUint32(36, true)
+    }
+    const maps = Deno.readTextFileSync("/proc/self/maps")
+    const line = maps.split("\n").find((l) => l.endsWith(path))
+    assert(line, "mapping not found in /proc/self/maps")
+    return line.split(/\s+/)[1]
+}
+
+Deno.test("MMAP_EXEC maps a page read+execute", async () => {
+    const path = await Deno.makeTempFile()
+    // A page of x86 `ret` instructions (0xC3).
+    await Deno.writeFile(path, new Uint8Array(4096).fill(0xc3))
+    const lenBuf = new BigUint64Array(1)
+    const len = new Uint8Array(lenBuf.buffer)
+
+    // Without the confirmation constant, or for a writable open, the flag is rejected.
+    assert(isNull(lib.symbols.mmap_open_ex(cString(path), len, MMAP_EXEC)))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assert(isNull(lib.symbols.mmap_open_write_ex(cString(path), len, 0n, MMAP_EXEC | MMAP_EXEC_CONFIRM)))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    const p = lib.symbols.mmap_open_ex(cString(path), len, MMAP_EXEC | MMAP_EXEC_CONFIRM)
+    if (Deno.build.os === "darwin") {
+        assert(isNull(p))
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+    } else {
+        assert(!isNull(p), "executable mapping failed")
+        if (Deno.build.os === "windows") {
+            assertEquals(protectionOf(p!, path), PAGE_EXECUTE_READ)
+        } else {
+            assertEquals(protectionOf(p!, path), "r-xp")
+        }
+        lib.symbols.mmap_close(p, lenBuf[0])
+    }
+    await Deno.remove(path)
+})
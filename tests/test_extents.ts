@@ -0,0 +1,45 @@
+// mmap_extents: the data/hole map of a sparse file.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_punch_hole: { parameters: ["pointer", "u64", "u64"], result: "i32" },
+    mmap_extents: { parameters: ["pointer", "buffer", "usize"], result: "usize" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const MMAP_ERR_CLOSED = -6
+const KiB = 1024
+
+Deno.test("mmap_extents reports the data around a punched hole", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(256 * KiB).fill(1))
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 0n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    // Without holes (or hole support) the whole file is one extent.
+    const one = new BigUint64Array(2)
+    assertEquals(lib.symbols.mmap_extents(h, new Uint8Array(one.buffer), 1n), 1n)
+    assertEquals([...one], [0n, BigInt(256 * KiB)])
+
+    if (lib.symbols.mmap_punch_hole(h, BigInt(64 * KiB), BigInt(128 * KiB)) === 0) {
+        assertEquals(lib.symbols.mmap_extents(h, null, 0n), 2n)
+        const pairs = new BigUint64Array(4)
+        assertEquals(lib.symbols.mmap_extents(h, new Uint8Array(pairs.buffer), 2n), 2n)
+        assertEquals([...pairs], [0n, BigInt(64 * KiB), BigInt(192 * KiB), BigInt(64 * KiB)])
+        // A short buffer gets the first extents and the total.
+        one.fill(0n)
+        assertEquals(lib.symbols.mmap_extents(h, new Uint8Array(one.buffer), 1n), 2n)
+        assertEquals([...one], [0n, BigInt(64 * KiB)])
+    }
+
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    assertEquals(lib.symbols.mmap_extents(h, null, 0n), 0xffff_ffff_ffff_ffffn)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
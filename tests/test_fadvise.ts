@@ -0,0 +1,57 @@
+// mmap_fadvise: page-cache hints for a file by path, before it is mapped.
+
+import { assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, libPath } from "./common.ts"
+
+const MMAP_ADVICE_NORMAL = 0
+const MMAP_ADVICE_SEQUENTIAL = 1
+const MMAP_ADVICE_RANDOM = 2
+const MMAP_ADVICE_WILLNEED = 3
+const MMAP_ADVICE_DONTNEED = 4
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OS = -2
+
+const lib = Deno.dlopen(libPath, {
+    mmap_fadvise: { parameters: ["buffer", "u64", "u64", "i32"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+Deno.test("mmap_fadvise accepts every advice on a file", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(1024 * 1024).fill(7))
+    const advices = [
+        MMAP_ADVICE_NORMAL,
+        MMAP_ADVICE_SEQUENTIAL,
+        MMAP_ADVICE_RANDOM,
+        MMAP_ADVICE_WILLNEED,
+        MMAP_ADVICE_DONTNEED,
+    ]
+    for (const advice of advices) {
+        assertEquals(lib.symbols.mmap_fadvise(cString(path), 0n, 0n, advice), 0, `advice ${advice}`)
+        assertEquals(lib.symbols.mmap_fadvise(cString(path), 4096n, 8192n, advice), 0, `advice ${advice}`)
+    }
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_fadvise rejects unknown advice and a null path", async () => {
+    const path = await Deno.makeTempFile()
+    for (const advice of [-1, MMAP_ADVICE_DONTNEED + 1]) {
+        assertEquals(lib.symbols.mmap_fadvise(cString(path), 0n, 0n, advice), -1, `advice ${advice}`)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    }
+    assertEquals(lib.symbols.mmap_fadvise(null, 0n, 0n, MMAP_ADVICE_NORMAL), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    await Deno.remove(path)
+})
+
+Deno.test({
+    name: "mmap_fadvise reports a missing file",
+    // Windows has no equivalent and never opens the file.
+    ignore: Deno.build.os === "windows",
+    fn: async () => {
+        const dir = await Deno.makeTempDir()
+        assertEquals(lib.symbols.mmap_fadvise(cString(`${dir}/missing`), 0n, 0n, MMAP_ADVICE_NORMAL), -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+        await Deno.remove(dir)
+    },
+})
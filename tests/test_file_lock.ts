@@ -0,0 +1,90 @@
+// mmap_lock_file / mmap_unlock_file: whole-file advisory locks shared across processes.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const symbols = {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_clone: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_lock_file: { parameters: ["pointer", "i32", "i32"], result: "i32" },
+    mmap_unlock_file: { parameters: ["pointer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+} as const
+const lib = Deno.dlopen(libPath, symbols)
+
+const MMAP_ERR_CLOSED = -6
+const MMAP_ERR_WOULD_BLOCK = -23
+
+function open(path: string): Deno.PointerValue {
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    return h
+}
+
+Deno.test("an exclusive lock held by another process keeps this one out", async () => {
+    const path = await Deno.makeTempFile()
+    const script = `
+        const lib = Deno.dlopen(${JSON.stringify(libPath)}, ${JSON.stringify(symbols)})
+        const cString = (s) => new TextEncoder().encode(s + "\\0")
+        const h = lib.symbols.mmap_handle_open_write(cString(${JSON.stringify(path)}), 4096n)
+        if (lib.symbols.mmap_lock_file(h, 1, 0) !== 0) Deno.exit(1)
+        console.log("locked")
+        // Hold the lock until the parent closes our stdin.
+        for await (const _ of Deno.stdin.readable) {}
+    `
+    const child = new Deno.Command(Deno.execPath(), {
+        args: ["eval", "--unstable-ffi", script],
+        stdin: "piped",
+        stdout: "piped",
+    }).spawn()
+    const out = child.stdout.getReader()
+    let said = ""
+    while (!said.includes("locked")) {
+        const { value, done } = await out.read()
+        assert(!done, "the child exited without locking")
+        said += new TextDecoder().decode(value)
+    }
+
+    const h = open(path)
+    assertEquals(lib.symbols.mmap_lock_file(h, 1, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_WOULD_BLOCK)
+    assertEquals(lib.symbols.mmap_lock_file(h, 0, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_WOULD_BLOCK)
+
+    // The child's lock goes with it; a blocking request then gets through.
+    await child.stdin.close()
+    out.releaseLock()
+    await child.stdout.cancel()
+    assert((await child.status).success)
+    assertEquals(lib.symbols.mmap_lock_file(h, 1, 1), 0)
+    assertEquals(lib.symbols.mmap_unlock_file(h), 0)
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("readers share a lock that a writer can't take", async () => {
+    const path = await Deno.makeTempFile()
+    const [a, b, w] = [open(path), open(path), open(path)]
+
+    assertEquals(lib.symbols.mmap_lock_file(a, 0, 0), 0)
+    assertEquals(lib.symbols.mmap_lock_file(b, 0, 0), 0)
+    assertEquals(lib.symbols.mmap_lock_file(w, 1, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_WOULD_BLOCK)
+
+    // Clones hold the lock together: it stays until the last of them is closed.
+    const clone = lib.symbols.mmap_handle_clone(a)
+    assertEquals(lib.symbols.mmap_unlock_file(b), 0)
+    assertEquals(lib.symbols.mmap_handle_close(a), 0)
+    assertEquals(lib.symbols.mmap_lock_file(w, 1, 0), -1)
+    assertEquals(lib.symbols.mmap_handle_close(clone), 0)
+    assertEquals(lib.symbols.mmap_lock_file(w, 1, 0), 0)
+
+    assertEquals(lib.symbols.mmap_lock_file(a, 0, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    for (const h of [a, b, w, clone]) lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
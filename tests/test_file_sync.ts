@@ -0,0 +1,87 @@
+// mmap_file_sync / mmap_handle_sync_all: fsync/fdatasync the file behind a writable handle.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_file_sync: { parameters: ["pointer", "i32"], result: "i32" },
+    mmap_flush_full: { parameters: ["pointer", "i32"], result: "i32" },
+    mmap_handle_sync_all: { parameters: ["pointer"], result: "i32" },
+    mmap_ensure_capacity: { parameters: ["pointer", "usize", "buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+})
+
+const MMAP_ERR_OS = -2
+
+Deno.test("mmap_file_sync persists content and extended length", async () => {
+    const path = await Deno.makeTempFile()
+    // Extends the empty temp file to 20000 bytes.
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 20000n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    const data = new TextEncoder().encode("end")
+    assertEquals(lib.symbols.mmap_handle_write(h, 19990n, data, 3n), 3n)
+    assertEquals(lib.symbols.mmap_handle_flush(h, 19990n, 3n), 0)
+    assertEquals(lib.symbols.mmap_file_sync(h, 1), 0)
+    assertEquals(lib.symbols.mmap_file_sync(h, 0), 0)
+    lib.symbols.mmap_handle_free(h)
+
+    const file = await Deno.readFile(path)
+    assertEquals(file.length, 20000)
+    assertEquals(file.subarray(19990, 19993), data)
+    await Deno.remove(path)
+})
+
+// F_FULLFSYNC is only reachable on macOS; elsewhere `full` maps to fsync / FlushFileBuffers,
+// so the same assertions exercise each platform's strongest primitive.
+Deno.test("mmap_flush_full flushes the view and syncs the file", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    const data = new TextEncoder().encode("full")
+    lib.symbols.mmap_handle_write(h, 0n, data, 4n)
+    assertEquals(lib.symbols.mmap_flush_full(h, 1), 0)
+    assertEquals(lib.symbols.mmap_flush_full(h, 0), 0)
+    lib.symbols.mmap_handle_free(h)
+
+    assertEquals((await Deno.readFile(path)).subarray(0, 4), data)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_handle_sync_all persists a new, grown file", async () => {
+    const dir = await Deno.makeTempDir()
+    const path = `${dir}/new.bin`
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    assertEquals(lib.symbols.mmap_ensure_capacity(h, 3n * 4096n, null), 0)
+    const data = new TextEncoder().encode("grown")
+    assertEquals(lib.symbols.mmap_handle_write(h, 3n * 4096n - 5n, data, 5n), 5n)
+    assertEquals(lib.symbols.mmap_handle_sync_all(h), 0)
+    lib.symbols.mmap_handle_free(h)
+
+    const file = await Deno.readFile(path)
+    assert(file.length >= 3 * 4096)
+    assertEquals(file.subarray(3 * 4096 - 5, 3 * 4096), data)
+    await Deno.remove(dir, { recursive: true })
+})
+
+Deno.test({
+    name: "mmap_handle_sync_all fails when the directory is gone",
+    // Windows doesn't let the directory of an open file be removed, nor sync directories.
+    ignore: Deno.build.os === "windows",
+    fn: async () => {
+        const dir = await Deno.makeTempDir()
+        const h = lib.symbols.mmap_handle_open_write(cString(`${dir}/f.bin`), 4096n)
+        assert(!isNull(h), "mmap_handle_open_write failed")
+        await Deno.remove(dir, { recursive: true })
+        assertEquals(lib.symbols.mmap_handle_sync_all(h), -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+        lib.symbols.mmap_handle_free(h)
+    },
+})
@@ -0,0 +1,89 @@
+// mmap_flush with offsets that are not page-aligned, mmap_flush_all, and range checks.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_flush_ranges: { parameters: ["pointer", "buffer", "usize"], result: "isize" },
+    mmap_flush_all: { parameters: ["pointer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+
+Deno.test("mmap_flush accepts mid-page and page-spanning ranges", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 3n * 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const enc = new TextEncoder()
+    const dec = new TextDecoder()
+
+    lib.symbols.mmap_write(p, 100n, enc.encode("mid"), 3n)
+    assertEquals(lib.symbols.mmap_flush(p, 100n, 3n), 0)
+    assertEquals(dec.decode((await Deno.readFile(path)).subarray(100, 103)), "mid")
+
+    // Crosses the boundary between the first and second 4 KiB page.
+    lib.symbols.mmap_write(p, 4090n, enc.encode("span-page"), 9n)
+    assertEquals(lib.symbols.mmap_flush(p, 4090n, 9n), 0)
+    assertEquals(dec.decode((await Deno.readFile(path)).subarray(4090, 4099)), "span-page")
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_flush_ranges flushes several disjoint ranges in one call", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 5n * 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    const offsets = [10n, 2n * 4096n + 5n, 4n * 4096n + 100n]
+    const data = new TextEncoder().encode("abc")
+    for (const off of offsets) lib.symbols.mmap_write(p, off, data, 3n)
+
+    // MmapRange[] { offset: usize, len: usize }
+    const ranges = new BigUint64Array(offsets.flatMap((off) => [off, 3n]))
+    assertEquals(lib.symbols.mmap_flush_ranges(p, new Uint8Array(ranges.buffer), 3n), -1n)
+
+    const file = await Deno.readFile(path)
+    for (const off of offsets) assertEquals(file.subarray(Number(off), Number(off) + 3), data)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_flush_all flushes the whole mapping", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 2n * 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const data = new TextEncoder().encode("tail")
+
+    lib.symbols.mmap_write(p, 2n * 4096n - 4n, data, 4n)
+    assertEquals(lib.symbols.mmap_flush_all(p), 0)
+    assertEquals((await Deno.readFile(path)).subarray(2 * 4096 - 4), data)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_flush rejects ranges outside the mapping", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_flush(p, 0n, 4096n), 0)
+    assertEquals(lib.symbols.mmap_flush(p, 4000n, 200n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_flush(p, 0xffff_ffff_ffff_ff00n, 0x200n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
@@ -0,0 +1,48 @@
+// mmap_flush_durable: page flush followed by a file-level flush.
+// The call sequence is only observable in builds with `--features test-hooks`;
+// with other builds the sequence assertions are skipped.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush_durable: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_test_take_trace: { parameters: ["buffer", "usize"], result: "usize", optional: true },
+})
+
+function takeTrace(): string | null {
+    const take = lib.symbols.mmap_test_take_trace
+    if (!take) return null
+    const buf = new Uint8Array(256)
+    const n = Number(take(buf, BigInt(buf.length)))
+    return new TextDecoder().decode(buf.subarray(0, n))
+}
+
+Deno.test("mmap_flush_durable flushes the view, then the file", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const len = new Uint8Array(lenBuf.buffer)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), len, 8192n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    takeTrace()
+
+    lib.symbols.mmap_write(p, 10n, new Uint8Array([42]), 1n)
+    assertEquals(lib.symbols.mmap_flush_durable(p, 10n, 1n), 0)
+    const trace = takeTrace()
+    if (trace !== null) assertEquals(trace, "flush_view,flush_file")
+    assertEquals((await Deno.readFile(path))[10], 42)
+    lib.symbols.mmap_close(p, lenBuf[0])
+
+    // Read-only mappings have no file-level step.
+    const ro = lib.symbols.mmap_open(cString(path), len)
+    assert(!isNull(ro), "mmap_open failed")
+    assertEquals(lib.symbols.mmap_flush_durable(ro, 0n, 1n), 0)
+    const roTrace = takeTrace()
+    if (roTrace !== null) assertEquals(roTrace, "flush_view")
+    lib.symbols.mmap_close(ro, lenBuf[0])
+    await Deno.remove(path)
+})
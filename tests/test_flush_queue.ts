@@ -0,0 +1,75 @@
+// mmap_flush_enqueue / mmap_flush_poll: flushes on a background worker, polled by ticket.
+
+import { assert, assertEquals, assertNotEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush_enqueue: { parameters: ["pointer", "usize", "usize"], result: "u64" },
+    mmap_flush_poll: { parameters: ["u64"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_shutdown: { parameters: [], result: "void" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const SIZE = 1024 * 1024
+
+async function pollUntilDone(ticket: bigint): Promise<number> {
+    for (;;) {
+        const rc = lib.symbols.mmap_flush_poll(ticket)
+        if (rc !== 0) return rc
+        await new Promise((resolve) => setTimeout(resolve, 1))
+    }
+}
+
+Deno.test("queued flushes complete and their data reaches the file", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(SIZE))
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const enc = new TextEncoder()
+
+    const tickets: bigint[] = []
+    for (let i = 0; i < 5; i++) {
+        const off = BigInt(i * 65536)
+        lib.symbols.mmap_write(p, off, enc.encode(`q${i}`), 2n)
+        const t = lib.symbols.mmap_flush_enqueue(p, off, 2n)
+        assertNotEquals(t, 0n)
+        tickets.push(t)
+    }
+    for (const t of tickets) assertEquals(await pollUntilDone(t), 1)
+
+    const file = await Deno.readFile(path)
+    const dec = new TextDecoder()
+    for (let i = 0; i < 5; i++) assertEquals(dec.decode(file.subarray(i * 65536, i * 65536 + 2)), `q${i}`)
+
+    // A completed ticket is reclaimed by the poll that reported it.
+    assertEquals(lib.symbols.mmap_flush_poll(tickets[0]), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    lib.symbols.mmap_shutdown()
+    await Deno.remove(path)
+})
+
+Deno.test("bad enqueues and unknown tickets are rejected", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_flush_enqueue(p, 4000n, 200n), 0n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_flush_poll(0xdead_beefn), MMAP_ERR_INVALID_ARG)
+
+    // Closing the mapping reclaims its tickets.
+    const t = lib.symbols.mmap_flush_enqueue(p, 0n, 4096n)
+    assertNotEquals(t, 0n)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    assertEquals(lib.symbols.mmap_flush_poll(t), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_shutdown()
+    await Deno.remove(path)
+})
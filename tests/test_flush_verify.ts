@@ -0,0 +1,78 @@
+// mmap_flush_verify / mmap_last_error_message: read-back verification after a durable flush.
+// A real on-disk mismatch can't be staged, so builds with `--features test-hooks` flip a byte
+// of the read-back data to prove mismatches are reported.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush_verify: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_last_error_message: { parameters: ["buffer", "usize"], result: "usize" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_test_corrupt_verify: { parameters: ["i64"], result: "void", optional: true },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_VERIFY = -20
+
+function lastMessage(): string {
+    const len = Number(lib.symbols.mmap_last_error_message(null, 0n))
+    const buf = new Uint8Array(len + 1)
+    assertEquals(lib.symbols.mmap_last_error_message(buf, BigInt(buf.length)), BigInt(len))
+    return new TextDecoder().decode(buf.subarray(0, len))
+}
+
+Deno.test("mmap_flush_verify accepts a range that reached the file", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    // Not a multiple of the page size, so the read-back ends inside a page.
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 3n * 1024n * 1024n + 123n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    lib.symbols.mmap_write(p, 5000n, new TextEncoder().encode("journal"), 7n)
+    assertEquals(lib.symbols.mmap_flush_verify(p, 0n, lenBuf[0]), 0)
+    assertEquals(lib.symbols.mmap_flush_verify(p, 4001n, 2000n), 0)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test({
+    name: "mmap_flush_verify reports the first mismatching offset",
+    ignore: lib.symbols.mmap_test_corrupt_verify === null,
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 64n * 1024n)
+        assert(!isNull(p), "mmap_open_write_with_size failed")
+
+        lib.symbols.mmap_write(p, 5000n, new TextEncoder().encode("journal"), 7n)
+        lib.symbols.mmap_test_corrupt_verify!(5003n)
+        try {
+            assertEquals(lib.symbols.mmap_flush_verify(p, 0n, lenBuf[0]), -1)
+            assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_VERIFY)
+            assertEquals(lastMessage(), "flushed data differs from the mapping at offset 5003")
+            // A range that doesn't cover the corrupted byte still verifies.
+            assertEquals(lib.symbols.mmap_flush_verify(p, 6000n, 100n), 0)
+        } finally {
+            lib.symbols.mmap_test_corrupt_verify!(-1n)
+        }
+
+        lib.symbols.mmap_close(p, lenBuf[0])
+        await Deno.remove(path)
+    },
+})
+
+Deno.test("mmap_last_error_message describes errors without details", () => {
+    assertEquals(lib.symbols.mmap_flush_verify(null, 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lastMessage(), "invalid argument")
+
+    // Truncated to the buffer, always NUL-terminated.
+    const small = new Uint8Array(6)
+    assertEquals(lib.symbols.mmap_last_error_message(small, 6n), 16n)
+    assertEquals(new TextDecoder().decode(small), "inval\0")
+})
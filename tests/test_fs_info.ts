@@ -0,0 +1,65 @@
+// mmap_fs_info / MMAP_LOCAL_ONLY: file system classification of a mapped file.
+// The temp directory is assumed to be on a local disk (or tmpfs on Linux); /dev/shm gives a
+// tmpfs file on Linux.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_ex: { parameters: ["buffer", "buffer", "u32"], result: "pointer" },
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_fs_info: { parameters: ["pointer", "buffer", "buffer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_LOCAL_ONLY = 1 << 8
+const MMAP_FS_LOCAL = 0
+const MMAP_FS_TMPFS = 1
+const MMAP_ERR_INVALID_ARG = -1
+
+function fsInfo(path: string): { kind: number; flags: number } {
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+    const kind = new Int32Array(1)
+    const flags = new Uint32Array(1)
+    assertEquals(
+        lib.symbols.mmap_fs_info(h, new Uint8Array(kind.buffer), new Uint8Array(flags.buffer)),
+        0,
+    )
+    lib.symbols.mmap_handle_free(h)
+    return { kind: kind[0], flags: flags[0] }
+}
+
+Deno.test("a temp file is on a local file system", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(4096).fill(1))
+
+    const { kind, flags } = fsInfo(path)
+    assert(kind === MMAP_FS_LOCAL || kind === MMAP_FS_TMPFS, `unexpected kind ${kind}`)
+    assertEquals(flags, 0)
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_ex(cString(path), new Uint8Array(lenBuf.buffer), MMAP_LOCAL_ONLY)
+    assert(!isNull(p), "MMAP_LOCAL_ONLY refused a local file")
+    assertEquals(lenBuf[0], 4096n)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test({
+    name: "files in /dev/shm are reported as tmpfs",
+    ignore: Deno.build.os !== "linux",
+    fn: async () => {
+        const path = `/dev/shm/deno-mmap-fs-info-${Deno.pid}`
+        await Deno.writeFile(path, new Uint8Array(4096))
+        assertEquals(fsInfo(path).kind, MMAP_FS_TMPFS)
+        await Deno.remove(path)
+    },
+})
+
+Deno.test("mmap_fs_info rejects a null handle", () => {
+    assertEquals(lib.symbols.mmap_fs_info(null, new Uint8Array(4), null), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+})
@@ -0,0 +1,46 @@
+// Growth policy and mmap_ensure_capacity: policy-driven file growth with remapping.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_set_growth_policy: { parameters: ["usize", "usize", "u32"], result: "void" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_len: { parameters: ["pointer"], result: "usize" },
+    mmap_handle_ptr: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_read: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_ensure_capacity: { parameters: ["pointer", "usize", "buffer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+})
+
+Deno.test("mmap_ensure_capacity grows the file per the policy and keeps data", async () => {
+    const path = await Deno.makeTempFile()
+    // Start at 8 KiB, grow by at least 4 KiB or to 2x the current size.
+    lib.symbols.mmap_set_growth_policy(8192n, 4096n, 200)
+    try {
+        const h = lib.symbols.mmap_handle_open_write(cString(path), 0n)
+        assert(!isNull(h), "mmap_handle_open_write failed")
+        assertEquals(lib.symbols.mmap_handle_len(h), 8192n)
+
+        const marker = new TextEncoder().encode("tail")
+        lib.symbols.mmap_handle_write(h, 8000n, marker, 4n)
+
+        const baseOut = new BigUint64Array(1)
+        // [needed, expected length]: no-op, 2x, 2x, then `needed` wins (rounded to pages).
+        for (const [needed, expected] of [[8192n, 8192n], [9000n, 16384n], [20000n, 32768n], [100000n, 102400n]]) {
+            assertEquals(lib.symbols.mmap_ensure_capacity(h, needed, new Uint8Array(baseOut.buffer)), 0)
+            assertEquals(lib.symbols.mmap_handle_len(h), expected)
+            assertEquals((await Deno.stat(path)).size, Number(expected))
+            assertEquals(baseOut[0], Deno.UnsafePointer.value(lib.symbols.mmap_handle_ptr(h)))
+        }
+
+        const out = new Uint8Array(4)
+        lib.symbols.mmap_handle_read(h, 8000n, out, 4n)
+        assertEquals(out, marker)
+        lib.symbols.mmap_handle_free(h)
+    } finally {
+        lib.symbols.mmap_set_growth_policy(0n, 0n, 0)
+        await Deno.remove(path)
+    }
+})
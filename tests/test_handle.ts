@@ -0,0 +1,466 @@
+// Handle API: bounds-checked access, idempotent close and reference-counted clones.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ALREADY_CLOSED = 1
+const MMAP_ERR_CLOSED = -6
+const MMAP_ERR_READ_ONLY = -7
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_UNSUPPORTED = -11
+const MMAP_ERR_UNALIGNED = -13
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_file_changed: { parameters: ["pointer"], result: "i32" },
+    mmap_is_stale: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_same_file: { parameters: ["pointer", "pointer"], result: "i32" },
+    mmap_refresh: { parameters: ["pointer", "buffer", "buffer"], result: "i32" },
+    mmap_clamp_to_file: { parameters: ["pointer"], result: "isize" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_read: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_ptr: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_contains: { parameters: ["pointer", "pointer"], result: "i32" },
+    mmap_handle_is_writable: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_handle_stats: { parameters: ["pointer", "buffer"], result: "i32" },
+    mmap_write_grow: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_append: { parameters: ["pointer", "buffer", "usize"], result: "isize" },
+    mmap_handle_tell: { parameters: ["pointer"], result: "isize" },
+    mmap_handle_seek: { parameters: ["pointer", "usize"], result: "i32" },
+    mmap_handle_len: { parameters: ["pointer"], result: "usize" },
+    mmap_handle_path: { parameters: ["pointer", "buffer", "usize"], result: "isize" },
+    mmap_set_delete_on_close: { parameters: ["pointer", "i32"], result: "i32" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_clone: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_subview: { parameters: ["pointer", "u64", "usize", "buffer"], result: "pointer" },
+    mmap_ensure_capacity: { parameters: ["pointer", "usize", "buffer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+Deno.test("mmap_handle_close is idempotent", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    const data = new TextEncoder().encode("hello")
+    assertEquals(lib.symbols.mmap_handle_write(h, 0n, data, BigInt(data.length)), BigInt(data.length))
+
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    assertEquals(lib.symbols.mmap_handle_close(h), MMAP_ALREADY_CLOSED)
+    assertEquals(lib.symbols.mmap_handle_close(h), MMAP_ALREADY_CLOSED)
+
+    // Access after close is reported, not a crash.
+    const out = new Uint8Array(5)
+    assertEquals(lib.symbols.mmap_handle_read(h, 0n, out, 5n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_handle_contains checks [base, base + len)", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const base = Deno.UnsafePointer.value(lib.symbols.mmap_handle_ptr(h))
+    const at = (addr: bigint) => Deno.UnsafePointer.create(addr)
+
+    assertEquals(lib.symbols.mmap_handle_contains(h, at(base)), 1)
+    assertEquals(lib.symbols.mmap_handle_contains(h, at(base + 4095n)), 1)
+    assertEquals(lib.symbols.mmap_handle_contains(h, at(base + 4096n)), 0)
+    assertEquals(lib.symbols.mmap_handle_contains(h, at(base - 1n)), 0)
+    assertEquals(lib.symbols.mmap_handle_contains(h, null), 0)
+
+    lib.symbols.mmap_handle_close(h)
+    assertEquals(lib.symbols.mmap_handle_contains(h, at(base)), 0)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_handle_is_writable reports the open mode", async () => {
+    const path = await Deno.makeTempFile()
+    const rw = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(rw), "mmap_handle_open_write failed")
+    const ro = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(ro), "mmap_handle_open failed")
+
+    assertEquals(lib.symbols.mmap_handle_is_writable(rw), 1)
+    assertEquals(lib.symbols.mmap_handle_is_writable(ro), 0)
+    assertEquals(lib.symbols.mmap_handle_is_writable(null), -1)
+    lib.symbols.mmap_handle_close(ro)
+    assertEquals(lib.symbols.mmap_handle_is_writable(ro), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+
+    lib.symbols.mmap_handle_free(ro)
+    lib.symbols.mmap_handle_free(rw)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_handle_stats counts successful reads, writes and flushes", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    const buf = new Uint8Array(10)
+    lib.symbols.mmap_handle_write(h, 0n, buf, 10n)
+    lib.symbols.mmap_handle_write(h, 100n, buf, 5n)
+    lib.symbols.mmap_handle_read(h, 0n, buf, 3n)
+    lib.symbols.mmap_handle_read(h, 5000n, buf, 3n) // out of bounds: not counted
+    assertEquals(lib.symbols.mmap_handle_flush(h, 0n, 4096n), 0)
+    lib.symbols.mmap_handle_close(h)
+
+    // MmapStats { bytes_read, bytes_written, read_count, write_count, flush_count }
+    const stats = new BigUint64Array(5)
+    assertEquals(lib.symbols.mmap_handle_stats(h, new Uint8Array(stats.buffer)), 0)
+    assertEquals([...stats], [3n, 15n, 1n, 2n, 1n])
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_handle_file_changed detects out-of-band modification", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(100))
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_handle_file_changed(h), 0)
+
+    await Deno.writeFile(path, new Uint8Array([1]), { append: true })
+    assertEquals(lib.symbols.mmap_handle_file_changed(h), 1)
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test({
+    name: "mmap_is_stale detects a file renamed over the mapped one",
+    // Windows refuses to replace a file that is mapped.
+    ignore: Deno.build.os === "windows",
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        await Deno.writeFile(path, new Uint8Array(100).fill(1))
+        const h = lib.symbols.mmap_handle_open(cString(path))
+        assert(!isNull(h), "mmap_handle_open failed")
+        assertEquals(lib.symbols.mmap_is_stale(h), 0)
+
+        // Same size, different inode: only the identity gives it away.
+        const next = path + ".next"
+        await Deno.writeFile(next, new Uint8Array(100).fill(2))
+        await Deno.rename(next, path)
+        assertEquals(lib.symbols.mmap_is_stale(h), 1)
+        // The mapped file itself didn't change.
+        assertEquals(lib.symbols.mmap_handle_file_changed(h), 0)
+
+        const h2 = lib.symbols.mmap_handle_open(cString(path))
+        assertEquals(lib.symbols.mmap_is_stale(h2), 0)
+        await Deno.writeFile(path, new Uint8Array([3]), { append: true })
+        assertEquals(lib.symbols.mmap_is_stale(h2), 1)
+        await Deno.remove(path)
+        assertEquals(lib.symbols.mmap_is_stale(h2), 1)
+
+        lib.symbols.mmap_handle_free(h)
+        lib.symbols.mmap_handle_free(h2)
+    },
+})
+
+Deno.test("mmap_handle_same_file sees through hard links", async () => {
+    const dir = await Deno.makeTempDir()
+    const path = `${dir}/data`
+    const link = `${dir}/link`
+    await Deno.writeFile(path, new Uint8Array(100).fill(1))
+    await Deno.link(path, link)
+    // Same size and contents, different file.
+    const other = `${dir}/other`
+    await Deno.copyFile(path, other)
+
+    const a = lib.symbols.mmap_handle_open(cString(path))
+    const b = lib.symbols.mmap_handle_open(cString(link))
+    const c = lib.symbols.mmap_handle_open(cString(other))
+    assert(!isNull(a) && !isNull(b) && !isNull(c), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_handle_same_file(a, b), 1)
+    assertEquals(lib.symbols.mmap_handle_same_file(b, a), 1)
+    assertEquals(lib.symbols.mmap_handle_same_file(a, a), 1)
+    assertEquals(lib.symbols.mmap_handle_same_file(a, c), 0)
+    assertEquals(lib.symbols.mmap_handle_same_file(a, null), -1)
+
+    // The identities were captured at open: closing doesn't change the answer.
+    assertEquals(lib.symbols.mmap_handle_close(b), 0)
+    assertEquals(lib.symbols.mmap_handle_same_file(a, b), 1)
+    for (const h of [a, b, c]) lib.symbols.mmap_handle_free(h)
+    await Deno.remove(dir, { recursive: true })
+})
+
+Deno.test("mmap_set_delete_on_close removes the file when the mapping goes", async () => {
+    const dir = await Deno.makeTempDir()
+    const exists = (p: string) => Deno.stat(p).then(() => true, () => false)
+
+    const gone = `${dir}/gone`
+    const h = lib.symbols.mmap_handle_open_write(cString(gone), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const clone = lib.symbols.mmap_handle_clone(h)
+    assertEquals(lib.symbols.mmap_set_delete_on_close(h, 1), 0)
+    // Not while it is mapped, and not until the last clone is closed.
+    assert(await exists(gone))
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    assert(await exists(gone))
+    assertEquals(lib.symbols.mmap_handle_close(clone), 0)
+    // Windows deletes the file when its last descriptor goes, at free.
+    for (const x of [h, clone]) lib.symbols.mmap_handle_free(x)
+    assert(!(await exists(gone)), "the file is still there")
+
+    const kept = `${dir}/kept`
+    const k = lib.symbols.mmap_handle_open_write(cString(kept), 4096n)
+    assert(!isNull(k), "mmap_handle_open_write failed")
+    assertEquals(lib.symbols.mmap_set_delete_on_close(k, 1), 0)
+    assertEquals(lib.symbols.mmap_set_delete_on_close(k, 0), 0)
+    assertEquals(lib.symbols.mmap_handle_close(k), 0)
+    assertEquals(lib.symbols.mmap_set_delete_on_close(k, 1), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    lib.symbols.mmap_handle_free(k)
+    assertEquals((await Deno.stat(kept)).size, 4096)
+
+    await Deno.remove(dir, { recursive: true })
+})
+
+Deno.test("mmap_refresh maps bytes appended by someone else", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(100).fill(1))
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+    const base = new BigUint64Array(1)
+    const len = new BigUint64Array(1)
+    const baseOut = new Uint8Array(base.buffer)
+    const lenBuf = new Uint8Array(len.buffer)
+
+    assertEquals(lib.symbols.mmap_refresh(h, baseOut, lenBuf), 0)
+    assertEquals(len[0], 100n)
+
+    await Deno.writeFile(path, new Uint8Array(5000).fill(2), { append: true })
+    const tail = new Uint8Array(10)
+    assertEquals(lib.symbols.mmap_handle_read(h, 5090n, tail, 10n), -1n)
+    assertEquals(lib.symbols.mmap_refresh(h, baseOut, lenBuf), 1)
+    assertEquals(len[0], 5100n)
+    assertEquals(lib.symbols.mmap_handle_len(h), 5100n)
+    assertEquals(BigInt(Deno.UnsafePointer.value(lib.symbols.mmap_handle_ptr(h))), base[0])
+    assertEquals(lib.symbols.mmap_handle_read(h, 5090n, tail, 10n), 10n)
+    assertEquals(tail, new Uint8Array(10).fill(2))
+    const head = new Uint8Array(1)
+    assertEquals(lib.symbols.mmap_handle_read(h, 99n, head, 1n), 1n)
+    assertEquals(head[0], 1)
+
+    // Nothing new: a no-op.
+    assertEquals(lib.symbols.mmap_refresh(h, null, null), 0)
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    assertEquals(lib.symbols.mmap_refresh(h, null, null), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test({
+    name: "mmap_clamp_to_file keeps checked reads out of a truncated tail",
+    // Windows doesn't let a mapped file be truncated.
+    ignore: Deno.build.os === "windows",
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        await Deno.writeFile(path, new Uint8Array(3 * 4096).fill(7))
+        const h = lib.symbols.mmap_handle_open(cString(path))
+        assert(!isNull(h), "mmap_handle_open failed")
+        assertEquals(lib.symbols.mmap_clamp_to_file(h), BigInt(3 * 4096))
+
+        await Deno.truncate(path, 5000)
+        assertEquals(lib.symbols.mmap_clamp_to_file(h), 5000n)
+        assertEquals(lib.symbols.mmap_handle_len(h), 5000n)
+        const buf = new Uint8Array(10)
+        assertEquals(lib.symbols.mmap_handle_read(h, 8192n, buf, 10n), -1n)
+        assertEquals(lib.symbols.mmap_handle_read(h, 4990n, buf, 10n), 10n)
+        assertEquals(buf, new Uint8Array(10).fill(7))
+
+        // Growing back is let in up to the mapped length only.
+        await Deno.truncate(path, 5 * 4096)
+        assertEquals(lib.symbols.mmap_clamp_to_file(h), BigInt(3 * 4096))
+
+        assertEquals(lib.symbols.mmap_handle_close(h), 0)
+        assertEquals(lib.symbols.mmap_clamp_to_file(h), -1n)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+        lib.symbols.mmap_handle_free(h)
+        await Deno.remove(path)
+    },
+})
+
+Deno.test("mmap_handle_append writes at the cursor and grows the file", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    const record = new Uint8Array(1000).fill(0x78)
+    for (let i = 0; i < 10; i++) {
+        assertEquals(lib.symbols.mmap_handle_append(h, record, 1000n), BigInt(i * 1000))
+    }
+    assertEquals(lib.symbols.mmap_handle_tell(h), 10000n)
+    assert(lib.symbols.mmap_handle_len(h) >= 10000n, "append should have grown the mapping")
+
+    // Seeking back overwrites from there.
+    assertEquals(lib.symbols.mmap_handle_seek(h, 5n), 0)
+    assertEquals(lib.symbols.mmap_handle_append(h, new TextEncoder().encode("HEAD"), 4n), 5n)
+    assertEquals(lib.symbols.mmap_handle_tell(h), 9n)
+    const out = new Uint8Array(10)
+    lib.symbols.mmap_handle_read(h, 0n, out, 10n)
+    assertEquals(new TextDecoder().decode(out), "xxxxxHEADx")
+
+    assertEquals(lib.symbols.mmap_handle_seek(h, lib.symbols.mmap_handle_len(h) + 1n), -1)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_write_grow grows the mapping to fit the write", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    const data = new TextEncoder().encode("far out")
+    // In bounds: the same as mmap_handle_write.
+    assertEquals(lib.symbols.mmap_write_grow(h, 100n, data, 7n), 7n)
+    assertEquals(lib.symbols.mmap_handle_len(h), 4096n)
+
+    assertEquals(lib.symbols.mmap_write_grow(h, 1_000_000n, data, 7n), 7n)
+    assert(lib.symbols.mmap_handle_len(h) >= 1_000_007n, "the write should have grown the mapping")
+    const out = new Uint8Array(7)
+    lib.symbols.mmap_handle_read(h, 100n, out, 7n)
+    assertEquals(new TextDecoder().decode(out), "far out")
+    lib.symbols.mmap_handle_read(h, 1_000_000n, out, 7n)
+    assertEquals(new TextDecoder().decode(out), "far out")
+    // The stored base follows the remap.
+    const view = new Deno.UnsafePointerView(lib.symbols.mmap_handle_ptr(h)!)
+    assertEquals(view.getUint8(1_000_000), data[0])
+
+    assertEquals(lib.symbols.mmap_handle_flush(h, 0n, lib.symbols.mmap_handle_len(h)), 0)
+    lib.symbols.mmap_handle_free(h)
+    const file = await Deno.readFile(path)
+    assertEquals(new TextDecoder().decode(file.subarray(1_000_000, 1_000_007)), "far out")
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_write_grow refuses read-only handles", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(10))
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_write_grow(h, 100n, new Uint8Array(1), 1n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    assertEquals(lib.symbols.mmap_handle_len(h), 10n)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_handle_path returns the path the handle was opened with", async () => {
+    const path = await Deno.makeTempFile({ suffix: "-é.bin" })
+    await Deno.writeFile(path, new Uint8Array(16))
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+
+    const expected = new TextEncoder().encode(path)
+    const len = lib.symbols.mmap_handle_path(h, null, 0n)
+    assertEquals(len, BigInt(expected.length))
+    const out = new Uint8Array(expected.length + 1)
+    assertEquals(lib.symbols.mmap_handle_path(h, out, BigInt(out.length)), len)
+    assertEquals(out.subarray(0, expected.length), expected)
+    assertEquals(out[expected.length], 0)
+
+    // Truncation keeps whole characters: cutting into "é" drops both of its bytes.
+    const short = new Uint8Array(expected.length - 4)
+    assertEquals(lib.symbols.mmap_handle_path(h, short, BigInt(short.length)), len)
+    assertEquals(short.subarray(0, short.length - 2), expected.subarray(0, short.length - 2))
+    assertEquals(short[short.length - 2], 0)
+
+    // Still available after close, for error handlers.
+    lib.symbols.mmap_handle_close(h)
+    assertEquals(lib.symbols.mmap_handle_path(h, null, 0n), len)
+    assertEquals(lib.symbols.mmap_handle_path(null, null, 0n), -1n)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("a cloned handle keeps the mapping alive after the original closes", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new TextEncoder().encode("shared mapping"))
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+    const clone = lib.symbols.mmap_handle_clone(h)
+    assert(!isNull(clone), "mmap_handle_clone failed")
+
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    assertEquals(lib.symbols.mmap_handle_close(h), MMAP_ALREADY_CLOSED)
+    const out = new Uint8Array(6)
+    assertEquals(lib.symbols.mmap_handle_read(h, 0n, out, 6n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    assert(isNull(lib.symbols.mmap_handle_clone(h)), "cloned a closed handle")
+
+    // The clone still reads, and its close is the one that unmaps.
+    assertEquals(lib.symbols.mmap_handle_read(clone, 0n, out, 6n), 6n)
+    assertEquals(new TextDecoder().decode(out), "shared")
+    assertEquals(lib.symbols.mmap_handle_close(clone), 0)
+    assertEquals(lib.symbols.mmap_handle_read(clone, 0n, out, 6n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+
+    lib.symbols.mmap_handle_free(h)
+    lib.symbols.mmap_handle_free(clone)
+    await Deno.remove(path)
+})
+
+Deno.test("a subview maps part of the file and outlives its handle", async () => {
+    // 64 KiB is the mapping granularity on Windows and a page multiple elsewhere.
+    const K64 = 65536n
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 3n * K64)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const enc = new TextEncoder()
+    const word = enc.encode("middle")
+    assertEquals(lib.symbols.mmap_handle_write(h, K64, word, 6n), 6n)
+
+    const lenBuf = new BigUint64Array(1)
+    const sub = lib.symbols.mmap_handle_subview(h, K64, K64, new Uint8Array(lenBuf.buffer))
+    assert(!isNull(sub), "mmap_handle_subview failed")
+    assertEquals(lenBuf[0], K64)
+    assertEquals(lib.symbols.mmap_handle_is_writable(sub), 1)
+    const out = new Uint8Array(6)
+    assertEquals(lib.symbols.mmap_handle_read(sub, 0n, out, 6n), 6n)
+    assertEquals(out, word)
+    // Both map the same file pages.
+    assertEquals(lib.symbols.mmap_handle_write(sub, 10n, enc.encode("both"), 4n), 4n)
+    assertEquals(lib.symbols.mmap_handle_read(h, K64 + 10n, out, 4n), 4n)
+    assertEquals(new TextDecoder().decode(out.subarray(0, 4)), "both")
+
+    // Length 0 is the rest of the file.
+    const rest = lib.symbols.mmap_handle_subview(h, K64, 0n, new Uint8Array(lenBuf.buffer))
+    assert(!isNull(rest), "mmap_handle_subview failed")
+    assertEquals(lenBuf[0], 2n * K64)
+    assertEquals(lib.symbols.mmap_handle_len(rest), 2n * K64)
+
+    assert(isNull(lib.symbols.mmap_handle_subview(h, 1n, 0n, null)), "mapped an unaligned offset")
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNALIGNED)
+    assert(isNull(lib.symbols.mmap_handle_subview(h, K64, 3n * K64, null)), "mapped past the end")
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assert(isNull(lib.symbols.mmap_handle_subview(h, 3n * K64, 0n, null)), "mapped an empty range")
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_ensure_capacity(sub, 4n * K64, null), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+
+    // Closing the handle leaves the subviews, and the file under them, usable.
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    lib.symbols.mmap_handle_free(h)
+    assertEquals(lib.symbols.mmap_handle_write(rest, K64, enc.encode("last"), 4n), 4n)
+    assertEquals(lib.symbols.mmap_handle_read(sub, 0n, out, 6n), 6n)
+    assertEquals(new TextDecoder().decode(out), "middle")
+    lib.symbols.mmap_handle_free(sub)
+    lib.symbols.mmap_handle_free(rest)
+
+    const file = await Deno.readFile(path)
+    const at = Number(2n * K64)
+    assertEquals(new TextDecoder().decode(file.subarray(at, at + 4)), "last")
+    await Deno.remove(path)
+})
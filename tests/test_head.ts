@@ -0,0 +1,41 @@
+// mmap_open_head: map at most the first N bytes of a file.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_EMPTY = -4
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_head: { parameters: ["buffer", "usize", "buffer"], result: "pointer" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+Deno.test("mmap_open_head maps min(file size, max_len)", async () => {
+    const path = await Deno.makeTempFile()
+    const content = new Uint8Array(100_000).fill(0x78)
+    content.set(new TextEncoder().encode("MAGIC"))
+    await Deno.writeFile(path, content)
+    const lenBuf = new BigUint64Array(1)
+    const len = new Uint8Array(lenBuf.buffer)
+
+    const p = lib.symbols.mmap_open_head(cString(path), 16n, len)
+    assert(!isNull(p), "mmap_open_head failed")
+    assertEquals(lenBuf[0], 16n)
+    const magic = new Uint8Array(5)
+    lib.symbols.mmap_read(magic, p, 0n, 5n)
+    assertEquals(new TextDecoder().decode(magic), "MAGIC")
+    lib.symbols.mmap_close(p, lenBuf[0])
+
+    // Smaller than max_len: the whole file.
+    const whole = lib.symbols.mmap_open_head(cString(path), 1n << 30n, len)
+    assert(!isNull(whole), "mmap_open_head failed")
+    assertEquals(lenBuf[0], 100_000n)
+    lib.symbols.mmap_close(whole, lenBuf[0])
+
+    await Deno.writeFile(path, new Uint8Array(0))
+    assert(isNull(lib.symbols.mmap_open_head(cString(path), 16n, len)))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_EMPTY)
+    await Deno.remove(path)
+})
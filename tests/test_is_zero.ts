@@ -0,0 +1,39 @@
+// mmap_is_zero: detecting all-zero ranges, e.g. to keep holes when copying.
+
+import { assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_is_zero: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+
+Deno.test("mmap_is_zero finds the one non-zero byte", async () => {
+    const size = 64 * 1024 + 3
+    const bytes = new Uint8Array(size)
+    bytes[size - 1] = 1
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, bytes)
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    const n = BigInt(size)
+
+    // Unaligned starts and lengths, so the word scan has ragged ends.
+    assertEquals(lib.symbols.mmap_is_zero(p, 0n, n - 1n), 1)
+    assertEquals(lib.symbols.mmap_is_zero(p, 1n, n - 2n), 1)
+    assertEquals(lib.symbols.mmap_is_zero(p, 0n, n), 0)
+    assertEquals(lib.symbols.mmap_is_zero(p, 5n, n - 5n), 0)
+    assertEquals(lib.symbols.mmap_is_zero(p, n - 1n, 1n), 0)
+
+    assertEquals(lib.symbols.mmap_is_zero(p, n - 1n, 0n), 1)
+    assertEquals(lib.symbols.mmap_is_zero(null, 0n, 0n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
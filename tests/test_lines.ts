@@ -0,0 +1,35 @@
+// mmap_next_line: native line scanning over a mapped text file.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_next_line: { parameters: ["pointer", "usize", "usize", "buffer", "buffer"], result: "isize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+Deno.test("mmap_next_line splits \\n and \\r\\n lines, including a last unterminated one", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "one\r\ntwo\n\nlast")
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+
+    const start = new BigUint64Array(1)
+    const len = new BigUint64Array(1)
+    const lines: string[] = []
+    let cursor = 0n
+    while (true) {
+        cursor = lib.symbols.mmap_next_line(p, lenBuf[0], cursor, new Uint8Array(start.buffer), new Uint8Array(len.buffer))
+        if (cursor < 0n) break
+        const line = new Uint8Array(Number(len[0]))
+        if (line.length > 0) lib.symbols.mmap_read(line, p, start[0], len[0])
+        lines.push(new TextDecoder().decode(line))
+    }
+    assertEquals(lines, ["one", "two", "", "last"])
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
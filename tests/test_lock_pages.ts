@@ -0,0 +1,102 @@
+// mmap_lock_pages / mmap_unlock_pages: pinning part of a mapping in RAM. What is locked is read
+// back from /proc/self/smaps on Linux; elsewhere the test only exercises the calls.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_QUOTA = -24
+const SIZE = 1024 * 1024
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_lock_pages: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_unlock_pages: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_resize: { parameters: ["pointer", "usize", "buffer", "buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_page_size: { parameters: [], result: "usize" },
+})
+const PAGE = Number(lib.symbols.mmap_page_size())
+
+/**
+ * Bytes locked in `[p, p + len)`, or null where smaps isn't available. Locking part of a
+ * mapping splits it, so every mapping starting in the range counts.
+ */
+function lockedBytes(p: Deno.PointerValue, len: number): number | null {
+    if (Deno.build.os !== "linux") return null
+    const start = Deno.UnsafePointer.value(p) as bigint
+    let locked = 0
+    let inside = false
+    for (const line of Deno.readTextFileSync("/proc/self/smaps").split("\n")) {
+        const range = line.match(/^([0-9a-f]+)-[0-9a-f]+ /)
+        if (range) {
+            const at = BigInt("0x" + range[1])
+            inside = at >= start && at < start + BigInt(len)
+        } else if (inside && line.startsWith("Locked:")) {
+            locked += parseInt(line.split(/\s+/)[1]) * 1024
+        }
+    }
+    return locked
+}
+
+Deno.test("mmap_lock_pages pins whole pages of a range until they are unlocked", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(SIZE))
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    const rc = lib.symbols.mmap_lock_pages(p, BigInt(PAGE + 100), BigInt(2 * PAGE))
+    if (rc === -1 && lib.symbols.mmap_last_error() === MMAP_ERR_QUOTA) {
+        console.warn("no locked memory allowed here; skipping")
+        lib.symbols.mmap_close(p, lenBuf[0])
+        await Deno.remove(path)
+        return
+    }
+    assertEquals(rc, 0)
+    // Widened to [PAGE, 4 * PAGE).
+    assertEquals(lockedBytes(p, SIZE) ?? 3 * PAGE, 3 * PAGE)
+    // Overlapping pins merge, and unlocking part of one keeps the rest.
+    assertEquals(lib.symbols.mmap_lock_pages(p, BigInt(3 * PAGE), BigInt(2 * PAGE)), 0)
+    assertEquals(lockedBytes(p, SIZE) ?? 4 * PAGE, 4 * PAGE)
+    assertEquals(lib.symbols.mmap_unlock_pages(p, BigInt(2 * PAGE), 1n), 0)
+    assertEquals(lockedBytes(p, SIZE) ?? 3 * PAGE, 3 * PAGE)
+
+    // The pins follow the mapping when it moves.
+    const baseBuf = new BigUint64Array(1)
+    assertEquals(
+        lib.symbols.mmap_resize(p, BigInt(4 * SIZE), new Uint8Array(baseBuf.buffer), new Uint8Array(lenBuf.buffer)),
+        0,
+    )
+    const q = Deno.UnsafePointer.create(baseBuf[0])
+    assertEquals(lockedBytes(q, 4 * SIZE) ?? 3 * PAGE, 3 * PAGE)
+
+    assertEquals(lib.symbols.mmap_unlock_pages(q, 0n, BigInt(4 * SIZE)), 0)
+    assertEquals(lockedBytes(q, 4 * SIZE) ?? 0, 0)
+    // Nothing pinned is fine too, and closing unpins what is left.
+    assertEquals(lib.symbols.mmap_unlock_pages(q, 0n, BigInt(PAGE)), 0)
+    assertEquals(lib.symbols.mmap_lock_pages(q, 0n, BigInt(PAGE)), 0)
+    lib.symbols.mmap_close(q, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_lock_pages checks the range", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(8 * PAGE))
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_lock_pages(p, BigInt(8 * PAGE), 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_unlock_pages(p, BigInt(4 * PAGE), BigInt(5 * PAGE)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_lock_pages(p, 0n, 0n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    const inside = Deno.UnsafePointer.create(Deno.UnsafePointer.value(p) + BigInt(PAGE))
+    assertEquals(lib.symbols.mmap_lock_pages(inside, 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
@@ -0,0 +1,158 @@
+// mmap_lock_range / mmap_unlock_range / mmap_lock_test: byte-range locks for slots owned by
+// different processes.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const symbols = {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_lock_range: { parameters: ["pointer", "u64", "u64", "i32", "i32"], result: "i32" },
+    mmap_unlock_range: { parameters: ["pointer", "u64", "u64"], result: "i32" },
+    mmap_lock_test: { parameters: ["pointer", "u64", "u64", "i32", "buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+} as const
+const lib = Deno.dlopen(libPath, symbols)
+// The same call on a worker thread, for a blocking request that must not stall the test.
+const waiting = Deno.dlopen(libPath, {
+    mmap_lock_range: { ...symbols.mmap_lock_range, nonblocking: true },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_CLOSED = -6
+const MMAP_ERR_READ_ONLY = -7
+const MMAP_ERR_WOULD_BLOCK = -23
+
+function open(path: string): Deno.PointerValue {
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    return h
+}
+
+/** Starts a process that locks `[0, 100)` exclusively and holds it until its stdin closes. */
+async function holdFirstSlot(path: string): Promise<Deno.ChildProcess> {
+    const script = `
+        const lib = Deno.dlopen(${JSON.stringify(libPath)}, ${JSON.stringify(symbols)})
+        const cString = (s) => new TextEncoder().encode(s + "\\0")
+        const h = lib.symbols.mmap_handle_open_write(cString(${JSON.stringify(path)}), 4096n)
+        if (lib.symbols.mmap_lock_range(h, 0n, 100n, 1, 0) !== 0) Deno.exit(1)
+        console.log("locked")
+        for await (const _ of Deno.stdin.readable) {}
+    `
+    const child = new Deno.Command(Deno.execPath(), {
+        args: ["eval", "--unstable-ffi", script],
+        stdin: "piped",
+        stdout: "piped",
+    }).spawn()
+    const out = child.stdout.getReader()
+    let said = ""
+    while (!said.includes("locked")) {
+        const { value, done } = await out.read()
+        assert(!done, "the child exited without locking")
+        said += new TextDecoder().decode(value)
+    }
+    out.releaseLock()
+    return child
+}
+
+async function release(child: Deno.ChildProcess) {
+    await child.stdin.close()
+    await child.stdout.cancel()
+    assert((await child.status).success)
+}
+
+Deno.test("disjoint ranges lock side by side, overlapping ones conflict", async () => {
+    const path = await Deno.makeTempFile()
+    const child = await holdFirstSlot(path)
+    const h = open(path)
+
+    assertEquals(lib.symbols.mmap_lock_range(h, 100n, 100n, 1, 0), 0)
+    assertEquals(lib.symbols.mmap_lock_range(h, 50n, 100n, 1, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_WOULD_BLOCK)
+    assertEquals(lib.symbols.mmap_lock_range(h, 99n, 1n, 0, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_WOULD_BLOCK)
+
+    // A blocking request waits for the child's lock to go.
+    let settled = false
+    const pending = waiting.symbols.mmap_lock_range(h, 0n, 50n, 1, 1).finally(() => settled = true)
+    await new Promise((resolve) => setTimeout(resolve, 200))
+    assert(!settled, "the lock was granted while the child held it")
+    await release(child)
+    assertEquals(await pending, 0)
+
+    assertEquals(lib.symbols.mmap_unlock_range(h, 0n, 50n), 0)
+    assertEquals(lib.symbols.mmap_unlock_range(h, 100n, 100n), 0)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
+
+Deno.test("a range lock granted after its handle was closed is dropped again", async () => {
+    const path = await Deno.makeTempFile()
+    const child = await holdFirstSlot(path)
+    const h = open(path)
+
+    const pending = waiting.symbols.mmap_lock_range(h, 0n, 50n, 1, 1)
+    await new Promise((resolve) => setTimeout(resolve, 200))
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    // The wait ends with the lock granted to a closed handle, which must not keep it.
+    await release(child)
+    // -1 with MMAP_ERR_CLOSED, on the worker's thread.
+    assertEquals(await pending, -1)
+    const other = open(path)
+    assertEquals(lib.symbols.mmap_lock_test(other, 0n, 50n, 1, null), 0)
+
+    for (const x of [h, other]) lib.symbols.mmap_handle_free(x)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_lock_range checks the range and the handle", async () => {
+    const path = await Deno.makeTempFile()
+    const h = open(path)
+    assertEquals(lib.symbols.mmap_lock_range(h, 0n, 0n, 1, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_lock_range(h, (1n << 63n) - 1n, 2n, 1, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    // Past the end of the file is fine.
+    assertEquals(lib.symbols.mmap_lock_range(h, 1n << 40n, 4096n, 1, 0), 0)
+    assertEquals(lib.symbols.mmap_unlock_range(h, 1n << 40n, 4096n), 0)
+
+    const ro = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(ro), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_lock_range(ro, 0n, 10n, 1, 0), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    assertEquals(lib.symbols.mmap_lock_range(ro, 0n, 10n, 0, 0), 0)
+    for (const x of [h, ro]) lib.symbols.mmap_handle_free(x)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_lock_test reports a lock held by another process without taking it", async () => {
+    const path = await Deno.makeTempFile()
+    const child = await holdFirstSlot(path)
+    const h = open(path)
+    const pid = new Int32Array(1)
+    const test = (offset: bigint, len: bigint, exclusive: number) =>
+        lib.symbols.mmap_lock_test(h, offset, len, exclusive, new Uint8Array(pid.buffer))
+
+    assertEquals(test(0n, 100n, 1), 1)
+    // The holder's PID, where the OS keeps one (not for Linux open file description locks).
+    assert(pid[0] === child.pid || pid[0] === -1, `unexpected holder ${pid[0]}`)
+    assertEquals(test(99n, 10n, 0), 1)
+    assertEquals(test(100n, 100n, 1), 0)
+    assertEquals(pid[0], 0)
+    // Probing took nothing: the range can still be locked, and then conflicts for others.
+    assertEquals(lib.symbols.mmap_lock_range(h, 100n, 100n, 1, 0), 0)
+    const other = open(path)
+    assertEquals(lib.symbols.mmap_lock_test(other, 150n, 10n, 0, null), 1)
+
+    await release(child)
+    assertEquals(test(0n, 100n, 1), 0)
+    assertEquals(test(0n, 0n, 1), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    lib.symbols.mmap_handle_close(h)
+    assertEquals(test(0n, 100n, 1), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    for (const x of [h, other]) lib.symbols.mmap_handle_free(x)
+    await Deno.remove(path)
+})
@@ -0,0 +1,45 @@
+// MMAP_LOCKED: pages are pinned while mapped and unlocked by mmap_close.
+// Lock accounting is read from VmLck in /proc/self/status on Linux.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_LOCKED = 1 << 5
+const MMAP_LOCK_BEST_EFFORT = 1 << 6
+const MMAP_OK = 0
+const MMAP_ERR_LOCK_FAILED = -12
+const SIZE = 64 * 1024
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_ex: { parameters: ["buffer", "buffer", "u32"], result: "pointer" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+function lockedBytes(): number | null {
+    if (Deno.build.os !== "linux") return null
+    const line = Deno.readTextFileSync("/proc/self/status").split("\n").find((l) => l.startsWith("VmLck:"))!
+    return parseInt(line.split(/\s+/)[1]) * 1024
+}
+
+Deno.test("MMAP_LOCKED locks the mapping until close", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(SIZE).fill(1))
+    const lenBuf = new BigUint64Array(1)
+    const before = lockedBytes()
+
+    // Best effort, so the test still runs where the memlock limit is too small to lock.
+    const p = lib.symbols.mmap_open_ex(cString(path), new Uint8Array(lenBuf.buffer), MMAP_LOCKED | MMAP_LOCK_BEST_EFFORT)
+    assert(!isNull(p), "mmap_open_ex failed")
+    const status = lib.symbols.mmap_last_error()
+    assert(status === MMAP_OK || status === MMAP_ERR_LOCK_FAILED, `unexpected status ${status}`)
+
+    if (before !== null && status === MMAP_OK) {
+        assertEquals(lockedBytes()! - before, SIZE)
+    }
+    lib.symbols.mmap_close(p, lenBuf[0])
+    if (before !== null) {
+        assertEquals(lockedBytes(), before)
+    }
+    await Deno.remove(path)
+})
@@ -0,0 +1,69 @@
+// mmap_log_*: an append-only log with a persisted committed length, addressed by id.
+// The kill-the-writer crash test lives in ffi/tests/log.rs.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_log_open: { parameters: ["buffer", "usize", "buffer"], result: "u64" },
+    mmap_log_append: { parameters: ["u64", "buffer", "usize"], result: "u64" },
+    mmap_log_len: { parameters: ["u64"], result: "u64" },
+    mmap_log_read: { parameters: ["u64", "u64", "buffer", "usize"], result: "isize" },
+    mmap_log_close: { parameters: ["u64"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const MMAP_OK = 0
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_LOG_ERROR = 0xffff_ffff_ffff_ffffn
+
+function open(path: string, capacity: bigint): bigint {
+    const err = new Int32Array(1)
+    const id = lib.symbols.mmap_log_open(cString(path), capacity, new Uint8Array(err.buffer))
+    assertEquals(err[0], MMAP_OK)
+    assert(id !== 0n, "mmap_log_open failed")
+    return id
+}
+
+function readText(id: bigint, offset: bigint, len: number): string {
+    const buf = new Uint8Array(len)
+    assertEquals(lib.symbols.mmap_log_read(id, offset, buf, BigInt(len)), BigInt(len))
+    return new TextDecoder().decode(buf)
+}
+
+Deno.test("records survive close and reopen, and appends resume at the end", async () => {
+    const path = await Deno.makeTempFile()
+    const enc = new TextEncoder()
+    let id = open(path, 16n)
+    assertEquals(lib.symbols.mmap_log_append(id, enc.encode("alpha"), 5n), 0n)
+    // Past the initial capacity: the file grows.
+    assertEquals(lib.symbols.mmap_log_append(id, enc.encode("bravo charlie delta"), 19n), 5n)
+    assertEquals(lib.symbols.mmap_log_len(id), 24n)
+    assertEquals(lib.symbols.mmap_log_close(id), 0)
+
+    id = open(path, 0n)
+    assertEquals(lib.symbols.mmap_log_len(id), 24n)
+    assertEquals(readText(id, 0n, 5), "alpha")
+    assertEquals(lib.symbols.mmap_log_append(id, enc.encode("echo"), 4n), 24n)
+    assertEquals(readText(id, 5n, 23), "bravo charlie deltaecho")
+    // Only committed bytes can be read.
+    assertEquals(lib.symbols.mmap_log_read(id, 20n, new Uint8Array(16), 16n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+    assertEquals(lib.symbols.mmap_log_close(id), 0)
+    assertEquals(lib.symbols.mmap_log_len(id), MMAP_LOG_ERROR)
+    assertEquals(lib.symbols.mmap_log_close(id), -1)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_log_open rejects a file that isn't a log", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "just some text")
+    const err = new Int32Array(1)
+    assertEquals(lib.symbols.mmap_log_open(cString(path), 0n, new Uint8Array(err.buffer)), 0n)
+    assertEquals(err[0], MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(await Deno.readTextFile(path), "just some text")
+    await Deno.remove(path)
+})
@@ -0,0 +1,66 @@
+// MMAP_NORESERVE: huge sparse writable mappings without swap reservation.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_NORESERVE = 1 << 4
+const SIZE = 4n * 1024n * 1024n * 1024n
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_ex: { parameters: ["buffer", "buffer", "usize", "u32"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+// Most useful with vm.overcommit_memory=2 (strict accounting); the sandboxed test can't change it,
+// so it checks that a sparse multi-gigabyte file maps, extends, writes and flushes with the flag.
+Deno.test({
+    name: "MMAP_NORESERVE maps a sparse multi-gigabyte file",
+    ignore: Deno.build.os !== "linux",
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        await Deno.truncate(path, Number(SIZE / 2n))
+        const lenBuf = new BigUint64Array(1)
+
+        // Extends the sparse file to SIZE while mapping it.
+        const p = lib.symbols.mmap_open_write_ex(cString(path), new Uint8Array(lenBuf.buffer), SIZE, MMAP_NORESERVE)
+        assert(!isNull(p), "mmap_open_write_ex failed")
+        assertEquals(lenBuf[0], SIZE)
+
+        assertEquals(lib.symbols.mmap_write(p, SIZE - 1n, new Uint8Array([0x58]), 1n), 1n)
+        assertEquals(lib.symbols.mmap_flush(p, SIZE - 4096n, 4096n), 0)
+        lib.symbols.mmap_close(p, lenBuf[0])
+
+        const info = await Deno.stat(path)
+        assertEquals(BigInt(info.size), SIZE)
+        assert(info.blocks! * 512 < 1024 * 1024, "file should stay sparse")
+        await Deno.remove(path)
+    },
+})
+
+Deno.test({
+    name: "MMAP_NORESERVE maps a 100 GB sparse index from an empty file",
+    ignore: Deno.build.os !== "linux",
+    fn: async () => {
+        const size = 100n * 1024n * 1024n * 1024n
+        const path = await Deno.makeTempFile()
+        const lenBuf = new BigUint64Array(1)
+
+        const p = lib.symbols.mmap_open_write_ex(cString(path), new Uint8Array(lenBuf.buffer), size, MMAP_NORESERVE)
+        assert(!isNull(p), "mmap_open_write_ex failed")
+        assertEquals(lenBuf[0], size)
+
+        // Touch a few pages far apart; only those get blocks on disk.
+        for (const off of [0n, size / 2n, size - 1n]) {
+            assertEquals(lib.symbols.mmap_write(p, off, new Uint8Array([0x49]), 1n), 1n)
+        }
+        assertEquals(lib.symbols.mmap_flush(p, size - 1n, 1n), 0)
+        lib.symbols.mmap_close(p, lenBuf[0])
+
+        const info = await Deno.stat(path)
+        assertEquals(BigInt(info.size), size)
+        assert(info.blocks! * 512 < 1024 * 1024, "file should stay sparse")
+        await Deno.remove(path)
+    },
+})
@@ -0,0 +1,101 @@
+// mmap_open_fd / mmap_open_handle: mapping a file the caller already has open.
+// Deno doesn't expose raw descriptors, so the test opens the file through libc (kernel32 on
+// Windows) itself.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const windows = Deno.build.os === "windows"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    ...(windows
+        ? { mmap_open_handle: { parameters: ["isize", "usize", "bool", "buffer"], result: "pointer" } } as const
+        : { mmap_open_fd: { parameters: ["i32", "usize", "bool", "buffer"], result: "pointer" } } as const),
+})
+
+const MMAP_ERR_OS = -2
+
+/** Opens `path` read-write (or read-only) and returns the raw descriptor / handle and a closer. */
+function osOpen(path: string, write: boolean): { fd: number | bigint; close: () => void } {
+    if (windows) {
+        const k32 = Deno.dlopen("kernel32.dll", {
+            CreateFileA: {
+                parameters: ["buffer", "u32", "u32", "pointer", "u32", "u32", "pointer"],
+                result: "isize",
+            },
+            CloseHandle: { parameters: ["isize"], result: "i32" },
+        })
+        const GENERIC_READ = 0x80000000
+        const GENERIC_WRITE = 0x40000000
+        const access = write ? (GENERIC_READ | GENERIC_WRITE) >>> 0 : GENERIC_READ
+        // FILE_SHARE_READ | FILE_SHARE_WRITE, OPEN_EXISTING, FILE_ATTRIBUTE_NORMAL
+        const h = k32.symbols.CreateFileA(cString(path), access, 3, null, 3, 0x80, null)
+        assert(h !== -1n, "CreateFileA failed")
+        return { fd: h, close: () => (k32.symbols.CloseHandle(h), k32.close()) }
+    }
+    const libc = Deno.dlopen(Deno.build.os === "darwin" ? "libSystem.dylib" : "libc.so.6", {
+        open: { parameters: ["buffer", "i32"], result: "i32" },
+        close: { parameters: ["i32"], result: "i32" },
+    })
+    const O_RDONLY = 0
+    const O_RDWR = 2
+    const fd = libc.symbols.open(cString(path), write ? O_RDWR : O_RDONLY)
+    assert(fd >= 0, "open failed")
+    return { fd, close: () => (libc.symbols.close(fd), libc.close()) }
+}
+
+function openFd(fd: number | bigint, len: bigint, writable: boolean, lenBuf: BigUint64Array): Deno.PointerValue {
+    const out = new Uint8Array(lenBuf.buffer)
+    // deno-lint-ignore no-explicit-any
+    const s = lib.symbols as any
+    return windows ? s.mmap_open_handle(fd, len, writable, out) : s.mmap_open_fd(fd, len, writable, out)
+}
+
+Deno.test("maps a descriptor sized from the file and leaves it usable", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "hello world")
+    const file = osOpen(path, true)
+
+    const lenBuf = new BigUint64Array(1)
+    const p = openFd(file.fd, 0n, true, lenBuf)
+    assert(!isNull(p), "mapping the descriptor failed")
+    assertEquals(lenBuf[0], 11n)
+    // The mapping holds its own duplicate: closing ours first must not matter.
+    file.close()
+
+    lib.symbols.mmap_write(p, 0n, new TextEncoder().encode("HELLO"), 5n)
+    assertEquals(lib.symbols.mmap_flush(p, 0n, lenBuf[0]), 0)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    assertEquals(await Deno.readTextFile(path), "HELLO world")
+    await Deno.remove(path)
+})
+
+Deno.test("len caps read-only maps and extends writable ones", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "hello world")
+    const lenBuf = new BigUint64Array(1)
+
+    const ro = osOpen(path, false)
+    const p = openFd(ro.fd, 5n, false, lenBuf)
+    assert(!isNull(p), "read-only map failed")
+    assertEquals(lenBuf[0], 5n)
+    lib.symbols.mmap_close(p, lenBuf[0])
+
+    // A read-only descriptor can't back a writable mapping.
+    assert(isNull(openFd(ro.fd, 0n, true, lenBuf)))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+    ro.close()
+
+    const rw = osOpen(path, true)
+    const q = openFd(rw.fd, 8192n, true, lenBuf)
+    assert(!isNull(q), "writable map failed")
+    assertEquals(lenBuf[0], 8192n)
+    lib.symbols.mmap_close(q, lenBuf[0])
+    rw.close()
+    assertEquals((await Deno.stat(path)).size, 8192)
+    await Deno.remove(path)
+})
@@ -0,0 +1,94 @@
+// mmap_open_secret: key material locked in RAM, kept out of core dumps and wiped on close.
+// The wipe can only be observed in builds with `--features test-hooks`, which look at the
+// pages right before they are unmapped; the VmFlags of /proc/self/smaps show the rest on Linux.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_LOCK_BEST_EFFORT = 1 << 6
+const MMAP_OK = 0
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_EMPTY = -4
+const MMAP_ERR_LOCK_FAILED = -12
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_secret: { parameters: ["buffer", "buffer", "u32", "buffer"], result: "pointer" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_test_take_wipe: { parameters: ["buffer"], result: "isize", optional: true },
+})
+
+/** The VmFlags of the mapping that holds `p`, or null where smaps isn't available. */
+function vmFlags(p: Deno.PointerValue): string[] | null {
+    if (Deno.build.os !== "linux") return null
+    const addr = Deno.UnsafePointer.value(p) as bigint
+    let inside = false
+    for (const line of Deno.readTextFileSync("/proc/self/smaps").split("\n")) {
+        const range = line.match(/^([0-9a-f]+)-([0-9a-f]+) /)
+        if (range) {
+            inside = BigInt("0x" + range[1]) <= addr && addr < BigInt("0x" + range[2])
+        } else if (inside && line.startsWith("VmFlags:")) {
+            return line.split(/\s+/).slice(1)
+        }
+    }
+    throw new Error("mapping not in smaps")
+}
+
+Deno.test("mmap_open_secret holds a private, locked copy that is wiped on close", async () => {
+    const path = await Deno.makeTempFile()
+    const key = crypto.getRandomValues(new Uint8Array(5000))
+    await Deno.writeFile(path, key)
+    const lenBuf = new BigUint64Array(1)
+    const errBuf = new Int32Array(1)
+
+    // Best effort, so the test still runs where the memlock limit is too small to lock.
+    const p = lib.symbols.mmap_open_secret(
+        cString(path),
+        new Uint8Array(lenBuf.buffer),
+        MMAP_LOCK_BEST_EFFORT,
+        new Uint8Array(errBuf.buffer),
+    )
+    assert(!isNull(p), "mmap_open_secret failed")
+    assertEquals(lenBuf[0], BigInt(key.length))
+    assert(errBuf[0] === MMAP_OK || errBuf[0] === MMAP_ERR_LOCK_FAILED, `unexpected status ${errBuf[0]}`)
+    assertEquals(lib.symbols.mmap_last_error(), errBuf[0])
+    const out = new Uint8Array(key.length)
+    lib.symbols.mmap_read(out, p, 0n, BigInt(out.length))
+    assertEquals(out, key)
+
+    const flags = vmFlags(p)
+    if (flags !== null) {
+        assert(flags.includes("dd"), `not excluded from core dumps: ${flags}`)
+        if (errBuf[0] === MMAP_OK) assert(flags.includes("lo"), `not locked: ${flags}`)
+    }
+
+    // Writes stay in the copy.
+    lib.symbols.mmap_write(p, 0n, new Uint8Array(16), 16n)
+    assertEquals(await Deno.readFile(path), key)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    if (lib.symbols.mmap_test_take_wipe !== null) {
+        const wipedLen = new BigUint64Array(1)
+        assertEquals(lib.symbols.mmap_test_take_wipe(new Uint8Array(wipedLen.buffer)), 0n)
+        assertEquals(wipedLen[0], BigInt(key.length))
+        assertEquals(lib.symbols.mmap_test_take_wipe(null), -1n)
+    }
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_open_secret rejects empty files and unknown flags", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const errBuf = new Int32Array(1)
+    const p = lib.symbols.mmap_open_secret(cString(path), new Uint8Array(lenBuf.buffer), 0, new Uint8Array(errBuf.buffer))
+    assert(isNull(p))
+    assertEquals(errBuf[0], MMAP_ERR_EMPTY)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_EMPTY)
+
+    await Deno.writeTextFile(path, "key")
+    assert(isNull(lib.symbols.mmap_open_secret(cString(path), new Uint8Array(lenBuf.buffer), 1, null)))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    await Deno.remove(path)
+})
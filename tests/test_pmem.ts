@@ -0,0 +1,85 @@
+// mmap_flush_pmem / mmap_open_pmem / mmap_persist: cache-line write-back for DAX mappings
+// (x86_64), and the msync fallback everywhere else.
+// Ordinary files aren't DAX, so the write-back itself only runs in builds with
+// `--features test-hooks`, which can make any file count as DAX; CLWB / CLFLUSH are harmless
+// on regular memory.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_flush_pmem: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_open_pmem: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_persist: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_test_force_dax: { parameters: ["i32"], result: "void", optional: true },
+})
+
+const MMAP_OK = 0
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_UNSUPPORTED = -11
+
+Deno.test("mmap_flush_pmem refuses mappings that aren't DAX", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 8192n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_flush_pmem(p, 0n, 8192n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test({
+    name: "mmap_flush_pmem writes cache lines back on x86_64",
+    ignore: Deno.build.arch !== "x86_64" || !lib.symbols.mmap_test_force_dax,
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 8192n)
+        assert(!isNull(p), "mmap_open_write_with_size failed")
+        lib.symbols.mmap_test_force_dax!(1)
+        try {
+            lib.symbols.mmap_write(p, 100n, new TextEncoder().encode("pmem"), 4n)
+            assertEquals(lib.symbols.mmap_flush_pmem(p, 100n, 4n), 0)
+            assertEquals(lib.symbols.mmap_flush_pmem(p, 0n, 8192n), 0)
+            assertEquals(lib.symbols.mmap_flush_pmem(p, 8000n, 500n), -1)
+            assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+            assertEquals(lib.symbols.mmap_persist(p, 100n, 4n), 0)
+        } finally {
+            lib.symbols.mmap_test_force_dax!(0)
+        }
+        lib.symbols.mmap_close(p, lenBuf[0])
+        await Deno.remove(path)
+    },
+})
+
+Deno.test("mmap_persist makes writes to a mmap_open_pmem mapping durable either way", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(8192))
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_pmem(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_pmem failed")
+    assertEquals(lenBuf[0], 8192n)
+    // The temp directory is rarely on DAX; the mapping is then an ordinary one.
+    const err = lib.symbols.mmap_last_error()
+    assert(err === MMAP_OK || err === MMAP_ERR_UNSUPPORTED, `unexpected ${err}`)
+
+    const data = new TextEncoder().encode("persistent")
+    lib.symbols.mmap_write(p, 3000n, data, BigInt(data.length))
+    assertEquals(lib.symbols.mmap_persist(p, 3000n, BigInt(data.length)), 0)
+    assertEquals((await Deno.readFile(path)).subarray(3000, 3000 + data.length), data)
+
+    assertEquals(lib.symbols.mmap_persist(p, 0n, 0n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_persist(p, 8000n, 500n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
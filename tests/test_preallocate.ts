@@ -0,0 +1,83 @@
+// mmap_preallocate: growing a handle's file with its blocks allocated up front. Block counts
+// are only checked on Linux, where fallocate allocates exactly and st_blocks is reliable; a
+// full file system can't be staged here.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_len: { parameters: ["pointer"], result: "usize" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_preallocate: { parameters: ["pointer", "u64", "i32"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const MMAP_PREALLOC_SPARSE = 0
+const MMAP_PREALLOC_FULL = 1
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_READ_ONLY = -7
+const LEN = 4 * 1024 * 1024
+
+async function preallocated(mode: number): Promise<Deno.FileInfo> {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 4096n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    assertEquals(lib.symbols.mmap_preallocate(h, BigInt(LEN), mode), 0)
+    // The mapping follows the file, so the new end is writable right away.
+    assertEquals(lib.symbols.mmap_handle_len(h), BigInt(LEN))
+    const tail = new TextEncoder().encode("tail")
+    assertEquals(lib.symbols.mmap_handle_write(h, BigInt(LEN - 4), tail, 4n), 4n)
+    // Never shrinks.
+    assertEquals(lib.symbols.mmap_preallocate(h, 4096n, mode), 0)
+    assertEquals(lib.symbols.mmap_handle_len(h), BigInt(LEN))
+
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    lib.symbols.mmap_handle_free(h)
+    const info = await Deno.stat(path)
+    const data = await Deno.readFile(path)
+    assertEquals(new TextDecoder().decode(data.subarray(LEN - 4)), "tail")
+    await Deno.remove(path)
+    return info
+}
+
+Deno.test("MMAP_PREALLOC_FULL allocates the whole file", async () => {
+    const info = await preallocated(MMAP_PREALLOC_FULL)
+    assertEquals(info.size, LEN)
+    if (Deno.build.os === "linux") {
+        assert(info.blocks! * 512 >= LEN, `only ${info.blocks} blocks allocated`)
+    }
+})
+
+Deno.test("MMAP_PREALLOC_SPARSE only sets the length", async () => {
+    const info = await preallocated(MMAP_PREALLOC_SPARSE)
+    assertEquals(info.size, LEN)
+    if (Deno.build.os === "linux") {
+        assert(info.blocks! * 512 < LEN, `${info.blocks} blocks allocated`)
+    }
+})
+
+Deno.test("mmap_preallocate rejects read-only handles and bad arguments", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(100))
+    const ro = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(ro), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_preallocate(ro, 4096n, MMAP_PREALLOC_FULL), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    lib.symbols.mmap_handle_close(ro)
+    lib.symbols.mmap_handle_free(ro)
+
+    const rw = lib.symbols.mmap_handle_open_write(cString(path), 0n)
+    assert(!isNull(rw), "mmap_handle_open_write failed")
+    assertEquals(lib.symbols.mmap_preallocate(rw, 4096n, 7), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_preallocate(rw, 0n, MMAP_PREALLOC_FULL), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    lib.symbols.mmap_handle_close(rw)
+    lib.symbols.mmap_handle_free(rw)
+    await Deno.remove(path)
+})
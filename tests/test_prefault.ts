@@ -0,0 +1,46 @@
+// MMAP_PREFAULT: the mapping is paged in at open time. Residency is checked via
+// /proc/self/smaps on Linux; elsewhere the test only exercises the code path.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_PREFAULT = 1 << 3
+const SIZE = 8 * 1024 * 1024
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_ex: { parameters: ["buffer", "buffer", "u32"], result: "pointer" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+/** Resident size in bytes of the mapping of `path`, or null where smaps isn't available. */
+function residentBytes(path: string): number | null {
+    if (Deno.build.os !== "linux") return null
+    const lines = Deno.readTextFileSync("/proc/self/smaps").split("\n")
+    const start = lines.findIndex((l) => l.endsWith(path))
+    assert(start >= 0, "mapping not found in /proc/self/smaps")
+    const rss = lines.slice(start + 1).find((l) => l.startsWith("Rss:"))!
+    return parseInt(rss.split(/\s+/)[1]) * 1024
+}
+
+Deno.test("MMAP_PREFAULT pages the file in at open time", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(SIZE).fill(7))
+    const lenBuf = new BigUint64Array(1)
+
+    for (const flags of [0, MMAP_PREFAULT]) {
+        const p = lib.symbols.mmap_open_ex(cString(path), new Uint8Array(lenBuf.buffer), flags)
+        assert(!isNull(p), "mmap_open_ex failed")
+        assertEquals(lenBuf[0], BigInt(SIZE))
+
+        const rss = residentBytes(path)
+        if (rss !== null) {
+            if (flags & MMAP_PREFAULT) {
+                assertEquals(rss, SIZE)
+            } else {
+                assert(rss < SIZE, `unexpectedly resident: ${rss}`)
+            }
+        }
+        lib.symbols.mmap_close(p, lenBuf[0])
+    }
+    await Deno.remove(path)
+})
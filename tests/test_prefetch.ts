@@ -0,0 +1,94 @@
+// mmap_prefetch / mmap_prefetch_v: reading chosen ranges in ahead of use. Residency is checked
+// with mincore on Linux; elsewhere the test only exercises the calls.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ADVICE_DONTNEED = 4
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MIB = 1024 * 1024
+const SIZE = 32 * MIB
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_fadvise: { parameters: ["buffer", "u64", "u64", "i32"], result: "i32" },
+    mmap_prefetch: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_prefetch_v: { parameters: ["pointer", "buffer", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_page_size: { parameters: [], result: "usize" },
+})
+const PAGE = Number(lib.symbols.mmap_page_size())
+
+const libc = Deno.build.os === "linux"
+    ? Deno.dlopen("libc.so.6", {
+        mincore: { parameters: ["pointer", "usize", "buffer"], result: "i32" },
+    })
+    : null
+
+/** Number of resident pages in `[offset, offset + len)` of the mapping, or null. */
+function residentPages(p: Deno.PointerValue, offset: number, len: number): number | null {
+    if (libc === null) return null
+    const vec = new Uint8Array(len / PAGE)
+    const at = Deno.UnsafePointer.create(Deno.UnsafePointer.value(p) + BigInt(offset))
+    assertEquals(libc.symbols.mincore(at, BigInt(len), vec), 0)
+    return vec.reduce((n, v) => n + (v & 1), 0)
+}
+
+Deno.test("mmap_prefetch_v reads the given ranges in", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(SIZE).fill(7))
+    const file = await Deno.open(path, { write: true })
+    await file.syncData()
+    file.close()
+    // Clean pages are dropped from the page cache, so the file starts out cold.
+    assertEquals(lib.symbols.mmap_fadvise(cString(path), 0n, 0n, MMAP_ADVICE_DONTNEED), 0)
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    const cold = residentPages(p, 0, SIZE)
+    if (cold !== null && cold > 0) console.warn(`${cold} pages resident before prefetching`)
+
+    // MmapRange[] { offset: usize, len: usize }
+    const ranges: [number, number][] = [[MIB, MIB], [20 * MIB, 2 * MIB]]
+    const packed = new BigUint64Array(ranges.flatMap(([off, len]) => [BigInt(off), BigInt(len)]))
+    assertEquals(lib.symbols.mmap_prefetch_v(p, new Uint8Array(packed.buffer), 2n), 0)
+    if (libc !== null) {
+        // The reads run in the background; give them a moment.
+        const want = ranges.reduce((n, [, len]) => n + len / PAGE, 0)
+        let got = 0
+        for (let i = 0; i < 40 && got < want; i++) {
+            got = ranges.reduce((n, [off, len]) => n + residentPages(p, off, len)!, 0)
+            if (got < want) await new Promise((resolve) => setTimeout(resolve, 50))
+        }
+        assertEquals(got, want)
+    }
+    assertEquals(lib.symbols.mmap_prefetch(p, BigInt(30 * MIB), BigInt(MIB)), 0)
+    assertEquals(lib.symbols.mmap_prefetch(p, 0n, 0n), 0)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_prefetch_v checks every range first", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(8 * PAGE))
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+
+    const packed = new BigUint64Array([0n, BigInt(PAGE), BigInt(8 * PAGE), 1n])
+    assertEquals(lib.symbols.mmap_prefetch_v(p, new Uint8Array(packed.buffer), 2n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_prefetch(p, BigInt(4 * PAGE), BigInt(5 * PAGE)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_prefetch_v(p, null, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_prefetch(null, 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
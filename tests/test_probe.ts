@@ -0,0 +1,42 @@
+// mmap_probe: detect ranges that are out of bounds or no longer backed by a truncated file.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_TRUNCATED = -16
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_probe: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_page_size: { parameters: [], result: "usize" },
+})
+const PAGE = BigInt(lib.symbols.mmap_page_size())
+
+Deno.test({
+    name: "mmap_probe reports ranges truncated away",
+    // Windows refuses to truncate a mapped file.
+    ignore: Deno.build.os === "windows",
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        await Deno.writeFile(path, new Uint8Array(Number(4n * PAGE)))
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+        assert(!isNull(p), "mmap_open failed")
+
+        assertEquals(lib.symbols.mmap_probe(p, 0n, 4n * PAGE), 0)
+        assertEquals(lib.symbols.mmap_probe(p, 4n * PAGE - 10n, 20n), -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+        // Shrink to 1.25 pages: the first two pages stay backed, the rest would SIGBUS.
+        await Deno.truncate(path, Number(PAGE + PAGE / 4n))
+        assertEquals(lib.symbols.mmap_probe(p, 0n, 2n * PAGE), 0)
+        assertEquals(lib.symbols.mmap_probe(p, 2n * PAGE, 1n), -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_TRUNCATED)
+
+        lib.symbols.mmap_close(p, lenBuf[0])
+        await Deno.remove(path)
+    },
+})
@@ -0,0 +1,49 @@
+// mmap_process_stats: process memory counters and the library's count of open mappings.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_process_stats: { parameters: ["buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MiB = 1024 * 1024
+
+/** rss_bytes, minor_faults, major_faults, mappings */
+function stats(): bigint[] {
+    const out = new BigUint64Array(4)
+    assertEquals(lib.symbols.mmap_process_stats(new Uint8Array(out.buffer)), 0)
+    return [...out]
+}
+
+Deno.test("mmap_process_stats counts open mappings and the faults touching them", async () => {
+    const path = await Deno.makeTempFile()
+    const [, minor0, major0, mappings0] = stats()
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(4 * MiB))
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    assertEquals(stats()[3], mappings0 + 1n)
+
+    // Touch every page: each first access is a fault.
+    const view = new Uint8Array(Deno.UnsafePointerView.getArrayBuffer(p!, 4 * MiB))
+    for (let i = 0; i < view.length; i += 4096) view[i] = 1
+    const [rss, minor1, major1] = stats()
+    // 0 where the platform doesn't report it.
+    if (rss !== 0n) assert(rss >= BigInt(4 * MiB), `rss ${rss}`)
+    // At least one fault per page, even with 16 KiB pages.
+    assert(minor1 + major1 >= minor0 + major0 + 256n, "touching 4 MiB should fault")
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    assertEquals(stats()[3], mappings0)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_process_stats rejects a null output", () => {
+    assertEquals(lib.symbols.mmap_process_stats(null), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+})
@@ -0,0 +1,89 @@
+// mmap_punch_hole / mmap_zero_range: reclaiming or clearing ranges of a mapped file. Block
+// counts are only checked on Linux, where st_blocks tracks the punched blocks exactly.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_read: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_punch_hole: { parameters: ["pointer", "u64", "u64"], result: "i32" },
+    mmap_zero_range: { parameters: ["pointer", "u64", "u64"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_READ_ONLY = -7
+const MMAP_ERR_UNSUPPORTED = -11
+const MiB = 1024 * 1024
+
+function read(h: Deno.PointerValue, offset: number, len: number): Uint8Array {
+    const out = new Uint8Array(len)
+    assertEquals(lib.symbols.mmap_handle_read(h, BigInt(offset), out, BigInt(len)), BigInt(len))
+    return out
+}
+
+Deno.test("mmap_punch_hole frees the range and the mapping reads zeros", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(MiB).fill(0xab))
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 0n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const before = (await Deno.stat(path)).blocks!
+
+    const rc = lib.symbols.mmap_punch_hole(h, 4096n, BigInt(MiB / 2))
+    if (rc !== 0) {
+        // File systems without hole punching (e.g. FAT) report it; nothing else to check.
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+    } else {
+        assertEquals(read(h, 4096, MiB / 2), new Uint8Array(MiB / 2))
+        assertEquals(read(h, 4095, 1)[0], 0xab)
+        assertEquals(read(h, 4096 + MiB / 2, 1)[0], 0xab)
+        // Unaligned edges are zeroed too.
+        assertEquals(lib.symbols.mmap_punch_hole(h, 700001n, 10n), 0)
+        assertEquals(read(h, 700000, 12), Uint8Array.of(0xab, ...new Uint8Array(10), 0xab))
+        if (Deno.build.os === "linux") {
+            const after = (await Deno.stat(path)).blocks!
+            assert(before - after >= MiB / 2 / 512, `blocks went from ${before} to ${after}`)
+        }
+    }
+
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    lib.symbols.mmap_handle_free(h)
+    const size = (await Deno.stat(path)).size
+    assertEquals(size, MiB)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_zero_range clears a range through the mapping", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(8192).fill(0xab))
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 0n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+
+    assertEquals(lib.symbols.mmap_zero_range(h, 100n, 5000n), 0)
+    assertEquals(read(h, 99, 5002), Uint8Array.of(0xab, ...new Uint8Array(5000), 0xab))
+
+    assertEquals(lib.symbols.mmap_zero_range(h, 8000n, 200n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_punch_hole(h, 0n, 0n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    lib.symbols.mmap_handle_free(h)
+    const data = await Deno.readFile(path)
+    assertEquals(data.subarray(100, 5100), new Uint8Array(5000))
+
+    const ro = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(ro), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_zero_range(ro, 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    assertEquals(lib.symbols.mmap_punch_hole(ro, 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    lib.symbols.mmap_handle_close(ro)
+    lib.symbols.mmap_handle_free(ro)
+    await Deno.remove(path)
+})
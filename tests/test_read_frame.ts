@@ -0,0 +1,89 @@
+// mmap_read_frame: reading length-prefixed frames without decoding the length in JS.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_read_frame: {
+        parameters: ["pointer", "usize", "u8", "bool", "buffer", "usize", "buffer"],
+        result: "isize",
+    },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_TOO_LARGE = -18
+
+/** Frames of "alpha" (u32 LE), "" (u8) and "gamma!" (u16 BE), back to back. */
+function frames(): Uint8Array {
+    const enc = new TextEncoder()
+    const bytes = new Uint8Array(4 + 5 + 1 + 2 + 6)
+    const view = new DataView(bytes.buffer)
+    view.setUint32(0, 5, true)
+    bytes.set(enc.encode("alpha"), 4)
+    view.setUint8(9, 0)
+    view.setUint16(10, 6, false)
+    bytes.set(enc.encode("gamma!"), 12)
+    return bytes
+}
+
+Deno.test("mmap_read_frame walks frames with any length field", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, frames())
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    const out = new Uint8Array(16)
+    const payloadLen = new BigUint64Array(1)
+    const payloadLenOut = new Uint8Array(payloadLen.buffer)
+    const text = () => new TextDecoder().decode(out.subarray(0, Number(payloadLen[0])))
+
+    let next = lib.symbols.mmap_read_frame(p, 0n, 4, true, out, BigInt(out.length), payloadLenOut)
+    assertEquals(next, 9n)
+    assertEquals(text(), "alpha")
+    next = lib.symbols.mmap_read_frame(p, next, 1, true, null, 0n, payloadLenOut)
+    assertEquals(next, 10n)
+    assertEquals(payloadLen[0], 0n)
+    next = lib.symbols.mmap_read_frame(p, next, 2, false, out, BigInt(out.length), payloadLenOut)
+    assertEquals(next, lenBuf[0])
+    assertEquals(text(), "gamma!")
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_read_frame rejects frames that don't fit", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, frames())
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    const out = new Uint8Array(16)
+    const payloadLen = new BigUint64Array(1)
+    const payloadLenOut = new Uint8Array(payloadLen.buffer)
+
+    // Too small a buffer still reports how long the payload is.
+    assertEquals(lib.symbols.mmap_read_frame(p, 0n, 4, true, out, 4n, payloadLenOut), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_TOO_LARGE)
+    assertEquals(payloadLen[0], 5n)
+    // Read big-endian, the first length is 0x05000000: past the end of the file.
+    assertEquals(lib.symbols.mmap_read_frame(p, 0n, 4, false, out, BigInt(out.length), null), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    // So is a length field that starts inside the mapping and ends past it.
+    assertEquals(lib.symbols.mmap_read_frame(p, lenBuf[0] - 4n, 8, true, out, BigInt(out.length), null), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+    assertEquals(lib.symbols.mmap_read_frame(p, 0n, 3, true, out, BigInt(out.length), null), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    assertEquals(lib.symbols.mmap_read_frame(p, 0n, 4, true, null, 16n, null), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    const inside = Deno.UnsafePointer.create(Deno.UnsafePointer.value(p) + 1n)
+    assertEquals(lib.symbols.mmap_read_frame(inside, 0n, 4, true, out, BigInt(out.length), null), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
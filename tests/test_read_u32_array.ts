@@ -0,0 +1,42 @@
+// mmap_read_u32_array: bulk decoding of unaligned little- / big-endian u32s.
+
+import { assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_read_u32_array: {
+        parameters: ["pointer", "usize", "usize", "bool", "buffer", "usize"],
+        result: "isize",
+    },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+
+Deno.test("mmap_read_u32_array decodes both byte orders from an unaligned offset", async () => {
+    const values = [1, 0x01020304, 0xdeadbeef, 0]
+    const bytes = new Uint8Array(1 + values.length * 4)
+    const view = new DataView(bytes.buffer)
+    values.forEach((v, i) => view.setUint32(1 + i * 4, v, false))
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, bytes)
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    const out = new Uint32Array(values.length)
+    const dst = new Uint8Array(out.buffer)
+
+    assertEquals(lib.symbols.mmap_read_u32_array(p, 1n, 4n, false, dst, 4n), 4n)
+    assertEquals([...out], values)
+
+    assertEquals(lib.symbols.mmap_read_u32_array(p, 1n, 4n, true, dst, 4n), 4n)
+    assertEquals([...out], values.map((v, i) => view.getUint32(1 + i * 4, true)))
+
+    assertEquals(lib.symbols.mmap_read_u32_array(p, 1n, 4n, false, dst, 3n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
@@ -0,0 +1,73 @@
+// mmap_open_reserve / mmap_extend_in_place: growing a file mapping without it moving.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_reserve: { parameters: ["buffer", "usize", "usize", "buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_extend_in_place: { parameters: ["pointer", "usize"], result: "i32" },
+    mmap_resize: { parameters: ["pointer", "usize", "buffer", "buffer"], result: "i32" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_UNSUPPORTED = -11
+const MMAP_ERR_TOO_LARGE = -18
+const MiB = 1024 * 1024
+
+Deno.test("the base stays put through several extensions", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const err = new Int32Array(1)
+    const p = lib.symbols.mmap_open_reserve(cString(path), BigInt(256 * MiB), 10000n, new Uint8Array(lenBuf.buffer), new Uint8Array(err.buffer))
+    if (isNull(p) && err[0] === MMAP_ERR_UNSUPPORTED) return // Windows without placeholders
+    assert(!isNull(p), `mmap_open_reserve failed: ${err[0]}`)
+    assertEquals(lenBuf[0], 10000n)
+    const base = Deno.UnsafePointer.value(p)
+
+    // Each extension writes a marker at its start that must survive the later ones.
+    let size = 10000
+    const sizes = [size]
+    for (let i = 0; i < 6; i++) {
+        const grown = size * 3 + 123
+        assertEquals(lib.symbols.mmap_extend_in_place(p, BigInt(grown)), 0)
+        assertEquals(lib.symbols.mmap_write(p, BigInt(grown - 1), new Uint8Array([i + 1]), 1n), 1n)
+        size = grown
+        sizes.push(size)
+    }
+    assertEquals(Deno.UnsafePointer.value(p), base)
+    const byte = new Uint8Array(1)
+    for (let i = 1; i < sizes.length; i++) {
+        assertEquals(lib.symbols.mmap_read(byte, p, BigInt(sizes[i] - 1), 1n), 1n)
+        assertEquals(byte[0], i)
+    }
+    assert((await Deno.stat(path)).size >= size)
+
+    // Shrinking, growing past the reservation and moving resizes are refused.
+    assertEquals(lib.symbols.mmap_extend_in_place(p, 10n), -1)
+    assertEquals(lib.symbols.mmap_extend_in_place(p, BigInt(512 * MiB)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_TOO_LARGE)
+    assertEquals(lib.symbols.mmap_resize(p, BigInt(size * 2), null, null), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+
+    assertEquals(lib.symbols.mmap_flush(p, 0n, BigInt(size)), 0)
+    lib.symbols.mmap_close(p, BigInt(size))
+    const data = await Deno.readFile(path)
+    assertEquals(data[sizes[6] - 1], 6)
+    await Deno.remove(path)
+})
+
+Deno.test("ordinary mappings can't be extended in place", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    assertEquals(lib.symbols.mmap_extend_in_place(p, 8192n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
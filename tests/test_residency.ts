@@ -0,0 +1,111 @@
+// mmap_residency / mmap_resident_bytes: which pages of a mapping are in memory. The split
+// after an eviction is only checked on Linux, where mmap_evict drops pages from the page cache
+// (but not those of files in tmpfs, which have nowhere else to be).
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MIB = 1024 * 1024
+const SIZE = 8 * MIB
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_evict: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_residency: { parameters: ["pointer", "usize", "usize", "buffer", "usize"], result: "i64" },
+    mmap_resident_bytes: { parameters: ["pointer"], result: "i64" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+    mmap_page_size: { parameters: [], result: "usize" },
+})
+const PAGE = Number(lib.symbols.mmap_page_size())
+
+/** Whether temporary files are in tmpfs: their pages have no file to be dropped back to. */
+function tempInTmpfs(): boolean {
+    const tmp = Deno.realPathSync(Deno.env.get("TMPDIR") ?? "/tmp")
+    const mount = Deno.readTextFileSync("/proc/self/mounts").split("\n")
+        .map((line) => line.split(" "))
+        .filter(([, dir]) => dir === "/" || tmp === dir || tmp.startsWith(dir + "/"))
+        .sort((a, b) => b[1].length - a[1].length)[0]
+    return mount[2] === "tmpfs"
+}
+
+/** Whether page `i` is marked resident in `bitmap`. */
+function bit(bitmap: Uint8Array, i: number): boolean {
+    return (bitmap[i >> 3] & (1 << (i & 7))) !== 0
+}
+
+Deno.test({
+    name: "mmap_residency tells the pages read in from those evicted",
+    ignore: Deno.build.os !== "linux" || tempInTmpfs(),
+    fn: async () => {
+        const path = await Deno.makeTempFile()
+        await Deno.writeFile(path, new Uint8Array(SIZE).fill(7))
+        // Dirty pages can't be dropped from the page cache.
+        const f = await Deno.open(path, { write: true })
+        await f.sync()
+        f.close()
+        const lenBuf = new BigUint64Array(1)
+        const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+        assert(!isNull(p), "mmap_open failed")
+
+        assertEquals(lib.symbols.mmap_evict(p, 0n, 0n), 0)
+        assertEquals(lib.symbols.mmap_resident_bytes(p), 0n)
+        // Readahead may bring in more than is read, so read it all and drop the second half.
+        lib.symbols.mmap_read(new Uint8Array(SIZE), p, 0n, BigInt(SIZE))
+        assertEquals(lib.symbols.mmap_evict(p, BigInt(SIZE / 2), 0n), 0)
+
+        const pages = SIZE / PAGE
+        const bitmap = new Uint8Array(pages / 8)
+        assertEquals(lib.symbols.mmap_residency(p, 0n, 0n, bitmap, BigInt(bitmap.length)), BigInt(pages / 2))
+        for (let i = 0; i < pages; i++) {
+            assertEquals(bit(bitmap, i), i < pages / 2, `page ${i}`)
+        }
+        assertEquals(lib.symbols.mmap_resident_bytes(p), BigInt(SIZE / 2))
+
+        lib.symbols.mmap_close(p, lenBuf[0])
+        await Deno.remove(path)
+    },
+})
+
+Deno.test("mmap_residency counts whole pages of the range and checks its arguments", async () => {
+    const size = 3 * PAGE + 100
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(size).fill(1))
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    lib.symbols.mmap_read(new Uint8Array(size), p, 0n, BigInt(size))
+
+    const bitmap = new Uint8Array(2).fill(0xff)
+    assertEquals(lib.symbols.mmap_residency(p, 0n, 0n, bitmap, 2n), 4n)
+    assertEquals([...bitmap], [0b1111, 0])
+    // The range starts at the page holding `offset`.
+    assertEquals(lib.symbols.mmap_residency(p, BigInt(PAGE + 5), BigInt(PAGE), bitmap, 1n), 2n)
+    assertEquals(bitmap[0], 0b11)
+    assertEquals(lib.symbols.mmap_residency(p, 0n, 0n, null, 0n), 4n)
+    // The last page counts only as far as the mapping goes.
+    assertEquals(lib.symbols.mmap_resident_bytes(p), BigInt(size))
+
+    assertEquals(lib.symbols.mmap_residency(p, 0n, BigInt(8 * PAGE + 1), new Uint8Array(1), 1n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_residency(p, BigInt(size + 1), 0n, null, 0n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    const big = await Deno.makeTempFile()
+    await Deno.writeFile(big, new Uint8Array(9 * PAGE))
+    const q = lib.symbols.mmap_open(cString(big), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(q), "mmap_open failed")
+    // 9 pages need 2 bytes.
+    assertEquals(lib.symbols.mmap_residency(q, 0n, 0n, new Uint8Array(1), 1n), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    lib.symbols.mmap_close(q, lenBuf[0])
+    await Deno.remove(big)
+
+    const inside = Deno.UnsafePointer.create(Deno.UnsafePointer.value(p) + BigInt(PAGE))
+    assertEquals(lib.symbols.mmap_resident_bytes(inside), -1n)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    lib.symbols.mmap_close(p, BigInt(size))
+    await Deno.remove(path)
+})
@@ -0,0 +1,103 @@
+// mmap_resize: growing and shrinking a live mapping together with its file.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_flush_all: { parameters: ["pointer"], result: "i32" },
+    mmap_resize: { parameters: ["pointer", "usize", "buffer", "buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_READ_ONLY = -7
+const MiB = 1024n * 1024n
+
+/** Calls mmap_resize and returns the reported base and length. */
+function resize(p: Deno.PointerValue, size: bigint): { rc: number; base: Deno.PointerValue; len: bigint } {
+    const baseBuf = new BigUint64Array(1)
+    const lenBuf = new BigUint64Array(1)
+    const rc = lib.symbols.mmap_resize(p, size, new Uint8Array(baseBuf.buffer), new Uint8Array(lenBuf.buffer))
+    return { rc, base: Deno.UnsafePointer.create(baseBuf[0]), len: lenBuf[0] }
+}
+
+function readText(p: Deno.PointerValue, offset: bigint, len: number): string {
+    const buf = new Uint8Array(len)
+    lib.symbols.mmap_read(buf, p, offset, BigInt(len))
+    return new TextDecoder().decode(buf)
+}
+
+Deno.test("growing from 1 MiB to 16 MiB keeps the data written before", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), MiB)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const enc = new TextEncoder()
+    lib.symbols.mmap_write(p, 0n, enc.encode("first"), 5n)
+    lib.symbols.mmap_write(p, MiB - 4n, enc.encode("last"), 4n)
+
+    const r = resize(p, 16n * MiB)
+    assertEquals(r.rc, 0)
+    assertEquals(r.len, 16n * MiB)
+    assertEquals((await Deno.stat(path)).size, 16 * 1024 * 1024)
+    assertEquals(readText(r.base, 0n, 5), "first")
+    assertEquals(readText(r.base, MiB - 4n, 4), "last")
+    // The new tail is usable and the registry follows the move.
+    lib.symbols.mmap_write(r.base, 16n * MiB - 3n, enc.encode("end"), 3n)
+    assertEquals(lib.symbols.mmap_flush_all(r.base), 0)
+    if (Deno.UnsafePointer.value(r.base) !== Deno.UnsafePointer.value(p)) {
+        assertEquals(lib.symbols.mmap_flush_all(p), -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    }
+
+    lib.symbols.mmap_close(r.base, r.len)
+    const data = await Deno.readFile(path)
+    assertEquals(new TextDecoder().decode(data.subarray(data.length - 3)), "end")
+    await Deno.remove(path)
+})
+
+Deno.test("shrinking truncates the file and keeps the head", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), MiB)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    lib.symbols.mmap_write(p, 4000n, new TextEncoder().encode("kept"), 4n)
+
+    const r = resize(p, 4100n)
+    assertEquals(r.rc, 0)
+    assertEquals(r.len, 4100n)
+    assertEquals((await Deno.stat(path)).size, 4100)
+    assertEquals(readText(r.base, 4000n, 4), "kept")
+    assertEquals(lib.symbols.mmap_flush_all(r.base), 0)
+
+    lib.symbols.mmap_close(r.base, r.len)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_resize rejects read-only mappings and a zero size", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "hello")
+    const lenBuf = new BigUint64Array(1)
+    const ro = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(ro), "mmap_open failed")
+    const r = resize(ro, 4096n)
+    assertEquals(r.rc, -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    // Still reported, unchanged.
+    assertEquals(Deno.UnsafePointer.value(r.base), Deno.UnsafePointer.value(ro))
+    assertEquals(r.len, 5n)
+    assertEquals((await Deno.stat(path)).size, 5)
+    lib.symbols.mmap_close(ro, lenBuf[0])
+
+    const rw = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 0n)
+    assert(!isNull(rw), "mmap_open_write_with_size failed")
+    assertEquals(resize(rw, 0n).rc, -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    lib.symbols.mmap_close(rw, lenBuf[0])
+    await Deno.remove(path)
+})
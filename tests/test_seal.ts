@@ -0,0 +1,153 @@
+// mmap_memfd_create / mmap_seal: kernel-enforced immutability of a shared-memory file (Linux).
+
+import { assert, assertEquals, assertThrows } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OS = -2
+const MMAP_ERR_READ_ONLY = -7
+const MMAP_ERR_UNSUPPORTED = -11
+const linux = Deno.build.os === "linux"
+
+const symbols = {
+    mmap_memfd_create: { parameters: ["usize", "buffer"], result: "pointer" },
+    mmap_seal: { parameters: ["pointer", "bool", "bool", "bool"], result: "i32" },
+    mmap_handle_open: { parameters: ["buffer"], result: "pointer" },
+    mmap_handle_ptr: { parameters: ["pointer"], result: "pointer" },
+    mmap_handle_path: { parameters: ["pointer", "buffer", "usize"], result: "isize" },
+    mmap_handle_is_writable: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_sync_all: { parameters: ["pointer"], result: "i32" },
+    mmap_is_stale: { parameters: ["pointer"], result: "i32" },
+    mmap_set_delete_on_close: { parameters: ["pointer", "i32"], result: "i32" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_commit_atomic: { parameters: ["pointer", "buffer"], result: "i32" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_read: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_ensure_capacity: { parameters: ["pointer", "usize", "buffer"], result: "i32" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+} as const
+const lib = Deno.dlopen(libPath, symbols)
+
+/** A path that reopens the only open memfd of the library, through /proc/self/fd. */
+function memfdPath(): string {
+    const fds = [...Deno.readDirSync("/proc/self/fd")]
+        .map((e) => `/proc/self/fd/${e.name}`)
+        .filter((fd) => {
+            try {
+                return Deno.readLinkSync(fd).startsWith("/memfd:mmap ")
+            } catch {
+                return false
+            }
+        })
+    assert(fds.length > 0, "no memfd open")
+    return fds[0]
+}
+
+Deno.test({
+    name: "a memfd sealed against writes can't be written at all",
+    ignore: !linux,
+    fn: () => {
+        const lenBuf = new BigUint64Array(1)
+        const h = lib.symbols.mmap_memfd_create(8192n, new Uint8Array(lenBuf.buffer))
+        assert(!isNull(h), "mmap_memfd_create failed")
+        assertEquals(lenBuf[0], 8192n)
+        const blob = new TextEncoder().encode("immutable blob")
+        assertEquals(lib.symbols.mmap_handle_write(h, 100n, blob, BigInt(blob.length)), BigInt(blob.length))
+
+        assertEquals(lib.symbols.mmap_seal(h, false, false, false), 0)
+        assertEquals(lib.symbols.mmap_handle_is_writable(h), 0)
+        assertEquals(lib.symbols.mmap_handle_write(h, 0n, blob, BigInt(blob.length)), -1n)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+        const out = new Uint8Array(blob.length)
+        lib.symbols.mmap_handle_read(h, 100n, out, BigInt(out.length))
+        assertEquals(out, blob)
+
+        // The seal holds for every descriptor, not just the handle's.
+        const file = Deno.openSync(memfdPath(), { write: true })
+        assertThrows(() => file.writeSync(blob), Deno.errors.PermissionDenied)
+        assertThrows(() => file.truncateSync(16384), Deno.errors.PermissionDenied)
+        file.close()
+        lib.symbols.mmap_handle_free(h)
+    },
+})
+
+Deno.test({
+    name: "writing through the pointer of a write-sealed memfd faults",
+    ignore: !linux,
+    fn: async () => {
+        const script = `
+            const lib = Deno.dlopen(${JSON.stringify(libPath)}, ${JSON.stringify(symbols)})
+            const h = lib.symbols.mmap_memfd_create(4096n, new Uint8Array(8))
+            if (lib.symbols.mmap_seal(h, false, true, true) !== 0) Deno.exit(2)
+            lib.symbols.mmap_write(lib.symbols.mmap_handle_ptr(h), 0n, new Uint8Array([1]), 1n)
+            console.log("survived")
+        `
+        const child = await new Deno.Command(Deno.execPath(), {
+            args: ["eval", "--unstable-ffi", script],
+        }).output()
+        assert(child.code !== 2, "mmap_seal failed in the child")
+        assert(!child.success, "the child should have crashed")
+        assertEquals(new TextDecoder().decode(child.stdout), "")
+    },
+})
+
+Deno.test({
+    name: "a memfd sealed against growing stays writable",
+    ignore: !linux,
+    fn: () => {
+        const lenBuf = new BigUint64Array(1)
+        const h = lib.symbols.mmap_memfd_create(4096n, new Uint8Array(lenBuf.buffer))
+        assert(!isNull(h), "mmap_memfd_create failed")
+
+        assertEquals(lib.symbols.mmap_seal(h, true, false, true), 0)
+        assertEquals(lib.symbols.mmap_handle_is_writable(h), 1)
+        assertEquals(lib.symbols.mmap_handle_write(h, 0n, new Uint8Array([7]), 1n), 1n)
+        assertEquals(lib.symbols.mmap_ensure_capacity(h, 8192n, null), -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+        lib.symbols.mmap_handle_free(h)
+    },
+})
+
+Deno.test({
+    name: "a memfd has no path, so the steps that need one are skipped or refused",
+    ignore: !linux,
+    fn: async () => {
+        const lenBuf = new BigUint64Array(1)
+        const h = lib.symbols.mmap_memfd_create(4096n, new Uint8Array(lenBuf.buffer))
+        assert(!isNull(h), "mmap_memfd_create failed")
+        assertEquals(lib.symbols.mmap_handle_path(h, new Uint8Array(8), 8n), 0n)
+        assertEquals(lib.symbols.mmap_handle_sync_all(h), 0)
+        assertEquals(lib.symbols.mmap_is_stale(h), 0)
+
+        const target = await Deno.makeTempFile()
+        assertEquals(lib.symbols.mmap_commit_atomic(h, cString(target)), -1)
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+        assertEquals(await Deno.readFile(target), new Uint8Array(0))
+        await Deno.remove(target)
+
+        assertEquals(lib.symbols.mmap_set_delete_on_close(h, 1), 0)
+        assertEquals(lib.symbols.mmap_handle_close(h), 0)
+        lib.symbols.mmap_handle_free(h)
+    },
+})
+
+Deno.test("only memfds can be sealed", async () => {
+    const lenBuf = new BigUint64Array(1)
+    assert(isNull(lib.symbols.mmap_memfd_create(0n, new Uint8Array(lenBuf.buffer))))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    if (!linux) {
+        assert(isNull(lib.symbols.mmap_memfd_create(4096n, new Uint8Array(lenBuf.buffer))))
+        assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+    }
+
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "regular file")
+    const h = lib.symbols.mmap_handle_open(cString(path))
+    assert(!isNull(h), "mmap_handle_open failed")
+    assertEquals(lib.symbols.mmap_seal(h, false, false, false), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
@@ -0,0 +1,39 @@
+// mmap_open_sequential: a read-only mapping advised for a single forward scan.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_sequential: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_OS = -2
+
+Deno.test("mmap_open_sequential maps the whole file for reading", async () => {
+    const path = await Deno.makeTempFile()
+    const data = new Uint8Array(3 * 4096 + 100).map((_, i) => i % 251)
+    await Deno.writeFile(path, data)
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_sequential(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_sequential failed")
+    assertEquals(lenBuf[0], BigInt(data.length))
+    const out = new Uint8Array(data.length)
+    assertEquals(lib.symbols.mmap_read(out, p, 0n, lenBuf[0]), lenBuf[0])
+    assertEquals(out, data)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_open_sequential fails like mmap_open", async () => {
+    const dir = await Deno.makeTempDir()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_sequential(cString(`${dir}/missing`), new Uint8Array(lenBuf.buffer))
+    assert(isNull(p))
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+    await Deno.remove(dir)
+})
@@ -0,0 +1,37 @@
+// mmap_open_shared_ro: a read-only view that observes writes made by other processes.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_shared_ro: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+Deno.test("mmap_open_shared_ro sees writes from another process", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(4096).fill(0x61))
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_shared_ro(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_shared_ro failed")
+    assertEquals(lenBuf[0], 4096n)
+
+    // Overwrite two bytes in place from a child process while the mapping is open.
+    const script = `
+        const f = await Deno.open(${JSON.stringify(path)}, { write: true })
+        await f.seek(10, Deno.SeekMode.Start)
+        await f.write(new TextEncoder().encode("ZZ"))
+        f.close()
+    `
+    const child = await new Deno.Command(Deno.execPath(), { args: ["eval", script] }).output()
+    assert(child.success, new TextDecoder().decode(child.stderr))
+
+    const out = new Uint8Array(2)
+    lib.symbols.mmap_read(out, p, 10n, 2n)
+    assertEquals(new TextDecoder().decode(out), "ZZ")
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
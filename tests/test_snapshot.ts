@@ -0,0 +1,38 @@
+// Pseudo-files that report size 0 are served as anonymous snapshots (Linux).
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "pointer"], result: "pointer" },
+    mmap_open_snapshot: { parameters: ["buffer", "pointer"], result: "pointer" },
+    mmap_read: { parameters: ["pointer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+function readMapping(p: Deno.PointerValue, len: number): Uint8Array {
+    const out = new Uint8Array(len)
+    lib.symbols.mmap_read(Deno.UnsafePointer.of(out), p, 0n, BigInt(len))
+    return out
+}
+
+for (const fn of ["mmap_open", "mmap_open_snapshot"] as const) {
+    Deno.test({
+        name: `${fn} reads /proc/self/cmdline`,
+        ignore: Deno.build.os !== "linux",
+        fn() {
+            const lenBuf = new BigUint64Array(1)
+            const p = lib.symbols[fn](cString("/proc/self/cmdline"), Deno.UnsafePointer.of(lenBuf))
+            assert(!isNull(p), `${fn} failed`)
+
+            const len = Number(lenBuf[0])
+            assert(len > 0, "snapshot length is zero")
+            assertEquals(readMapping(p, len), Deno.readFileSync("/proc/self/cmdline"))
+
+            // Flushing a snapshot is a no-op that succeeds.
+            assertEquals(lib.symbols.mmap_flush(p, 0n, BigInt(len)), 0)
+            lib.symbols.mmap_close(p, BigInt(len))
+        },
+    })
+}
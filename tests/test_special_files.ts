@@ -0,0 +1,41 @@
+// Opening directories and FIFOs reports specific error codes instead of a bare null.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_IS_DIRECTORY = -8
+const MMAP_ERR_IS_PIPE = -9
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "pointer"], result: "pointer" },
+    mmap_open_write: { parameters: ["buffer", "pointer"], result: "pointer" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+function expectError(path: string, code: number) {
+    const lenBuf = new BigUint64Array(1)
+    for (const fn of ["mmap_open", "mmap_open_write"] as const) {
+        const p = lib.symbols[fn](cString(path), Deno.UnsafePointer.of(lenBuf))
+        assert(isNull(p), `${fn} unexpectedly mapped ${path}`)
+        assertEquals(lib.symbols.mmap_last_error(), code, fn)
+    }
+}
+
+Deno.test("opening a directory fails with MMAP_ERR_IS_DIRECTORY", async () => {
+    const dir = await Deno.makeTempDir()
+    expectError(dir, MMAP_ERR_IS_DIRECTORY)
+    await Deno.remove(dir)
+})
+
+Deno.test({
+    name: "opening a FIFO fails with MMAP_ERR_IS_PIPE without blocking",
+    ignore: Deno.build.os === "windows",
+    async fn() {
+        const dir = await Deno.makeTempDir()
+        const fifo = `${dir}/fifo`
+        const { success } = await new Deno.Command("mkfifo", { args: [fifo] }).output()
+        assert(success, "mkfifo failed")
+        expectError(fifo, MMAP_ERR_IS_PIPE)
+        await Deno.remove(dir, { recursive: true })
+    },
+})
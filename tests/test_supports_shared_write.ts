@@ -0,0 +1,35 @@
+// mmap_supports_shared_write: probing whether shared-mapping writes persist in a directory.
+// Local temp directories always pass; a failing network or overlay mount can't be staged here.
+
+import { assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_supports_shared_write: { parameters: ["buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OS = -2
+
+Deno.test("mmap_supports_shared_write passes for a local temp directory", async () => {
+    const dir = await Deno.makeTempDir()
+    assertEquals(lib.symbols.mmap_supports_shared_write(cString(dir)), 1)
+    // A file probes its directory, and the probe file is cleaned up.
+    const path = `${dir}/data.bin`
+    await Deno.writeFile(path, new Uint8Array(10))
+    assertEquals(lib.symbols.mmap_supports_shared_write(cString(path)), 1)
+    const names: string[] = []
+    for await (const entry of Deno.readDir(dir)) names.push(entry.name)
+    assertEquals(names, ["data.bin"])
+    await Deno.remove(dir, { recursive: true })
+})
+
+Deno.test("mmap_supports_shared_write fails without a usable directory", async () => {
+    const dir = await Deno.makeTempDir()
+    assertEquals(lib.symbols.mmap_supports_shared_write(cString(`${dir}/missing/x`)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OS)
+    assertEquals(lib.symbols.mmap_supports_shared_write(null), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    await Deno.remove(dir)
+})
@@ -0,0 +1,43 @@
+// mmap_enable_thp / mmap_disable_thp: transparent huge page hints, Linux only.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_enable_thp: { parameters: ["pointer", "usize"], result: "i32" },
+    mmap_disable_thp: { parameters: ["pointer", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_UNSUPPORTED = -11
+
+Deno.test("THP hints succeed or report unsupported", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeFile(path, new Uint8Array(4 * 1024 * 1024).fill(7))
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+
+    for (const hint of [lib.symbols.mmap_enable_thp, lib.symbols.mmap_disable_thp]) {
+        const rc = hint(p, lenBuf[0])
+        if (Deno.build.os === "linux") {
+            if (rc !== 0) assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+        } else {
+            assertEquals(rc, -1)
+            assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_UNSUPPORTED)
+        }
+    }
+
+    // Argument errors come first everywhere.
+    assertEquals(lib.symbols.mmap_enable_thp(p, lenBuf[0] + 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_enable_thp(null, 4096n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
@@ -0,0 +1,57 @@
+// mmap_open_tmp: scratch mappings over an unnamed temp file that vanishes on close.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_tmp: { parameters: ["usize", "buffer"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+function tempDir(): string {
+    return Deno.env.get("TMPDIR") ?? Deno.env.get("TEMP") ?? "/tmp"
+}
+
+/** Temp files this library created that still have a name. */
+function strayTempFiles(): string[] {
+    return [...Deno.readDirSync(tempDir())].map((e) => e.name).filter((n) => n.startsWith("mmap-"))
+}
+
+/** Linux: open descriptors on deleted temp files (what an unlinked scratch file looks like). */
+function deletedTempFds(): number {
+    if (Deno.build.os !== "linux") return 0
+    let n = 0
+    for (const e of Deno.readDirSync("/proc/self/fd")) {
+        try {
+            if (Deno.readLinkSync(`/proc/self/fd/${e.name}`).endsWith("(deleted)")) n++
+        } catch {
+            // The descriptor used to read the directory itself is gone by now.
+        }
+    }
+    return n
+}
+
+Deno.test("mmap_open_tmp leaves nothing behind after close", () => {
+    const strayBefore = strayTempFiles().length
+    const fdsBefore = deletedTempFds()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_tmp(1n << 20n, new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open_tmp failed")
+    assertEquals(lenBuf[0], 1n << 20n)
+
+    const data = new TextEncoder().encode("scratch")
+    lib.symbols.mmap_write(p, 1000n, data, 7n)
+    const out = new Uint8Array(7)
+    lib.symbols.mmap_read(out, p, 1000n, 7n)
+    assertEquals(out, data)
+    if (Deno.build.os !== "windows") {
+        // Already unlinked while mapped.
+        assertEquals(strayTempFiles().length, strayBefore)
+    }
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    assertEquals(strayTempFiles().length, strayBefore)
+    assertEquals(deletedTempFds(), fdsBefore)
+})
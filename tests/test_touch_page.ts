@@ -0,0 +1,56 @@
+// mmap_touch_page: faulting in the single page holding an offset. Residency is checked via
+// /proc/self/smaps on Linux; elsewhere the test only checks the bytes read.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_OK = 0
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const SIZE = 8 * 1024 * 1024
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_touch_page: { parameters: ["pointer", "usize"], result: "u8" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+/** Resident size in bytes of the mapping of `path`, or null where smaps isn't available. */
+function residentBytes(path: string): number | null {
+    if (Deno.build.os !== "linux") return null
+    const lines = Deno.readTextFileSync("/proc/self/smaps").split("\n")
+    const start = lines.findIndex((l) => l.endsWith(path))
+    assert(start >= 0, "mapping not found in /proc/self/smaps")
+    const rss = lines.slice(start + 1).find((l) => l.startsWith("Rss:"))!
+    return parseInt(rss.split(/\s+/)[1]) * 1024
+}
+
+Deno.test("mmap_touch_page reads the first byte of the page holding the offset", async () => {
+    // A multiple of every page size, so each marked byte starts a page.
+    const stride = 65536
+    const path = await Deno.makeTempFile()
+    const data = new Uint8Array(SIZE)
+    for (let i = 0; i < SIZE; i += stride) data[i] = (i / stride) % 251
+    await Deno.writeFile(path, data)
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+
+    assertEquals(lib.symbols.mmap_touch_page(p, BigInt(5 * stride + 17)), 5)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_OK)
+    const rss = residentBytes(path)
+    // One page, plus whatever the kernel maps around a fault on its own.
+    if (rss !== null) assert(rss > 0 && rss < SIZE / 4, `resident: ${rss}`)
+
+    // A zero byte is told apart from a failure by the last error.
+    assertEquals(lib.symbols.mmap_touch_page(p, 1n), 0)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_OK)
+    assertEquals(lib.symbols.mmap_touch_page(p, BigInt(SIZE)), 0)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_touch_page(null, 0n), 0)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
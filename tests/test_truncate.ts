@@ -0,0 +1,68 @@
+// mmap_truncate: setting the file length behind a mapping without remapping it.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_truncate: { parameters: ["pointer", "u64"], result: "i32" },
+    mmap_resize: { parameters: ["pointer", "usize", "buffer", "buffer"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+const MMAP_ERR_READ_ONLY = -7
+const MMAP_ERR_MAPPED_LARGER = -21
+
+Deno.test("mmap_truncate grows the file and refuses to cut into the mapping", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 65536n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    assertEquals(lib.symbols.mmap_truncate(p, 1n << 20n), 0)
+    assertEquals((await Deno.stat(path)).size, 1 << 20)
+    assertEquals(lib.symbols.mmap_truncate(p, 8192n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_MAPPED_LARGER)
+    assertEquals((await Deno.stat(path)).size, 1 << 20)
+    // Down to exactly the mapped length is fine.
+    assertEquals(lib.symbols.mmap_truncate(p, 65536n), 0)
+    assertEquals((await Deno.stat(path)).size, 65536)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("after the mapping is resized down the file can shrink with it", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 65536n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+
+    const baseBuf = new BigUint64Array(1)
+    assertEquals(lib.symbols.mmap_resize(p, 8192n, new Uint8Array(baseBuf.buffer), new Uint8Array(lenBuf.buffer)), 0)
+    const q = Deno.UnsafePointer.create(baseBuf[0])
+    // Preallocate, then trim back down to the (smaller) mapping.
+    assertEquals(lib.symbols.mmap_truncate(q, 65536n), 0)
+    assertEquals(lib.symbols.mmap_truncate(q, 8192n), 0)
+    assertEquals((await Deno.stat(path)).size, 8192)
+    assertEquals(lib.symbols.mmap_truncate(q, 4096n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_MAPPED_LARGER)
+
+    lib.symbols.mmap_close(q, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_truncate rejects read-only mappings", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "hello")
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    assertEquals(lib.symbols.mmap_truncate(p, 4096n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    assertEquals((await Deno.stat(path)).size, 5)
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
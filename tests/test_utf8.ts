@@ -0,0 +1,34 @@
+// mmap_validate_utf8: in-place UTF-8 validation of a mapped range.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_validate_utf8: { parameters: ["pointer", "usize", "usize", "buffer"], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+Deno.test("mmap_validate_utf8 reports the first invalid sequence", async () => {
+    const path = await Deno.makeTempFile()
+    const enc = new TextEncoder()
+    // "héllo" (6 bytes), a stray 0xFF, then "wörld".
+    await Deno.writeFile(path, new Uint8Array([...enc.encode("héllo"), 0xff, ...enc.encode("wörld")]))
+
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(p), "mmap_open failed")
+    const errOut = new BigUint64Array(1)
+    const err = new Uint8Array(errOut.buffer)
+
+    assertEquals(lib.symbols.mmap_validate_utf8(p, 0n, 6n, err), 0)
+    assertEquals(lib.symbols.mmap_validate_utf8(p, 0n, lenBuf[0], err), 1)
+    assertEquals(errOut[0], 6n)
+    // Starting inside "é" is invalid right away; the offset is relative to the mapping.
+    assertEquals(lib.symbols.mmap_validate_utf8(p, 2n, 4n, err), 1)
+    assertEquals(errOut[0], 2n)
+    assertEquals(lib.symbols.mmap_validate_utf8(null, 0n, 1n, err), -1)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
@@ -0,0 +1,26 @@
+// mmap_version / mmap_abi_version: build identification for loaders.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_version: { parameters: ["buffer", "usize"], result: "usize" },
+    mmap_abi_version: { parameters: [], result: "u32" },
+})
+
+Deno.test("mmap_version reports the crate version and ABI tag", () => {
+    const abi = lib.symbols.mmap_abi_version()
+    assert(abi >= 1)
+
+    const buf = new Uint8Array(128)
+    const n = Number(lib.symbols.mmap_version(buf, BigInt(buf.length)))
+    const info = new TextDecoder().decode(buf.subarray(0, n))
+    assert(/^\d+\.\d+\.\d+ /.test(info), info)
+    assert(info.includes(`abi=${abi}`), info)
+    assertEquals(buf[n], 0)
+
+    // Truncated to fit, still NUL-terminated.
+    const small = new Uint8Array(4)
+    assertEquals(lib.symbols.mmap_version(small, 4n), 3n)
+    assertEquals(small[3], 0)
+})
@@ -0,0 +1,76 @@
+// mmap_window_*: streaming through a file several times larger than the window.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_window_open: { parameters: ["buffer", "usize", "buffer"], result: "u64" },
+    mmap_window_seek: { parameters: ["u64", "u64", "buffer", "buffer"], result: "i32" },
+    mmap_window_close: { parameters: ["u64"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const KiB = 1024
+
+/** FNV-1a, continued from `h`. */
+function fnv1a(bytes: Uint8Array, h = 0x811c9dc5): number {
+    for (const b of bytes) h = Math.imul(h ^ b, 0x01000193) >>> 0
+    return h
+}
+
+function randomFile(len: number): Uint8Array {
+    const data = new Uint8Array(len)
+    for (let i = 0; i < len; i += 65536) crypto.getRandomValues(data.subarray(i, i + 65536))
+    return data
+}
+
+Deno.test("streaming through a window hashes the same as the whole file", async () => {
+    const path = await Deno.makeTempFile()
+    const data = randomFile(3 * 1024 * KiB + 17)
+    await Deno.writeFile(path, data)
+    const err = new Int32Array(1)
+    const id = lib.symbols.mmap_window_open(cString(path), BigInt(256 * KiB), new Uint8Array(err.buffer))
+    assert(id !== 0n, `mmap_window_open failed: ${err[0]}`)
+    assertEquals(err[0], 0)
+
+    const ptr = new BigUint64Array(1)
+    const valid = new BigUint64Array(1)
+    const seek = (offset: number) => {
+        assertEquals(lib.symbols.mmap_window_seek(id, BigInt(offset), new Uint8Array(ptr.buffer), new Uint8Array(valid.buffer)), 0)
+        return Number(valid[0])
+    }
+    // Odd-sized reads, so seeks land at unaligned offsets and near the window's edge.
+    let offset = 0
+    let hash: number | undefined
+    for (let n = seek(0); n > 0; n = seek(offset)) {
+        assert(n >= Math.min(256 * KiB, data.length - offset), `only ${n} bytes valid at ${offset}`)
+        const take = Math.min(n, 100_003)
+        const p = Deno.UnsafePointer.create(ptr[0])!
+        hash = fnv1a(new Uint8Array(Deno.UnsafePointerView.getArrayBuffer(p, take)), hash)
+        offset += take
+    }
+    assertEquals(offset, data.length)
+    assertEquals(hash, fnv1a(data))
+
+    // Backwards works too; past the end doesn't.
+    seek(12345)
+    const p = Deno.UnsafePointer.create(ptr[0])!
+    assertEquals(new Uint8Array(Deno.UnsafePointerView.getArrayBuffer(p, 4)), data.subarray(12345, 12349))
+    assertEquals(lib.symbols.mmap_window_seek(id, BigInt(data.length + 1), new Uint8Array(8), new Uint8Array(8)), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+
+    assertEquals(lib.symbols.mmap_window_close(id), 0)
+    assertEquals(lib.symbols.mmap_window_close(id), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_window_open rejects a zero window size", async () => {
+    const path = await Deno.makeTempFile()
+    const err = new Int32Array(1)
+    assertEquals(lib.symbols.mmap_window_open(cString(path), 0n, new Uint8Array(err.buffer)), 0n)
+    assertEquals(err[0], MMAP_ERR_INVALID_ARG)
+    await Deno.remove(path)
+})
@@ -0,0 +1,92 @@
+// mmap_handle_set_writethrough: checked writes that are flushed before they return.
+// Linux 6.5 and later tell how many of a file's cached pages are dirty (cachestat), which is
+// how the test sees that a write reached the disk; elsewhere it counts the flushes only.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_ERR_CLOSED = -6
+const SYS_CACHESTAT = 451
+
+const lib = Deno.dlopen(libPath, {
+    mmap_handle_open_write: { parameters: ["buffer", "usize"], result: "pointer" },
+    mmap_handle_set_writethrough: { parameters: ["pointer", "i32"], result: "i32" },
+    mmap_handle_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_append: { parameters: ["pointer", "buffer", "usize"], result: "isize" },
+    mmap_write_grow: { parameters: ["pointer", "usize", "buffer", "usize"], result: "isize" },
+    mmap_handle_stats: { parameters: ["pointer", "buffer"], result: "i32" },
+    mmap_handle_close: { parameters: ["pointer"], result: "i32" },
+    mmap_handle_free: { parameters: ["pointer"], result: "void" },
+    mmap_last_error: { parameters: [], result: "i32" },
+})
+
+const libc = Deno.build.os === "linux" && ["x86_64", "aarch64"].includes(Deno.build.arch)
+    ? Deno.dlopen("libc.so.6", {
+        open: { parameters: ["buffer", "i32"], result: "i32" },
+        close: { parameters: ["i32"], result: "i32" },
+        syscall: { parameters: ["i64", "i32", "buffer", "buffer", "u32"], result: "i64" },
+    })
+    : null
+
+/**
+ * Pages of `[offset, offset + len)` of the file at `path` that are dirty or still being written
+ * back, or null where cachestat is missing.
+ */
+function dirtyPages(path: string, offset: number, len: number): number | null {
+    if (libc === null) return null
+    const fd = libc.symbols.open(cString(path), 0)
+    assert(fd >= 0, "open failed")
+    // struct cachestat_range { off, len }, then struct cachestat { nr_cache, nr_dirty,
+    // nr_writeback, nr_evicted, nr_recently_evicted }.
+    const range = new BigUint64Array([BigInt(offset), BigInt(len)])
+    const stat = new BigUint64Array(5)
+    const rc = libc.symbols.syscall(
+        BigInt(SYS_CACHESTAT),
+        fd,
+        new Uint8Array(range.buffer),
+        new Uint8Array(stat.buffer),
+        0,
+    )
+    libc.symbols.close(fd)
+    return rc === 0n ? Number(stat[1] + stat[2]) : null
+}
+
+/** `flush_count` of the handle's `MmapStats`. */
+function flushes(h: Deno.PointerValue): bigint {
+    const stats = new BigUint64Array(5)
+    assertEquals(lib.symbols.mmap_handle_stats(h, new Uint8Array(stats.buffer)), 0)
+    return stats[4]
+}
+
+Deno.test("in write-through mode every checked write is flushed", async () => {
+    const path = await Deno.makeTempFile()
+    const h = lib.symbols.mmap_handle_open_write(cString(path), 8192n)
+    assert(!isNull(h), "mmap_handle_open_write failed")
+    const data = new TextEncoder().encode("port = 8080\n")
+
+    assertEquals(lib.symbols.mmap_handle_write(h, 0n, data, BigInt(data.length)), BigInt(data.length))
+    assertEquals(flushes(h), 0n)
+
+    assertEquals(lib.symbols.mmap_handle_set_writethrough(h, 1), 0)
+    assertEquals(lib.symbols.mmap_handle_write(h, 4096n, data, BigInt(data.length)), BigInt(data.length))
+    assertEquals(flushes(h), 1n)
+    // Its page is clean: on disk already.
+    assertEquals(dirtyPages(path, 4096, 4096) ?? 0, 0)
+    assertEquals((await Deno.readFile(path)).subarray(4096, 4096 + data.length), data)
+
+    // The append (at cursor 0) rewrites the first page, so nothing is left dirty after it.
+    assertEquals(lib.symbols.mmap_handle_append(h, data, BigInt(data.length)), 0n)
+    assertEquals(lib.symbols.mmap_write_grow(h, 16384n, data, BigInt(data.length)), BigInt(data.length))
+    assertEquals(flushes(h), 3n)
+    assertEquals(dirtyPages(path, 0, 20480) ?? 0, 0)
+
+    assertEquals(lib.symbols.mmap_handle_set_writethrough(h, 0), 0)
+    lib.symbols.mmap_handle_write(h, 0n, data, BigInt(data.length))
+    assertEquals(flushes(h), 3n)
+
+    assertEquals(lib.symbols.mmap_handle_close(h), 0)
+    assertEquals(lib.symbols.mmap_handle_set_writethrough(h, 1), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_CLOSED)
+    lib.symbols.mmap_handle_free(h)
+    await Deno.remove(path)
+})
@@ -0,0 +1,54 @@
+// mmap_writev: copying several source buffers back to back in one call.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_writev: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+/** MmapIoVec[] { ptr: *const u8, len: usize }; `null` entries encode a null pointer. */
+function ioVecs(bufs: (Uint8Array | null)[]): Uint8Array {
+    const words = new BigUint64Array(bufs.flatMap((b) =>
+        b === null ? [0n, 1n] : [BigInt(Deno.UnsafePointer.value(Deno.UnsafePointer.of(b))), BigInt(b.length)]
+    ))
+    return new Uint8Array(words.buffer)
+}
+
+Deno.test("mmap_writev writes a header and payload back to back", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const enc = new TextEncoder()
+    const header = enc.encode("HDR:")
+    const payload = enc.encode("payload")
+
+    assertEquals(lib.symbols.mmap_writev(p, 10n, ioVecs([header, payload]), 2n), 11n)
+    const out = new Uint8Array(11)
+    lib.symbols.mmap_read(out, p, 10n, 11n)
+    assertEquals(new TextDecoder().decode(out), "HDR:payload")
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_writev stops at the first null buffer", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), 4096n)
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const enc = new TextEncoder()
+
+    const iov = ioVecs([enc.encode("ab"), null, enc.encode("cd")])
+    assertEquals(lib.symbols.mmap_writev(p, 0n, iov, 3n), 2n)
+    const out = new Uint8Array(4)
+    lib.symbols.mmap_read(out, p, 0n, 4n)
+    assertEquals(out, new Uint8Array([0x61, 0x62, 0, 0]))
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
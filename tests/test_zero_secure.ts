@@ -0,0 +1,83 @@
+// mmap_zero_secure: scrubbing a range of an ordinary mapping, in memory and in the file.
+
+import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts"
+import { cString, isNull, libPath } from "./common.ts"
+
+const MMAP_LOCK_BEST_EFFORT = 1 << 6
+const MMAP_ERR_INVALID_ARG = -1
+const MMAP_ERR_OUT_OF_BOUNDS = -5
+const MMAP_ERR_READ_ONLY = -7
+const SIZE = 10000
+
+const lib = Deno.dlopen(libPath, {
+    mmap_open: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_write_with_size: { parameters: ["buffer", "buffer", "usize"], result: "pointer" },
+    mmap_open_snapshot: { parameters: ["buffer", "buffer"], result: "pointer" },
+    mmap_open_secret: { parameters: ["buffer", "buffer", "u32", "buffer"], result: "pointer" },
+    mmap_write: { parameters: ["pointer", "usize", "buffer", "usize"], result: "usize" },
+    mmap_read: { parameters: ["buffer", "pointer", "usize", "usize"], result: "usize" },
+    mmap_flush: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_zero_secure: { parameters: ["pointer", "usize", "usize"], result: "i32" },
+    mmap_last_error: { parameters: [], result: "i32" },
+    mmap_close: { parameters: ["pointer", "usize"], result: "void" },
+})
+
+Deno.test("mmap_zero_secure zeroes the range in the file too", async () => {
+    const path = await Deno.makeTempFile()
+    const lenBuf = new BigUint64Array(1)
+    const p = lib.symbols.mmap_open_write_with_size(cString(path), new Uint8Array(lenBuf.buffer), BigInt(SIZE))
+    assert(!isNull(p), "mmap_open_write_with_size failed")
+    const secret = new TextEncoder().encode("hunter2hunter2")
+    lib.symbols.mmap_write(p, 5000n, secret, BigInt(secret.length))
+    lib.symbols.mmap_write(p, BigInt(SIZE - 4), secret, 4n)
+    assertEquals(lib.symbols.mmap_flush(p, 0n, BigInt(SIZE)), 0)
+
+    // Nothing reads the zeros before the file is checked, so only a wipe that really
+    // happened shows up there.
+    assertEquals(lib.symbols.mmap_zero_secure(p, 5003n, 8n), 0)
+    let onDisk = await Deno.readFile(path)
+    assertEquals(new TextDecoder().decode(onDisk.subarray(5000, 5014)), "hun\0\0\0\0\0\0\0\0er2")
+    // A length of 0 runs to the end.
+    assertEquals(lib.symbols.mmap_zero_secure(p, BigInt(SIZE - 4), 0n), 0)
+    onDisk = await Deno.readFile(path)
+    assertEquals(onDisk.subarray(SIZE - 4), new Uint8Array(4))
+
+    assertEquals(lib.symbols.mmap_zero_secure(p, BigInt(SIZE), 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    assertEquals(lib.symbols.mmap_zero_secure(p, BigInt(SIZE + 1), 0n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_OUT_OF_BOUNDS)
+    const inside = Deno.UnsafePointer.create(Deno.UnsafePointer.value(p) + 1n)
+    assertEquals(lib.symbols.mmap_zero_secure(inside, 0n, 1n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_INVALID_ARG)
+
+    lib.symbols.mmap_close(p, lenBuf[0])
+    await Deno.remove(path)
+})
+
+Deno.test("mmap_zero_secure refuses read-only mappings but scrubs secret ones", async () => {
+    const path = await Deno.makeTempFile()
+    await Deno.writeTextFile(path, "hunter2")
+    const lenBuf = new BigUint64Array(1)
+
+    const r = lib.symbols.mmap_open(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(r), "mmap_open failed")
+    assertEquals(lib.symbols.mmap_zero_secure(r, 0n, 0n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    lib.symbols.mmap_close(r, lenBuf[0])
+    const s = lib.symbols.mmap_open_snapshot(cString(path), new Uint8Array(lenBuf.buffer))
+    assert(!isNull(s), "mmap_open_snapshot failed")
+    assertEquals(lib.symbols.mmap_zero_secure(s, 0n, 0n), -1)
+    assertEquals(lib.symbols.mmap_last_error(), MMAP_ERR_READ_ONLY)
+    lib.symbols.mmap_close(s, lenBuf[0])
+
+    const k = lib.symbols.mmap_open_secret(cString(path), new Uint8Array(lenBuf.buffer), MMAP_LOCK_BEST_EFFORT, null)
+    assert(!isNull(k), "mmap_open_secret failed")
+    assertEquals(lib.symbols.mmap_zero_secure(k, 0n, 0n), 0)
+    const out = new Uint8Array(Number(lenBuf[0]))
+    lib.symbols.mmap_read(out, k, 0n, lenBuf[0])
+    assertEquals(out, new Uint8Array(out.length))
+    // The file is left alone.
+    assertEquals(await Deno.readTextFile(path), "hunter2")
+    lib.symbols.mmap_close(k, lenBuf[0])
+    await Deno.remove(path)
+})